@@ -36,6 +36,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         request_timeout: std::time::Duration::from_millis(settings.daemon.ipc_request_timeout_ms),
         max_message_size: settings.daemon.ipc_max_frame_bytes,
         max_pending_requests: settings.daemon.ipc_max_inflight_per_conn,
+        max_decompressed_size: settings.daemon.ipc_max_decompressed_bytes,
+        compression: settings.daemon.ipc_compression,
+        max_reassembled_size: settings.daemon.ipc_max_reassembled_bytes,
+        ..Default::default()
     };
     let _ipc_client = IpcClient::connect_with_config(&settings.daemon.daemon_socket, ipc_config).await?;
     info!("Connected to core daemon via IPC");
@@ -6,7 +6,7 @@
 // Вариант с UI (Slint)
 #[cfg(feature = "ui")]
 mod with_ui {
-    use atom_ipc::IpcClient;
+    use atom_ipc::{IpcClient, IpcEndpoint, IpcStream};
     use atom_settings::Settings;
     use atom_ui::{AtomWindow, UiCommand, UiEvent};
     use std::error::Error;
@@ -38,6 +38,10 @@ mod with_ui {
                 request_timeout: Duration::from_millis(settings.daemon.ipc_request_timeout_ms),
                 max_message_size: settings.daemon.ipc_max_frame_bytes,
                 max_pending_requests: settings.daemon.ipc_max_inflight_per_conn,
+                max_decompressed_size: settings.daemon.ipc_max_decompressed_bytes,
+                compression: settings.daemon.ipc_compression,
+                max_reassembled_size: settings.daemon.ipc_max_reassembled_bytes,
+                ..Default::default()
             };
             let ipc_client = IpcClient::connect_with_config(&settings.daemon.daemon_socket, ipc_config)
                 .await
@@ -122,6 +126,9 @@ mod with_ui {
         // Флаг для анимации статуса поиска (крутилка через Rust)
         let searching_flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
         let searching_flag_ev = searching_flag.clone();
+        // Накопитель строк потоковой выдачи поиска (сбрасывается при старте нового поиска)
+        let search_items: std::sync::Arc<std::sync::Mutex<Vec<slint::SharedString>>> = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let search_items_ev = search_items.clone();
         rt.spawn(async move {
             while let Some(ev) = ui_events.recv().await {
                 let aw = app_ev.clone();
@@ -149,6 +156,8 @@ mod with_ui {
                         });
                         // Запомним время старта
                         if let Ok(mut slot) = search_start_ev.lock() { *slot = Some(std::time::Instant::now()); }
+                        if let Ok(mut acc) = search_items_ev.lock() { acc.clear(); }
+                        if let Ok(mut m) = items_meta_ev.lock() { m.clear(); }
                         // Запускаем анимацию статуса в отдельной задаче
                         searching_flag_ev.store(true, std::sync::atomic::Ordering::Relaxed);
                         let aw2 = app_ev.clone();
@@ -163,6 +172,23 @@ mod with_ui {
                             }
                         });
                     }
+                    UiEvent::SearchResultsChunk { results } => {
+                        // Показываем найденное сразу, не дожидаясь завершения rg
+                        let list = if let (Ok(mut acc), Ok(mut m)) = (search_items_ev.lock(), items_meta_ev.lock()) {
+                            for r in results {
+                                acc.push(format!("{}:{}: {}", r.path, r.line_number, r.line_text).into());
+                                m.push(r.path);
+                            }
+                            acc.clone()
+                        } else { continue };
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(app) = aw.upgrade() {
+                                let model = slint::VecModel::from(list.clone());
+                                let rc = std::rc::Rc::new(model);
+                                app.set_items(slint::ModelRc::from(rc));
+                            }
+                        });
+                    }
                     UiEvent::SearchResults { results } => {
                         let count = results.len();
                         let mut meta_vec: Vec<String> = Vec::with_capacity(count);
@@ -192,12 +218,35 @@ mod with_ui {
                         if let Ok(mut slot) = search_start_ev.lock() { *slot = None; }
                         searching_flag.store(false, std::sync::atomic::Ordering::Relaxed);
                     }
-                    UiEvent::Error { message } => {
+                    UiEvent::Error { code, message } => {
+                        use atom_ipc::ErrorCode;
+                        let text = match code {
+                            ErrorCode::Cancelled => "Cancelled".to_string(),
+                            ErrorCode::Backpressure | ErrorCode::DeadlineExceeded => {
+                                "Daemon is busy, try again in a moment".to_string()
+                            }
+                            ErrorCode::ToolMissing => format!("Required tool is missing (install ripgrep): {}", message),
+                            ErrorCode::PathOutsideWorkspace => format!("Outside of the workspace: {}", message),
+                            ErrorCode::NotFound => format!("Not found: {}", message),
+                            ErrorCode::PermissionDenied => format!("Permission denied: {}", message),
+                            ErrorCode::Unavailable => format!("Daemon unavailable: {}", message),
+                            _ => format!("Error: {}", message),
+                        };
                         let _ = slint::invoke_from_event_loop(move || {
-                            if let Some(app) = aw.upgrade() { app.set_status_text(format!("Error: {}", message).into()); }
+                            if let Some(app) = aw.upgrade() { app.set_status_text(text.into()); }
                         });
                         searching_flag.store(false, std::sync::atomic::Ordering::Relaxed);
                     }
+                    UiEvent::ConnectionStateChanged { state } => {
+                        let text = match state {
+                            atom_ipc::ConnectionState::Reconnecting => "Daemon connection lost, reconnecting…".to_string(),
+                            atom_ipc::ConnectionState::Connected => "Reconnected to daemon".to_string(),
+                            other => format!("Daemon connection: {:?}", other),
+                        };
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(app) = aw.upgrade() { app.set_status_text(text.into()); }
+                        });
+                    }
                     UiEvent::Stats { cancels, deadlines, backpressure } => {
                         let _ = slint::invoke_from_event_loop(move || {
                             if let Some(app) = aw.upgrade() {
@@ -205,7 +254,12 @@ mod with_ui {
                             }
                         });
                     }
-                    UiEvent::FileOpened { buffer_id: _, content } => {
+                    UiEvent::FileOpened { buffer_id: _, content, mode } => {
+                        let status = match mode {
+                            atom_ipc::BufferMode::Normal => "Opened file",
+                            atom_ipc::BufferMode::LargeFile => "Opened large file (syntax highlighting off)",
+                            atom_ipc::BufferMode::Binary => "Opened binary file (read-only hex view)",
+                        };
                         // отобразим содержимое файла справа построчно
                         let lines: Vec<slint::SharedString> = content
                             .lines()
@@ -216,7 +270,7 @@ mod with_ui {
                                 let model = slint::VecModel::from(lines.clone());
                                 let rc = std::rc::Rc::new(model);
                                 app.set_content_items(slint::ModelRc::from(rc));
-                                app.set_status_text("Opened file".into());
+                                app.set_status_text(status.into());
                             }
                         });
                     }
@@ -455,7 +509,8 @@ mod with_ui {
 
     async fn ensure_daemon_running(settings: &Settings) -> Result<(), Box<dyn Error + Send + Sync>> {
         // Быстрая проверка соединения
-        if tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await.is_ok() {
+        let endpoint = IpcEndpoint::parse(&settings.daemon.daemon_socket)?;
+        if IpcStream::connect(&endpoint).await.is_ok() {
             return Ok(());
         }
         if !settings.daemon.auto_start {
@@ -472,7 +527,7 @@ mod with_ui {
 
         let deadline = std::time::Instant::now() + Duration::from_secs(settings.daemon.connection_timeout);
         loop {
-            if tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await.is_ok() {
+            if IpcStream::connect(&endpoint).await.is_ok() {
                 info!("Daemon is up at {}", settings.daemon.daemon_socket);
                 break;
             }
@@ -502,7 +557,7 @@ mod with_ui {
 mod headless {
     use std::error::Error;
     use tracing::{error, info};
-    use atom_ipc::{IpcMessage, IpcPayload, CoreRequest, read_ipc_message, write_ipc_message, RequestId};
+    use atom_ipc::{IpcMessage, IpcPayload, CoreRequest, read_ipc_message, write_ipc_message, RequestId, IpcEndpoint, IpcStream};
     use atom_settings::Settings;
     use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
     use tokio::process::Command;
@@ -518,9 +573,10 @@ mod headless {
         // Попытка подключиться к демону и выполнить ping по реальному IPC протоколу
         let settings = Settings::load().await?;
         ensure_daemon_running(&settings).await?;
-        match tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await {
+        let endpoint = IpcEndpoint::parse(&settings.daemon.daemon_socket)?;
+        match IpcStream::connect(&endpoint).await {
             Ok(stream) => {
-                info!("TCP connected to {}", settings.daemon.daemon_socket);
+                info!("IPC connected to {}", endpoint);
                 let (read_half, write_half) = stream.into_split();
                 let mut reader = BufReader::new(read_half);
                 let mut writer = BufWriter::new(write_half);
@@ -533,13 +589,14 @@ mod headless {
                     Err(e) => error!("IPC read failed: {}", e),
                 }
             }
-            Err(e) => error!("TCP connect failed to {}: {}", settings.daemon.daemon_socket, e),
+            Err(e) => error!("IPC connect failed to {}: {}", endpoint, e),
         }
         Ok(())
     }
 
     async fn ensure_daemon_running(settings: &Settings) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await.is_ok() { return Ok(()); }
+        let endpoint = IpcEndpoint::parse(&settings.daemon.daemon_socket)?;
+        if IpcStream::connect(&endpoint).await.is_ok() { return Ok(()); }
         if !settings.daemon.auto_start { return Err("Демон недоступен и auto_start=false".into()); }
         tracing::info!("Daemon is not running; attempting auto-start...");
         let exe = resolve_daemon_executable(settings).await;
@@ -549,7 +606,7 @@ mod headless {
             .spawn()?;
         let deadline = std::time::Instant::now() + std::time::Duration::from_secs(settings.daemon.connection_timeout);
         loop {
-            if tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await.is_ok() { break; }
+            if IpcStream::connect(&endpoint).await.is_ok() { break; }
             if std::time::Instant::now() > deadline { let _ = child.start_kill(); return Err("Не удалось запустить демон вовремя".into()); }
             tokio::time::sleep(std::time::Duration::from_millis(150)).await;
         }
@@ -628,7 +685,8 @@ mod winit_ui {
 
     async fn open_via_ipc(open_path: &str) -> Result<(String, usize), Box<dyn Error + Send + Sync>> {
         let settings = Settings::load().await?;
-        let stream = tokio::net::TcpStream::connect(&settings.daemon.daemon_socket).await?;
+        let endpoint = atom_ipc::IpcEndpoint::parse(&settings.daemon.daemon_socket)?;
+        let stream = atom_ipc::IpcStream::connect(&endpoint).await?;
         let (read_half, write_half) = stream.into_split();
         let mut reader = BufReader::new(read_half);
         let mut writer = BufWriter::new(write_half);
@@ -645,7 +703,7 @@ mod winit_ui {
         writer.flush().await?;
         let msg = read_ipc_message(&mut reader).await?;
         match msg.payload {
-            IpcPayload::Response(atom_ipc::CoreResponse::BufferOpened { buffer_id, content }) => Ok((buffer_id, content.len())),
+            IpcPayload::Response(atom_ipc::CoreResponse::BufferOpened { buffer_id, content, .. }) => Ok((buffer_id, content.len())),
             other => Err(format!("Unexpected response: {:?}", other).into()),
         }
     }
@@ -33,7 +33,10 @@ async fn e2e_headless_backpressure() {
     // Второй попадёт под backpressure на сервере, но клиент всё равно получит Response::Error
     let (_id2, rx2) = cli.start_request(atom_ipc::CoreRequest::Sleep { millis: 10 }).await.expect("start2");
     match rx2.await {
-        Ok(Ok(atom_ipc::CoreResponse::Error { message })) => assert!(message.contains("Backpressure"), "msg: {}", message),
+        Ok(Ok(atom_ipc::CoreResponse::Error { code, message, .. })) => {
+            assert_eq!(code, atom_ipc::ErrorCode::Backpressure);
+            assert!(message.contains("Backpressure"), "msg: {}", message)
+        }
         other => panic!("unexpected: {:?}", other),
     }
 
@@ -42,7 +42,8 @@ async fn e2e_headless_deadline() {
     // Ожидаем ошибку Deadline exceeded
     let resp = read_ipc_message(&mut reader).await.expect("read");
     match resp.payload {
-        IpcPayload::Response(CoreResponse::Error { message }) => assert!(message.contains("Deadline exceeded"), "got: {}", message),
+        // Клиент без рукопожатия получает ошибку версии 1: без класса, только сообщение
+        IpcPayload::Response(CoreResponse::Error { message, .. }) => assert!(message.contains("Deadline exceeded"), "got: {}", message),
         other => panic!("unexpected: {:?}", other),
     }
 
@@ -0,0 +1,274 @@
+//! Кэш ответов по RequestId
+//!
+//! Клиент повторяет запрос с тем же `RequestId` после таймаута или
+//! переподключения. Повтор не должен выполнять операцию второй раз
+//! (двойная запись `SaveBuffer`): демон возвращает сохранённый ответ — байт
+//! в байт тот же `CoreResponse`. `OpenBuffer` в кэш не попадает: его повтор
+//! должен сделать новое соединение держателем буфера, а открыть файл
+//! повторно безопасно — уже открытый буфер просто возвращается.
+//!
+//! Кэш общий для всех соединений (повтор приходит уже по новому соединению).
+//! Пока исходный запрос выполняется, повтор ждёт его результата. Если исходный
+//! запрос отменён и не дал ответа, запись удаляется и повтор выполняется заново.
+//! Записи живут `ttl` с момента получения ответа. Ограничены и число записей,
+//! и их суммарный размер: ответы с содержимым файлов (`BufferOpened` от
+//! перечитывания) бывают большими. Ответ больше всего бюджета не сохраняется — повтор такого запроса
+//! выполняется заново.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use atom_ipc::{CoreResponse, RequestId};
+use tokio::sync::watch;
+
+enum Entry {
+    /// Запрос выполняется; приёмник закрывается, когда его `Ticket` завершён или удалён
+    Pending(watch::Receiver<()>),
+    /// Сериализованный ответ и момент его получения
+    Done { response: Arc<[u8]>, at: Instant },
+}
+
+/// Результат поиска в кэше
+pub enum Lookup {
+    /// Запрос уже выполнялся — вернуть сохранённый ответ
+    Hit(CoreResponse),
+    /// Первый запрос с этим id — выполнить и сохранить ответ через `Ticket`
+    Miss(Ticket),
+}
+
+pub struct ResponseCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<RequestId, Entry>,
+    /// Завершённые записи в порядке получения ответа (для TTL и вытеснения)
+    order: VecDeque<(RequestId, Instant)>,
+    /// Суммарный размер сериализованных ответов
+    bytes: usize,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, max_bytes: usize, ttl: Duration) -> Arc<Self> {
+        Arc::new(Self { inner: Mutex::new(Inner::default()), capacity, max_bytes, ttl })
+    }
+
+    /// Найти ответ по id запроса или занять id для выполнения
+    pub async fn begin(self: &Arc<Self>, id: RequestId) -> Lookup {
+        loop {
+            let mut pending = {
+                let mut inner = self.inner.lock().unwrap();
+                inner.evict_expired(self.ttl);
+                match inner.entries.get(&id) {
+                    Some(Entry::Done { response, .. }) => match bincode::deserialize(response) {
+                        Ok(response) => return Lookup::Hit(response),
+                        Err(e) => {
+                            tracing::warn!("Dropping undecodable cached response for {:?}: {}", id, e);
+                            inner.remove(&id);
+                            continue;
+                        }
+                    },
+                    Some(Entry::Pending(rx)) => rx.clone(),
+                    None => {
+                        let (tx, rx) = watch::channel(());
+                        inner.entries.insert(id, Entry::Pending(rx));
+                        return Lookup::Miss(Ticket { cache: Arc::clone(self), id, _done: tx, completed: false });
+                    }
+                }
+            };
+            // Ждём завершения исходного запроса (отправитель удаляется вместе с Ticket)
+            let _ = pending.changed().await;
+        }
+    }
+
+    fn complete(&self, id: RequestId, response: &CoreResponse) {
+        let bytes = match bincode::serialize(response) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!("Response for {:?} not cached: {}", id, e);
+                self.abandon(id);
+                return;
+            }
+        };
+        if bytes.len() > self.max_bytes {
+            tracing::debug!("Response for {:?} not cached: {} bytes", id, bytes.len());
+            self.abandon(id);
+            return;
+        }
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.bytes += bytes.len();
+        if let Some(Entry::Done { response, .. }) = inner.entries.insert(id, Entry::Done { response: bytes.into(), at: now }) {
+            inner.bytes -= response.len();
+        }
+        inner.order.push_back((id, now));
+        while inner.order.len() > self.capacity || inner.bytes > self.max_bytes {
+            if let Some((old, at)) = inner.order.pop_front() {
+                inner.remove_done(old, at);
+            }
+        }
+    }
+
+    fn abandon(&self, id: RequestId) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(Entry::Pending(_)) = inner.entries.get(&id) {
+            inner.entries.remove(&id);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    #[cfg(test)]
+    fn bytes(&self) -> usize {
+        self.inner.lock().unwrap().bytes
+    }
+}
+
+impl Inner {
+    fn evict_expired(&mut self, ttl: Duration) {
+        while let Some((id, at)) = self.order.front().copied() {
+            if at.elapsed() < ttl {
+                break;
+            }
+            self.order.pop_front();
+            self.remove_done(id, at);
+        }
+    }
+
+    /// Удалить завершённую запись, если её не заменили более поздней
+    fn remove_done(&mut self, id: RequestId, at: Instant) {
+        if matches!(self.entries.get(&id), Some(Entry::Done { at: done_at, .. }) if *done_at == at) {
+            self.remove(&id);
+        }
+    }
+
+    fn remove(&mut self, id: &RequestId) {
+        if let Some(Entry::Done { response, .. }) = self.entries.remove(id) {
+            self.bytes -= response.len();
+        }
+    }
+}
+
+/// Право выполнить запрос. Без `complete` (отмена, паника) запись удаляется
+pub struct Ticket {
+    cache: Arc<ResponseCache>,
+    id: RequestId,
+    _done: watch::Sender<()>,
+    completed: bool,
+}
+
+impl Ticket {
+    /// Сохранить ответ для повторов
+    pub fn complete(mut self, response: &CoreResponse) {
+        self.cache.complete(self.id, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cache.abandon(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn miss(lookup: Lookup) -> Ticket {
+        match lookup {
+            Lookup::Miss(ticket) => ticket,
+            Lookup::Hit(r) => panic!("unexpected hit: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_id_returns_identical_response() {
+        let cache = ResponseCache::new(8, 1024, Duration::from_secs(60));
+        let id = RequestId::new();
+        let response = CoreResponse::BufferOpened { buffer_id: "b1".into(), content: "text".into(), version: 0, mode: Default::default() };
+        miss(cache.begin(id).await).complete(&response);
+
+        match cache.begin(id).await {
+            Lookup::Hit(cached) => assert_eq!(
+                bincode::serialize(&cached).unwrap(),
+                bincode::serialize(&response).unwrap()
+            ),
+            Lookup::Miss(_) => panic!("expected hit"),
+        }
+        miss(cache.begin(RequestId::new()).await);
+    }
+
+    #[tokio::test]
+    async fn duplicate_waits_for_in_flight_request() {
+        let cache = ResponseCache::new(8, 1024, Duration::from_secs(60));
+        let id = RequestId::new();
+        let ticket = miss(cache.begin(id).await);
+
+        let waiter = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move { matches!(cache.begin(id).await, Lookup::Hit(CoreResponse::Success)) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        ticket.complete(&CoreResponse::Success);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn abandoned_request_runs_again() {
+        let cache = ResponseCache::new(8, 1024, Duration::from_secs(60));
+        let id = RequestId::new();
+        drop(miss(cache.begin(id).await));
+        miss(cache.begin(id).await);
+    }
+
+    #[tokio::test]
+    async fn entries_expire_and_are_bounded() {
+        let cache = ResponseCache::new(2, 1024, Duration::from_millis(50));
+        let ids: Vec<RequestId> = (0..3).map(|_| RequestId::new()).collect();
+        for id in &ids {
+            miss(cache.begin(*id).await).complete(&CoreResponse::Pong);
+        }
+        // Самая старая запись вытеснена по размеру
+        assert_eq!(cache.len(), 2);
+        miss(cache.begin(ids[0]).await).complete(&CoreResponse::Pong);
+        assert!(matches!(cache.begin(ids[2]).await, Lookup::Hit(CoreResponse::Pong)));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        miss(cache.begin(ids[2]).await);
+    }
+
+    #[tokio::test]
+    async fn cache_is_bounded_by_bytes() {
+        let opened = |content: &str| CoreResponse::BufferOpened {
+            buffer_id: "b1".into(),
+            content: content.into(),
+            version: 0,
+            mode: Default::default(),
+        };
+        let cache = ResponseCache::new(8, 256, Duration::from_secs(60));
+        let (first, second, huge) = (RequestId::new(), RequestId::new(), RequestId::new());
+        miss(cache.begin(first).await).complete(&opened(&"a".repeat(100)));
+        miss(cache.begin(second).await).complete(&opened(&"b".repeat(100)));
+        // Вместе не помещаются: первая запись вытеснена
+        assert_eq!(cache.len(), 1);
+        assert!(cache.bytes() <= 256);
+        assert!(matches!(cache.begin(second).await, Lookup::Hit(_)));
+
+        // Ответ больше бюджета не сохраняется и не вытесняет остальные
+        miss(cache.begin(huge).await).complete(&opened(&"c".repeat(1000)));
+        miss(cache.begin(huge).await);
+        assert!(matches!(cache.begin(second).await, Lookup::Hit(_)));
+    }
+}
@@ -3,19 +3,28 @@
 //! Backend service that handles file operations, indexing, LSP integration
 //! and plugin management.
 
-use atom_core::BufferManager;
+mod idempotency;
+mod notifications;
+mod sessions;
+
+use atom_core::{BufferManager, CoreError, Reloaded, TextBuffer};
 use atom_ipc::{
-    read_ipc_message_cfg, write_ipc_message_cfg, CoreRequest, CoreResponse, IpcMessage, IpcPayload,
-    RequestId, SearchOptions as IpcSearchOptions,
+    capabilities, read_ipc_frame, server_handshake, write_ipc_frame, BufferMode, CoreRequest, CoreResponse,
+    ErrorCode, ErrorDetail, FrameOptions, IpcEndpoint, IpcListener, IpcMessage, IpcPayload,
+    IpcWriteHalf, Notification, RequestId, SearchOptions as IpcSearchOptions, ServerHandshake,
+    SubscriptionTopic, LINES_PROTOCOL_VERSION,
 };
-use atom_settings::Settings;
+use idempotency::{Lookup, ResponseCache};
+use notifications::NotificationHub;
+use sessions::BufferSessions;
+use atom_settings::{DaemonSettings, Settings};
 use std::error::Error;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use std::collections::HashMap;
 use tokio::task::JoinHandle;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::io::AsyncWriteExt;
 
@@ -29,22 +38,32 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         env!("CARGO_PKG_VERSION")
     );
 
-    // Load settings
-    let mut settings = Settings::load().await.map_err(|e| {
+    // Load settings (настройки рабочего каталога перекрывают глобальные)
+    let workspace_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut settings = Settings::load_with_workspace(&workspace_dir).await.map_err(|e| {
         error!("Failed to load settings: {}", e);
         e
     })?;
 
     // Env overrides for tests/CI
+    if let Ok(v) = std::env::var("ATOMD_SOCKET") {
+        if !v.is_empty() { settings.daemon.daemon_socket = v; }
+    }
     if let Ok(v) = std::env::var("ATOMD_IPC_MAX_INFLIGHT") {
         if let Ok(n) = v.parse::<usize>() { settings.daemon.ipc_max_inflight_per_conn = n; }
     }
     if let Ok(v) = std::env::var("ATOMD_IPC_MAX_FRAME") {
         if let Ok(n) = v.parse::<u32>() { settings.daemon.ipc_max_frame_bytes = n; }
     }
+    if let Ok(v) = std::env::var("ATOMD_IPC_COMPRESSION") {
+        settings.daemon.ipc_compression = !matches!(v.as_str(), "0" | "false" | "off");
+    }
     if let Ok(v) = std::env::var("ATOMD_IPC_REQ_TIMEOUT_MS") {
         if let Ok(n) = v.parse::<u64>() { settings.daemon.ipc_request_timeout_ms = n; }
     }
+    if let Ok(v) = std::env::var("ATOMD_MAX_HIGHLIGHT_SIZE") {
+        if let Ok(n) = v.parse::<u32>() { settings.editor.max_highlight_size = n; }
+    }
 
     info!("Settings loaded successfully");
 
@@ -75,11 +94,9 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     };
 
     // Start IPC server to handle UI connections
-    let bind_addr = settings.daemon.daemon_socket.clone();
-    let max_inflight = settings.daemon.ipc_max_inflight_per_conn;
-    let max_frame = settings.daemon.ipc_max_frame_bytes;
+    let daemon_settings = settings.daemon.clone();
     let server_task = tokio::spawn(async move {
-        match start_ipc_server(&bind_addr, max_inflight, max_frame, buffer_manager, index_engine).await {
+        match start_ipc_server(&daemon_settings, buffer_manager, index_engine).await {
             Ok(_) => info!("IPC server started successfully"),
             Err(e) => error!("IPC server failed: {}", e),
         }
@@ -106,23 +123,45 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
 /// Start IPC server to handle UI connections
 async fn start_ipc_server(
-    bind_addr: &str,
-    max_inflight: usize,
-    max_frame: u32,
+    daemon: &DaemonSettings,
     buffer_manager: Arc<Mutex<BufferManager>>,
     _index_engine: Arc<Mutex<dyn dyn_index::IndexEngineLike + Send + Sync>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let metrics = Arc::new(ServerMetrics::default());
-    use tokio::net::TcpListener;
-    let listener = TcpListener::bind(bind_addr).await?;
-    info!("IPC server listening on {}", bind_addr);
+    let state = Arc::new(DaemonState {
+        buffer_manager,
+        metrics: ServerMetrics::default(),
+        notifications: NotificationHub::new(daemon.ipc_notification_queue),
+        // Ответы для повторов запросов (общие для всех соединений)
+        responses: ResponseCache::new(
+            daemon.ipc_idempotency_cache_entries,
+            daemon.ipc_idempotency_cache_bytes,
+            std::time::Duration::from_millis(daemon.ipc_idempotency_ttl_ms),
+        ),
+        sessions: BufferSessions::default(),
+    });
+    let max_inflight = daemon.ipc_max_inflight_per_conn;
+    let max_frame = daemon.ipc_max_frame_bytes;
+    let limits = FrameOptions {
+        max_decompressed_size: daemon.ipc_max_decompressed_bytes,
+        max_reassembled_size: daemon.ipc_max_reassembled_bytes,
+        ..FrameOptions::with_limit(max_frame)
+    };
+    // Возможности сервера: LZ4 можно отключить настройкой
+    let server_caps: Arc<[&'static str]> = capabilities::ALL
+        .iter()
+        .copied()
+        .filter(|c| daemon.ipc_compression || *c != capabilities::LZ4)
+        .collect();
+    let endpoint = IpcEndpoint::parse(&daemon.daemon_socket)?;
+    let listener = IpcListener::bind(&endpoint).await?;
+    info!("IPC server listening on {}", endpoint);
 
     loop {
         let (stream, addr) = listener.accept().await?;
-        let bm = Arc::clone(&buffer_manager);
         info!("New client connected: {}", addr);
 
-        let metrics_cl = Arc::clone(&metrics);
+        let state = Arc::clone(&state);
+        let server_caps = Arc::clone(&server_caps);
         tokio::spawn(async move {
             use tokio::io::{BufReader, BufWriter};
             let (r, w) = stream.into_split();
@@ -134,57 +173,120 @@ async fn start_ipc_server(
             // Текущий корень рабочей области для клиента
             let mut workspace_root: Option<PathBuf> = None;
 
-            while let Ok(IpcMessage { id, deadline_millis, payload }) = read_ipc_message_cfg(&mut reader, max_frame).await {
+            // Рукопожатие: клиент без Hello работает по протоколу версии 1,
+            // его первое сообщение обрабатывается как обычное
+            let handshake = {
+                let mut w = writer.lock().await;
+                let res = server_handshake(&mut reader, &mut *w, max_frame, &server_caps).await;
+                let _ = w.flush().await;
+                res
+            };
+            let (frame, mut first) = match handshake {
+                Ok(ServerHandshake::Negotiated(protocol)) => {
+                    info!("Client {} negotiated protocol v{} {:?}", addr, protocol.version, protocol.capabilities);
+                    (protocol.frame_options(&limits), None)
+                }
+                Ok(ServerHandshake::Legacy(message)) => (FrameOptions::handshake(max_frame), Some(message)),
+                Err(e) => {
+                    info!("Client {} handshake failed: {}", addr, e);
+                    return;
+                }
+            };
+
+            // Уведомления пишутся отдельной задачей через тот же писатель
+            let subscriber = state.notifications.register();
+            // Идентификатор подписчика служит идентификатором соединения
+            let conn_id = subscriber.id();
+            let pump = tokio::spawn(notifications::pump(Arc::clone(&subscriber), Arc::clone(&writer), frame));
+
+            loop {
+                let IpcMessage { id, deadline_millis, payload } = match first.take() {
+                    Some(message) => message,
+                    None => match read_ipc_frame(&mut reader, &frame).await {
+                        Ok(message) => message,
+                        Err(_) => break,
+                    },
+                };
+                // Потоковый запрос отличается только способом доставки ответа
+                let streaming = matches!(payload, IpcPayload::StreamRequest(_));
                 match payload {
-                    IpcPayload::Request(req) => {
+                    IpcPayload::Request(req) | IpcPayload::StreamRequest(req) => {
                         // Deadline‑reject
                         if deadline_millis > 0 {
                             use std::time::{SystemTime, UNIX_EPOCH};
                             let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
                             if now > deadline_millis {
-                                metrics_cl.deadlines.fetch_add(1, Ordering::Relaxed);
-                                let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Error { message: "Deadline exceeded".into() }) };
+                                state.metrics.deadlines.fetch_add(1, Ordering::Relaxed);
+                                let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::error_with_detail(ErrorCode::DeadlineExceeded, "Deadline exceeded", ErrorDetail::Deadline { deadline_millis })) };
                                 let mut w = writer.lock().await;
-                                let _ = write_ipc_message_cfg(&mut *w, &resp, max_frame).await;
+                                let _ = write_ipc_frame(&mut *w, &resp, &frame).await;
                                 let _ = w.flush().await;
                                 continue;
                             }
                         }
                         if inflight.len() >= max_inflight {
-                            metrics_cl.backpressure.fetch_add(1, Ordering::Relaxed);
-                            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Error { message: "Backpressure: too many in-flight requests".into() }) };
+                            state.metrics.backpressure.fetch_add(1, Ordering::Relaxed);
+                            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::error_with_detail(ErrorCode::Backpressure, "Backpressure: too many in-flight requests", ErrorDetail::Limit { limit: max_inflight as u64 })) };
                             let mut w = writer.lock().await;
-                            let _ = write_ipc_message_cfg(&mut *w, &resp, max_frame).await;
+                            let _ = write_ipc_frame(&mut *w, &resp, &frame).await;
                             let _ = w.flush().await;
                             continue;
                         }
 
                         // Обновляем рабочий корень, если клиент открыл папку
-                        if let CoreRequest::GetProjectFiles { root_path } = &req {
-                            workspace_root = Some(PathBuf::from(root_path.clone()))
+                        match &req {
+                            CoreRequest::GetProjectFiles { root_path } => {
+                                let root = PathBuf::from(root_path.clone());
+                                state.notifications.watch_root(&root, conn_id);
+                                if let Some(previous) = workspace_root.replace(root.clone()) {
+                                    if previous != root {
+                                        state.notifications.unwatch_root(&previous, conn_id);
+                                    }
+                                }
+                            }
+                            CoreRequest::Subscribe { topics } => subscriber.subscribe(topics),
+                            CoreRequest::Unsubscribe { topics } => subscriber.unsubscribe(topics),
+                            _ => {}
                         }
 
-                        let bm_cl = Arc::clone(&bm);
+                        let state_h = Arc::clone(&state);
                         let writer_cl = Arc::clone(&writer);
                         let root_for_req = workspace_root.clone();
                         let req_clone = req;
-                        let metrics_h = Arc::clone(&metrics_cl);
                         let h = tokio::spawn(async move {
-                            let response = handle_core_request_with_root(req_clone, root_for_req, &bm_cl, &metrics_h).await;
-                            let mut w = writer_cl.lock().await;
-                            let _ = write_ipc_message_cfg(&mut *w, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(response) }, max_frame).await;
-                            let _ = w.flush().await;
+                            let sink = ResponseSink { id, writer: writer_cl, frame };
+                            let response = match req_clone {
+                                CoreRequest::Search { query, options } if streaming => {
+                                    stream_search(&query, root_for_req, &options, &sink).await
+                                }
+                                // Открытие делает соединение держателем буфера: повтор (уже по
+                                // новому соединению) выполняется заново, иначе буфер, закрытый
+                                // с прежним соединением, остался бы без держателя
+                                open @ CoreRequest::OpenBuffer { .. } => {
+                                    handle_core_request_with_root(open, root_for_req, &state_h, conn_id, frame.version).await
+                                }
+                                // Повтор с тем же RequestId получает сохранённый ответ
+                                other => match state_h.responses.begin(id).await {
+                                    Lookup::Hit(response) => response,
+                                    Lookup::Miss(ticket) => {
+                                        let response = handle_core_request_with_root(other, root_for_req, &state_h, conn_id, frame.version).await;
+                                        ticket.complete(&response);
+                                        response
+                                    }
+                                },
+                            };
+                            sink.send(response).await;
                         });
                         inflight.insert(id, h);
                     }
                     IpcPayload::Cancel(cancel_id) => {
-                        metrics_cl.cancels.fetch_add(1, Ordering::Relaxed);
+                        state.metrics.cancels.fetch_add(1, Ordering::Relaxed);
                         if let Some(h) = inflight.remove(&cancel_id) {
                             h.abort();
                             // Подтвердим отмену техническим ответом
-                            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Error { message: "Cancelled".into() }) };
+                            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::error(ErrorCode::Cancelled, "Cancelled")) };
                             let mut w = writer.lock().await;
-                            let _ = write_ipc_message_cfg(&mut *w, &resp, max_frame).await;
+                            let _ = write_ipc_frame(&mut *w, &resp, &frame).await;
                             let _ = w.flush().await;
                         }
                     }
@@ -196,7 +298,21 @@ async fn start_ipc_server(
                 // Периодически чистим завершённые задачи
                 inflight.retain(|_, h| !h.is_finished());
             }
-            info!("Client {} disconnected", addr);
+            pump.abort();
+            state.notifications.unregister(conn_id);
+            // Буферы, которые больше никто не держит, закрываются (как при CloseBuffer
+            // от последнего держателя); блокировка менеджера — как в CloseBuffer
+            let orphaned = {
+                let mut bm = state.buffer_manager.lock().await;
+                let orphaned = state.sessions.release_all(conn_id);
+                for buffer_id in &orphaned {
+                    if let Err(e) = bm.close_buffer(buffer_id) {
+                        warn!("Closing orphaned buffer {} failed: {}", buffer_id, e);
+                    }
+                }
+                orphaned
+            };
+            info!("Client {} disconnected (closed {} buffer(s))", addr, orphaned.len());
         });
     }
 }
@@ -207,8 +323,9 @@ async fn start_ipc_server(
 async fn handle_core_request_with_root(
     req: CoreRequest,
     workspace_root: Option<PathBuf>,
-    buffer_manager: &Arc<Mutex<BufferManager>>,
-    metrics: &Arc<ServerMetrics>,
+    state: &DaemonState,
+    conn_id: u64,
+    protocol: u8,
 ) -> CoreResponse {
     match req {
         CoreRequest::Ping => CoreResponse::Pong,
@@ -219,60 +336,213 @@ async fn handle_core_request_with_root(
         }
 
         CoreRequest::OpenBuffer { path } => {
-            let mut bm = buffer_manager.lock().await;
+            let mut bm = state.buffer_manager.lock().await;
             match bm.open_file(&path).await {
                 Ok(buffer_id) => {
-                    let content = bm
+                    state.hold_buffer(&buffer_id, conn_id);
+                    let (content, version, mode) = bm
                         .get_buffer(&buffer_id)
-                        .map(|b| b.content.to_string())
+                        .map(|b| (opened_content(b, protocol), b.version, b.mode))
                         .unwrap_or_default();
-                    CoreResponse::BufferOpened { buffer_id, content }
+                    CoreResponse::BufferOpened { buffer_id, content, version, mode }
                 }
-                Err(e) => CoreResponse::Error {
-                    message: format!("OpenBuffer failed: {}", e),
-                },
+                Err(e) => core_error("OpenBuffer", e),
             }
         }
 
-        CoreRequest::SaveBuffer { buffer_id, content } => {
-            let mut bm = buffer_manager.lock().await;
-            // Если контент передан — заменить до сохранения
+        CoreRequest::SaveBuffer { buffer_id, content, force } => {
+            let mut bm = state.buffer_manager.lock().await;
+            // Конфликт с файлом на диске проверяется до замены содержимого:
+            // отклонённое сохранение не должно менять буфер у держателей
+            if !force {
+                if let Err(e) = bm.check_disk(&buffer_id).await {
+                    return core_error("SaveBuffer", e);
+                }
+            }
+            // Если контент передан — заменить до сохранения (одним шагом истории)
             if !content.is_empty() {
-                if let Some(buf) = bm.get_buffer_mut(&buffer_id) {
-                    buf.content = ropey::Rope::from_str(&content);
-                    buf.is_dirty = true;
-                } else {
-                    return CoreResponse::Error {
-                        message: format!("Unknown buffer_id: {}", buffer_id),
-                    };
+                match bm.set_content(&buffer_id, &content).await {
+                    Ok(applied) => {
+                        let version = bm.get_buffer(&buffer_id).map(|b| b.version).unwrap_or_default();
+                        // Остальные держатели буфера узнают о новом содержимом
+                        state.notifications.publish(
+                            Notification::BufferChanged {
+                                buffer_id: buffer_id.clone(),
+                                changes: vec![text_change(applied)],
+                                version,
+                            },
+                            Some(conn_id),
+                        );
+                    }
+                    Err(e) => return core_error("SaveBuffer", e),
+                }
+            }
+
+            match bm.save_buffer(&buffer_id, None, force).await {
+                Ok(applied) => {
+                    let version = bm.get_buffer(&buffer_id).map(|b| b.version).unwrap_or_default();
+                    // Правки конвейера сохранения (пробелы, концы строк) видны всем держателям
+                    let changes: Vec<_> = applied.into_iter().map(text_change).collect();
+                    if !changes.is_empty() {
+                        state.notifications.publish(
+                            Notification::BufferChanged { buffer_id: buffer_id.clone(), changes: changes.clone(), version },
+                            Some(conn_id),
+                        );
+                    }
+                    CoreResponse::BufferSaved { buffer_id, version, changes }
+                }
+                Err(e) => core_error("SaveBuffer", e),
+            }
+        }
+
+        CoreRequest::ApplyEdits { buffer_id, base_version, edits } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let edits = edits.into_iter().map(core_edit).collect();
+            match bm.apply_edits(&buffer_id, base_version, edits).await {
+                Ok(applied) => {
+                    let version = bm.get_buffer(&buffer_id).map(|b| b.version).unwrap_or_default();
+                    let changes: Vec<_> = applied.into_iter().map(text_change).collect();
+                    // Пустой список правок означает пересинхронизацию
+                    if !changes.is_empty() {
+                        state.notifications.publish(
+                            Notification::BufferChanged { buffer_id: buffer_id.clone(), changes, version },
+                            Some(conn_id),
+                        );
+                    }
+                    CoreResponse::EditsApplied { buffer_id, version }
+                }
+                Err(e) => core_error("ApplyEdits", e),
+            }
+        }
+
+        CoreRequest::Undo { buffer_id } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let result = bm.undo(&buffer_id).await;
+            history_applied("Undo", buffer_id, result, &bm, state, conn_id)
+        }
+
+        CoreRequest::Redo { buffer_id } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let result = bm.redo(&buffer_id).await;
+            history_applied("Redo", buffer_id, result, &bm, state, conn_id)
+        }
+
+        CoreRequest::ReopenWithEncoding { buffer_id, encoding, force } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let result = bm.reopen_with_encoding(&buffer_id, &encoding, force).await;
+            reloaded("ReopenWithEncoding", buffer_id, result, &bm, state, conn_id, protocol)
+        }
+
+        CoreRequest::ReloadBuffer { buffer_id } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let result = bm.reload_buffer(&buffer_id).await;
+            reloaded("ReloadBuffer", buffer_id, result, &bm, state, conn_id, protocol)
+        }
+
+        CoreRequest::GetHighlights { buffer_id, start_line, end_line } => {
+            let mut bm = state.buffer_manager.lock().await;
+            match bm.highlights(&buffer_id, start_line, end_line) {
+                Ok((version, spans)) => CoreResponse::Highlights { buffer_id, version, spans },
+                Err(e) => core_error("GetHighlights", e),
+            }
+        }
+
+        CoreRequest::GetLines { buffer_id, start_line, end_line } => {
+            let bm = state.buffer_manager.lock().await;
+            match bm.lines(&buffer_id, start_line, end_line) {
+                Ok((version, line_count, text)) => {
+                    CoreResponse::Lines { buffer_id, version, start_line: start_line.min(line_count), line_count, text }
                 }
+                Err(e) => core_error("GetLines", e),
             }
+        }
 
-            match bm.save_buffer(&buffer_id, None).await {
-                Ok(_) => CoreResponse::BufferSaved { buffer_id },
-                Err(e) => CoreResponse::Error {
-                    message: format!("SaveBuffer failed: {}", e),
-                },
+        CoreRequest::GetFoldingRanges { buffer_id } => {
+            let mut bm = state.buffer_manager.lock().await;
+            match bm.folding_ranges(&buffer_id) {
+                Ok((version, ranges)) => CoreResponse::FoldingRanges { buffer_id, version, ranges },
+                Err(e) => core_error("GetFoldingRanges", e),
+            }
+        }
+
+        CoreRequest::GetDocumentSymbols { buffer_id } => {
+            let mut bm = state.buffer_manager.lock().await;
+            match bm.document_symbols(&buffer_id) {
+                Ok((version, symbols)) => CoreResponse::DocumentSymbols { buffer_id, version, symbols },
+                Err(e) => core_error("GetDocumentSymbols", e),
+            }
+        }
+
+        CoreRequest::ExpandSelection { buffer_id, ranges } => {
+            let bm = state.buffer_manager.lock().await;
+            let ranges: Vec<_> = ranges.into_iter().map(core_range).collect();
+            let result = bm.expand_selection(&buffer_id, &ranges);
+            selections("ExpandSelection", buffer_id, result)
+        }
+
+        CoreRequest::ShrinkSelection { buffer_id, ranges } => {
+            let bm = state.buffer_manager.lock().await;
+            let ranges: Vec<_> = ranges.into_iter().map(core_range).collect();
+            let result = bm.shrink_selection(&buffer_id, &ranges);
+            selections("ShrinkSelection", buffer_id, result)
+        }
+
+        CoreRequest::EditSelections { buffer_id, base_version, selections, edit } => {
+            let mut bm = state.buffer_manager.lock().await;
+            let selections = selection_set(selections);
+            match bm.edit_selections(&buffer_id, base_version, &selections, &edit).await {
+                Ok((applied, selections)) => {
+                    let version = bm.get_buffer(&buffer_id).map(|b| b.version).unwrap_or_default();
+                    let changes: Vec<_> = applied.into_iter().map(text_change).collect();
+                    if !changes.is_empty() {
+                        state.notifications.publish(
+                            Notification::BufferChanged { buffer_id: buffer_id.clone(), changes: changes.clone(), version },
+                            Some(conn_id),
+                        );
+                    }
+                    CoreResponse::SelectionsEdited { buffer_id, version, changes, selections: selection_ranges(&selections) }
+                }
+                Err(e) => core_error("EditSelections", e),
+            }
+        }
+
+        CoreRequest::AddCursorAtNextMatch { buffer_id, selections } => {
+            let bm = state.buffer_manager.lock().await;
+            match bm.add_cursor_at_next_match(&buffer_id, &selection_set(selections)) {
+                Ok((version, selections)) => {
+                    CoreResponse::Selections { buffer_id, version, ranges: selection_ranges(&selections) }
+                }
+                Err(e) => core_error("AddCursorAtNextMatch", e),
             }
         }
 
         CoreRequest::CloseBuffer { buffer_id } => {
-            let mut bm = buffer_manager.lock().await;
+            let mut bm = state.buffer_manager.lock().await;
+            // Буфер закрывается вместе с последним держателем
+            if state.release_buffer(&buffer_id, conn_id) > 0 {
+                return CoreResponse::BufferClosed { buffer_id };
+            }
             match bm.close_buffer(&buffer_id) {
                 Ok(()) => CoreResponse::BufferClosed { buffer_id },
-                Err(e) => CoreResponse::Error {
-                    message: format!("CloseBuffer failed: {}", e),
-                },
+                Err(e) => core_error("CloseBuffer", e),
             }
         }
 
         CoreRequest::Search { query, options } => {
-            let root = workspace_root.unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
-            match search_with_ripgrep(&query, &root, &options).await {
-                Ok(results) => CoreResponse::SearchResults { results },
-                Err(e) => CoreResponse::Error {
-                    message: format!("Search failed: {}", e),
-                },
+            let root = search_root(workspace_root);
+            // Непотоковый режим: собираем все чанки в один ответ
+            let (tx, mut rx) = mpsc::channel::<Vec<atom_ipc::SearchResult>>(SEARCH_CHANNEL_CAPACITY);
+            let collect = async {
+                let mut results = Vec::new();
+                while let Some(chunk) = rx.recv().await {
+                    results.extend(chunk);
+                }
+                results
+            };
+            let (outcome, results) = tokio::join!(search_with_ripgrep(&query, &root, &options, tx), collect);
+            match outcome {
+                Ok(_) => CoreResponse::SearchResults { results },
+                Err(e) => e.into_response("Search"),
             }
         }
 
@@ -280,10 +550,11 @@ async fn handle_core_request_with_root(
             let root_dir = PathBuf::from(root_path);
             match list_project_files(&root_dir).await {
                 Ok(files) => CoreResponse::ProjectFiles { files },
-                Err(e) => CoreResponse::Error { message: format!("GetProjectFiles failed: {}", e) },
+                Err(e) => e.into_response("GetProjectFiles"),
             }
         }
         CoreRequest::GetStats => {
+            let metrics = &state.metrics;
             CoreResponse::Stats {
                 cancels: metrics.cancels.load(Ordering::Relaxed),
                 deadlines: metrics.deadlines.load(Ordering::Relaxed),
@@ -291,25 +562,306 @@ async fn handle_core_request_with_root(
             }
         }
 
-        CoreRequest::LspRequest { .. } => CoreResponse::Error {
-            message: "LSP bridge not implemented".into(),
-        },
+        CoreRequest::LspRequest { .. } => {
+            CoreResponse::error(ErrorCode::NotImplemented, "LSP bridge not implemented")
+        }
+
+        // Темы подписки уже обновлены в цикле соединения
+        CoreRequest::Subscribe { .. } | CoreRequest::Unsubscribe { .. } => CoreResponse::Success,
     }
 }
 
-/// Поиск через ripgrep с таймаутом и маппингом в IPC SearchResult
+/// Диапазон из запроса в координатах `atom_core`
+fn core_range(range: atom_ipc::TextRange) -> atom_core::Range {
+    atom_core::Range {
+        start: atom_core::Position { line: range.start_line, column: range.start_column },
+        end: atom_core::Position { line: range.end_line, column: range.end_column },
+    }
+}
+
+/// Диапазон `atom_core` для ответа клиенту
+fn text_range(range: atom_core::Range) -> atom_ipc::TextRange {
+    atom_ipc::TextRange {
+        start_line: range.start.line,
+        start_column: range.start.column,
+        end_line: range.end.line,
+        end_column: range.end.column,
+    }
+}
+
+/// Правка из запроса в координатах `atom_core`
+fn core_edit(edit: atom_ipc::TextEdit) -> atom_core::TextEdit {
+    atom_core::TextEdit { range: core_range(edit.range), new_text: edit.new_text }
+}
+
+/// Применённая правка для уведомления `BufferChanged`
+fn text_change(applied: atom_core::AppliedEdit) -> atom_ipc::TextChange {
+    atom_ipc::TextChange {
+        range: text_range(applied.edit.range),
+        new_text: applied.edit.new_text,
+        old_text: applied.old_text,
+    }
+}
+
+/// Ответ на `ExpandSelection`/`ShrinkSelection`
+fn selections(
+    operation: &str,
+    buffer_id: String,
+    result: Result<(u64, Vec<atom_core::Range>), CoreError>,
+) -> CoreResponse {
+    match result {
+        Ok((version, ranges)) => {
+            CoreResponse::Selections { buffer_id, version, ranges: ranges.into_iter().map(text_range).collect() }
+        }
+        Err(e) => core_error(operation, e),
+    }
+}
+
+/// Выделения из запроса: начало диапазона — якорь, конец — курсор, последнее — основное
+fn selection_set(ranges: Vec<atom_ipc::TextRange>) -> atom_core::SelectionSet {
+    let primary = ranges.len().saturating_sub(1);
+    let selections = ranges
+        .into_iter()
+        .map(|range| {
+            let range = core_range(range);
+            atom_core::Selection::new(range.start, range.end)
+        })
+        .collect();
+    atom_core::SelectionSet::new(selections, primary)
+}
+
+/// Выделения для ответа: в порядке документа, основное — последним
+fn selection_ranges(set: &atom_core::SelectionSet) -> Vec<atom_ipc::TextRange> {
+    let mut selections = set.selections().to_vec();
+    let primary = selections.remove(set.primary_index());
+    selections.push(primary);
+    selections
+        .into_iter()
+        .map(|selection| text_range(atom_core::Range { start: selection.anchor, end: selection.head }))
+        .collect()
+}
+
+/// Ответ на `Undo`/`Redo`; остальные держатели буфера получают те же правки
+fn history_applied(
+    operation: &str,
+    buffer_id: String,
+    result: Result<Vec<atom_core::AppliedEdit>, CoreError>,
+    bm: &BufferManager,
+    state: &DaemonState,
+    conn_id: u64,
+) -> CoreResponse {
+    let applied = match result {
+        Ok(applied) => applied,
+        Err(e) => return core_error(operation, e),
+    };
+    let version = bm.get_buffer(&buffer_id).map(|b| b.version).unwrap_or_default();
+    let changes: Vec<_> = applied.into_iter().map(text_change).collect();
+    if !changes.is_empty() {
+        state.notifications.publish(
+            Notification::BufferChanged { buffer_id: buffer_id.clone(), changes: changes.clone(), version },
+            Some(conn_id),
+        );
+    }
+    CoreResponse::HistoryApplied { buffer_id, version, changes }
+}
+
+/// Содержимое буфера для `BufferOpened`: большой файл пиру с `GetLines` не
+/// отправляется — тот запрашивает только видимые строки
+fn opened_content(buffer: &TextBuffer, protocol: u8) -> String {
+    if buffer.mode == BufferMode::LargeFile && protocol >= LINES_PROTOCOL_VERSION {
+        String::new()
+    } else {
+        buffer.content.to_string()
+    }
+}
+
+/// Ответ на перечитывание файла буфера: новое содержимое (см. `opened_content`);
+/// остальные держатели получают замену, если текст изменился, а после замены
+/// большого файла целиком — метку пересинхронизации
+fn reloaded(
+    operation: &str,
+    buffer_id: String,
+    result: Result<Reloaded, CoreError>,
+    bm: &BufferManager,
+    state: &DaemonState,
+    conn_id: u64,
+    protocol: u8,
+) -> CoreResponse {
+    let reloaded = match result {
+        Ok(reloaded) => reloaded,
+        Err(e) => return core_error(operation, e),
+    };
+    let (content, version, mode) =
+        bm.get_buffer(&buffer_id).map(|b| (opened_content(b, protocol), b.version, b.mode)).unwrap_or_default();
+    let changes = match reloaded {
+        Reloaded::Unchanged => None,
+        Reloaded::Edited(applied) => Some(vec![text_change(applied)]),
+        Reloaded::Replaced => Some(Vec::new()),
+    };
+    if let Some(changes) = changes {
+        state.notifications.publish(
+            Notification::BufferChanged { buffer_id: buffer_id.clone(), changes, version },
+            Some(conn_id),
+        );
+    }
+    CoreResponse::BufferOpened { buffer_id, content, version, mode }
+}
+
+/// Состояние демона, общее для всех соединений
+struct DaemonState {
+    buffer_manager: Arc<Mutex<BufferManager>>,
+    metrics: ServerMetrics,
+    notifications: Arc<NotificationHub>,
+    responses: Arc<ResponseCache>,
+    sessions: BufferSessions,
+}
+
+impl DaemonState {
+    /// Соединение держит буфер и получает уведомления о его изменениях
+    fn hold_buffer(&self, buffer_id: &str, conn_id: u64) {
+        if self.sessions.acquire(buffer_id, conn_id) {
+            if let Some(subscriber) = self.notifications.subscriber(conn_id) {
+                subscriber.subscribe(&[SubscriptionTopic::Buffer { buffer_id: buffer_id.to_string() }]);
+            }
+        }
+    }
+
+    /// Снять удержание буфера соединением; возвращает число оставшихся держателей
+    fn release_buffer(&self, buffer_id: &str, conn_id: u64) -> usize {
+        if let Some(subscriber) = self.notifications.subscriber(conn_id) {
+            subscriber.unsubscribe(&[SubscriptionTopic::Buffer { buffer_id: buffer_id.to_string() }]);
+        }
+        self.sessions.release(buffer_id, conn_id)
+    }
+}
+
+/// Ответ-ошибка операции над буферами: класс и подробности берутся из `CoreError`
+fn core_error(operation: &str, e: CoreError) -> CoreResponse {
+    CoreResponse::Error {
+        code: e.error_code(),
+        message: format!("{} failed: {}", operation, e),
+        detail: e.error_detail(),
+    }
+}
+
+/// Ошибка запуска внешнего инструмента (rg)
+#[derive(Debug)]
+enum ToolError {
+    /// Исполняемый файл не найден в PATH
+    Missing { tool: &'static str },
+    /// Инструмент не уложился в таймаут
+    TimedOut { tool: &'static str },
+    /// Каталог для запуска не найден
+    NoSuchDirectory { path: PathBuf },
+    /// Инструмент завершился с ошибкой
+    Failed { tool: &'static str, stderr: String },
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolError::Missing { tool } => write!(f, "{} not found in PATH", tool),
+            ToolError::TimedOut { tool } => write!(f, "{} timed out", tool),
+            ToolError::NoSuchDirectory { path } => write!(f, "No such directory: {}", path.display()),
+            ToolError::Failed { tool, stderr } => write!(f, "{} failed: {}", tool, stderr),
+            ToolError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ToolError {
+    fn from(e: std::io::Error) -> Self {
+        ToolError::Io(e)
+    }
+}
+
+impl ToolError {
+    fn spawn(tool: &'static str, e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::NotFound { ToolError::Missing { tool } } else { ToolError::Io(e) }
+    }
+
+    fn into_response(self, operation: &str) -> CoreResponse {
+        let message = format!("{} failed: {}", operation, self);
+        match self {
+            ToolError::Missing { tool } => {
+                CoreResponse::error_with_detail(ErrorCode::ToolMissing, message, ErrorDetail::Tool { name: tool.to_string() })
+            }
+            ToolError::TimedOut { .. } => CoreResponse::error(ErrorCode::Timeout, message),
+            ToolError::NoSuchDirectory { path } => CoreResponse::error_with_detail(
+                ErrorCode::NotFound,
+                message,
+                ErrorDetail::Path { path: path.display().to_string() },
+            ),
+            ToolError::Failed { .. } => CoreResponse::error(ErrorCode::Internal, message),
+            ToolError::Io(e) => CoreResponse::error(ErrorCode::from_io_kind(e.kind()), message),
+        }
+    }
+}
+
+/// Канал доставки ответов клиенту от имени одного запроса
+struct ResponseSink {
+    id: RequestId,
+    writer: SharedWriter,
+    frame: FrameOptions,
+}
+
+impl ResponseSink {
+    /// Записать кадр ответа (промежуточный или финальный) под RequestId запроса
+    async fn send(&self, response: CoreResponse) {
+        let mut w = self.writer.lock().await;
+        let _ = write_ipc_frame(&mut *w, &IpcMessage { id: self.id, deadline_millis: 0, payload: IpcPayload::Response(response) }, &self.frame).await;
+        let _ = w.flush().await;
+    }
+}
+
+type SharedWriter = Arc<Mutex<tokio::io::BufWriter<IpcWriteHalf>>>;
+
+/// Размер пачки результатов в одном `SearchResultsChunk`
+const SEARCH_CHUNK_SIZE: usize = 64;
+/// Максимальная задержка отправки неполной пачки
+const SEARCH_CHUNK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+/// Ёмкость канала пачек между парсером rg и писателем (ограничивает чтение при медленном клиенте)
+const SEARCH_CHANNEL_CAPACITY: usize = 4;
+
+fn search_root(workspace_root: Option<PathBuf>) -> PathBuf {
+    workspace_root.unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+}
+
+/// Потоковый поиск: отправляет `SearchResultsChunk` по мере разбора вывода rg,
+/// возвращает финальный кадр (`SearchComplete` или `Error`)
+async fn stream_search(
+    query: &str,
+    workspace_root: Option<PathBuf>,
+    options: &IpcSearchOptions,
+    sink: &ResponseSink,
+) -> CoreResponse {
+    let root = search_root(workspace_root);
+    let (tx, mut rx) = mpsc::channel::<Vec<atom_ipc::SearchResult>>(SEARCH_CHANNEL_CAPACITY);
+    let forward = async {
+        while let Some(results) = rx.recv().await {
+            sink.send(CoreResponse::SearchResultsChunk { results }).await;
+        }
+    };
+    let (outcome, _) = tokio::join!(search_with_ripgrep(query, &root, options, tx), forward);
+    match outcome {
+        Ok(total) => CoreResponse::SearchComplete { total },
+        Err(e) => e.into_response("Search"),
+    }
+}
+
+/// Поиск через `rg --json` с таймаутом: результаты отдаются пачками в `chunks`
+/// по мере разбора вывода. Возвращает общее число найденных совпадений.
 async fn search_with_ripgrep(
     query: &str,
     root_path: &Path,
     options: &IpcSearchOptions,
-) -> Result<Vec<atom_ipc::SearchResult>, Box<dyn Error + Send + Sync>> {
+    chunks: mpsc::Sender<Vec<atom_ipc::SearchResult>>,
+) -> Result<usize, ToolError> {
+    use tokio::io::AsyncBufReadExt;
     use tokio::process::Command;
     let mut cmd = Command::new("rg");
-    cmd.arg("--line-number")
-        .arg("--column")
-        .arg("--no-heading")
-        .arg("--with-filename")
-        .arg("--color=never");
+    cmd.arg("--json");
 
     if let Some(max) = options.max_results { cmd.arg("--max-count").arg(max.to_string()); }
     if !options.case_sensitive { cmd.arg("--ignore-case"); }
@@ -318,56 +870,101 @@ async fn search_with_ripgrep(
     if let Some(excl) = &options.exclude_pattern { cmd.arg("--glob").arg(format!("!{}", excl)); }
     if let Some(incl) = &options.include_pattern { if !incl.is_empty() { cmd.arg("--glob").arg(incl); } }
 
-    cmd.arg(query).arg(root_path);
+    cmd.arg("--").arg(query).arg(root_path);
+    // При отмене запроса (abort задачи) процесс rg должен завершиться вместе с ней
+    cmd.stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = cmd.spawn().map_err(|e| ToolError::spawn("rg", e))?;
+    let (Some(stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(ToolError::Failed { tool: "rg", stderr: "stdio unavailable".into() });
+    };
+
+    let parse = async {
+        let mut lines = tokio::io::BufReader::new(stdout).lines();
+        let mut batch = Vec::with_capacity(SEARCH_CHUNK_SIZE);
+        let mut last_flush = std::time::Instant::now();
+        let mut total = 0usize;
+        while let Some(line) = lines.next_line().await? {
+            let Some(result) = parse_rg_json_line(&line) else { continue };
+            batch.push(result);
+            total += 1;
+            if batch.len() >= SEARCH_CHUNK_SIZE || last_flush.elapsed() >= SEARCH_CHUNK_INTERVAL {
+                if chunks.send(std::mem::take(&mut batch)).await.is_err() { break; }
+                last_flush = std::time::Instant::now();
+            }
+        }
+        if !batch.is_empty() {
+            let _ = chunks.send(batch).await;
+        }
+        let mut err_text = String::new();
+        let _ = tokio::io::AsyncReadExt::read_to_string(&mut stderr, &mut err_text).await;
+        let status = child.wait().await?;
+        Ok::<_, ToolError>((total, status, err_text))
+    };
 
     // Таймаут на выполнение rg
-    let output = match tokio::time::timeout(std::time::Duration::from_secs(15), cmd.output()).await {
+    let (total, status, stderr) = match tokio::time::timeout(std::time::Duration::from_secs(15), parse).await {
         Ok(res) => res?,
-        Err(_) => return Err("ripgrep timed out".into()),
+        Err(_) => return Err(ToolError::TimedOut { tool: "rg" }),
     };
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ripgrep failed: {}", stderr).into());
+    // Код 1 у rg означает «совпадений нет», это не ошибка
+    if !status.success() && status.code() != Some(1) {
+        return Err(ToolError::Failed { tool: "rg", stderr });
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut results = Vec::new();
-    for line in stdout.lines() {
-        // path:line:column:content
-        let parts: Vec<&str> = line.splitn(4, ':').collect();
-        if parts.len() < 4 { continue; }
-        let path = parts[0].to_string();
-        let line_no = parts[1].parse::<usize>().unwrap_or(1);
-        let col = parts[2].parse::<usize>().unwrap_or(0);
-        let content = parts[3].to_string();
-
-        results.push(atom_ipc::SearchResult {
-            path,
-            line_number: line_no,
-            column: col,
-            line_text: content.clone(),
-            match_text: query.to_string(),
-        });
-    }
-    Ok(results)
+    Ok(total)
+}
+
+/// Разобрать одну строку `rg --json`; возвращает результат только для сообщений `match`
+fn parse_rg_json_line(line: &str) -> Option<atom_ipc::SearchResult> {
+    #[derive(serde::Deserialize)]
+    struct RgMessage { #[serde(rename = "type")] kind: String, data: Option<RgMatch> }
+    #[derive(serde::Deserialize)]
+    struct RgMatch { path: RgText, lines: RgText, line_number: Option<usize>, #[serde(default)] submatches: Vec<RgSubmatch> }
+    #[derive(serde::Deserialize)]
+    struct RgText { text: Option<String> }
+    #[derive(serde::Deserialize)]
+    struct RgSubmatch { #[serde(rename = "match")] matched: RgText, start: usize }
+
+    let msg: RgMessage = serde_json::from_str(line).ok()?;
+    if msg.kind != "match" { return None; }
+    let data = msg.data?;
+    // Не‑UTF‑8 пути/строки rg отдаёт в base64 (`bytes`) — пропускаем их
+    let path = data.path.text?;
+    let line_text = data.lines.text?.trim_end_matches(['\n', '\r']).to_string();
+    let first = data.submatches.first();
+    Some(atom_ipc::SearchResult {
+        path,
+        line_number: data.line_number.unwrap_or(1),
+        // rg --column был 1‑based по байтам; сохраняем ту же семантику
+        column: first.map(|m| m.start + 1).unwrap_or(0),
+        match_text: first.and_then(|m| m.matched.text.clone()).unwrap_or_default(),
+        line_text,
+    })
 }
 
 /// Список файлов проекта через ripgrep --files
-async fn list_project_files(root_path: &Path) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+async fn list_project_files(root_path: &Path) -> Result<Vec<String>, ToolError> {
     use tokio::process::Command;
+    // Иначе spawn с несуществующим cwd неотличим от отсутствующего rg
+    if !root_path.is_dir() {
+        return Err(ToolError::NoSuchDirectory { path: root_path.to_path_buf() });
+    }
     let mut cmd = Command::new("rg");
     cmd.arg("--files");
     cmd.current_dir(root_path);
 
     let output = match tokio::time::timeout(std::time::Duration::from_secs(20), cmd.output()).await {
-        Ok(res) => res?,
-        Err(_) => return Err("ripgrep --files timed out".into()),
+        Ok(res) => res.map_err(|e| ToolError::spawn("rg", e))?,
+        Err(_) => return Err(ToolError::TimedOut { tool: "rg" }),
     };
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("ripgrep --files failed: {}", stderr).into());
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(ToolError::Failed { tool: "rg", stderr });
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
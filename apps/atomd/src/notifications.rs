@@ -0,0 +1,477 @@
+//! Подписки соединений на уведомления демона
+//!
+//! Каждое соединение регистрирует `Subscriber` в общем `NotificationHub` и
+//! подписывается на темы (`SubscriptionTopic`). Издатели (сохранение буфера,
+//! наблюдатель файловой системы) кладут уведомления в очереди подписчиков,
+//! а задача `pump` соединения пишет их кадрами `IpcPayload::Notification`
+//! через общий писатель.
+//!
+//! Очередь подписчика ограничена: медленный клиент не блокирует ни издателей,
+//! ни обработку запросов. Уведомления об одном и том же объекте схлопываются
+//! (путь, URI диагностик, буфер), а при переполнении отбрасываются самые старые.
+//!
+//! Правки буфера молча терять нельзя — держатель разошёлся бы с демоном.
+//! Поэтому вместо отброшенных или слишком накопившихся правок в очереди
+//! остаётся метка пересинхронизации: `BufferChanged` без правок с последней
+//! версией, по которой клиент перечитывает буфер. Таких записей не больше,
+//! чем буферов в подписке.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use atom_ipc::{
+    write_ipc_frame, FileChangeType, FrameOptions, IpcMessage, IpcPayload, Notification,
+    RequestId, SubscriptionTopic,
+};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::SharedWriter;
+
+/// Предел правок, накопленных в одном `BufferChanged`, — дальше метка пересинхронизации
+const MAX_MERGED_CHANGES: usize = 1024;
+/// Предел текста (старого и нового) в накопленных правках одного `BufferChanged`
+const MAX_MERGED_BYTES: usize = 1 << 20;
+
+/// Подписчик — одно соединение клиента
+pub struct Subscriber {
+    id: u64,
+    topics: Mutex<HashSet<SubscriptionTopic>>,
+    queue: Mutex<VecDeque<Notification>>,
+    ready: Notify,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+impl Subscriber {
+    fn new(id: u64, capacity: usize) -> Self {
+        Self {
+            id,
+            topics: Mutex::new(HashSet::new()),
+            queue: Mutex::new(VecDeque::new()),
+            ready: Notify::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Идентификатор подписчика (совпадает с `origin` при публикации от его имени)
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn subscribe(&self, topics: &[SubscriptionTopic]) {
+        self.topics.lock().unwrap().extend(topics.iter().cloned());
+    }
+
+    pub fn unsubscribe(&self, topics: &[SubscriptionTopic]) {
+        let mut current = self.topics.lock().unwrap();
+        for topic in topics {
+            current.remove(topic);
+        }
+    }
+
+    fn wants(&self, topic: &SubscriptionTopic) -> bool {
+        self.topics.lock().unwrap().contains(topic)
+    }
+
+    /// Число уведомлений, отброшенных из-за переполнения очереди
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Поставить уведомление в очередь, схлопнув его с ожидающим уведомлением
+    /// о том же объекте; при переполнении отбросить самое старое
+    fn push(&self, notification: Notification) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if let Some(rest) = coalesce(&mut queue, notification) {
+                if queue.len() >= self.capacity {
+                    self.evict(&mut queue);
+                }
+                queue.push_back(rest);
+            }
+        }
+        self.ready.notify_one();
+    }
+
+    /// Освободить место в очереди: отбросить самое старое уведомление, кроме
+    /// правок буферов, а если в очереди только они — заменить самые старые
+    /// правки меткой пересинхронизации
+    fn evict(&self, queue: &mut VecDeque<Notification>) {
+        if let Some(index) = queue.iter().position(|n| !matches!(n, Notification::BufferChanged { .. })) {
+            queue.remove(index);
+        } else if let Some(changes) = queue.iter_mut().find_map(|n| match n {
+            Notification::BufferChanged { changes, .. } if !changes.is_empty() => Some(changes),
+            _ => None,
+        }) {
+            *changes = Vec::new();
+        } else {
+            // Одни метки: по одной на буфер, им место в очереди оставляется
+            return;
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Дождаться и забрать все накопившиеся уведомления
+    pub async fn next_batch(&self) -> Vec<Notification> {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if !queue.is_empty() {
+                    return queue.drain(..).collect();
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// Схлопнуть уведомление с уже ожидающим в очереди.
+///
+/// Возвращает уведомление обратно, если схлопывать не с чем.
+fn coalesce(queue: &mut VecDeque<Notification>, notification: Notification) -> Option<Notification> {
+    for queued in queue.iter_mut() {
+        match (queued, &notification) {
+            (
+                Notification::FileSystemChanged { path: queued_path, change_type: queued_type },
+                Notification::FileSystemChanged { path, change_type },
+            ) if queued_path == path
+                && !matches!(queued_type, FileChangeType::Renamed { .. })
+                && !matches!(change_type, FileChangeType::Renamed { .. }) =>
+            {
+                // Клиенту важно итоговое состояние файла
+                *queued_type = change_type.clone();
+                return None;
+            }
+            (
+                Notification::DiagnosticsUpdate { uri: queued_uri, diagnostics: queued_diagnostics },
+                Notification::DiagnosticsUpdate { uri, .. },
+            ) if queued_uri == uri => {
+                // Новый набор диагностик полностью заменяет старый
+                if let Notification::DiagnosticsUpdate { diagnostics, .. } = notification {
+                    *queued_diagnostics = diagnostics;
+                }
+                return None;
+            }
+            (
+                Notification::BufferChanged { buffer_id: queued_id, changes: queued_changes, version: queued_version },
+                Notification::BufferChanged { buffer_id, .. },
+            ) if queued_id == buffer_id => {
+                // Правки применяются последовательно — достаточно дописать их;
+                // к метке пересинхронизации дописывать нечего
+                if let Notification::BufferChanged { changes, version, .. } = notification {
+                    if !queued_changes.is_empty() {
+                        queued_changes.extend(changes);
+                        let bytes: usize =
+                            queued_changes.iter().map(|c| c.new_text.len() + c.old_text.len()).sum();
+                        if queued_changes.len() > MAX_MERGED_CHANGES || bytes > MAX_MERGED_BYTES {
+                            *queued_changes = Vec::new();
+                        }
+                    }
+                    *queued_version = version;
+                }
+                return None;
+            }
+            _ => {}
+        }
+    }
+    Some(notification)
+}
+
+/// Реестр подписчиков демона
+pub struct NotificationHub {
+    subscribers: Mutex<HashMap<u64, Arc<Subscriber>>>,
+    next_id: AtomicU64,
+    queue_capacity: usize,
+    /// Наблюдатели за корнями рабочих областей (по одному на корень)
+    watchers: Mutex<HashMap<PathBuf, RootWatch>>,
+}
+
+/// Наблюдатель за корнем и соединения, открывшие этот корень
+struct RootWatch {
+    _watcher: RecommendedWatcher,
+    holders: HashSet<u64>,
+}
+
+impl NotificationHub {
+    pub fn new(queue_capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            subscribers: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            queue_capacity,
+            watchers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Зарегистрировать соединение; подписок у нового подписчика нет
+    pub fn register(&self) -> Arc<Subscriber> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let subscriber = Arc::new(Subscriber::new(id, self.queue_capacity));
+        self.subscribers.lock().unwrap().insert(id, Arc::clone(&subscriber));
+        subscriber
+    }
+
+    /// Подписчик соединения, если оно ещё открыто
+    pub fn subscriber(&self, id: u64) -> Option<Arc<Subscriber>> {
+        self.subscribers.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Снять подписчика закрытого соединения; наблюдение за корнями, которые
+    /// больше никто не открыл, прекращается
+    pub fn unregister(&self, id: u64) {
+        self.unwatch_roots(id);
+        if let Some(subscriber) = self.subscribers.lock().unwrap().remove(&id) {
+            let dropped = subscriber.dropped();
+            if dropped > 0 {
+                debug!("Subscriber {} dropped {} notification(s) on overflow", id, dropped);
+            }
+        }
+    }
+
+    /// Разослать уведомление подписчикам темы, кроме соединения-источника
+    pub fn publish(&self, notification: Notification, origin: Option<u64>) {
+        let topic = notification.topic();
+        let targets: Vec<Arc<Subscriber>> = self
+            .subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| Some(s.id) != origin && s.wants(&topic))
+            .cloned()
+            .collect();
+        for subscriber in targets {
+            subscriber.push(notification.clone());
+        }
+    }
+
+    /// Начать наблюдение за корнем рабочей области, открытым соединением
+    /// `holder` (для уже наблюдаемого корня только учитывается держатель)
+    pub fn watch_root(self: &Arc<Self>, root: &Path, holder: u64) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(watch) = watchers.get_mut(root) {
+            watch.holders.insert(holder);
+            return;
+        }
+        let hub: Weak<Self> = Arc::downgrade(self);
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let (Ok(event), Some(hub)) = (res, hub.upgrade()) else { return };
+            for notification in fs_notifications(event) {
+                hub.publish(notification, None);
+            }
+        });
+        let watcher = watcher.and_then(|mut w| w.watch(root, RecursiveMode::Recursive).map(|_| w));
+        match watcher {
+            Ok(w) => {
+                debug!("Watching workspace root {}", root.display());
+                watchers.insert(root.to_path_buf(), RootWatch { _watcher: w, holders: HashSet::from([holder]) });
+            }
+            Err(e) => warn!("Failed to watch {}: {}", root.display(), e),
+        }
+    }
+
+    /// Соединение `holder` перешло к другому корню или закрылось: наблюдатель
+    /// за `root` останавливается, если держателей не осталось
+    pub fn unwatch_root(&self, root: &Path, holder: u64) {
+        self.release_roots(holder, |watched| watched == root);
+    }
+
+    /// Снять все корни соединения
+    pub fn unwatch_roots(&self, holder: u64) {
+        self.release_roots(holder, |_| true);
+    }
+
+    fn release_roots(&self, holder: u64, matches: impl Fn(&Path) -> bool) {
+        self.watchers.lock().unwrap().retain(|root, watch| {
+            if matches(root) {
+                watch.holders.remove(&holder);
+            }
+            let keep = !watch.holders.is_empty();
+            if !keep {
+                debug!("Stopped watching workspace root {}", root.display());
+            }
+            keep
+        });
+    }
+
+    /// Корни, за которыми сейчас идёт наблюдение
+    #[cfg(test)]
+    fn watched_roots(&self) -> HashSet<PathBuf> {
+        self.watchers.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Преобразовать событие наблюдателя в уведомления `FileSystemChanged`
+fn fs_notifications(event: notify::Event) -> Vec<Notification> {
+    let path_str = |p: &PathBuf| p.to_string_lossy().into_owned();
+    let change = |path: &PathBuf, change_type: FileChangeType| Notification::FileSystemChanged {
+        path: path_str(path),
+        change_type,
+    };
+    match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            let (old_path, new_path) = (path_str(&event.paths[0]), path_str(&event.paths[1]));
+            vec![Notification::FileSystemChanged {
+                path: new_path.clone(),
+                change_type: FileChangeType::Renamed { old_path, new_path },
+            }]
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
+            event.paths.iter().map(|p| change(p, FileChangeType::Deleted)).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) | EventKind::Create(_) => {
+            event.paths.iter().map(|p| change(p, FileChangeType::Created)).collect()
+        }
+        EventKind::Modify(_) => event.paths.iter().map(|p| change(p, FileChangeType::Modified)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Доставлять уведомления подписчика клиенту, пока соединение живо
+pub async fn pump(subscriber: Arc<Subscriber>, writer: SharedWriter, frame: FrameOptions) {
+    loop {
+        let batch = subscriber.next_batch().await;
+        let mut w = writer.lock().await;
+        for notification in batch {
+            let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Notification(notification) };
+            if write_ipc_frame(&mut *w, &msg, &frame).await.is_err() {
+                return;
+            }
+        }
+        if w.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atom_ipc::{TextChange, TextRange};
+
+    fn fs(path: &str, change_type: FileChangeType) -> Notification {
+        Notification::FileSystemChanged { path: path.into(), change_type }
+    }
+
+    fn changed(buffer_id: &str, new_text: &str, version: u64) -> Notification {
+        let range = TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 0 };
+        let changes = vec![TextChange { range, new_text: new_text.into(), old_text: String::new() }];
+        Notification::BufferChanged { buffer_id: buffer_id.into(), changes, version }
+    }
+
+    #[tokio::test]
+    async fn queue_coalesces_same_object() {
+        let hub = NotificationHub::new(16);
+        let sub = hub.register();
+        sub.subscribe(&[SubscriptionTopic::FileSystem, SubscriptionTopic::Buffer { buffer_id: "b1".into() }]);
+
+        hub.publish(fs("a.rs", FileChangeType::Created), None);
+        hub.publish(fs("b.rs", FileChangeType::Modified), None);
+        hub.publish(fs("a.rs", FileChangeType::Modified), None);
+        for (version, new_text) in [(1, "y"), (2, "z")] {
+            let range = TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 1 };
+            let changes = vec![TextChange { range, new_text: new_text.into(), old_text: "x".into() }];
+            hub.publish(Notification::BufferChanged { buffer_id: "b1".into(), changes, version }, None);
+        }
+        // Другой буфер не подписан
+        hub.publish(Notification::BufferChanged { buffer_id: "b2".into(), changes: Vec::new(), version: 1 }, None);
+
+        let batch = sub.next_batch().await;
+        assert_eq!(batch.len(), 3, "got {:?}", batch);
+        assert!(matches!(&batch[0], Notification::FileSystemChanged { path, change_type: FileChangeType::Modified } if path == "a.rs"));
+        assert!(matches!(&batch[1], Notification::FileSystemChanged { path, .. } if path == "b.rs"));
+        assert!(matches!(&batch[2], Notification::BufferChanged { changes, version: 2, .. } if changes.len() == 2));
+        assert_eq!(sub.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn overflow_drops_oldest_and_skips_origin() {
+        let hub = NotificationHub::new(2);
+        let sub = hub.register();
+        let origin = hub.register();
+        sub.subscribe(&[SubscriptionTopic::FileSystem]);
+        origin.subscribe(&[SubscriptionTopic::FileSystem]);
+
+        for path in ["1", "2", "3"] {
+            hub.publish(fs(path, FileChangeType::Modified), Some(origin.id()));
+        }
+
+        let batch = sub.next_batch().await;
+        let paths: Vec<_> = batch
+            .iter()
+            .map(|n| match n {
+                Notification::FileSystemChanged { path, .. } => path.as_str(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(paths, ["2", "3"]);
+        assert_eq!(sub.dropped(), 1);
+        assert!(origin.queue.lock().unwrap().is_empty());
+
+        hub.unregister(sub.id());
+        hub.publish(fs("4", FileChangeType::Modified), None);
+        assert!(sub.queue.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn buffer_changes_turn_into_resync_marker_instead_of_growing_or_vanishing() {
+        let hub = NotificationHub::new(2);
+        let sub = hub.register();
+        let topic = |id: &str| SubscriptionTopic::Buffer { buffer_id: id.into() };
+        sub.subscribe(&[SubscriptionTopic::FileSystem, topic("b1"), topic("b2"), topic("b3")]);
+
+        // Застрявший клиент и занятой буфер: накопление ограничено
+        for version in 1..=MAX_MERGED_CHANGES as u64 + 1 {
+            hub.publish(changed("b1", "x", version), None);
+        }
+        let batch = sub.next_batch().await;
+        assert!(matches!(&batch[..], [Notification::BufferChanged { changes, version, .. }]
+            if changes.is_empty() && *version == MAX_MERGED_CHANGES as u64 + 1));
+        hub.publish(changed("b1", &"x".repeat(MAX_MERGED_BYTES), 1), None);
+        hub.publish(changed("b1", "x", 2), None);
+        assert!(matches!(&sub.next_batch().await[..], [Notification::BufferChanged { changes, version: 2, .. }] if changes.is_empty()));
+
+        // Переполнение вытесняет другие уведомления, а правки — только меткой
+        hub.publish(fs("a.rs", FileChangeType::Modified), None);
+        hub.publish(changed("b1", "x", 3), None);
+        hub.publish(changed("b2", "y", 1), None);
+        hub.publish(changed("b3", "z", 1), None);
+        hub.publish(changed("b1", "x", 4), None);
+        let batch = sub.next_batch().await;
+        let summary: Vec<_> = batch
+            .iter()
+            .map(|n| match n {
+                Notification::BufferChanged { buffer_id, changes, version } => (buffer_id.as_str(), changes.len(), *version),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(summary, [("b1", 0, 4), ("b2", 1, 1), ("b3", 1, 1)]);
+        assert_eq!(sub.dropped(), 2);
+    }
+
+    #[test]
+    fn root_watch_stops_with_last_holder() {
+        let hub = NotificationHub::new(4);
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let (a, b) = (hub.register(), hub.register());
+
+        hub.watch_root(first.path(), a.id());
+        hub.watch_root(first.path(), b.id());
+        hub.watch_root(second.path(), a.id());
+        assert_eq!(hub.watched_roots().len(), 2);
+
+        // Соединение a перешло к другому корню, но first ещё открыт у b
+        hub.unwatch_root(second.path(), a.id());
+        assert_eq!(hub.watched_roots(), HashSet::from([first.path().to_path_buf()]));
+        hub.unregister(a.id());
+        assert_eq!(hub.watched_roots().len(), 1);
+        hub.unregister(b.id());
+        assert!(hub.watched_roots().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+//! Совместные буферы нескольких клиентов
+//!
+//! Все соединения работают с одним `BufferManager`: IDE и ext-host (или два
+//! окна) открывают один и тот же файл и получают один `buffer_id`. Здесь
+//! учитывается, какие соединения держат буфер: `CloseBuffer` снимает только
+//! удержание своего соединения, а сам буфер закрывается, когда держателей
+//! не осталось. Держатели буфера подписаны на его тему и получают
+//! `Notification::BufferChanged` о правках остальных.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct BufferSessions {
+    holders: Mutex<HashMap<String, HashSet<u64>>>,
+}
+
+impl BufferSessions {
+    /// Отметить, что соединение держит буфер. Возвращает `true` для нового удержания
+    pub fn acquire(&self, buffer_id: &str, conn_id: u64) -> bool {
+        self.holders.lock().unwrap().entry(buffer_id.to_string()).or_default().insert(conn_id)
+    }
+
+    /// Снять удержание соединения; возвращает число оставшихся держателей
+    pub fn release(&self, buffer_id: &str, conn_id: u64) -> usize {
+        let mut holders = self.holders.lock().unwrap();
+        let Some(set) = holders.get_mut(buffer_id) else { return 0 };
+        set.remove(&conn_id);
+        let remaining = set.len();
+        if remaining == 0 {
+            holders.remove(buffer_id);
+        }
+        remaining
+    }
+
+    /// Снять все удержания закрытого соединения; возвращает буферы, у которых
+    /// не осталось держателей (их нужно закрыть)
+    pub fn release_all(&self, conn_id: u64) -> Vec<String> {
+        let mut holders = self.holders.lock().unwrap();
+        let mut orphaned = Vec::new();
+        holders.retain(|buffer_id, set| {
+            if set.remove(&conn_id) && set.is_empty() {
+                orphaned.push(buffer_id.clone());
+            }
+            !set.is_empty()
+        });
+        orphaned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_is_released_with_last_holder() {
+        let sessions = BufferSessions::default();
+        assert!(sessions.acquire("b1", 1));
+        assert!(!sessions.acquire("b1", 1));
+        assert!(sessions.acquire("b1", 2));
+        assert!(sessions.acquire("b2", 2));
+
+        assert_eq!(sessions.release("b1", 1), 1);
+        assert_eq!(sessions.release_all(2).len(), 2);
+        // Повторное закрытие и чужой буфер — держателей нет
+        assert_eq!(sessions.release("b1", 2), 0);
+        assert_eq!(sessions.release("b3", 1), 0);
+    }
+
+    #[test]
+    fn release_all_reports_only_orphaned_buffers() {
+        let sessions = BufferSessions::default();
+        sessions.acquire("shared", 1);
+        sessions.acquire("shared", 2);
+        sessions.acquire("own", 1);
+
+        assert_eq!(sessions.release_all(1), vec!["own".to_string()]);
+        assert_eq!(sessions.release_all(2), vec!["shared".to_string()]);
+        assert!(sessions.release_all(2).is_empty());
+    }
+}
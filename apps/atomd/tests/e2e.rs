@@ -1,68 +1,226 @@
 use assert_cmd::prelude::*;
 use std::process::{Command, Stdio, Child};
 use std::time::{Duration, Instant};
-use tokio::net::TcpStream;
 use tokio::time::sleep;
 use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
-use atom_ipc::{IpcMessage, IpcPayload, CoreRequest, CoreResponse, RequestId, read_ipc_message, write_ipc_message};
+use atom_ipc::{IpcEndpoint, IpcMessage, IpcPayload, IpcStream, CoreRequest, CoreResponse, RequestId, read_ipc_message, write_ipc_message};
 
-async fn wait_port(addr: &str, timeout: Duration) -> bool {
-    let started = Instant::now();
-    while started.elapsed() < timeout {
-        if TcpStream::connect(addr).await.is_ok() { return true; }
-        sleep(Duration::from_millis(100)).await;
+/// Транспорт, на котором поднимается демон в тесте
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    Tcp,
+    #[cfg(unix)]
+    Unix,
+}
+
+/// Запущенный atomd на собственном адресе; процесс убивается при drop
+struct Daemon {
+    child: Child,
+    endpoint: String,
+    env: Vec<(String, String)>,
+    /// Рабочий каталог демона (он же корень, внутри которого разрешено сохранение)
+    workdir: Option<std::path::PathBuf>,
+    _dir: tempfile::TempDir,
+}
+
+impl Daemon {
+    fn spawn(transport: Transport) -> Self {
+        Self::spawn_with_env(transport, &[])
+    }
+
+    fn spawn_with_env(transport: Transport, env: &[(&str, &str)]) -> Self {
+        Self::spawn_configured(transport, env, None)
+    }
+
+    /// Демон с рабочим каталогом `workdir` (нужно для SaveBuffer вне каталога пакета)
+    fn spawn_in(transport: Transport, workdir: &std::path::Path) -> Self {
+        Self::spawn_configured(transport, &[], Some(workdir))
+    }
+
+    fn spawn_configured(transport: Transport, env: &[(&str, &str)], workdir: Option<&std::path::Path>) -> Self {
+        let dir = tempfile::tempdir().expect("tmp");
+        let endpoint = match transport {
+            Transport::Tcp => {
+                // Свободный порт, чтобы тесты не делили один демон
+                let probe = std::net::TcpListener::bind("127.0.0.1:0").expect("probe bind");
+                probe.local_addr().unwrap().to_string()
+            }
+            #[cfg(unix)]
+            Transport::Unix => format!("unix:{}", dir.path().join("atomd.sock").display()),
+        };
+        let env: Vec<(String, String)> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let workdir = workdir.map(|p| p.to_path_buf());
+        let child = Self::start(&endpoint, &env, workdir.as_deref());
+        Self { child, endpoint, env, workdir, _dir: dir }
+    }
+
+    fn start(endpoint: &str, env: &[(String, String)], workdir: Option<&std::path::Path>) -> Child {
+        let mut cmd = Command::cargo_bin("atomd").expect("binary built");
+        cmd.env("ATOMD_SOCKET", endpoint);
+        if let Some(dir) = workdir { cmd.current_dir(dir); }
+        for (k, v) in env { cmd.env(k, v); }
+        cmd.stdout(Stdio::null()).stderr(Stdio::null());
+        cmd.spawn().expect("spawn atomd")
+    }
+
+    /// Перезапустить демон на том же адресе (имитация падения/обновления)
+    fn restart(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        self.child = Self::start(&self.endpoint, &self.env, self.workdir.as_deref());
+    }
+
+    async fn wait_ready(&self, timeout: Duration) -> bool {
+        let endpoint = IpcEndpoint::parse(&self.endpoint).expect("endpoint");
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if IpcStream::connect(&endpoint).await.is_ok() { return true; }
+            sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    async fn connect_raw(&self) -> IpcStream {
+        IpcStream::connect(&IpcEndpoint::parse(&self.endpoint).expect("endpoint")).await.expect("connect")
     }
-    false
 }
 
-fn spawn_daemon() -> Child {
-    let mut cmd = Command::cargo_bin("atomd").expect("binary built");
-    cmd.stdout(Stdio::null()).stderr(Stdio::null());
-    cmd.spawn().expect("spawn atomd")
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
 }
 
-fn spawn_daemon_with_env(k: &str, v: &str) -> Child {
-    let mut cmd = Command::cargo_bin("atomd").expect("binary built");
-    cmd.env(k, v);
-    cmd.stdout(Stdio::null()).stderr(Stdio::null());
-    cmd.spawn().expect("spawn atomd with env")
+/// Каждый e2e‑сценарий прогоняется и по TCP, и по Unix domain socket
+macro_rules! e2e_both_transports {
+    ($($name:ident),* $(,)?) => {
+        $(
+            mod $name {
+                #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+                async fn tcp() { super::$name(super::Transport::Tcp).await }
+
+                #[cfg(unix)]
+                #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+                async fn unix() { super::$name(super::Transport::Unix).await }
+            }
+        )*
+    };
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_ping() {
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+e2e_both_transports!(
+    e2e_ping,
+    e2e_handshake_negotiates,
+    e2e_openbuffer,
+    e2e_openbuffer_large_compressed,
+    e2e_openbuffer_large_chunked,
+    e2e_cancel_long_op,
+    e2e_stats_cancel_increments,
+    e2e_project_files,
+    e2e_deadline_reject,
+    e2e_backpressure_reject,
+    e2e_search_streams_chunks,
+    e2e_reconnect_after_daemon_restart,
+    e2e_subscription_notifications,
+    e2e_retried_request_id_is_not_reexecuted,
+    e2e_replayed_open_holds_the_buffer,
+    e2e_error_codes,
+    e2e_shared_buffer_sessions,
+    e2e_disconnect_closes_orphaned_buffers,
+    e2e_apply_edits_versions,
+    e2e_undo_redo_shared_history,
+    e2e_legacy_encoding_round_trip,
+    e2e_binary_and_large_files,
+    e2e_external_changes,
+    e2e_save_pipeline,
+    e2e_editorconfig,
+    e2e_highlights,
+    e2e_folding_and_structural_selection,
+    e2e_document_symbols,
+    e2e_multi_cursor_editing,
+);
+
+async fn e2e_ping(transport: Transport) {
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     cli.ping().await.expect("ping ok");
+}
+
+async fn e2e_handshake_negotiates(transport: Transport) {
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let _ = child.kill();
+    // Клиент с рукопожатием получает текущую версию и потоковые ответы
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let protocol = cli.protocol().await;
+    assert_eq!(protocol.version, atom_ipc::PROTOCOL_VERSION);
+    assert!(protocol.supports(atom_ipc::capabilities::STREAMING));
+    cli.ping().await.expect("ping ok");
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_openbuffer() {
+async fn e2e_openbuffer(transport: Transport) {
     use std::fs; use tempfile::tempdir;
     let dir = tempdir().expect("tmp");
     let file_path = dir.path().join("e2e.rs");
     fs::write(&file_path, b"hello world\n").expect("write");
 
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     // путь относительный к CWD демона; для простоты отправим абсолютный
     let res = cli.request(atom_ipc::CoreRequest::OpenBuffer{ path: file_path.to_string_lossy().to_string() }).await.expect("resp");
     match res { atom_ipc::CoreResponse::BufferOpened { content, .. } => assert!(content.contains("hello world")), other => panic!("unexpected: {:?}", other) }
+}
+
+async fn e2e_openbuffer_large_compressed(transport: Transport) {
+    use std::fs; use tempfile::tempdir;
+    let dir = tempdir().expect("tmp");
+    let file_path = dir.path().join("large.txt");
+    // 3 MiB текста не помещаются в кадр 1 MiB без сжатия
+    let text = "the quick brown fox jumps over the lazy dog\n".repeat(72 * 1024);
+    fs::write(&file_path, &text).expect("write");
+
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    assert!(cli.protocol().await.supports(atom_ipc::capabilities::LZ4));
+    let res = cli.request(atom_ipc::CoreRequest::OpenBuffer{ path: file_path.to_string_lossy().to_string() }).await.expect("resp");
+    match res { atom_ipc::CoreResponse::BufferOpened { content, .. } => assert_eq!(content.len(), text.len()), other => panic!("unexpected: {:?}", other) }
+}
+
+async fn e2e_openbuffer_large_chunked(transport: Transport) {
+    use std::fs; use tempfile::tempdir;
+    let dir = tempdir().expect("tmp");
+    let file_path = dir.path().join("build.log");
+    // Несжимаемые 3 MiB: доставляются только несколькими кадрами
+    let mut state: u64 = 42;
+    let text: String = (0..3 * 1024 * 1024)
+        .map(|i| {
+            if i % 80 == 79 { return '\n'; }
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            char::from_digit(((state >> 59) & 0xF) as u32, 16).unwrap()
+        })
+        .collect();
+    fs::write(&file_path, &text).expect("write");
+
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let _ = child.kill();
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    assert!(cli.protocol().await.supports(atom_ipc::capabilities::CHUNKED));
+    let res = cli.request(atom_ipc::CoreRequest::OpenBuffer{ path: file_path.to_string_lossy().to_string() }).await.expect("resp");
+    match res { atom_ipc::CoreResponse::BufferOpened { content, .. } => assert!(content == text, "content mismatch"), other => panic!("unexpected: {:?}", other) }
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_cancel_long_op() {
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+async fn e2e_cancel_long_op(transport: Transport) {
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     let (id, rx) = cli.start_request(atom_ipc::CoreRequest::Sleep { millis: 5000 }).await.expect("start");
     // отложенная отмена
     sleep(Duration::from_millis(50)).await;
@@ -73,16 +231,13 @@ async fn e2e_cancel_long_op() {
         Ok(Err(atom_ipc::IpcError::Cancelled)) => {},
         other => panic!("expected Cancelled error, got {:?}", other),
     }
-
-    let _ = child.kill();
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_stats_cancel_increments() {
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+async fn e2e_stats_cancel_increments(transport: Transport) {
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     // Start a long running request and cancel it
     let (id, _rx) = cli.start_request(atom_ipc::CoreRequest::Sleep { millis: 3_000 }).await.expect("start");
     sleep(Duration::from_millis(50)).await;
@@ -94,12 +249,9 @@ async fn e2e_stats_cancel_increments() {
         atom_ipc::CoreResponse::Stats { cancels, .. } => assert!(cancels >= 1, "expected cancels>=1, got {}", cancels),
         other => panic!("unexpected: {:?}", other)
     }
-
-    let _ = child.kill();
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_project_files() {
+async fn e2e_project_files(transport: Transport) {
     use std::fs; use tempfile::tempdir;
     if which::which("rg").is_err() {
         eprintln!("skipping e2e_project_files: ripgrep (rg) not found in PATH");
@@ -110,59 +262,69 @@ async fn e2e_project_files() {
     fs::write(dir.path().join("src/main.rs"), b"fn main(){}\n").unwrap();
     fs::write(dir.path().join("README.md"), b"readme\n").unwrap();
 
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     let res = cli.request(atom_ipc::CoreRequest::GetProjectFiles { root_path: dir.path().to_string_lossy().to_string() }).await.expect("resp");
     match res { atom_ipc::CoreResponse::ProjectFiles { files } => {
         assert!(files.iter().any(|f| f.ends_with("src/main.rs")));
         assert!(files.iter().any(|f| f.ends_with("README.md")));
     }, other => panic!("unexpected: {:?}", other) }
-
-    let _ = child.kill();
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_deadline_reject() {
-    let mut child = spawn_daemon();
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+async fn e2e_deadline_reject(transport: Transport) {
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
     // Низкоуровневое подключение и отправка запроса с просроченным дедлайном
-    let stream = tokio::net::TcpStream::connect("127.0.0.1:8877").await.expect("connect");
+    let stream = daemon.connect_raw().await;
     let (r, w) = stream.into_split();
     let mut reader = BufReader::new(r);
     let mut writer = BufWriter::new(w);
 
+    // Рукопожатие: коды ошибок есть только с версии 3
+    let handshake = atom_ipc::FrameOptions::handshake(atom_ipc::MAX_MESSAGE_SIZE);
+    let hello = atom_ipc::Hello::new::<&str>(&[]);
+    let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Hello(hello) };
+    atom_ipc::write_ipc_frame(&mut writer, &msg, &handshake).await.expect("write hello");
+    let frame = match atom_ipc::read_ipc_frame(&mut reader, &handshake).await.expect("read welcome").payload {
+        IpcPayload::Welcome(welcome) => atom_ipc::FrameOptions { version: welcome.version, ..handshake },
+        other => panic!("unexpected: {:?}", other),
+    };
+    assert_eq!(frame.version, atom_ipc::PROTOCOL_VERSION);
+
     // Отправляем Ping с дедлайном в прошлом
     let past_deadline = (std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_millis() as u64) - 1000;
     let msg = IpcMessage { id: RequestId::new(), deadline_millis: past_deadline, payload: IpcPayload::Request(CoreRequest::Ping) };
-    write_ipc_message(&mut writer, &msg).await.expect("write");
-    writer.flush().await.expect("flush");
+    atom_ipc::write_ipc_frame(&mut writer, &msg, &frame).await.expect("write");
 
     // Ожидаем ошибку Deadline exceeded
-    let resp = read_ipc_message(&mut reader).await.expect("read");
+    let resp = atom_ipc::read_ipc_frame(&mut reader, &frame).await.expect("read");
     match resp.payload {
-        IpcPayload::Response(CoreResponse::Error { message }) => assert!(message.contains("Deadline exceeded"), "got: {}", message),
+        IpcPayload::Response(CoreResponse::Error { code, message, .. }) => {
+            assert_eq!(code, atom_ipc::ErrorCode::DeadlineExceeded);
+            assert!(message.contains("Deadline exceeded"), "got: {}", message)
+        }
         other => panic!("unexpected: {:?}", other),
     }
-
-    let _ = child.kill();
 }
 
-#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
-async fn e2e_backpressure_reject() {
+async fn e2e_backpressure_reject(transport: Transport) {
     // Запускаем демон с низким лимитом in-flight = 1
-    let mut child = spawn_daemon_with_env("ATOMD_IPC_MAX_INFLIGHT", "1");
-    assert!(wait_port("127.0.0.1:8877", Duration::from_secs(10)).await, "daemon not ready");
+    let daemon = Daemon::spawn_with_env(transport, &[("ATOMD_IPC_MAX_INFLIGHT", "1")]);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
 
-    let cli = atom_ipc::IpcClient::connect("127.0.0.1:8877").await.expect("ipc connect");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
     // Запускаем длинную операцию (не завершаем сразу)
     let (id1, _rx1) = cli.start_request(atom_ipc::CoreRequest::Sleep { millis: 5_000 }).await.expect("start1");
     // Второй запрос должен попасть под backpressure на сервере
     let (_id2, rx2) = cli.start_request(atom_ipc::CoreRequest::Sleep { millis: 10 }).await.expect("start2");
     match rx2.await {
-        Ok(Ok(CoreResponse::Error { message })) => assert!(message.contains("Backpressure"), "msg: {}", message),
+        Ok(Ok(CoreResponse::Error { code, message, .. })) => {
+            assert_eq!(code, atom_ipc::ErrorCode::Backpressure);
+            assert!(message.contains("Backpressure"), "msg: {}", message)
+        }
         other => panic!("expected backpressure error, got {:?}", other),
     }
 
@@ -172,6 +334,897 @@ async fn e2e_backpressure_reject() {
         CoreResponse::Stats { backpressure, .. } => assert!(backpressure >= 1, "backpressure: {}", backpressure),
         other => panic!("unexpected stats resp: {:?}", other),
     }
+}
+
+async fn e2e_search_streams_chunks(transport: Transport) {
+    use futures::StreamExt;
+    use std::fs; use tempfile::tempdir;
+    if which::which("rg").is_err() {
+        eprintln!("skipping e2e_search_streams_chunks: ripgrep (rg) not found in PATH");
+        return;
+    }
+    let dir = tempdir().expect("tmp");
+    // Достаточно совпадений, чтобы выдача разбилась на несколько чанков
+    for i in 0..20 {
+        let body = format!("let needle_{} = 0;\n", i).repeat(10);
+        fs::write(dir.path().join(format!("f{}.rs", i)), body).unwrap();
+    }
+
+    let daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    // Корень поиска задаётся открытием папки
+    cli.request(CoreRequest::GetProjectFiles { root_path: dir.path().to_string_lossy().to_string() }).await.expect("files");
+
+    let (_id, mut stream) = cli.start_stream(CoreRequest::Search { query: "needle".into(), options: atom_ipc::SearchOptions::default() }).await.expect("start stream");
+    let mut chunks = 0;
+    let mut received = 0;
+    let mut total = None;
+    while let Some(frame) = stream.next().await {
+        match frame.expect("frame") {
+            CoreResponse::SearchResultsChunk { results } => { chunks += 1; received += results.len(); }
+            CoreResponse::SearchComplete { total: t } => total = Some(t),
+            other => panic!("unexpected: {:?}", other),
+        }
+    }
+    assert!(chunks >= 2, "expected several chunks, got {}", chunks);
+    assert_eq!(received, 200);
+    assert_eq!(total, Some(200));
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn e2e_unix_socket_is_private_and_stale_file_is_replaced() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().expect("tmp");
+    let sock = dir.path().join("stale.sock");
+    // Файл сокета от «упавшего» демона: слушатель закрыт, файл остался
+    drop(std::os::unix::net::UnixListener::bind(&sock).expect("bind stale"));
+    assert!(sock.exists());
+
+    let endpoint = format!("unix:{}", sock.display());
+    let daemon = Daemon { child: Daemon::start(&endpoint, &[], None), endpoint, env: Vec::new(), workdir: None, _dir: dir };
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let mode = std::fs::metadata(&sock).expect("socket metadata").permissions().mode();
+    assert_eq!(mode & 0o777, 0o600, "socket mode: {:o}", mode);
+
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    cli.ping().await.expect("ping ok");
+
+    // Второй демон на том же сокете не должен отобрать его у первого
+    let mut second = Command::cargo_bin("atomd").expect("binary built");
+    second.env("ATOMD_SOCKET", &daemon.endpoint).stdout(Stdio::null()).stderr(Stdio::null());
+    let mut second = second.spawn().expect("spawn second atomd");
+    sleep(Duration::from_millis(500)).await;
+    let _ = second.kill();
+    let _ = second.wait();
+    cli.ping().await.expect("first daemon still serves");
+}
+
+async fn e2e_reconnect_after_daemon_restart(transport: Transport) {
+    let mut daemon = Daemon::spawn(transport);
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let config = atom_ipc::IpcConfig {
+        max_reconnect_attempts: 20,
+        reconnect_base_delay: Duration::from_millis(100),
+        ..atom_ipc::IpcConfig::default()
+    };
+    let cli = atom_ipc::IpcClient::connect_with_config(&daemon.endpoint, config).await.expect("ipc connect");
+    cli.ping().await.expect("ping ok");
+    let mut states = cli.watch_state();
+
+    daemon.restart();
+    tokio::time::timeout(Duration::from_secs(10), states.wait_for(|s| *s == atom_ipc::ConnectionState::Reconnecting))
+        .await
+        .expect("reconnecting observed")
+        .expect("state channel open");
+
+    // Запрос во время переподключения дожидается нового соединения
+    tokio::time::timeout(Duration::from_secs(20), cli.ping()).await.expect("ping in time").expect("ping after restart");
+    assert_eq!(cli.state(), atom_ipc::ConnectionState::Connected);
+    assert!(cli.protocol().await.supports(atom_ipc::capabilities::STREAMING));
+}
+
+async fn e2e_subscription_notifications(transport: Transport) {
+    use atom_ipc::{Notification, SubscriptionTopic};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("shared.txt");
+    std::fs::write(&file_path, b"old\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let watcher = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    assert!(watcher.protocol().await.supports(atom_ipc::capabilities::SUBSCRIPTIONS));
+    let mut notifications = watcher.notifications().await.expect("notification receiver");
+    let buffer_id = match watcher.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    // Открытие папки включает наблюдение за файлами рабочей области
+    let root_path = dir.path().to_string_lossy().into_owned();
+    watcher.request(CoreRequest::GetProjectFiles { root_path }).await.expect("project files");
+    watcher
+        .subscribe(vec![SubscriptionTopic::FileSystem, SubscriptionTopic::Buffer { buffer_id: buffer_id.clone() }])
+        .await
+        .expect("subscribe");
+
+    // Второй клиент сохраняет тот же буфер
+    let editor = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    editor.request(open).await.expect("open");
+    let saved = editor
+        .request(CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: "new\n".into(), force: false })
+        .await
+        .expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+
+    let (mut buffer_changed, mut fs_changed) = (false, false);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !(buffer_changed && fs_changed) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match tokio::time::timeout(left, notifications.recv()).await.expect("notifications in time") {
+            Some(Notification::BufferChanged { buffer_id: id, changes, .. }) => {
+                assert_eq!(id, buffer_id);
+                assert_eq!(changes.last().expect("change").new_text, "new\n");
+                buffer_changed = true;
+            }
+            Some(Notification::FileSystemChanged { path, .. }) => fs_changed |= path.ends_with("shared.txt"),
+            Some(other) => panic!("unexpected notification: {:?}", other),
+            None => panic!("notification channel closed"),
+        }
+    }
+}
+
+/// Отправить запрос с заданным RequestId по «сырому» соединению (протокол v1) и прочитать ответ
+async fn raw_roundtrip(stream: IpcStream, id: RequestId, request: CoreRequest) -> CoreResponse {
+    let (r, w) = stream.into_split();
+    raw_request(&mut BufReader::new(r), &mut BufWriter::new(w), id, request).await
+}
+
+/// Запрос по уже открытому «сырому» соединению
+async fn raw_request<R, W>(reader: &mut R, writer: &mut W, id: RequestId, request: CoreRequest) -> CoreResponse
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let msg = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Request(request) };
+    write_ipc_message(writer, &msg).await.expect("write");
+    writer.flush().await.expect("flush");
+    match read_ipc_message(reader).await.expect("read").payload {
+        IpcPayload::Response(response) => response,
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+async fn e2e_retried_request_id_is_not_reexecuted(transport: Transport) {
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("retry.txt");
+    std::fs::write(&file_path, b"original\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    // Буфер держит отдельный клиент: сырые соединения закрываются после каждого запроса
+    let holder = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let buffer_id = match holder.request(open).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    let save_id = RequestId::new();
+    let save = CoreRequest::SaveBuffer { buffer_id, content: "saved\n".into(), force: false };
+    let first = raw_roundtrip(daemon.connect_raw().await, save_id, save.clone()).await;
+    assert!(matches!(first, CoreResponse::BufferSaved { .. }), "got {:?}", first);
+
+    // Файл изменён извне; повтор того же запроса (новое соединение) не должен его перезаписать
+    std::fs::write(&file_path, b"external\n").expect("write");
+    let retried = raw_roundtrip(daemon.connect_raw().await, save_id, save).await;
+    assert_eq!(bincode::serialize(&retried).unwrap(), bincode::serialize(&first).unwrap());
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "external\n");
+}
+
+async fn e2e_replayed_open_holds_the_buffer(transport: Transport) {
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("replay.txt");
+    std::fs::write(&file_path, b"on disk\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    // Соединение оборвалось сразу после открытия: буфер закрыт вместе с ним
+    let open_id = RequestId::new();
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let first = raw_roundtrip(daemon.connect_raw().await, open_id, open.clone()).await;
+    assert!(matches!(first, CoreResponse::BufferOpened { .. }), "got {:?}", first);
+    sleep(Duration::from_millis(300)).await;
+
+    // Клиент повторяет открытие с тем же RequestId и работает с буфером дальше
+    let (r, w) = daemon.connect_raw().await.into_split();
+    let mut reader = BufReader::new(r);
+    let mut writer = BufWriter::new(w);
+    let buffer_id = match raw_request(&mut reader, &mut writer, open_id, open).await {
+        CoreResponse::BufferOpened { buffer_id, content, .. } => {
+            assert_eq!(content, "on disk\n");
+            buffer_id
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+    let save = CoreRequest::SaveBuffer { buffer_id, content: "replayed\n".into(), force: false };
+    let saved = raw_request(&mut reader, &mut writer, RequestId::new(), save).await;
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+    assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "replayed\n");
+}
+
+async fn e2e_error_codes(transport: Transport) {
+    use atom_ipc::{ErrorCode, ErrorDetail};
+    let workspace = tempfile::tempdir().expect("tmp");
+    let outside = tempfile::tempdir().expect("tmp");
+    let outside_file = outside.path().join("outside.txt");
+    std::fs::write(&outside_file, b"x\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, workspace.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+
+    let missing = workspace.path().join("missing.rs").to_string_lossy().into_owned();
+    let resp = cli.request(CoreRequest::OpenBuffer { path: missing }).await.expect("open");
+    assert_eq!(resp.error_code(), Some(ErrorCode::NotFound), "got {:?}", resp);
+
+    let resp = cli.request(CoreRequest::SaveBuffer { buffer_id: "no-such-buffer".into(), content: "x".into(), force: false }).await.expect("save");
+    match resp {
+        CoreResponse::Error { code: ErrorCode::NotFound, detail: Some(ErrorDetail::Buffer { buffer_id }), .. } => assert_eq!(buffer_id, "no-such-buffer"),
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // Файл вне рабочей области открыть можно, а сохранить — нет
+    let buffer_id = match cli.request(CoreRequest::OpenBuffer { path: outside_file.to_string_lossy().into() }).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    let resp = cli.request(CoreRequest::SaveBuffer { buffer_id, content: "y\n".into(), force: false }).await.expect("save");
+    match resp {
+        CoreResponse::Error { code: ErrorCode::PathOutsideWorkspace, detail: Some(ErrorDetail::OutsideWorkspace { path, .. }), .. } => {
+            assert!(path.ends_with("outside.txt"), "path: {}", path)
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    let resp = cli.request(CoreRequest::GetProjectFiles { root_path: "/no/such/dir".into() }).await.expect("files");
+    assert_eq!(resp.error_code(), Some(ErrorCode::NotFound), "got {:?}", resp);
+}
+
+async fn e2e_shared_buffer_sessions(transport: Transport) {
+    use atom_ipc::{ErrorCode, Notification};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("shared.txt");
+    std::fs::write(&file_path, b"old\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let opened = |resp: CoreResponse| match resp {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let mut a_notifications = a.notifications().await.expect("notification receiver");
+    let buffer_id = opened(a.request(open.clone()).await.expect("open"));
+    assert_eq!(opened(b.request(open).await.expect("open")), buffer_id);
+
+    // Держатель буфера получает правки других клиентов без явной подписки
+    let save = |content: &str| CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: content.into(), force: false };
+    let saved = b.request(save("from b\n")).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+    match tokio::time::timeout(Duration::from_secs(10), a_notifications.recv()).await.expect("notification in time") {
+        Some(Notification::BufferChanged { buffer_id: id, changes, .. }) => {
+            assert_eq!(id, buffer_id);
+            assert_eq!(changes.last().expect("change").new_text, "from b\n");
+        }
+        other => panic!("unexpected notification: {:?}", other),
+    }
+
+    // Закрытие одним клиентом не закрывает буфер для другого
+    let close = CoreRequest::CloseBuffer { buffer_id: buffer_id.clone() };
+    let closed = a.request(close.clone()).await.expect("close");
+    assert!(matches!(closed, CoreResponse::BufferClosed { .. }), "got {:?}", closed);
+    let saved = b.request(save("still open\n")).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+
+    let closed = b.request(close).await.expect("close");
+    assert!(matches!(closed, CoreResponse::BufferClosed { .. }), "got {:?}", closed);
+    let resp = b.request(save("gone\n")).await.expect("save");
+    assert_eq!(resp.error_code(), Some(ErrorCode::NotFound), "got {:?}", resp);
+}
+
+async fn e2e_disconnect_closes_orphaned_buffers(transport: Transport) {
+    use atom_ipc::{TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("orphan.txt");
+    std::fs::write(&file_path, b"on disk\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let opened = |resp: CoreResponse| match resp {
+        CoreResponse::BufferOpened { buffer_id, content, version, .. } => (buffer_id, content, version),
+        other => panic!("unexpected: {:?}", other),
+    };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let (buffer_id, _, version) = opened(a.request(open.clone()).await.expect("open"));
+    b.request(open.clone()).await.expect("open");
+    let edit = TextEdit { range: TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 0 }, new_text: "unsaved ".into() };
+    let resp = a.request(CoreRequest::ApplyEdits { buffer_id, base_version: version, edits: vec![edit] }).await.expect("apply");
+    assert!(matches!(resp, CoreResponse::EditsApplied { .. }), "got {:?}", resp);
+
+    // b ещё держит буфер: отключение a его не закрывает
+    drop(a);
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(opened(b.request(open.clone()).await.expect("open")).1, "unsaved on disk\n");
+
+    // Последний держатель отключился — буфер закрыт, новый клиент читает файл заново
+    drop(b);
+    sleep(Duration::from_millis(300)).await;
+    let c = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let (_, content, version) = opened(c.request(open).await.expect("open"));
+    assert_eq!((content.as_str(), version), ("on disk\n", 0));
+}
+
+async fn e2e_apply_edits_versions(transport: Transport) {
+    use atom_ipc::{ErrorCode, ErrorDetail, Notification, TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("typing.txt");
+    std::fs::write(&file_path, "fn main() {}\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let mut b_notifications = b.notifications().await.expect("notification receiver");
+    let (buffer_id, version) = match a.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, version, .. } => (buffer_id, version),
+        other => panic!("unexpected: {:?}", other),
+    };
+    b.request(open).await.expect("open");
+
+    let insert = |column: usize, text: &str| TextEdit {
+        range: TextRange { start_line: 0, start_column: column, end_line: 0, end_column: column },
+        new_text: text.into(),
+    };
+    let edits = vec![insert(11, "\n    println!(\"hi\");\n"), insert(0, "pub ")];
+    let resp = a
+        .request(CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: version, edits })
+        .await
+        .expect("apply");
+    let new_version = match resp {
+        CoreResponse::EditsApplied { version: v, .. } => v,
+        other => panic!("unexpected: {:?}", other),
+    };
+    assert_eq!(new_version, version + 1);
+
+    match tokio::time::timeout(Duration::from_secs(10), b_notifications.recv()).await.expect("notification in time") {
+        Some(Notification::BufferChanged { buffer_id: id, changes, version: v }) => {
+            assert_eq!(id, buffer_id);
+            assert_eq!(v, new_version);
+            assert_eq!(changes.len(), 2);
+            assert_eq!(changes[1].new_text, "pub ");
+        }
+        other => panic!("unexpected notification: {:?}", other),
+    }
+
+    // Правка по устаревшей версии отклоняется
+    let stale = CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: version, edits: vec![insert(0, "x")] };
+    match b.request(stale).await.expect("apply") {
+        CoreResponse::Error { code: ErrorCode::Conflict, detail: Some(ErrorDetail::Version { expected, actual, .. }), .. } => {
+            assert_eq!((expected, actual), (version, new_version))
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    let saved = b.request(CoreRequest::SaveBuffer { buffer_id, content: String::new(), force: false }).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+    assert_eq!(std::fs::read_to_string(&file_path).expect("read"), "pub fn main() {\n    println!(\"hi\");\n}\n");
+}
+
+async fn e2e_undo_redo_shared_history(transport: Transport) {
+    use atom_ipc::{Notification, TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("history.txt");
+    std::fs::write(&file_path, "a\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let (buffer_id, version) = match a.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, version, .. } => (buffer_id, version),
+        other => panic!("unexpected: {:?}", other),
+    };
+    b.request(open).await.expect("open");
+    let mut a_notifications = a.notifications().await.expect("notification receiver");
+    let mut b_notifications = b.notifications().await.expect("notification receiver");
+
+    let edit = TextEdit { range: TextRange { start_line: 0, start_column: 1, end_line: 0, end_column: 1 }, new_text: "bc".into() };
+    let edits = CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: version, edits: vec![edit] };
+    assert!(matches!(a.request(edits).await.expect("apply"), CoreResponse::EditsApplied { .. }));
+    let saved = a.request(CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: String::new(), force: false }).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+
+    // История общая: второй клиент отменяет правку первого и после сохранения
+    match b.request(CoreRequest::Undo { buffer_id: buffer_id.clone() }).await.expect("undo") {
+        CoreResponse::HistoryApplied { changes, version: v, .. } => {
+            assert_eq!(v, version + 2);
+            assert_eq!(changes.len(), 1);
+            assert_eq!((changes[0].old_text.as_str(), changes[0].new_text.as_str()), ("bc", ""));
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+    match b.request(CoreRequest::Undo { buffer_id: buffer_id.clone() }).await.expect("undo") {
+        CoreResponse::HistoryApplied { changes, .. } => assert!(changes.is_empty()),
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // Первый клиент получил уведомление об отмене и повторяет отменённое
+    match a.request(CoreRequest::Redo { buffer_id: buffer_id.clone() }).await.expect("redo") {
+        CoreResponse::HistoryApplied { changes, .. } => assert_eq!(changes[0].new_text, "bc"),
+        other => panic!("unexpected: {:?}", other),
+    }
+    match tokio::time::timeout(Duration::from_secs(10), a_notifications.recv()).await.expect("notification in time") {
+        Some(Notification::BufferChanged { changes, .. }) => assert_eq!(changes[0].old_text, "bc"),
+        other => panic!("unexpected notification: {:?}", other),
+    }
+    let mut versions = Vec::new();
+    while versions.len() < 2 {
+        match tokio::time::timeout(Duration::from_secs(10), b_notifications.recv()).await.expect("notification in time") {
+            Some(Notification::BufferChanged { version, .. }) => versions.push(version),
+            other => panic!("unexpected notification: {:?}", other),
+        }
+    }
+    assert_eq!(versions, [version + 1, version + 3]);
+}
+
+async fn e2e_legacy_encoding_round_trip(transport: Transport) {
+    use atom_ipc::{ErrorCode, TextEdit, TextRange};
+    const TEXT: &str = "// Расчёт премии сотрудникам отдела\nпроцедура Рассчитать(оклад)\n";
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("legacy.bsl");
+    let (cp1251, _, _) = encoding_rs::WINDOWS_1251.encode(TEXT);
+    std::fs::write(&file_path, &cp1251).expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+
+    let buffer_id = match cli.request(CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() }).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, content, .. } => {
+            assert_eq!(content, TEXT);
+            buffer_id
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    // Сохранение пишет файл обратно в CP1251
+    let edited = TEXT.replace("премии", "отпуска");
+    let saved = cli.request(CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: edited.clone(), force: false }).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+    let (expected, _, _) = encoding_rs::WINDOWS_1251.encode(&edited);
+    assert_eq!(std::fs::read(&file_path).expect("read"), expected.as_ref());
+
+    let reopen = |encoding: &str, force: bool| CoreRequest::ReopenWithEncoding { buffer_id: buffer_id.clone(), encoding: encoding.into(), force };
+    let version = match cli.request(reopen("koi8-r", false)).await.expect("reopen") {
+        CoreResponse::BufferOpened { content, version, .. } => {
+            assert_ne!(content, edited);
+            version
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    // Несохранённые правки не теряются без `force`
+    let edit = TextEdit { range: TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 0 }, new_text: "x".into() };
+    let resp = cli.request(CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: version, edits: vec![edit] }).await.expect("apply");
+    assert!(matches!(resp, CoreResponse::EditsApplied { .. }), "got {:?}", resp);
+    let resp = cli.request(reopen("cp1251", false)).await.expect("reopen");
+    assert_eq!(resp.error_code(), Some(ErrorCode::Conflict), "got {:?}", resp);
+    match cli.request(reopen("cp1251", true)).await.expect("reopen") {
+        CoreResponse::BufferOpened { content, .. } => assert_eq!(content, edited),
+        other => panic!("unexpected: {:?}", other),
+    }
+    let resp = cli.request(reopen("no-such-charset", false)).await.expect("reopen");
+    assert_eq!(resp.error_code(), Some(ErrorCode::Unsupported), "got {:?}", resp);
+}
+
+async fn e2e_binary_and_large_files(transport: Transport) {
+    use atom_ipc::{BufferMode, ErrorCode, TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let binary = dir.path().join("tool.bin");
+    std::fs::write(&binary, b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00").expect("write");
+    let large = dir.path().join("large.rs");
+    std::fs::write(&large, "fn f() {}\n".repeat(200)).expect("write");
+    let large_binary = dir.path().join("large.bin");
+    std::fs::write(&large_binary, vec![0u8; 4096]).expect("write");
+
+    let daemon = Daemon::spawn_configured(transport, &[("ATOMD_MAX_HIGHLIGHT_SIZE", "1024")], Some(dir.path()));
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let open = |path: &std::path::Path| CoreRequest::OpenBuffer { path: path.to_string_lossy().into() };
+
+    // Двоичный файл открывается шестнадцатеричным дампом только для чтения
+    let buffer_id = match cli.request(open(&binary)).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, content, mode, .. } => {
+            assert_eq!(mode, BufferMode::Binary);
+            assert!(content.starts_with("00000000  7f 45 4c 46"), "content: {}", content);
+            buffer_id
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+    let edit = TextEdit { range: TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 0 }, new_text: "x".into() };
+    let resp = cli.request(CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: 0, edits: vec![edit.clone()] }).await.expect("apply");
+    assert_eq!(resp.error_code(), Some(ErrorCode::PermissionDenied), "got {:?}", resp);
+    let resp = cli.request(CoreRequest::SaveBuffer { buffer_id, content: String::new(), force: false }).await.expect("save");
+    assert_eq!(resp.error_code(), Some(ErrorCode::PermissionDenied), "got {:?}", resp);
+
+    // Большой файл: режим без разбора, содержимое — по строкам, редактирование работает
+    let buffer_id = match cli.request(open(&large)).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, content, mode, .. } => {
+            assert_eq!(mode, BufferMode::LargeFile);
+            assert!(content.is_empty(), "content: {}", content.len());
+            buffer_id
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+    let lines = CoreRequest::GetLines { buffer_id: buffer_id.clone(), start_line: 199, end_line: 300 };
+    match cli.request(lines).await.expect("lines") {
+        CoreResponse::Lines { start_line, line_count, text, .. } => {
+            assert_eq!((start_line, line_count, text.as_str()), (199, 201, "fn f() {}\n"));
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+    let resp = cli.request(CoreRequest::ApplyEdits { buffer_id, base_version: 0, edits: vec![edit] }).await.expect("apply");
+    assert!(matches!(resp, CoreResponse::EditsApplied { version: 1, .. }), "got {:?}", resp);
+
+    let resp = cli.request(open(&large_binary)).await.expect("open");
+    assert_eq!(resp.error_code(), Some(ErrorCode::Unsupported), "got {:?}", resp);
+    // Клиент версии 1 не знает GetLines и получает содержимое целиком
+    let stream = daemon.connect_raw().await;
+    let (r, w) = stream.into_split();
+    let (mut reader, mut writer) = (BufReader::new(r), BufWriter::new(w));
+    let legacy = atom_ipc::FrameOptions::handshake(atom_ipc::MAX_MESSAGE_SIZE);
+    let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Request(open(&large)) };
+    atom_ipc::write_ipc_frame(&mut writer, &msg, &legacy).await.expect("write");
+    writer.flush().await.expect("flush");
+    match atom_ipc::read_ipc_frame(&mut reader, &legacy).await.expect("read").payload {
+        IpcPayload::Response(CoreResponse::BufferOpened { content, .. }) => assert_eq!(content.len(), 2001),
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+async fn e2e_external_changes(transport: Transport) {
+    use atom_ipc::{ErrorCode, ErrorDetail, Notification, TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("config.toml");
+    std::fs::write(&file_path, "name = \"atom\"\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let mut b_notifications = b.notifications().await.expect("notification receiver");
+    let buffer_id = match a.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    b.request(open).await.expect("open");
+
+    // Файл изменили извне (git checkout): чистый буфер перечитывается,
+    // второй держатель получает новое содержимое
+    std::fs::write(&file_path, "name = \"atom\"\nversion = 2\n").expect("write");
+    match a.request(CoreRequest::ReloadBuffer { buffer_id: buffer_id.clone() }).await.expect("reload") {
+        CoreResponse::BufferOpened { content, version, .. } => {
+            assert_eq!(content, "name = \"atom\"\nversion = 2\n");
+            assert_eq!(version, 1);
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+    match tokio::time::timeout(Duration::from_secs(10), b_notifications.recv()).await.expect("notification in time") {
+        Some(Notification::BufferChanged { buffer_id: id, changes, version }) => {
+            assert_eq!((id.as_str(), version), (buffer_id.as_str(), 1));
+            assert_eq!(changes[0].new_text, "name = \"atom\"\nversion = 2\n");
+        }
+        other => panic!("unexpected notification: {:?}", other),
+    }
+
+    // Сохранение поверх изменённого на диске файла — конфликт, пока не принудительное;
+    // отклонённое сохранение не трогает буфер
+    std::fs::write(&file_path, "name = \"other\"\n").expect("write");
+    let save = |force| CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: "name = \"mine\"\n".into(), force };
+    match a.request(save(false)).await.expect("save") {
+        CoreResponse::Error { code: ErrorCode::Conflict, detail: Some(ErrorDetail::Path { path }), .. } => {
+            assert!(path.ends_with("config.toml"), "path: {}", path)
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+    assert_eq!(std::fs::read_to_string(&file_path).expect("read"), "name = \"other\"\n");
+    let quiet = tokio::time::timeout(Duration::from_millis(300), b_notifications.recv()).await;
+    assert!(quiet.is_err(), "rejected save changed the buffer: {:?}", quiet);
+
+    // Удалённый файл — тоже конфликт
+    std::fs::remove_file(&file_path).expect("remove");
+    let resp = a.request(save(false)).await.expect("save");
+    assert_eq!(resp.error_code(), Some(ErrorCode::Conflict), "got {:?}", resp);
+    assert!(!file_path.exists());
+    std::fs::write(&file_path, "name = \"other\"\n").expect("write");
+
+    // Буфер с несохранёнными правками не перечитывается
+    let edit = TextEdit { range: TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 0 }, new_text: "# local\n".into() };
+    let resp = a.request(CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: 1, edits: vec![edit] }).await.expect("apply");
+    assert!(matches!(resp, CoreResponse::EditsApplied { .. }), "got {:?}", resp);
+    let resp = a.request(CoreRequest::ReloadBuffer { buffer_id: buffer_id.clone() }).await.expect("reload");
+    assert_eq!(resp.error_code(), Some(ErrorCode::Conflict), "got {:?}", resp);
+
+    let saved = a.request(save(true)).await.expect("save");
+    assert!(matches!(saved, CoreResponse::BufferSaved { .. }), "got {:?}", saved);
+    assert_eq!(std::fs::read_to_string(&file_path).expect("read"), "name = \"mine\"\n");
+}
+
+async fn e2e_save_pipeline(transport: Transport) {
+    use atom_ipc::Notification;
+    let dir = tempfile::tempdir().expect("tmp");
+    // Настройки рабочего каталога: без финального перевода строки
+    std::fs::create_dir_all(dir.path().join(".atom-ide")).expect("mkdir");
+    std::fs::write(dir.path().join(".atom-ide/settings.json"), r#"{"editor": {"insert_final_newline": false}}"#).expect("write");
+    let file_path = dir.path().join("notes.txt");
+    std::fs::write(&file_path, "one\r\ntwo\r\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let mut b_notifications = b.notifications().await.expect("notification receiver");
+    let buffer_id = match a.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    b.request(open).await.expect("open");
+
+    // Пробелы в конце строк убираются, концы строк приводятся к CRLF файла
+    let save = CoreRequest::SaveBuffer { buffer_id: buffer_id.clone(), content: "one  \r\ntwo\nthree".into(), force: false };
+    let (version, changes) = match a.request(save).await.expect("save") {
+        CoreResponse::BufferSaved { version, changes, .. } => (version, changes),
+        other => panic!("unexpected: {:?}", other),
+    };
+    assert_eq!(std::fs::read_to_string(&file_path).expect("read"), "one\r\ntwo\r\nthree");
+    assert_eq!(version, 2);
+    let summary: Vec<_> = changes.iter().map(|c| (c.range.start_line, c.old_text.as_str(), c.new_text.as_str())).collect();
+    assert_eq!(summary, [(0, "  ", ""), (1, "\n", "\r\n")]);
+
+    // Второй держатель получает замену содержимого и правки сохранения
+    let mut last = 0;
+    while last != version {
+        match tokio::time::timeout(Duration::from_secs(10), b_notifications.recv()).await.expect("notification in time") {
+            Some(Notification::BufferChanged { version, .. }) => last = version,
+            other => panic!("unexpected notification: {:?}", other),
+        }
+    }
+}
+
+async fn e2e_editorconfig(transport: Transport) {
+    let dir = tempfile::tempdir().expect("tmp");
+    std::fs::write(
+        dir.path().join(".editorconfig"),
+        "root = true\n\n[*]\ntrim_trailing_whitespace = true\n\n[*.bat]\nend_of_line = crlf\n\n\
+         [docs/**.md]\ntrim_trailing_whitespace = false\ninsert_final_newline = false\ncharset = latin1\n",
+    )
+    .expect("write");
+    std::fs::create_dir_all(dir.path().join("docs")).expect("mkdir");
+    let script = dir.path().join("build.bat");
+    std::fs::write(&script, "@echo off\nexit /b 0\n").expect("write");
+    let readme = dir.path().join("docs/readme.md");
+    std::fs::write(&readme, "# Title\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let save = |path: &std::path::Path, content: &str| {
+        let open = CoreRequest::OpenBuffer { path: path.to_string_lossy().into() };
+        let content = content.to_string();
+        let cli = &cli;
+        async move {
+            let buffer_id = match cli.request(open).await.expect("open") {
+                CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+                other => panic!("unexpected: {:?}", other),
+            };
+            let resp = cli.request(CoreRequest::SaveBuffer { buffer_id, content, force: false }).await.expect("save");
+            assert!(matches!(resp, CoreResponse::BufferSaved { .. }), "got {:?}", resp);
+        }
+    };
+
+    // Концы строк из .editorconfig, а не из файла
+    save(&script, "@echo off \nexit /b 1\n").await;
+    assert_eq!(std::fs::read(&script).expect("read"), b"@echo off\r\nexit /b 1\r\n");
+
+    // Для документации пробелы и отсутствие перевода строки сохраняются, кодировка latin1
+    save(&readme, "# Café  \n\nText").await;
+    assert_eq!(std::fs::read(&readme).expect("read"), b"# Caf\xe9  \n\nText");
+}
+
+async fn e2e_highlights(transport: Transport) {
+    use atom_ipc::{HighlightKind, TextEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("app.py");
+    let source = (0..500).map(|i| format!("def f{}():\n    return {}", i, i)).collect::<Vec<_>>().join("\n");
+    std::fs::write(&file_path, &source).expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let buffer_id = match cli.request(CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() }).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    // Только видимая область: строки 400..402
+    let highlights = |start_line, end_line| CoreRequest::GetHighlights { buffer_id: buffer_id.clone(), start_line, end_line };
+    match cli.request(highlights(400, 402)).await.expect("highlights") {
+        CoreResponse::Highlights { version, spans, .. } => {
+            assert_eq!(version, 0);
+            assert!(spans.iter().all(|s| s.line == 400 || s.line == 401), "{:?}", spans);
+            let kind_at = |line, column| spans.iter().find(|s| s.line == line && s.start_column == column).map(|s| s.kind);
+            assert_eq!(kind_at(400, 0), Some(HighlightKind::Keyword));
+            assert_eq!(kind_at(401, 11), Some(HighlightKind::Number));
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // После правки подсветка соответствует новой версии
+    let edit = TextEdit {
+        range: TextRange { start_line: 401, start_column: 11, end_line: 401, end_column: 14 },
+        new_text: "\"text\"".into(),
+    };
+    cli.request(CoreRequest::ApplyEdits { buffer_id: buffer_id.clone(), base_version: 0, edits: vec![edit] }).await.expect("apply");
+    match cli.request(highlights(401, 402)).await.expect("highlights") {
+        CoreResponse::Highlights { version, spans, .. } => {
+            assert_eq!(version, 1);
+            let string = spans.iter().find(|s| s.start_column == 11).expect("string span");
+            assert_eq!((string.kind, string.length), (HighlightKind::String, 6));
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+async fn e2e_folding_and_structural_selection(transport: Transport) {
+    use atom_ipc::{FoldingKind, FoldingRange, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("app.js");
+    std::fs::write(&file_path, "import a from 'a';\nimport b from 'b';\n\nfunction run() {\n  return call(1, 2);\n}\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let buffer_id = match cli.request(CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() }).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    match cli.request(CoreRequest::GetFoldingRanges { buffer_id: buffer_id.clone() }).await.expect("folds") {
+        CoreResponse::FoldingRanges { version, ranges, .. } => {
+            assert_eq!(version, 0);
+            assert_eq!(ranges, [
+                FoldingRange { start_line: 0, end_line: 1, kind: FoldingKind::Imports },
+                FoldingRange { start_line: 3, end_line: 5, kind: FoldingKind::Region },
+            ]);
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+
+    // Курсор внутри `call` → имя → вызов; вызов сужается обратно до имени
+    let at = |start_column, end_column| TextRange { start_line: 4, start_column, end_line: 4, end_column };
+    let selected = |response| match response {
+        CoreResponse::Selections { ranges, .. } => ranges.into_iter().map(|r| (r.start_column, r.end_column)).collect::<Vec<_>>(),
+        other => panic!("unexpected: {:?}", other),
+    };
+    let expand = CoreRequest::ExpandSelection { buffer_id: buffer_id.clone(), ranges: vec![at(10, 10), at(9, 13)] };
+    assert_eq!(selected(cli.request(expand).await.expect("expand")), [(9, 13), (9, 19)]);
+    let shrink = CoreRequest::ShrinkSelection { buffer_id: buffer_id.clone(), ranges: vec![at(9, 19)] };
+    assert_eq!(selected(cli.request(shrink).await.expect("shrink")), [(9, 13)]);
+}
+
+async fn e2e_document_symbols(transport: Transport) {
+    use atom_ipc::{SymbolKind, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("shapes.py");
+    std::fs::write(&file_path, "class Circle:\n    def area(self):\n        return 3\n\ndef main():\n    pass\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let cli = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let buffer_id = match cli.request(CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() }).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+
+    match cli.request(CoreRequest::GetDocumentSymbols { buffer_id }).await.expect("symbols") {
+        CoreResponse::DocumentSymbols { version, symbols, .. } => {
+            assert_eq!(version, 0);
+            let top: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+            assert_eq!(top, [("Circle", SymbolKind::Class), ("main", SymbolKind::Function)]);
+            let area = &symbols[0].children[0];
+            assert_eq!((area.name.as_str(), area.kind), ("area", SymbolKind::Method));
+            assert_eq!(area.selection_range, TextRange { start_line: 1, start_column: 8, end_line: 1, end_column: 12 });
+        }
+        other => panic!("unexpected: {:?}", other),
+    }
+}
+
+async fn e2e_multi_cursor_editing(transport: Transport) {
+    use atom_ipc::{Notification, SelectionEdit, TextRange};
+    let dir = tempfile::tempdir().expect("tmp");
+    let file_path = dir.path().join("names.txt");
+    std::fs::write(&file_path, "let name = name + 1;\nprint(name)\n").expect("write");
+
+    let daemon = Daemon::spawn_in(transport, dir.path());
+    assert!(daemon.wait_ready(Duration::from_secs(10)).await, "daemon not ready");
+    let open = CoreRequest::OpenBuffer { path: file_path.to_string_lossy().into() };
+    let a = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let b = atom_ipc::IpcClient::connect(&daemon.endpoint).await.expect("ipc connect");
+    let mut b_notifications = b.notifications().await.expect("notification receiver");
+    let buffer_id = match a.request(open.clone()).await.expect("open") {
+        CoreResponse::BufferOpened { buffer_id, .. } => buffer_id,
+        other => panic!("unexpected: {:?}", other),
+    };
+    b.request(open).await.expect("open");
+
+    // Курсор в слове → слово → ещё два вхождения
+    let mut selections = vec![TextRange { start_line: 0, start_column: 5, end_line: 0, end_column: 5 }];
+    for _ in 0..3 {
+        let next = CoreRequest::AddCursorAtNextMatch { buffer_id: buffer_id.clone(), selections };
+        selections = match a.request(next).await.expect("next match") {
+            CoreResponse::Selections { ranges, .. } => ranges,
+            other => panic!("unexpected: {:?}", other),
+        };
+    }
+    let starts: Vec<_> = selections.iter().map(|r| (r.start_line, r.start_column, r.end_column)).collect();
+    assert_eq!(starts, [(0, 4, 8), (0, 11, 15), (1, 6, 10)]);
+
+    let edit = SelectionEdit::Insert { text: "id".into() };
+    let request = CoreRequest::EditSelections { buffer_id: buffer_id.clone(), base_version: 0, selections, edit };
+    let (version, changes) = match a.request(request).await.expect("edit selections") {
+        CoreResponse::SelectionsEdited { version, changes, selections, .. } => {
+            let cursors: Vec<_> = selections.iter().map(|r| (r.end_line, r.end_column)).collect();
+            assert_eq!(cursors, [(0, 6), (0, 11), (1, 8)]);
+            (version, changes)
+        }
+        other => panic!("unexpected: {:?}", other),
+    };
+    assert_eq!((version, changes.len()), (1, 3));
 
-    let _ = child.kill();
+    // Все правки одним уведомлением, в порядке применения
+    match tokio::time::timeout(Duration::from_secs(10), b_notifications.recv()).await.expect("notification in time") {
+        Some(Notification::BufferChanged { changes, version: v, .. }) => {
+            assert_eq!(v, version);
+            let lines: Vec<_> = changes.iter().map(|c| c.range.start_line).collect();
+            assert_eq!(lines, [1, 0, 0]);
+        }
+        other => panic!("unexpected notification: {:?}", other),
+    }
+    // Вся пачка — один шаг истории
+    match a.request(CoreRequest::Undo { buffer_id }).await.expect("undo") {
+        CoreResponse::HistoryApplied { changes, .. } => assert_eq!(changes.len(), 3),
+        other => panic!("unexpected: {:?}", other),
+    }
 }
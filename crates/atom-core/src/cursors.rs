@@ -0,0 +1,598 @@
+//! Multiple selections and the edits one action makes across all of them
+//!
+//! A selection runs from its anchor (where it was started) to its head (where
+//! the cursor is); a cursor is an empty selection. A `SelectionSet` keeps its
+//! selections sorted and merges the ones that overlap or touch. An action
+//! replaces char ranges of the text, at most one per selection or line, and
+//! becomes one batch of edits in reverse document order, so that the range
+//! of every edit is still valid in the text left by the edits before it.
+
+use std::collections::BTreeMap;
+use std::ops::Range as CharRange;
+
+use atom_ipc::SelectionEdit;
+use ropey::Rope;
+
+use crate::{line_terminator_len, BufferManager, CoreError, Position, Range, TextEdit};
+
+/// Selection from `anchor` to `head`; the cursor is at `head`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Position,
+    pub head: Position,
+}
+
+impl Selection {
+    pub fn new(anchor: Position, head: Position) -> Self {
+        Self { anchor, head }
+    }
+
+    /// Empty selection at `position`
+    pub fn cursor(position: Position) -> Self {
+        Self { anchor: position, head: position }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Selected range, start before end
+    pub fn range(&self) -> Range {
+        Range { start: self.anchor.min(self.head), end: self.anchor.max(self.head) }
+    }
+}
+
+/// Selections of a buffer, sorted and disjoint. The primary one (usually
+/// the one added last) is the one the view follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectionSet {
+    selections: Vec<Selection>,
+    primary: usize,
+}
+
+impl SelectionSet {
+    /// Set of `selections` whose primary one is at index `primary`; an empty
+    /// list gives a cursor at the start of the text
+    pub fn new(selections: Vec<Selection>, primary: usize) -> Self {
+        if selections.is_empty() {
+            return Self::single(Selection::cursor(Position { line: 0, column: 0 }));
+        }
+        let primary = primary.min(selections.len() - 1);
+        let mut indexed: Vec<_> = selections.into_iter().enumerate().collect();
+        indexed.sort_by_key(|(_, selection)| selection.range().start);
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(indexed.len());
+        let mut merged_primary = 0;
+        for (index, selection) in indexed {
+            match merged.last_mut() {
+                Some(last) if selection.range().start <= last.range().end => {
+                    let end = last.range().end.max(selection.range().end);
+                    // The merged selection keeps the direction of the first one
+                    *last = if last.head < last.anchor {
+                        Selection::new(end, last.range().start)
+                    } else {
+                        Selection::new(last.range().start, end)
+                    };
+                }
+                _ => merged.push(selection),
+            }
+            if index == primary {
+                merged_primary = merged.len() - 1;
+            }
+        }
+        Self { selections: merged, primary: merged_primary }
+    }
+
+    /// Set of one selection
+    pub fn single(selection: Selection) -> Self {
+        Self { selections: vec![selection], primary: 0 }
+    }
+
+    /// Rectangular selection between two corners: on every line between them,
+    /// the columns from the anchor's to the head's. Columns past the end of a
+    /// line stop at its end, and lines ending before the left edge are skipped.
+    pub fn block(text: &Rope, anchor: Position, head: Position) -> Self {
+        let left = anchor.column.min(head.column);
+        let last_line = text.len_lines().saturating_sub(1);
+        let lines = anchor.line.min(head.line)..=anchor.line.max(head.line).min(last_line);
+        let mut selections = Vec::new();
+        let mut primary = 0;
+        for line in lines {
+            let slice = text.line(line);
+            let len = slice.len_chars() - line_terminator_len(&slice);
+            if len < left {
+                continue;
+            }
+            if line == head.line {
+                primary = selections.len();
+            }
+            let at = |column: usize| Position { line, column: column.min(len) };
+            selections.push(Selection::new(at(anchor.column), at(head.column)));
+        }
+        if selections.is_empty() {
+            return Self::single(Selection::cursor(anchor));
+        }
+        Self { selections, primary }
+    }
+
+    /// Selections in document order
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections
+    }
+
+    pub fn primary(&self) -> Selection {
+        self.selections[self.primary]
+    }
+
+    /// Index of the primary selection in `selections()`
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    /// Add a selection and make it the primary one
+    pub fn add(&mut self, selection: Selection) {
+        let mut selections = std::mem::take(&mut self.selections);
+        selections.push(selection);
+        *self = Self::new(selections, usize::MAX);
+    }
+}
+
+/// What line actions need to know about the buffer: how `Indent` and
+/// `Outdent` change line starts, and the comment tokens of its language for
+/// `ToggleComment`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LineStyle<'a> {
+    pub insert_spaces: bool,
+    pub tab_size: usize,
+    pub line_comment: Option<&'a str>,
+    pub block_comment: Option<(&'a str, &'a str)>,
+}
+
+/// A selection in char offsets
+#[derive(Clone, Copy)]
+struct Offsets {
+    anchor: usize,
+    head: usize,
+}
+
+impl Offsets {
+    fn range(&self) -> CharRange<usize> {
+        self.anchor.min(self.head)..self.anchor.max(self.head)
+    }
+}
+
+/// Edits that apply `action` at every selection, and the selections after them
+pub(crate) fn edit(
+    text: &Rope,
+    selections: &SelectionSet,
+    action: &SelectionEdit,
+    style: LineStyle,
+) -> Result<(Vec<TextEdit>, SelectionSet), CoreError> {
+    // A zero tab size from settings would indent by nothing yet still bump the version
+    let style = LineStyle { tab_size: style.tab_size.max(1), ..style };
+    let offsets = selections
+        .selections()
+        .iter()
+        .map(|selection| {
+            Ok(Offsets {
+                anchor: BufferManager::position_to_char_idx_checked(text, selection.anchor)?,
+                head: BufferManager::position_to_char_idx_checked(text, selection.head)?,
+            })
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+
+    let replacements = match action {
+        SelectionEdit::Insert { text: new_text } => {
+            offsets.iter().map(|selection| (selection.range(), new_text.clone())).collect()
+        }
+        SelectionEdit::DeleteBackward => deletions(&offsets, |cursor| {
+            let crlf = cursor >= 2 && text.char(cursor - 2) == '\r' && text.char(cursor - 1) == '\n';
+            cursor.saturating_sub(if crlf { 2 } else { 1 })..cursor
+        }),
+        SelectionEdit::DeleteForward => deletions(&offsets, |cursor| {
+            let len = text.len_chars();
+            let crlf = cursor + 1 < len && text.char(cursor) == '\r' && text.char(cursor + 1) == '\n';
+            cursor..(cursor + if crlf { 2 } else { 1 }).min(len)
+        }),
+        SelectionEdit::Indent => {
+            let unit = if style.insert_spaces { " ".repeat(style.tab_size) } else { "\t".to_string() };
+            selected_lines(text, &offsets)
+                .filter(|&(line, on_cursor)| on_cursor || !is_blank(text, line))
+                .map(|(line, _)| {
+                    let start = text.line_to_char(line);
+                    (start..start, unit.clone())
+                })
+                .collect()
+        }
+        SelectionEdit::Outdent => selected_lines(text, &offsets)
+            .filter_map(|(line, _)| {
+                let start = text.line_to_char(line);
+                let width = outdent_width(text, line, style.tab_size);
+                (width > 0).then(|| (start..start + width, String::new()))
+            })
+            .collect(),
+        SelectionEdit::ToggleComment => toggle_comment(text, &offsets, style),
+    };
+
+    let edits = replacements
+        .iter()
+        .rev()
+        .map(|(range, new_text)| TextEdit {
+            range: Range {
+                start: BufferManager::char_idx_to_position(text, range.start),
+                end: BufferManager::char_idx_to_position(text, range.end),
+            },
+            new_text: new_text.clone(),
+        })
+        .collect();
+
+    let mut result = text.clone();
+    for (range, new_text) in replacements.iter().rev() {
+        result.remove(range.clone());
+        result.insert(range.start, new_text);
+    }
+    let moved = offsets
+        .iter()
+        .map(|selection| {
+            let position = |offset| BufferManager::char_idx_to_position(&result, map_offset(offset, &replacements));
+            Selection::new(position(selection.anchor), position(selection.head))
+        })
+        .collect();
+    Ok((edits, SelectionSet::new(moved, selections.primary_index())))
+}
+
+/// Selection set with a new selection at the next occurrence of the primary
+/// selection's text, after it and wrapping around; occurrences already
+/// selected are skipped. An empty primary selection grows to the word under
+/// the cursor instead.
+pub(crate) fn add_next_match(text: &Rope, selections: &SelectionSet) -> Result<SelectionSet, CoreError> {
+    let primary = selections.primary();
+    let start = BufferManager::position_to_char_idx_checked(text, primary.range().start)?;
+    let end = BufferManager::position_to_char_idx_checked(text, primary.range().end)?;
+
+    if start == end {
+        let is_word = |index: usize| text.char(index).is_alphanumeric() || text.char(index) == '_';
+        let word_start = (0..start).rev().take_while(|&index| is_word(index)).last().unwrap_or(start);
+        let word_end = (end..text.len_chars()).find(|&index| !is_word(index)).unwrap_or(text.len_chars());
+        if word_start == word_end {
+            return Ok(selections.clone());
+        }
+        let mut word = selections.selections().to_vec();
+        word[selections.primary_index()] = Selection::new(
+            BufferManager::char_idx_to_position(text, word_start),
+            BufferManager::char_idx_to_position(text, word_end),
+        );
+        return Ok(SelectionSet::new(word, selections.primary_index()));
+    }
+
+    let needle: Vec<char> = text.slice(start..end).chars().collect();
+    // After the selection first, then from the top of the buffer back to it
+    let candidates = find_from(text, &needle, end).chain(find_from(text, &needle, 0).take_while(|&index| index < end));
+    let selected = selections.selections().iter().map(Selection::range).collect::<Vec<_>>();
+    for index in candidates {
+        let found_start = BufferManager::char_idx_to_position(text, index);
+        let found_end = BufferManager::char_idx_to_position(text, index + needle.len());
+        if selected.iter().any(|range| range.start < found_end && found_start < range.end) {
+            continue;
+        }
+        let mut next = selections.clone();
+        next.add(Selection::new(found_start, found_end));
+        return Ok(next);
+    }
+    Ok(selections.clone())
+}
+
+/// Char indices where `needle` starts at or after `from`, found lazily by
+/// walking the rope's chunks (Knuth–Morris–Pratt, so no text is copied)
+fn find_from<'a>(text: &'a Rope, needle: &'a [char], from: usize) -> impl Iterator<Item = usize> + 'a {
+    // fallback[i]: length of the longest proper border of needle[..=i]
+    let mut fallback = vec![0; needle.len()];
+    let mut border = 0;
+    for i in 1..needle.len() {
+        while border > 0 && needle[i] != needle[border] {
+            border = fallback[border - 1];
+        }
+        if needle[i] == needle[border] {
+            border += 1;
+        }
+        fallback[i] = border;
+    }
+
+    let mut chars = text.chars_at(from).enumerate();
+    let mut matched = 0;
+    std::iter::from_fn(move || {
+        if needle.is_empty() {
+            return None;
+        }
+        for (offset, c) in chars.by_ref() {
+            while matched > 0 && c != needle[matched] {
+                matched = fallback[matched - 1];
+            }
+            if c == needle[matched] {
+                matched += 1;
+            }
+            if matched == needle.len() {
+                // Matches don't overlap, like `str::match_indices`
+                matched = 0;
+                return Some(from + offset + 1 - needle.len());
+            }
+        }
+        None
+    })
+}
+
+/// Deletions of the selected text, or of the range `at_cursor` gives for
+/// empty selections; ranges overlapping the previous one are trimmed
+fn deletions(
+    offsets: &[Offsets],
+    at_cursor: impl Fn(usize) -> CharRange<usize>,
+) -> Vec<(CharRange<usize>, String)> {
+    let mut deletions: Vec<(CharRange<usize>, String)> = Vec::new();
+    for selection in offsets {
+        let mut range = selection.range();
+        if range.is_empty() {
+            range = at_cursor(selection.head);
+        }
+        if let Some((previous, _)) = deletions.last() {
+            range.start = range.start.max(previous.end);
+        }
+        if !range.is_empty() {
+            deletions.push((range, String::new()));
+        }
+    }
+    deletions
+}
+
+/// Lines touched by the selections, in order, each with whether an empty
+/// selection is on it. A selection ending at the start of a line does not
+/// touch that line.
+fn selected_lines(text: &Rope, offsets: &[Offsets]) -> impl Iterator<Item = (usize, bool)> {
+    let mut lines = BTreeMap::new();
+    for selection in offsets {
+        let range = selection.range();
+        let first = text.char_to_line(range.start);
+        let mut last = text.char_to_line(range.end);
+        if last > first && text.line_to_char(last) == range.end {
+            last -= 1;
+        }
+        for line in first..=last {
+            *lines.entry(line).or_insert(false) |= range.is_empty();
+        }
+    }
+    lines.into_iter()
+}
+
+/// Comment out the non-blank selected lines, or uncomment them if all of
+/// them are commented. Line comments go at the smallest indentation of the
+/// lines; without a line comment token each line is wrapped in a block comment.
+fn toggle_comment(text: &Rope, offsets: &[Offsets], style: LineStyle) -> Vec<(CharRange<usize>, String)> {
+    // (first char, first non-whitespace char, end before the line break) of each line
+    let lines: Vec<(usize, usize, usize)> = selected_lines(text, offsets)
+        .filter(|&(line, _)| !is_blank(text, line))
+        .map(|(line, _)| {
+            let slice = text.line(line);
+            let start = text.line_to_char(line);
+            let indent = slice.chars().take_while(|&c| c == ' ' || c == '\t').count();
+            (start, start + indent, start + slice.len_chars() - line_terminator_len(&slice))
+        })
+        .collect();
+    let starts_with = |at: usize, token: &str| {
+        let len = token.chars().count();
+        at + len <= text.len_chars() && text.slice(at..at + len) == token
+    };
+    // A space after the opening token goes with it
+    let token_end = |at: usize, token: &str| {
+        let end = at + token.chars().count();
+        if end < text.len_chars() && text.char(end) == ' ' { end + 1 } else { end }
+    };
+
+    if let Some(token) = style.line_comment {
+        if lines.iter().all(|&(_, content, _)| starts_with(content, token)) {
+            return lines.iter().map(|&(_, content, _)| (content..token_end(content, token), String::new())).collect();
+        }
+        let indent = lines.iter().map(|&(start, content, _)| content - start).min().unwrap_or(0);
+        return lines.iter().map(|&(start, _, _)| (start + indent..start + indent, format!("{} ", token))).collect();
+    }
+
+    let Some((open, close)) = style.block_comment else { return Vec::new() };
+    let close_len = close.chars().count();
+    let wrapped = |&(_, content, end): &(usize, usize, usize)| {
+        starts_with(content, open) && end >= content + open.chars().count() + close_len && starts_with(end - close_len, close)
+    };
+    if lines.iter().all(wrapped) {
+        return lines
+            .iter()
+            .flat_map(|&(_, content, end)| {
+                let close_start = if end > close_len && text.char(end - close_len - 1) == ' ' { end - close_len - 1 } else { end - close_len };
+                let open_end = token_end(content, open).min(close_start);
+                [(content..open_end, String::new()), (close_start..end, String::new())]
+            })
+            .collect();
+    }
+    lines
+        .iter()
+        .flat_map(|&(_, content, end)| [(content..content, format!("{} ", open)), (end..end, format!(" {}", close))])
+        .collect()
+}
+
+fn is_blank(text: &Rope, line: usize) -> bool {
+    let slice = text.line(line);
+    slice.len_chars() == line_terminator_len(&slice)
+}
+
+/// Chars of leading whitespace one outdent removes: a tab, or the spaces
+/// back to the previous tab stop
+fn outdent_width(text: &Rope, line: usize, tab_size: usize) -> usize {
+    let mut chars = text.line(line).chars();
+    match chars.next() {
+        Some('\t') => 1,
+        Some(' ') => {
+            let spaces = 1 + chars.take_while(|&c| c == ' ').count();
+            let to_stop = spaces % tab_size.max(1);
+            if to_stop == 0 { tab_size.min(spaces) } else { to_stop }
+        }
+        _ => 0,
+    }
+}
+
+/// Offset in the original text mapped into the text after `replacements`
+/// (sorted, disjoint). Offsets inside a replaced range, or at an insertion
+/// point, move to the end of the new text.
+fn map_offset(offset: usize, replacements: &[(CharRange<usize>, String)]) -> usize {
+    let mut shifted = offset;
+    for (range, new_text) in replacements {
+        if offset < range.start {
+            break;
+        }
+        let new_len = new_text.chars().count();
+        if offset > range.end || (offset == range.end && !range.is_empty()) {
+            shifted = shifted + new_len - range.len();
+        } else {
+            return shifted - (offset - range.start) + new_len;
+        }
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: LineStyle = LineStyle { insert_spaces: true, tab_size: 4, line_comment: Some("//"), block_comment: Some(("/*", "*/")) };
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    fn cursors(positions: &[(usize, usize)]) -> SelectionSet {
+        let selections = positions.iter().map(|&(line, column)| Selection::cursor(at(line, column))).collect();
+        SelectionSet::new(selections, 0)
+    }
+
+    /// Text after the action, and the (line, column) of every head
+    fn apply(source: &str, selections: &SelectionSet, action: SelectionEdit) -> (String, Vec<(usize, usize)>) {
+        let mut text = Rope::from_str(source);
+        let (edits, after) = edit(&text, selections, &action, SPACES).unwrap();
+        // Applied in order, each against the text left by the previous one
+        for edit in edits {
+            let start = BufferManager::position_to_char_idx_checked(&text, edit.range.start).unwrap();
+            let end = BufferManager::position_to_char_idx_checked(&text, edit.range.end).unwrap();
+            text.remove(start..end);
+            text.insert(start, &edit.new_text);
+        }
+        let heads = after.selections().iter().map(|s| (s.head.line, s.head.column)).collect();
+        (text.to_string(), heads)
+    }
+
+    #[test]
+    fn merges_overlapping_selections_and_keeps_the_primary() {
+        let set = SelectionSet::new(
+            vec![Selection::new(at(0, 4), at(0, 2)), Selection::cursor(at(1, 0)), Selection::new(at(0, 3), at(0, 6))],
+            2,
+        );
+        assert_eq!(set.selections(), [Selection::new(at(0, 6), at(0, 2)), Selection::cursor(at(1, 0))]);
+        assert_eq!(set.primary_index(), 0);
+    }
+
+    #[test]
+    fn inserts_and_deletes_at_every_cursor() {
+        let source = "ab\r\ncd\nëf\n";
+        let set = cursors(&[(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(apply(source, &set, SelectionEdit::Insert { text: "XY".into() }), (
+            "aXYb\r\ncXYd\nëXYf\n".into(),
+            vec![(0, 3), (1, 3), (2, 3)]
+        ));
+        assert_eq!(apply(source, &set, SelectionEdit::DeleteBackward), ("b\r\nd\nf\n".into(), vec![(0, 0), (1, 0), (2, 0)]));
+        // CRLF goes as one line break
+        let ends = cursors(&[(0, 2), (1, 2)]);
+        assert_eq!(apply(source, &ends, SelectionEdit::DeleteForward), ("abcdëf\n".into(), vec![(0, 2), (0, 4)]));
+        // Cursors meeting after the deletion merge
+        let adjacent = cursors(&[(0, 1), (0, 2)]);
+        assert_eq!(apply(source, &adjacent, SelectionEdit::DeleteBackward), ("\r\ncd\nëf\n".into(), vec![(0, 0)]));
+    }
+
+    #[test]
+    fn indents_and_outdents_selected_lines() {
+        let source = "a\n\n  b\nc\n";
+        let lines = SelectionSet::single(Selection::new(at(0, 0), at(3, 0)));
+        let (indented, _) = apply(source, &lines, SelectionEdit::Indent);
+        assert_eq!(indented, "    a\n\n      b\nc\n");
+        // Back to the previous tab stop
+        assert_eq!(apply(&indented, &lines, SelectionEdit::Outdent).0, "a\n\n    b\nc\n");
+        assert_eq!(apply(source, &cursors(&[(2, 3)]), SelectionEdit::Outdent), ("a\n\nb\nc\n".into(), vec![(2, 1)]));
+    }
+
+    #[test]
+    fn toggles_line_comments() {
+        let source = "fn f() {\n    a();\n\n        b();\n}\n";
+        let lines = SelectionSet::single(Selection::new(at(1, 0), at(3, 2)));
+        let (commented, heads) = apply(source, &lines, SelectionEdit::ToggleComment);
+        // At the smallest indentation; blank lines stay as they are
+        assert_eq!(commented, "fn f() {\n    // a();\n\n    //     b();\n}\n");
+        assert_eq!(heads, [(3, 2)]);
+        assert_eq!(apply(&commented, &lines, SelectionEdit::ToggleComment).0, source);
+        // Partly commented lines are commented again
+        let mixed = SelectionSet::single(Selection::new(at(0, 0), at(1, 1)));
+        assert_eq!(apply(&commented, &mixed, SelectionEdit::ToggleComment).0, "// fn f() {\n//     // a();\n\n    //     b();\n}\n");
+    }
+
+    #[test]
+    fn wraps_lines_in_block_comments_without_a_line_token() {
+        let css = LineStyle { line_comment: None, block_comment: Some(("/*", "*/")), ..SPACES };
+        let source = "a { color: red; }\n  b { }\n";
+        let mut text = Rope::from_str(source);
+        let lines = SelectionSet::single(Selection::new(at(0, 0), at(1, 3)));
+        for expected in ["/* a { color: red; } */\n  /* b { } */\n", source] {
+            let (edits, _) = edit(&text, &lines, &SelectionEdit::ToggleComment, css).unwrap();
+            for edit in edits {
+                let start = BufferManager::position_to_char_idx_checked(&text, edit.range.start).unwrap();
+                let end = BufferManager::position_to_char_idx_checked(&text, edit.range.end).unwrap();
+                text.remove(start..end);
+                text.insert(start, &edit.new_text);
+            }
+            assert_eq!(text, expected);
+        }
+        let plain = LineStyle { line_comment: None, block_comment: None, ..SPACES };
+        assert!(edit(&text, &lines, &SelectionEdit::ToggleComment, plain).unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn block_selection_spans_lines() {
+        let text = Rope::from_str("abcdef\nab\nabcdef\n");
+        let block = SelectionSet::block(&text, at(0, 3), at(2, 5));
+        let ranges: Vec<_> = block.selections().iter().map(|s| (s.anchor.line, s.anchor.column, s.head.column)).collect();
+        assert_eq!(ranges, [(0, 3, 5), (2, 3, 5)]);
+        assert_eq!(block.primary().head, at(2, 5));
+        let (typed, _) = apply("abcdef\nab\nabcdef\n", &block, SelectionEdit::Insert { text: "-".into() });
+        assert_eq!(typed, "abc-f\nab\nabc-f\n");
+    }
+
+    #[test]
+    fn adds_cursors_at_next_matches() {
+        let text = Rope::from_str("foo bar foo\nfoo_x foo\n");
+        let mut set = add_next_match(&text, &cursors(&[(0, 9)])).unwrap();
+        assert_eq!(set.primary(), Selection::new(at(0, 8), at(0, 11)));
+        let mut found = Vec::new();
+        for _ in 0..4 {
+            set = add_next_match(&text, &set).unwrap();
+            found.push(set.primary().range().start);
+        }
+        // `foo_x` contains `foo` as text; the search wraps and then runs out
+        assert_eq!(found, [at(1, 0), at(1, 6), at(0, 0), at(0, 0)]);
+        assert_eq!(set.selections().len(), 4);
+
+        // Char offsets past multi-byte text, and a wrap that ends on the selection itself
+        let text = Rope::from_str("ёж ёж ёёж\n");
+        let set = add_next_match(&text, &SelectionSet::single(Selection::new(at(0, 3), at(0, 5)))).unwrap();
+        assert_eq!(set.primary(), Selection::new(at(0, 7), at(0, 9)));
+        let set = add_next_match(&text, &set).unwrap();
+        assert_eq!(set.primary(), Selection::new(at(0, 0), at(0, 2)));
+    }
+
+    #[test]
+    fn zero_tab_size_indents_by_one_column() {
+        let style = LineStyle { tab_size: 0, ..SPACES };
+        let (edits, _) = edit(&Rope::from_str("a\n"), &cursors(&[(0, 0)]), &SelectionEdit::Indent, style).unwrap();
+        assert_eq!(edits.iter().map(|edit| edit.new_text.as_str()).collect::<Vec<_>>(), [" "]);
+    }
+}
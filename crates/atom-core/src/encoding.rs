@@ -0,0 +1,322 @@
+//! Text encodings of files on disk
+//!
+//! Files are decoded on open: a byte order mark wins, then valid UTF-8, then
+//! a charset guess (legacy single-byte and CJK encodings such as
+//! Windows-1251). The encoding and the presence of a BOM are kept on the
+//! buffer so that saving writes the file back the way it was read.
+
+use std::borrow::Cow;
+use std::fmt::Write as _;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use ropey::{Rope, RopeBuilder};
+
+/// How many leading bytes are inspected for binary content
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Encoding of a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileEncoding {
+    pub encoding: &'static Encoding,
+    /// The file starts with a byte order mark
+    pub bom: bool,
+}
+
+impl Default for FileEncoding {
+    /// UTF-8 without BOM
+    fn default() -> Self {
+        FileEncoding { encoding: UTF_8, bom: false }
+    }
+}
+
+impl From<atom_settings::Charset> for FileEncoding {
+    /// Encoding for an `.editorconfig` charset
+    fn from(charset: atom_settings::Charset) -> Self {
+        use atom_settings::Charset;
+        match charset {
+            // WHATWG maps latin1 to its superset windows-1252
+            Charset::Latin1 => FileEncoding { encoding: encoding_rs::WINDOWS_1252, bom: false },
+            Charset::Utf8 => FileEncoding { encoding: UTF_8, bom: false },
+            Charset::Utf8Bom => FileEncoding { encoding: UTF_8, bom: true },
+            Charset::Utf16Be => FileEncoding { encoding: UTF_16BE, bom: true },
+            Charset::Utf16Le => FileEncoding { encoding: UTF_16LE, bom: true },
+        }
+    }
+}
+
+impl FileEncoding {
+    /// Encoding by its WHATWG label (`utf-8`, `windows-1251`, `cp1251`, `utf-16le`, ...)
+    pub fn for_label(label: &str) -> Option<FileEncoding> {
+        Encoding::for_label(label.trim().as_bytes()).map(|encoding| FileEncoding { encoding, bom: false })
+    }
+
+    /// Canonical encoding name (`UTF-8`, `windows-1251`, `UTF-16LE`, ...)
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+}
+
+/// Decoded file content (borrowed when the bytes already are UTF-8)
+#[derive(Debug)]
+pub struct Decoded<'a> {
+    pub text: Cow<'a, str>,
+    pub encoding: FileEncoding,
+    /// Some bytes were not valid in the encoding and were replaced with U+FFFD
+    pub had_errors: bool,
+}
+
+/// Detect the encoding of `bytes` and decode them
+pub fn decode(bytes: &[u8]) -> Decoded<'_> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return decode_with(bytes, FileEncoding { encoding, bom: true });
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Decoded { text: Cow::Borrowed(text), encoding: FileEncoding::default(), had_errors: false };
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, true);
+    decode_with(bytes, FileEncoding { encoding, bom: false })
+}
+
+/// Decode `bytes` in the given encoding; a BOM of that encoding is stripped
+pub fn decode_with(bytes: &[u8], encoding: FileEncoding) -> Decoded<'_> {
+    let (bom, body) = match Encoding::for_bom(bytes) {
+        Some((found, len)) if found == encoding.encoding => (true, &bytes[len..]),
+        _ => (false, bytes),
+    };
+    let (text, had_errors) = encoding.encoding.decode_without_bom_handling(body);
+    Decoded {
+        text,
+        encoding: FileEncoding { encoding: encoding.encoding, bom: bom || encoding.bom },
+        had_errors,
+    }
+}
+
+/// `decode` for a file read in chunks, building the rope as it goes.
+///
+/// Without a BOM the file is first taken as UTF-8. If it turns out not to be,
+/// `finish` returns the guessed encoding instead, and the file has to be read
+/// again through an `EncodingDecoder` for it.
+pub struct ChunkDecoder {
+    decoder: EncodingDecoder,
+    /// Charset guess, kept while the file may still not be UTF-8
+    detector: Option<EncodingDetector>,
+}
+
+impl ChunkDecoder {
+    /// Decoder for a file starting with `head`: its BOM encoding, or UTF-8
+    pub fn sniff(head: &[u8]) -> Self {
+        match Encoding::for_bom(head) {
+            Some((encoding, _)) => Self { decoder: EncodingDecoder::new(FileEncoding { encoding, bom: true }), detector: None },
+            None => Self { decoder: EncodingDecoder::new(FileEncoding::default()), detector: Some(EncodingDetector::new()) },
+        }
+    }
+
+    /// Decode the next chunk; `last` marks the end of the file
+    pub fn feed(&mut self, bytes: &[u8], last: bool) {
+        if let Some(detector) = &mut self.detector {
+            detector.feed(bytes, last);
+            if self.decoder.had_errors {
+                // Not UTF-8 after all: only the guess matters now
+                return;
+            }
+        }
+        self.decoder.feed(bytes, last);
+    }
+
+    /// The decoded rope, its encoding and whether bytes were replaced; or,
+    /// for a file that is not UTF-8, the encoding to decode it with
+    pub fn finish(self) -> Result<(Rope, FileEncoding, bool), FileEncoding> {
+        match self.detector {
+            Some(detector) if self.decoder.had_errors => {
+                Err(FileEncoding { encoding: detector.guess(None, true), bom: false })
+            }
+            _ => Ok(self.decoder.finish()),
+        }
+    }
+}
+
+/// Chunked decoding in a known encoding; a BOM of that encoding is stripped
+pub struct EncodingDecoder {
+    encoding: FileEncoding,
+    decoder: Decoder,
+    text: String,
+    rope: RopeBuilder,
+    had_errors: bool,
+}
+
+impl EncodingDecoder {
+    pub fn new(encoding: FileEncoding) -> Self {
+        Self {
+            encoding,
+            decoder: encoding.encoding.new_decoder_with_bom_removal(),
+            text: String::new(),
+            rope: RopeBuilder::new(),
+            had_errors: false,
+        }
+    }
+
+    /// Decode the next chunk; `last` marks the end of the file
+    pub fn feed(&mut self, mut bytes: &[u8], last: bool) {
+        loop {
+            self.text.reserve(self.decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len()));
+            let (result, read, had_errors) = self.decoder.decode_to_string(bytes, &mut self.text, last);
+            self.had_errors |= had_errors;
+            bytes = &bytes[read..];
+            self.rope.append(&self.text);
+            self.text.clear();
+            if result == CoderResult::InputEmpty {
+                break;
+            }
+        }
+    }
+
+    /// The decoded rope, its encoding and whether bytes were replaced
+    pub fn finish(self) -> (Rope, FileEncoding, bool) {
+        (self.rope.finish(), self.encoding, self.had_errors)
+    }
+}
+
+/// Whether `bytes` look like binary data rather than text: a NUL byte near
+/// the start of a file without a BOM (UTF-16 text is full of NULs)
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    Encoding::for_bom(bytes).is_none() && bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+/// Read-only hex view of binary data: offset, 16 bytes in hex, printable ASCII
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() / 16 * 78 + 78);
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", row * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => {
+                    let _ = write!(out, " {:02x}", b);
+                }
+                None => out.push_str("   "),
+            }
+            if i == 7 {
+                out.push(' ');
+            }
+        }
+        out.push_str("  |");
+        out.extend(chunk.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+/// Encode `text` for writing to disk, prefixed with a BOM if the encoding has one.
+///
+/// Returns the first character the encoding cannot represent as an error.
+pub fn encode(text: &str, encoding: FileEncoding) -> Result<Vec<u8>, char> {
+    // encoding_rs follows the WHATWG spec, which never encodes into UTF-16
+    let utf16 = |to_bytes: fn(u16) -> [u8; 2]| text.encode_utf16().flat_map(to_bytes).collect::<Vec<u8>>();
+    let (bom, body): (&[u8], Vec<u8>) = if encoding.encoding == UTF_16LE {
+        (b"\xFF\xFE", utf16(u16::to_le_bytes))
+    } else if encoding.encoding == UTF_16BE {
+        (b"\xFE\xFF", utf16(u16::to_be_bytes))
+    } else if encoding.encoding == UTF_8 {
+        (b"\xEF\xBB\xBF", text.as_bytes().to_vec())
+    } else {
+        let mut encoder = encoding.encoding.new_encoder();
+        let mut body = Vec::with_capacity(text.len());
+        let mut rest = text;
+        loop {
+            body.reserve(encoder.max_buffer_length_from_utf8_without_replacement(rest.len()).unwrap_or(rest.len()));
+            let (result, read) = encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut body, true);
+            rest = &rest[read..];
+            match result {
+                encoding_rs::EncoderResult::InputEmpty => break,
+                encoding_rs::EncoderResult::OutputFull => continue,
+                encoding_rs::EncoderResult::Unmappable(c) => return Err(c),
+            }
+        }
+        (b"", body)
+    };
+
+    if encoding.bom && !bom.is_empty() {
+        let mut out = Vec::with_capacity(bom.len() + body.len());
+        out.extend_from_slice(bom);
+        out.extend_from_slice(&body);
+        Ok(out)
+    } else {
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUSSIAN: &str = "// Модуль расчёта заработной платы\nпроцедура Рассчитать(сотрудник)\n    возврат оклад * коэффициент;\n";
+
+    #[test]
+    fn detects_windows_1251_and_round_trips() {
+        let (bytes, _, _) = encoding_rs::WINDOWS_1251.encode(RUSSIAN);
+        let decoded = decode(&bytes);
+        assert_eq!(decoded.encoding.name(), "windows-1251");
+        assert_eq!(decoded.text, RUSSIAN);
+        assert!(!decoded.had_errors);
+        assert_eq!(encode(&decoded.text, decoded.encoding).unwrap(), bytes.as_ref());
+        // Characters outside the code page are reported instead of being lost
+        assert_eq!(encode("символ ✓", decoded.encoding), Err('✓'));
+    }
+
+    #[test]
+    fn bom_is_detected_and_written_back() {
+        for (bom, name) in [(&b"\xEF\xBB\xBF"[..], "UTF-8"), (b"\xFF\xFE", "UTF-16LE"), (b"\xFE\xFF", "UTF-16BE")] {
+            let encoding = FileEncoding { encoding: Encoding::for_label(name.as_bytes()).unwrap(), bom: true };
+            let bytes = encode(RUSSIAN, encoding).unwrap();
+            assert!(bytes.starts_with(bom), "{}", name);
+            let decoded = decode(&bytes);
+            assert_eq!((decoded.encoding.name(), decoded.encoding.bom), (name, true));
+            assert_eq!(decoded.text, RUSSIAN);
+        }
+        assert_eq!(decode(RUSSIAN.as_bytes()).encoding, FileEncoding::default());
+    }
+
+    #[test]
+    fn chunked_decoding_matches_decode() {
+        // Odd chunk size: multi-byte characters are split between chunks
+        fn feed_chunks(bytes: &[u8], mut feed: impl FnMut(&[u8], bool)) {
+            let mut chunks = bytes.chunks(7).peekable();
+            while let Some(chunk) = chunks.next() {
+                feed(chunk, chunks.peek().is_none());
+            }
+        }
+        fn decode_chunks(bytes: &[u8], mut decoder: ChunkDecoder) -> Result<(Rope, FileEncoding, bool), FileEncoding> {
+            feed_chunks(bytes, |chunk, last| decoder.feed(chunk, last));
+            decoder.finish()
+        }
+
+        let utf16 = encode(RUSSIAN, FileEncoding { encoding: UTF_16LE, bom: true }).unwrap();
+        for bytes in [RUSSIAN.as_bytes(), &utf16] {
+            let (text, encoding, had_errors) = decode_chunks(bytes, ChunkDecoder::sniff(bytes)).unwrap();
+            assert_eq!((text.to_string(), encoding, had_errors), (RUSSIAN.to_string(), decode(bytes).encoding, false));
+        }
+
+        // Not UTF-8: the guess comes back, the second pass decodes with it
+        let (cp1251, _, _) = encoding_rs::WINDOWS_1251.encode(RUSSIAN);
+        let guess = decode_chunks(&cp1251, ChunkDecoder::sniff(&cp1251)).unwrap_err();
+        assert_eq!(guess, decode(&cp1251).encoding);
+        let mut decoder = EncodingDecoder::new(guess);
+        feed_chunks(&cp1251, |chunk, last| decoder.feed(chunk, last));
+        let (text, encoding, had_errors) = decoder.finish();
+        assert_eq!((text.to_string(), encoding, had_errors), (RUSSIAN.to_string(), guess, false));
+    }
+
+    #[test]
+    fn binary_content_is_detected() {
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00"));
+        assert!(!looks_binary(&encode(RUSSIAN, FileEncoding { encoding: UTF_16LE, bom: true }).unwrap()));
+        assert!(!looks_binary(RUSSIAN.as_bytes()));
+        assert_eq!(
+            hex_dump(b"\x7fELF\x00 ok"),
+            "00000000  7f 45 4c 46 00 20 6f 6b                           |.ELF. ok|\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! On-disk state of a buffer's file
+//!
+//! A buffer remembers the size, modification time and content hash of its
+//! file as of the last open, save or reload. Before overwriting the file,
+//! the fingerprint tells whether someone else (git, another editor) changed
+//! or deleted it in the meantime. A changed mtime alone (`touch`) is not a change: the
+//! content hash decides.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskFingerprint {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub hash: u64,
+}
+
+impl DiskFingerprint {
+    /// Fingerprint of a file with the given content and metadata
+    pub fn new(bytes: &[u8], meta: &Metadata) -> Self {
+        let mut hasher = ContentHasher::default();
+        hasher.update(bytes);
+        hasher.finish(meta)
+    }
+
+    /// Whether the file at `path` still has the fingerprinted content.
+    /// A file that no longer exists has changed: someone deleted it.
+    pub async fn matches_file(&self, path: &Path) -> io::Result<bool> {
+        let meta = match tokio::fs::metadata(path).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if meta.len() != self.len {
+            return Ok(false);
+        }
+        if meta.modified().ok() == self.modified {
+            return Ok(true);
+        }
+        Ok(content_hash(&tokio::fs::read(path).await?) == self.hash)
+    }
+}
+
+/// Fingerprint of a file read in chunks: the same as `DiskFingerprint::new`
+/// over the concatenated chunks
+#[derive(Default)]
+pub struct ContentHasher {
+    hasher: DefaultHasher,
+    len: u64,
+}
+
+impl ContentHasher {
+    /// Feed the next chunk of the file
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.write(bytes);
+        self.len += bytes.len() as u64;
+    }
+
+    pub fn finish(self, meta: &Metadata) -> DiskFingerprint {
+        DiskFingerprint { len: self.len, modified: meta.modified().ok(), hash: self.hasher.finish() }
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn touch_is_not_a_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "pub mod app;\n").unwrap();
+        let fingerprint = DiskFingerprint::new(b"pub mod app;\n", &std::fs::metadata(&path).unwrap());
+        assert!(fingerprint.matches_file(&path).await.unwrap());
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert!(fingerprint.matches_file(&path).await.unwrap());
+
+        // Same size, different content
+        std::fs::write(&path, "pub mod ui2;\n").unwrap();
+        assert!(!fingerprint.matches_file(&path).await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(!fingerprint.matches_file(&path).await.unwrap());
+    }
+
+    #[test]
+    fn chunked_hash_matches_whole() {
+        let meta = std::fs::metadata(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let bytes: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut hasher = ContentHasher::default();
+        for chunk in bytes.chunks(977) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(&meta), DiskFingerprint::new(&bytes, &meta));
+    }
+}
@@ -0,0 +1,195 @@
+//! Folding ranges from syntax trees
+//!
+//! Each language has a small folds query (`LanguageDefinition::folds_query`):
+//! `@fold` marks nodes that fold on their own (functions, blocks, literals),
+//! `@fold.comment` and `@fold.imports` mark nodes whose runs on consecutive
+//! lines fold together.
+//! Only nodes spanning several lines fold, and of the ranges starting on one
+//! line the outermost is kept.
+
+use std::collections::HashMap;
+
+use atom_ipc::{FoldingKind, FoldingRange};
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Node, Query, QueryCursor, Tree};
+
+use crate::{CoreError, LanguageDefinition};
+
+pub(crate) const RUST_FOLDS: &str = r#"
+[
+  (function_item) (impl_item) (trait_item) (struct_item) (enum_item) (union_item)
+  (mod_item) (macro_definition) (block) (match_block) (closure_expression)
+  (array_expression) (arguments) (parameters) (field_initializer_list) (token_tree)
+] @fold
+[(line_comment) (block_comment)] @fold.comment
+(use_declaration) @fold.imports
+"#;
+
+pub(crate) const JAVASCRIPT_FOLDS: &str = r#"
+[
+  (function_declaration) (function_expression) (arrow_function) (method_definition)
+  (class_declaration) (class_body) (statement_block) (switch_body) (object) (array)
+  (arguments) (formal_parameters) (template_string) (jsx_element)
+] @fold
+(comment) @fold.comment
+(import_statement) @fold.imports
+"#;
+
+pub(crate) const TYPESCRIPT_FOLDS: &str = r#"
+[
+  (function_declaration) (function_expression) (arrow_function) (method_definition)
+  (class_declaration) (class_body) (statement_block) (switch_body) (object) (array)
+  (arguments) (formal_parameters) (template_string) (interface_body) (enum_body)
+  (object_type)
+] @fold
+(comment) @fold.comment
+(import_statement) @fold.imports
+"#;
+
+pub(crate) const PYTHON_FOLDS: &str = r#"
+[
+  (function_definition) (class_definition) (if_statement) (for_statement)
+  (while_statement) (with_statement) (try_statement) (match_statement)
+  (dictionary) (list) (tuple) (set) (argument_list) (parameters) (string)
+] @fold
+(comment) @fold.comment
+[(import_statement) (import_from_statement)] @fold.imports
+"#;
+
+pub(crate) const JSON_FOLDS: &str = r#"
+[(object) (array)] @fold
+(comment) @fold.comment
+"#;
+
+struct FoldsQuery {
+    query: Query,
+    /// Kind per capture index, `None` for captures the folder ignores
+    kinds: Vec<Option<FoldingKind>>,
+}
+
+/// Compiled folds queries, per language
+#[derive(Default)]
+pub struct Folder {
+    queries: HashMap<String, Option<FoldsQuery>>,
+}
+
+impl Folder {
+    /// Folding ranges of a tree, ordered by start line
+    pub fn folding_ranges(&mut self, language: &LanguageDefinition, tree: &Tree) -> Result<Vec<FoldingRange>, CoreError> {
+        let Some(query) = self.query(language, tree)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut folds = Vec::new();
+        // The run of comments or imports being merged
+        let mut run: Option<FoldingRange> = None;
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.captures(&query.query, tree.root_node(), &[] as &[u8]);
+        while let Some((m, index)) = matches.next() {
+            let capture = m.captures[*index];
+            let Some(kind) = query.kinds[capture.index as usize] else {
+                continue;
+            };
+            let (start_line, end_line) = node_lines(capture.node);
+            if kind == FoldingKind::Region {
+                folds.push(FoldingRange { start_line, end_line, kind });
+                continue;
+            }
+            match run.as_mut() {
+                Some(current) if current.kind == kind && start_line <= current.end_line + 1 => {
+                    current.end_line = current.end_line.max(end_line);
+                }
+                _ => folds.extend(run.replace(FoldingRange { start_line, end_line, kind })),
+            }
+        }
+        folds.extend(run);
+
+        folds.retain(|fold| fold.end_line > fold.start_line);
+        // The outermost range of each start line; captures of one node come in pattern order
+        folds.sort_by_key(|fold| (fold.start_line, std::cmp::Reverse(fold.end_line)));
+        folds.dedup_by_key(|fold| fold.start_line);
+        Ok(folds)
+    }
+
+    /// Compiled query of a language, `None` if the language has no folds
+    fn query(&mut self, language: &LanguageDefinition, tree: &Tree) -> Result<Option<&FoldsQuery>, CoreError> {
+        if !self.queries.contains_key(&language.id) {
+            let compiled = match &language.folds_query {
+                Some(source) => {
+                    let query = Query::new(&tree.language(), source).map_err(|e| {
+                        CoreError::ParseError(format!("Invalid {} folds query: {}", language.id, e))
+                    })?;
+                    let kinds = query
+                        .capture_names()
+                        .iter()
+                        .map(|name| match *name {
+                            "fold" => Some(FoldingKind::Region),
+                            "fold.comment" => Some(FoldingKind::Comment),
+                            "fold.imports" => Some(FoldingKind::Imports),
+                            _ => None,
+                        })
+                        .collect();
+                    Some(FoldsQuery { query, kinds })
+                }
+                None => None,
+            };
+            self.queries.insert(language.id.clone(), compiled);
+        }
+        Ok(self.queries[&language.id].as_ref())
+    }
+}
+
+/// First and last line of a node; a node ending with its line break (a line
+/// comment) ends on the line before
+fn node_lines(node: Node) -> (usize, usize) {
+    let start = node.start_position();
+    let end = node.end_position();
+    let end_line = if end.column == 0 && end.row > start.row { end.row - 1 } else { end.row };
+    (start.row, end_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folds(name: &str, source: &str) -> Vec<(usize, usize, FoldingKind)> {
+        let language = crate::LanguageRegistry::default().get(name).unwrap().clone();
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(language.grammar.as_ref().unwrap()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        Folder::default()
+            .folding_ranges(&language, &tree)
+            .unwrap()
+            .into_iter()
+            .map(|fold| (fold.start_line, fold.end_line, fold.kind))
+            .collect()
+    }
+
+    #[test]
+    fn folds_rust_items_comments_and_imports() {
+        let source = "use std::io;\nuse std::fs;\n\n// one\n// two\nfn main() {\n    if true {\n        run();\n    }\n}\n";
+        assert_eq!(
+            folds("rust", source),
+            [
+                (0, 1, FoldingKind::Imports),
+                (3, 4, FoldingKind::Comment),
+                (5, 9, FoldingKind::Region),
+                (6, 8, FoldingKind::Region),
+            ]
+        );
+    }
+
+    #[test]
+    fn folds_python_blocks() {
+        let source = "import os\nimport sys\n\nclass A:\n    def f(self):\n        return 1\n\nx = [\n    1,\n]\n";
+        assert_eq!(
+            folds("python", source),
+            [
+                (0, 1, FoldingKind::Imports),
+                (3, 5, FoldingKind::Region),
+                (4, 5, FoldingKind::Region),
+                (7, 9, FoldingKind::Region),
+            ]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Syntax highlighting from tree-sitter `highlights.scm` queries
+//!
+//! Only the requested lines are queried. Captures are painted outermost
+//! first, so a nested token (an escape inside a string) wins over the node
+//! around it; for the same node the earliest pattern in the query wins. The
+//! result is a list of per-line spans in char columns.
+
+use std::collections::HashMap;
+
+use atom_ipc::{HighlightKind, HighlightSpan};
+use ropey::Rope;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Query, QueryCursor, Tree};
+
+use crate::{line_terminator_len, CoreError, LanguageDefinition};
+
+/// Capture names of highlight queries and the kinds they map to; a capture
+/// takes the longest name that is a dotted prefix of it
+const CAPTURE_KINDS: &[(&str, HighlightKind)] = &[
+    ("attribute", HighlightKind::Attribute),
+    ("comment", HighlightKind::Comment),
+    ("constant", HighlightKind::Constant),
+    ("constant.builtin", HighlightKind::ConstantBuiltin),
+    ("constructor", HighlightKind::Constructor),
+    ("embedded", HighlightKind::Embedded),
+    ("escape", HighlightKind::Escape),
+    ("function", HighlightKind::Function),
+    ("function.builtin", HighlightKind::FunctionBuiltin),
+    ("function.macro", HighlightKind::FunctionMacro),
+    ("function.method", HighlightKind::FunctionMethod),
+    ("keyword", HighlightKind::Keyword),
+    ("label", HighlightKind::Label),
+    ("module", HighlightKind::Module),
+    ("number", HighlightKind::Number),
+    ("operator", HighlightKind::Operator),
+    ("property", HighlightKind::Property),
+    ("punctuation", HighlightKind::Punctuation),
+    ("punctuation.bracket", HighlightKind::PunctuationBracket),
+    ("punctuation.delimiter", HighlightKind::PunctuationDelimiter),
+    ("string", HighlightKind::String),
+    ("string.special", HighlightKind::StringSpecial),
+    ("tag", HighlightKind::Tag),
+    ("type", HighlightKind::Type),
+    ("type.builtin", HighlightKind::TypeBuiltin),
+    ("variable", HighlightKind::Variable),
+    ("variable.builtin", HighlightKind::VariableBuiltin),
+    ("variable.parameter", HighlightKind::VariableParameter),
+];
+
+/// Highlight kind for a query capture name (`function.method.call` → `FunctionMethod`)
+fn capture_kind(name: &str) -> Option<HighlightKind> {
+    CAPTURE_KINDS
+        .iter()
+        .filter(|(prefix, _)| {
+            name.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|&(_, kind)| kind)
+}
+
+struct HighlightQuery {
+    query: Query,
+    /// Kind per capture index
+    kinds: Vec<Option<HighlightKind>>,
+}
+
+/// Compiled highlight queries, per language
+#[derive(Default)]
+pub struct Highlighter {
+    queries: HashMap<String, Option<HighlightQuery>>,
+}
+
+impl Highlighter {
+    /// Highlight spans for lines `start_line..end_line` of `text`, parsed as `tree`
+    pub fn highlight(
+        &mut self,
+        language: &LanguageDefinition,
+        text: &Rope,
+        tree: &Tree,
+        start_line: usize,
+        end_line: usize,
+    ) -> Result<Vec<HighlightSpan>, CoreError> {
+        let Some(query) = self.query(language, tree)? else {
+            return Ok(Vec::new());
+        };
+        let end_line = end_line.min(text.len_lines());
+        if start_line >= end_line {
+            return Ok(Vec::new());
+        }
+        let start_byte = text.line_to_byte(start_line);
+        let end_byte = if end_line < text.len_lines() { text.line_to_byte(end_line) } else { text.len_bytes() };
+
+        // (start, end, pattern, kind) of every capture touching the range
+        let mut captures = Vec::new();
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(start_byte..end_byte);
+        let node_text = |node: tree_sitter::Node| text.byte_slice(node.byte_range()).chunks().map(str::as_bytes);
+        let mut matches = cursor.captures(&query.query, tree.root_node(), node_text);
+        while let Some((m, index)) = matches.next() {
+            let capture = m.captures[*index];
+            if let Some(kind) = query.kinds[capture.index as usize] {
+                let range = capture.node.byte_range();
+                captures.push((range.start.max(start_byte), range.end.min(end_byte), m.pattern_index, kind));
+            }
+        }
+        // Outer nodes before the nodes inside them; for one node, the first pattern
+        captures.sort_by_key(|&(start, end, pattern, _)| (start, std::cmp::Reverse(end), pattern));
+        captures.dedup_by_key(|&mut (start, end, _, _)| (start, end));
+
+        let mut painted = vec![None; end_byte - start_byte];
+        for (start, end, _, kind) in captures {
+            if start < end {
+                painted[start - start_byte..end - start_byte].fill(Some(kind));
+            }
+        }
+
+        let mut spans = Vec::new();
+        for line_idx in start_line..end_line {
+            let line = text.line(line_idx);
+            let line_byte = text.line_to_byte(line_idx);
+            let content_bytes = line.len_bytes() - line_terminator_len(&line);
+            let row = &painted[line_byte - start_byte..line_byte - start_byte + content_bytes];
+            let mut from = 0;
+            while from < row.len() {
+                let kind = row[from];
+                let to = row[from..].iter().position(|k| *k != kind).map_or(row.len(), |n| from + n);
+                if let Some(kind) = kind {
+                    let start_column = line.byte_to_char(from);
+                    spans.push(HighlightSpan {
+                        line: line_idx as u32,
+                        start_column: start_column as u32,
+                        length: (line.byte_to_char(to) - start_column) as u32,
+                        kind,
+                    });
+                }
+                from = to;
+            }
+        }
+        Ok(spans)
+    }
+
+    /// Compiled query of a language, `None` if the language has no highlights
+    fn query(&mut self, language: &LanguageDefinition, tree: &Tree) -> Result<Option<&HighlightQuery>, CoreError> {
+        if !self.queries.contains_key(&language.id) {
+            let compiled = match &language.highlights_query {
+                Some(source) => {
+                    let query = Query::new(&tree.language(), source).map_err(|e| {
+                        CoreError::ParseError(format!("Invalid {} highlight query: {}", language.id, e))
+                    })?;
+                    let kinds = query.capture_names().iter().map(|name| capture_kind(name)).collect();
+                    Some(HighlightQuery { query, kinds })
+                }
+                None => None,
+            };
+            self.queries.insert(language.id.clone(), compiled);
+        }
+        Ok(self.queries[&language.id].as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(name: &str, source: &str, lines: std::ops::Range<usize>) -> Vec<(u32, String, HighlightKind)> {
+        let language = crate::LanguageRegistry::default().get(name).unwrap().clone();
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(language.grammar.as_ref().unwrap()).unwrap();
+        let text = Rope::from_str(source);
+        let tree = crate::syntax::parse_rope(&mut parser, &text, None).unwrap();
+        Highlighter::default()
+            .highlight(&language, &text, &tree, lines.start, lines.end)
+            .unwrap()
+            .into_iter()
+            .map(|span| {
+                let line = text.line(span.line as usize);
+                let token = line.slice(span.start_column as usize..(span.start_column + span.length) as usize);
+                (span.line, token.to_string(), span.kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn maps_capture_names() {
+        assert_eq!(capture_kind("function.method.call"), Some(HighlightKind::FunctionMethod));
+        assert_eq!(capture_kind("function.call"), Some(HighlightKind::Function));
+        assert_eq!(capture_kind("keywordish"), None);
+        assert_eq!(capture_kind("local.definition"), None);
+    }
+
+    #[test]
+    fn highlights_requested_lines_only() {
+        let source = "// первая\nfn main() {\n    let s = \"a\\n\";\n}\n";
+        let got = spans("rust", source, 1..3);
+        assert!(got.iter().all(|(line, _, _)| (1..3).contains(line)), "{:?}", got);
+        assert!(got.contains(&(1, "fn".into(), HighlightKind::Keyword)), "{:?}", got);
+        assert!(got.contains(&(1, "main".into(), HighlightKind::Function)), "{:?}", got);
+        // The escape splits the string around it
+        assert!(got.contains(&(2, "\\n".into(), HighlightKind::Escape)), "{:?}", got);
+        assert!(got.contains(&(2, "\"a".into(), HighlightKind::String)), "{:?}", got);
+
+        // Char columns after non-ASCII text
+        assert_eq!(spans("rust", source, 0..1), [(0, "// первая".into(), HighlightKind::Comment)]);
+    }
+}
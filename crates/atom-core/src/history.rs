@@ -0,0 +1,341 @@
+//! Undo tree for text buffers
+//!
+//! Every change to a buffer is recorded as a list of replacements in char
+//! offsets. Changes made within the grouping window of each other form one
+//! undo step. Undoing and then editing starts a new branch; the old branch
+//! stays in the tree, and `redo` follows the most recently visited child.
+//!
+//! The tree is capped by step count and by the size of the recorded text.
+//! Past a cap, whole branches off the current line (its undo and redo steps)
+//! go first, oldest first, then the oldest steps of the line itself.
+
+use std::time::{Duration, Instant};
+
+/// Default cap on undo steps per buffer
+const DEFAULT_MAX_STEPS: usize = 10_000;
+/// Default cap on text recorded in undo steps per buffer (bytes)
+const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// One replacement: `old_text` at char offset `start` was replaced by `new_text`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub start: usize,
+    pub old_text: String,
+    pub new_text: String,
+}
+
+impl Change {
+    /// Replacement that reverts this one
+    pub fn inverse(&self) -> Change {
+        Change { start: self.start, old_text: self.new_text.clone(), new_text: self.old_text.clone() }
+    }
+
+    fn size(&self) -> usize {
+        self.old_text.len() + self.new_text.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    parent: usize,
+    /// Children in visiting order: the last one is the redo target
+    children: Vec<usize>,
+    /// Changes in the order they were applied
+    changes: Vec<Change>,
+    last_change: Instant,
+}
+
+impl Node {
+    fn size(&self) -> usize {
+        self.changes.iter().map(Change::size).sum()
+    }
+}
+
+/// Buffer edit history
+#[derive(Debug, Clone)]
+pub struct History {
+    /// Node 0 is the root (the state the buffer was opened with)
+    nodes: Vec<Node>,
+    current: usize,
+    group_window: Duration,
+    /// The current step is closed for grouping (after undo/redo or save)
+    sealed: bool,
+    /// Node matching the content on disk, unless it was pruned
+    saved: Option<usize>,
+    /// Text recorded in all nodes (bytes)
+    size: usize,
+    /// Caps on steps (nodes besides the root) and on `size`
+    max_steps: usize,
+    max_bytes: usize,
+}
+
+impl History {
+    pub fn new(group_window: Duration) -> Self {
+        let root = Node { parent: 0, children: Vec::new(), changes: Vec::new(), last_change: Instant::now() };
+        Self {
+            nodes: vec![root],
+            current: 0,
+            group_window,
+            sealed: true,
+            saved: Some(0),
+            size: 0,
+            max_steps: DEFAULT_MAX_STEPS,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// The same history capped at `max_steps` steps and `max_bytes` of recorded text
+    pub fn with_limits(mut self, max_steps: usize, max_bytes: usize) -> Self {
+        self.max_steps = max_steps;
+        self.max_bytes = max_bytes;
+        self.prune();
+        self
+    }
+
+    /// Record changes applied at `now`, joining the current step if it is
+    /// still open and the previous change happened within the grouping window
+    pub fn record(&mut self, changes: Vec<Change>, now: Instant) {
+        if changes.is_empty() {
+            return;
+        }
+        let node = &mut self.nodes[self.current];
+        let joins = !self.sealed
+            && self.current != 0
+            && node.children.is_empty()
+            && now.saturating_duration_since(node.last_change) <= self.group_window;
+        self.size += changes.iter().map(Change::size).sum::<usize>();
+        if joins {
+            node.changes.extend(changes);
+            node.last_change = now;
+        } else {
+            let id = self.nodes.len();
+            self.nodes.push(Node { parent: self.current, children: Vec::new(), changes, last_change: now });
+            self.nodes[self.current].children.push(id);
+            self.current = id;
+            self.sealed = false;
+        }
+        self.prune();
+    }
+
+    /// Close the current step: the next change starts a new one
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Remember the current state as the one written to disk
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+        self.sealed = true;
+    }
+
+    /// Whether the buffer content matches the last saved state
+    pub fn is_at_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    /// Changes that revert the current step, in application order
+    pub fn undo(&mut self) -> Option<Vec<Change>> {
+        if self.current == 0 {
+            return None;
+        }
+        let node = &self.nodes[self.current];
+        let changes = node.changes.iter().rev().map(Change::inverse).collect();
+        self.current = node.parent;
+        self.sealed = true;
+        Some(changes)
+    }
+
+    /// Changes that re-apply the most recently undone step
+    pub fn redo(&mut self) -> Option<Vec<Change>> {
+        let next = *self.nodes[self.current].children.last()?;
+        self.current = next;
+        self.sealed = true;
+        Some(self.nodes[next].changes.clone())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current != 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.nodes[self.current].children.is_empty()
+    }
+
+    fn within_limits(&self, nodes: usize, size: usize) -> bool {
+        nodes - 1 <= self.max_steps && size <= self.max_bytes
+    }
+
+    /// Drop steps past the caps: branches off the current line first, oldest
+    /// first, then the oldest steps of the line. The current step is kept,
+    /// but becomes the root (cannot be undone) if it alone is over the caps.
+    fn prune(&mut self) {
+        if self.within_limits(self.nodes.len(), self.size) {
+            return;
+        }
+        // The line: ancestors of the current node and its redo chain
+        let mut on_line = vec![false; self.nodes.len()];
+        let mut id = self.current;
+        while id != 0 {
+            on_line[id] = true;
+            id = self.nodes[id].parent;
+        }
+        on_line[0] = true;
+        let mut id = self.current;
+        while let Some(&next) = self.nodes[id].children.last() {
+            on_line[next] = true;
+            id = next;
+        }
+
+        let mut removed = vec![false; self.nodes.len()];
+        let (mut nodes, mut size) = (self.nodes.len(), self.size);
+        // Node ids grow with time: lower ids are older branches
+        for branch in 1..self.nodes.len() {
+            if self.within_limits(nodes, size) {
+                break;
+            }
+            if on_line[branch] || removed[branch] {
+                continue;
+            }
+            let mut subtree = vec![branch];
+            while let Some(id) = subtree.pop() {
+                removed[id] = true;
+                nodes -= 1;
+                size -= self.nodes[id].size();
+                subtree.extend(&self.nodes[id].children);
+            }
+        }
+
+        // Only the line is left: its oldest step becomes the new root
+        let mut root = 0;
+        while !self.within_limits(nodes, size) && root != self.current {
+            let next = self.nodes[root]
+                .children
+                .iter()
+                .copied()
+                .find(|&child| on_line[child] && !removed[child])
+                .expect("the current node descends from the root");
+            removed[root] = true;
+            nodes -= 1;
+            size -= self.nodes[next].size();
+            root = next;
+        }
+        if root != 0 {
+            self.nodes[root].changes.clear();
+        }
+        self.compact(&removed, root);
+        self.size = size;
+    }
+
+    /// Drop `removed` nodes and renumber the rest, `root` first
+    fn compact(&mut self, removed: &[bool], root: usize) {
+        let kept: Vec<usize> =
+            std::iter::once(root).chain((0..self.nodes.len()).filter(|&id| id != root && !removed[id])).collect();
+        let mut renumbered = vec![usize::MAX; self.nodes.len()];
+        for (new, &old) in kept.iter().enumerate() {
+            renumbered[old] = new;
+        }
+        let mut old_nodes: Vec<Option<Node>> = std::mem::take(&mut self.nodes).into_iter().map(Some).collect();
+        self.nodes = kept
+            .iter()
+            .map(|&old| {
+                let mut node = old_nodes[old].take().expect("each node is kept once");
+                node.parent = if old == root { 0 } else { renumbered[node.parent] };
+                node.children.retain(|&child| !removed[child]);
+                for child in &mut node.children {
+                    *child = renumbered[*child];
+                }
+                node
+            })
+            .collect();
+        self.current = renumbered[self.current];
+        self.saved = self.saved.map(|saved| renumbered[saved]).filter(|&saved| saved != usize::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(start: usize, text: &str) -> Vec<Change> {
+        vec![Change { start, old_text: String::new(), new_text: text.into() }]
+    }
+
+    #[test]
+    fn groups_within_window_and_branches_after_undo() {
+        let t0 = Instant::now();
+        let mut history = History::new(Duration::from_millis(500));
+        history.record(insert(0, "a"), t0);
+        history.record(insert(1, "b"), t0 + Duration::from_millis(100));
+        // Outside the window: a new step
+        history.record(insert(2, "c"), t0 + Duration::from_secs(2));
+
+        assert_eq!(history.undo().unwrap(), vec![insert(2, "c")[0].inverse()]);
+        let undone = history.undo().unwrap();
+        assert_eq!(undone.iter().map(|c| c.start).collect::<Vec<_>>(), [1, 0]);
+        assert!(!history.can_undo());
+
+        assert_eq!(history.redo().unwrap().len(), 2);
+        // A new edit after undo branches off and becomes the redo target
+        history.record(insert(2, "d"), t0 + Duration::from_secs(2));
+        assert!(!history.can_redo());
+        history.undo().unwrap();
+        assert_eq!(history.redo().unwrap(), insert(2, "d"));
+    }
+
+    fn step(history: &mut History, changes: Vec<Change>) {
+        history.record(changes, Instant::now());
+        history.seal();
+    }
+
+    #[test]
+    fn prunes_old_branches_then_old_steps() {
+        let mut history = History::new(Duration::ZERO).with_limits(3, usize::MAX);
+        step(&mut history, insert(0, "a"));
+        step(&mut history, insert(1, "b"));
+        history.undo().unwrap();
+        history.mark_saved();
+        // "b" is now a branch off the line and goes first
+        step(&mut history, insert(1, "c"));
+        step(&mut history, insert(2, "d"));
+        assert_eq!(history.nodes.len(), 4);
+        // Then the oldest step of the line: "a" can no longer be undone
+        step(&mut history, insert(3, "e"));
+        assert_eq!(history.nodes.len(), 4);
+        assert_eq!(history.undo().unwrap(), vec![insert(3, "e")[0].inverse()]);
+        history.undo().unwrap();
+        history.undo().unwrap();
+        assert!(!history.can_undo());
+        assert!(history.is_at_saved(), "the saved state survives as the root");
+        assert_eq!(history.redo().unwrap(), insert(1, "c"));
+        assert_eq!(history.redo().unwrap(), insert(2, "d"));
+        assert_eq!(history.redo().unwrap(), insert(3, "e"));
+    }
+
+    #[test]
+    fn caps_recorded_text() {
+        let mut history = History::new(Duration::ZERO).with_limits(usize::MAX, 10);
+        step(&mut history, insert(0, "12345"));
+        history.mark_saved();
+        step(&mut history, insert(5, "678"));
+        assert_eq!(history.size, 8);
+        // A replacement larger than the cap keeps only the current state
+        step(&mut history, vec![Change { start: 0, old_text: "12345678".into(), new_text: "x".repeat(20) }]);
+        assert_eq!((history.nodes.len(), history.size), (1, 0));
+        assert!(!history.can_undo());
+        assert!(!history.is_at_saved());
+        step(&mut history, insert(20, "y"));
+        assert_eq!(history.undo().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn seal_stops_grouping() {
+        let t0 = Instant::now();
+        let mut history = History::new(Duration::from_secs(60));
+        history.record(insert(0, "a"), t0);
+        history.seal();
+        history.record(insert(1, "b"), t0);
+        assert_eq!(history.undo().unwrap().len(), 1);
+        assert_eq!(history.undo().unwrap().len(), 1);
+        assert!(history.undo().is_none());
+    }
+}
@@ -0,0 +1,396 @@
+//! Language registry
+//!
+//! Everything atom-core knows about a language lives in one
+//! `LanguageDefinition`: how files are recognised, the tree-sitter grammar and
+//! queries, comment tokens (for `ToggleComment`) and the LSP language id
+//! (which picks the language server). Languages without a
+//! grammar are still recognised, they are just not parsed. The built-in
+//! definitions can be extended from settings (`[[languages]]`) and replaced
+//! or added at runtime by plugins.
+//!
+//! A file's language is decided by, in order: a vim or emacs modeline, the
+//! file name, the extension, and the `#!` interpreter.
+
+use std::path::Path;
+
+use ropey::Rope;
+use tree_sitter::Language;
+
+use crate::{folding, symbols};
+
+/// Lines at the start and end of a file searched for modelines (as in vim)
+const MODELINE_LINES: usize = 5;
+
+/// Everything known about one language
+#[derive(Debug, Clone, Default)]
+pub struct LanguageDefinition {
+    /// Language id stored in buffers (`rust`, `python`)
+    pub id: String,
+    /// Other names used in modelines (`js` for `javascript`)
+    pub aliases: Vec<String>,
+    /// File extensions without the dot, matched case-insensitively
+    pub extensions: Vec<String>,
+    /// Whole file names (`Makefile`, `Cargo.lock`)
+    pub file_names: Vec<String>,
+    /// Interpreters of `#!` lines (`python3`, `node`)
+    pub shebangs: Vec<String>,
+    /// Tree-sitter grammar; without one buffers are not parsed
+    pub grammar: Option<Language>,
+    /// `highlights.scm` query
+    pub highlights_query: Option<String>,
+    /// Folds query (`@fold`, `@fold.comment`, `@fold.imports`)
+    pub folds_query: Option<String>,
+    /// `tags.scm` query
+    pub tags_query: Option<String>,
+    /// Line comment token (`//`)
+    pub line_comment: Option<String>,
+    /// Block comment tokens (`/*`, `*/`)
+    pub block_comment: Option<(String, String)>,
+    /// Language id for LSP `textDocument/didOpen`, also the key of the
+    /// language's server in `LspManager`
+    pub lsp_id: String,
+}
+
+impl LanguageDefinition {
+    /// Definition with only an id (also used as the LSP id)
+    pub fn new(id: impl Into<String>) -> Self {
+        let id = id.into();
+        Self { lsp_id: id.clone(), id, ..Self::default() }
+    }
+
+    /// Whether `name` is the id or an alias of this language
+    fn is_named(&self, name: &str) -> bool {
+        self.id.eq_ignore_ascii_case(name) || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    /// Apply a language entry from settings: its file patterns are added,
+    /// the tokens and LSP id it sets replace the current ones
+    fn configure(&mut self, config: &atom_settings::LanguageSettings) {
+        self.extensions.extend(config.extensions.iter().cloned());
+        self.file_names.extend(config.file_names.iter().cloned());
+        self.shebangs.extend(config.shebangs.iter().cloned());
+        if config.line_comment.is_some() {
+            self.line_comment = config.line_comment.clone();
+        }
+        if config.block_comment.is_some() {
+            self.block_comment = config.block_comment.clone();
+        }
+        if let Some(lsp_id) = &config.lsp_id {
+            self.lsp_id = lsp_id.clone();
+        }
+    }
+}
+
+/// Known languages and the files they are used for
+#[derive(Debug, Clone)]
+pub struct LanguageRegistry {
+    languages: Vec<LanguageDefinition>,
+}
+
+impl Default for LanguageRegistry {
+    /// Registry with the built-in languages
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        for definition in builtin_languages() {
+            registry.register(definition);
+        }
+        registry
+    }
+}
+
+impl LanguageRegistry {
+    /// Registry without any language
+    pub fn empty() -> Self {
+        Self { languages: Vec::new() }
+    }
+
+    /// Built-in languages extended by the `languages` section of settings
+    pub fn with_settings(settings: &atom_settings::Settings) -> Self {
+        let mut registry = Self::default();
+        registry.apply_settings(&settings.languages);
+        registry
+    }
+
+    /// Add a language, replacing any language with the same id. Its file
+    /// patterns take precedence over those of earlier languages.
+    pub fn register(&mut self, definition: LanguageDefinition) {
+        self.languages.retain(|language| language.id != definition.id);
+        self.languages.push(definition);
+    }
+
+    /// Extend known languages, or add grammar-less ones, from settings
+    pub fn apply_settings(&mut self, configs: &[atom_settings::LanguageSettings]) {
+        for config in configs {
+            let mut definition = match self.languages.iter().position(|language| language.id == config.id) {
+                Some(index) => self.languages.remove(index),
+                None => LanguageDefinition::new(&config.id),
+            };
+            definition.configure(config);
+            self.languages.push(definition);
+        }
+    }
+
+    /// Language with the given id
+    pub fn get(&self, id: &str) -> Option<&LanguageDefinition> {
+        self.languages.iter().find(|language| language.id == id)
+    }
+
+    /// Language with the given id or alias
+    pub fn find(&self, name: &str) -> Option<&LanguageDefinition> {
+        self.languages.iter().rev().find(|language| language.is_named(name))
+    }
+
+    /// Ids of all registered languages
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.languages.iter().map(|language| language.id.as_str())
+    }
+
+    /// Language of the file at `path` with content `text`
+    pub fn detect(&self, path: &Path, text: &Rope) -> Option<&LanguageDefinition> {
+        if let Some(language) = modeline(text).and_then(|name| self.find(&name)) {
+            return Some(language);
+        }
+        // Later registrations win
+        let languages = || self.languages.iter().rev();
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            if let Some(language) = languages().find(|language| language.file_names.iter().any(|n| n == name)) {
+                return Some(language);
+            }
+        }
+        if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+            let matches = |language: &&LanguageDefinition| {
+                language.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension))
+            };
+            if let Some(language) = languages().find(matches) {
+                return Some(language);
+            }
+        }
+        let interpreter = shebang_interpreter(text)?;
+        // `python3.12` is also a `python`
+        let versionless = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        let runs = |language: &&LanguageDefinition| {
+            language.shebangs.iter().any(|name| name == &interpreter || name == versionless)
+        };
+        languages().find(runs)
+    }
+}
+
+/// Interpreter named by the `#!` line of `text`, without its directory
+/// (`#!/usr/bin/env -S python3 -u` → `python3`)
+fn shebang_interpreter(text: &Rope) -> Option<String> {
+    let first = text.lines().next()?.to_string();
+    let mut words = first.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    let interpreter = if program == "env" { words.find(|word| !word.starts_with('-'))? } else { program };
+    Some(interpreter.to_string())
+}
+
+/// Language named by a vim (`vim: set ft=python:`) or emacs
+/// (`-*- mode: python -*-`) modeline near the start or end of `text`
+fn modeline(text: &Rope) -> Option<String> {
+    let count = text.len_lines();
+    let head = 0..count.min(MODELINE_LINES);
+    let tail = count.saturating_sub(MODELINE_LINES).max(head.end)..count;
+    head.chain(tail).find_map(|index| {
+        let line = text.line(index).to_string();
+        emacs_mode(&line).or_else(|| vim_filetype(&line))
+    })
+}
+
+fn emacs_mode(line: &str) -> Option<String> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (inner, _) = rest.split_once("-*-")?;
+    if !inner.contains(':') {
+        return Some(inner.trim().to_lowercase()).filter(|mode| !mode.is_empty());
+    }
+    inner.split(';').find_map(|variable| {
+        let (key, value) = variable.split_once(':')?;
+        key.trim().eq_ignore_ascii_case("mode").then(|| value.trim().to_lowercase())
+    })
+}
+
+fn vim_filetype(line: &str) -> Option<String> {
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(index, _)| *index == 0 || line[..*index].ends_with(char::is_whitespace))
+            .map(|(index, _)| index + marker.len())
+    })?;
+    line[start..].split(|c: char| c == ':' || c.is_whitespace()).find_map(|option| {
+        let (key, value) = option.split_once('=')?;
+        matches!(key, "ft" | "filetype").then(|| value.to_lowercase()).filter(|value| !value.is_empty())
+    })
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+/// Definition of a language without a grammar
+fn plain(id: &str, extensions: &[&str], line_comment: Option<&str>, block_comment: Option<(&str, &str)>) -> LanguageDefinition {
+    LanguageDefinition {
+        extensions: strings(extensions),
+        line_comment: line_comment.map(str::to_string),
+        block_comment: block_comment.map(|(open, close)| (open.to_string(), close.to_string())),
+        ..LanguageDefinition::new(id)
+    }
+}
+
+fn builtin_languages() -> Vec<LanguageDefinition> {
+    let c_line = Some("//");
+    let c_block = Some(("/*", "*/"));
+    let markup_block = Some(("<!--", "-->"));
+    let with_tokens = |definition: LanguageDefinition| LanguageDefinition {
+        line_comment: c_line.map(str::to_string),
+        block_comment: c_block.map(|(open, close)| (open.to_string(), close.to_string())),
+        ..definition
+    };
+
+    vec![
+        with_tokens(LanguageDefinition {
+            aliases: strings(&["rs"]),
+            extensions: strings(&["rs"]),
+            grammar: Some(tree_sitter_rust::LANGUAGE.into()),
+            highlights_query: Some(tree_sitter_rust::HIGHLIGHTS_QUERY.to_string()),
+            folds_query: Some(folding::RUST_FOLDS.to_string()),
+            tags_query: Some(format!("{}\n{}", symbols::RUST_IMPL_TAGS, tree_sitter_rust::TAGS_QUERY)),
+            ..LanguageDefinition::new("rust")
+        }),
+        with_tokens(LanguageDefinition {
+            aliases: strings(&["js", "javascriptreact"]),
+            extensions: strings(&["js", "jsx", "mjs", "cjs"]),
+            shebangs: strings(&["node", "nodejs"]),
+            grammar: Some(tree_sitter_javascript::LANGUAGE.into()),
+            highlights_query: Some(format!(
+                "{}\n{}",
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY
+            )),
+            folds_query: Some(folding::JAVASCRIPT_FOLDS.to_string()),
+            tags_query: Some(tree_sitter_javascript::TAGS_QUERY.to_string()),
+            ..LanguageDefinition::new("javascript")
+        }),
+        // The TypeScript queries only add to the JavaScript ones, and go first to take precedence
+        with_tokens(LanguageDefinition {
+            aliases: strings(&["ts"]),
+            extensions: strings(&["ts", "tsx", "mts", "cts"]),
+            shebangs: strings(&["ts-node", "deno"]),
+            grammar: Some(tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into()),
+            highlights_query: Some(format!(
+                "{}\n{}",
+                tree_sitter_typescript::HIGHLIGHTS_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY
+            )),
+            folds_query: Some(folding::TYPESCRIPT_FOLDS.to_string()),
+            tags_query: Some(format!(
+                "{}\n{}",
+                tree_sitter_typescript::TAGS_QUERY,
+                tree_sitter_javascript::TAGS_QUERY
+            )),
+            ..LanguageDefinition::new("typescript")
+        }),
+        LanguageDefinition {
+            aliases: strings(&["py"]),
+            extensions: strings(&["py", "pyi", "pyw"]),
+            file_names: strings(&["SConstruct", "SConscript"]),
+            shebangs: strings(&["python", "python3", "pypy", "pypy3"]),
+            grammar: Some(tree_sitter_python::LANGUAGE.into()),
+            highlights_query: Some(tree_sitter_python::HIGHLIGHTS_QUERY.to_string()),
+            folds_query: Some(folding::PYTHON_FOLDS.to_string()),
+            tags_query: Some(tree_sitter_python::TAGS_QUERY.to_string()),
+            line_comment: Some("#".to_string()),
+            ..LanguageDefinition::new("python")
+        },
+        LanguageDefinition {
+            extensions: strings(&["json"]),
+            file_names: strings(&[".babelrc", "composer.lock"]),
+            grammar: Some(tree_sitter_json::LANGUAGE.into()),
+            highlights_query: Some(tree_sitter_json::HIGHLIGHTS_QUERY.to_string()),
+            folds_query: Some(folding::JSON_FOLDS.to_string()),
+            ..LanguageDefinition::new("json")
+        },
+        plain("go", &["go"], c_line, c_block),
+        plain("c", &["c", "h"], c_line, c_block),
+        plain("cpp", &["cpp", "cxx", "cc", "hpp", "hxx", "hh"], c_line, c_block),
+        plain("java", &["java"], c_line, c_block),
+        LanguageDefinition { file_names: strings(&["Cargo.lock", "Pipfile"]), ..plain("toml", &["toml"], Some("#"), None) },
+        plain("yaml", &["yaml", "yml"], Some("#"), None),
+        plain("html", &["html", "htm"], None, markup_block),
+        plain("css", &["css"], None, c_block),
+        LanguageDefinition { aliases: strings(&["md"]), ..plain("markdown", &["md", "markdown"], None, markup_block) },
+        LanguageDefinition {
+            shebangs: strings(&["sh", "bash", "zsh", "dash"]),
+            file_names: strings(&[".bashrc", ".bash_profile", ".zshrc", ".profile"]),
+            aliases: strings(&["sh", "bash"]),
+            ..plain("shellscript", &["sh", "bash", "zsh"], Some("#"), None)
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(path: &str, text: &str) -> Option<String> {
+        LanguageRegistry::default().detect(Path::new(path), &Rope::from_str(text)).map(|language| language.id.clone())
+    }
+
+    #[test]
+    fn detects_by_name_extension_shebang_and_modeline() {
+        assert_eq!(detect("src/Main.RS", ""), Some("rust".into()));
+        assert_eq!(detect("Cargo.lock", ""), Some("toml".into()));
+        assert_eq!(detect("bin/tool", "#!/usr/bin/env -S python3.12 -u\nprint()\n"), Some("python".into()));
+        assert_eq!(detect("bin/run", "#!/usr/local/bin/node\n"), Some("javascript".into()));
+        assert_eq!(detect("notes", "plain text\n"), None);
+
+        // Modelines win over the extension
+        assert_eq!(detect("build.txt", "# vim: set ft=python ts=4:\n"), Some("python".into()));
+        assert_eq!(detect("x.conf", "// -*- mode: js; indent-tabs-mode: nil -*-\n"), Some("javascript".into()));
+        let long = format!("{}\n// vi: filetype=rust\n", "line\n".repeat(20));
+        assert_eq!(detect("a.h", &long), Some("rust".into()));
+        // Not a modeline: `vim:` inside a word
+        assert_eq!(detect("a.txt", "navim:ft=python\n"), None);
+    }
+
+    #[test]
+    fn settings_extend_and_add_languages() {
+        let settings = atom_settings::Settings {
+            languages: vec![
+                atom_settings::LanguageSettings {
+                    id: "python".into(),
+                    extensions: vec!["pyx".into()],
+                    line_comment: Some("##".into()),
+                    lsp_id: Some("cython".into()),
+                    ..Default::default()
+                },
+                atom_settings::LanguageSettings {
+                    id: "terraform".into(),
+                    extensions: vec!["tf".into()],
+                    line_comment: Some("#".into()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let registry = LanguageRegistry::with_settings(&settings);
+        let python = registry.detect(Path::new("fast.pyx"), &Rope::new()).unwrap();
+        assert_eq!((python.id.as_str(), python.line_comment.as_deref()), ("python", Some("##")));
+        assert_eq!(python.lsp_id, "cython");
+        assert!(python.grammar.is_some());
+        let terraform = registry.detect(Path::new("main.tf"), &Rope::new()).unwrap();
+        assert_eq!((terraform.line_comment.as_deref(), terraform.grammar.is_none()), (Some("#"), true));
+        assert_eq!(terraform.lsp_id, "terraform");
+    }
+
+    #[test]
+    fn builtin_queries_compile() {
+        let registry = LanguageRegistry::default();
+        for language in registry.languages.iter() {
+            let Some(grammar) = &language.grammar else { continue };
+            for query in [&language.highlights_query, &language.folds_query, &language.tags_query].into_iter().flatten() {
+                if let Err(e) = tree_sitter::Query::new(grammar, query) {
+                    panic!("{}: {}", language.id, e);
+                }
+            }
+        }
+    }
+}
@@ -3,12 +3,43 @@
 //! This crate provides core functionality for Atom IDE including
 //! text buffer management, syntax parsing with tree-sitter, and configuration.
 
+use atom_ipc::{BufferMode, ErrorCode, ErrorDetail};
 use ropey::Rope;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::fs;
-use tree_sitter::{Language, Parser, Tree};
+use tree_sitter::{Parser, Tree};
+
+mod cursors;
+mod encoding;
+mod fingerprint;
+mod folding;
+mod highlight;
+mod history;
+mod languages;
+mod position_encoding;
+mod save;
+mod save_pipeline;
+mod selection;
+mod symbols;
+mod syntax;
+
+pub use cursors::{Selection, SelectionSet};
+pub use encoding::FileEncoding;
+pub use fingerprint::DiskFingerprint;
+use fingerprint::ContentHasher;
+use encoding::{ChunkDecoder, EncodingDecoder};
+pub use folding::Folder;
+pub use highlight::Highlighter;
+pub use history::{Change, History};
+pub use languages::{LanguageDefinition, LanguageRegistry};
+pub use position_encoding::PositionEncoding;
+pub use save_pipeline::{
+    InsertFinalNewline, NormalizeLineEndings, SaveOptions, SavePipeline, SaveTransform, TrimTrailingWhitespace,
+};
+pub use symbols::SymbolExtractor;
 
 /// Core errors
 #[derive(Debug, thiserror::Error)]
@@ -25,6 +56,82 @@ pub enum CoreError {
     UnsupportedLanguage(String),
     #[error("Settings error: {0}")]
     SettingsError(#[from] atom_settings::SettingsError),
+    #[error("Path traversal detected: {} is outside workspace {}", path.display(), workspace.display())]
+    PathOutsideWorkspace { path: PathBuf, workspace: PathBuf },
+    #[error("Cannot write to {}: {source}", path.display())]
+    WriteFailed {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Buffer {buffer_id} is at version {actual}, edits are based on version {expected}")]
+    VersionConflict {
+        buffer_id: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Invalid edit: {0}")]
+    InvalidEdit(String),
+    #[error("Unknown encoding: {0}")]
+    UnknownEncoding(String),
+    #[error("Character {character:?} cannot be saved in {encoding}")]
+    Unencodable { encoding: String, character: char },
+    #[error("Buffer is read-only: {0}")]
+    ReadOnly(String),
+    #[error("Binary file is too large to open: {}", .0.display())]
+    BinaryFile(PathBuf),
+    #[error("{} was modified on disk since it was opened", path.display())]
+    ModifiedOnDisk { path: PathBuf },
+    #[error("Buffer has unsaved changes: {0}")]
+    UnsavedChanges(String),
+    #[error("Buffer has no file: {0}")]
+    NoFile(String),
+}
+
+impl CoreError {
+    /// IPC error class for `CoreResponse::Error`
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            CoreError::IoError(e) | CoreError::WriteFailed { source: e, .. } => {
+                ErrorCode::from_io_kind(e.kind())
+            }
+            CoreError::IoErrorString(_) => ErrorCode::Io,
+            CoreError::BufferNotFound(_) => ErrorCode::NotFound,
+            CoreError::UnsupportedLanguage(_) => ErrorCode::Unsupported,
+            CoreError::PathOutsideWorkspace { .. } => ErrorCode::PathOutsideWorkspace,
+            CoreError::VersionConflict { .. } => ErrorCode::Conflict,
+            CoreError::InvalidEdit(_) => ErrorCode::InvalidRequest,
+            CoreError::UnknownEncoding(_) | CoreError::Unencodable { .. } => ErrorCode::Unsupported,
+            CoreError::ReadOnly(_) => ErrorCode::PermissionDenied,
+            CoreError::BinaryFile(_) => ErrorCode::Unsupported,
+            CoreError::ModifiedOnDisk { .. } | CoreError::UnsavedChanges(_) => ErrorCode::Conflict,
+            CoreError::NoFile(_) => ErrorCode::NoFile,
+            CoreError::ParseError(_) | CoreError::SettingsError(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// Structured detail for `CoreResponse::Error`, if any
+    pub fn error_detail(&self) -> Option<ErrorDetail> {
+        match self {
+            CoreError::BufferNotFound(buffer_id)
+            | CoreError::ReadOnly(buffer_id)
+            | CoreError::UnsavedChanges(buffer_id)
+            | CoreError::NoFile(buffer_id) => {
+                Some(ErrorDetail::Buffer { buffer_id: buffer_id.clone() })
+            }
+            CoreError::BinaryFile(path) | CoreError::ModifiedOnDisk { path } => Some(ErrorDetail::Path { path: path.display().to_string() }),
+            CoreError::PathOutsideWorkspace { path, workspace } => Some(ErrorDetail::OutsideWorkspace {
+                path: path.display().to_string(),
+                workspace: workspace.display().to_string(),
+            }),
+            CoreError::WriteFailed { path, .. } => Some(ErrorDetail::Path { path: path.display().to_string() }),
+            CoreError::VersionConflict { buffer_id, expected, actual } => Some(ErrorDetail::Version {
+                buffer_id: buffer_id.clone(),
+                expected: *expected,
+                actual: *actual,
+            }),
+            _ => None,
+        }
+    }
 }
 
 /// Text buffer with rope data structure
@@ -42,10 +149,18 @@ pub struct TextBuffer {
     pub is_dirty: bool,
     /// Syntax tree (if parsed)
     pub syntax_tree: Option<Tree>,
-    /// Buffer encoding
-    pub encoding: String,
+    /// Encoding of the file on disk
+    pub encoding: FileEncoding,
     /// Line ending style
     pub line_ending: LineEnding,
+    /// Content version, incremented on every change
+    pub version: u64,
+    /// Undo/redo history (kept across saves)
+    pub history: History,
+    /// Large-file and binary buffers skip parsing; binary ones are read-only
+    pub mode: BufferMode,
+    /// State of the file on disk as of the last open, save or reload
+    pub disk: Option<DiskFingerprint>,
 }
 
 #[cfg(test)]
@@ -60,10 +175,181 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "success");
     }
+    fn edit(start: (usize, usize), end: (usize, usize), new_text: &str) -> super::TextEdit {
+        let pos = |(line, column)| super::Position { line, column };
+        super::TextEdit { range: super::Range { start: pos(start), end: pos(end) }, new_text: new_text.into() }
+    }
+
+    #[tokio::test]
+    async fn apply_edits_checks_version_and_is_atomic() {
+        use super::{BufferManager, CoreError};
+        let mut bm = BufferManager::new(atom_settings::Settings::default());
+        let id = bm.new_buffer();
+        bm.apply_edit(&id, edit((0, 0), (0, 0), "héllo\nwörld\n")).await.unwrap();
+        assert_eq!(bm.get_buffer(&id).unwrap().version, 1);
+
+        // Each edit sees the text produced by the previous one
+        let applied = bm
+            .apply_edits(&id, 1, vec![edit((0, 1), (0, 5), "i"), edit((1, 0), (1, 5), "there")])
+            .await
+            .unwrap();
+        assert_eq!(applied[0].old_text, "éllo");
+        assert_eq!(applied[1].old_text, "wörld");
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert_eq!(buffer.content.to_string(), "hi\nthere\n");
+        assert_eq!(buffer.version, 2);
+
+        match bm.apply_edits(&id, 1, vec![edit((0, 0), (0, 0), "x")]).await {
+            Err(CoreError::VersionConflict { expected: 1, actual: 2, .. }) => {}
+            other => panic!("expected conflict, got {:?}", other),
+        }
+        // The second edit is out of range, so the first must not be applied either
+        let bad = vec![edit((0, 0), (0, 0), "x"), edit((0, 9), (0, 9), "y")];
+        assert!(matches!(bm.apply_edits(&id, 2, bad).await, Err(CoreError::InvalidEdit(_))));
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert_eq!(buffer.content.to_string(), "hi\nthere\n");
+        assert_eq!(buffer.version, 2);
+    }
+    #[tokio::test]
+    async fn edits_reparse_incrementally() {
+        use super::BufferManager;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "fn a() {}\n\nfn b() { 1 }\n").unwrap();
+
+        let mut bm = BufferManager::new(atom_settings::Settings::default());
+        let id = bm.open_file(&path).await.unwrap();
+        let changed = bm.apply_edit(&id, edit((2, 9), (2, 10), "x + 1")).await.unwrap();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|r| r.start_point.row == 2), "{:?}", changed);
+
+        // Undo goes through the same tree edits
+        bm.undo(&id).await.unwrap();
+        let tree = bm.get_buffer(&id).unwrap().syntax_tree.clone().unwrap();
+        assert_eq!(
+            tree.root_node().to_sexp(),
+            "(source_file (function_item name: (identifier) parameters: (parameters) body: (block)) \
+             (function_item name: (identifier) parameters: (parameters) body: (block (integer_literal))))"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dangling_symlinks_cannot_escape_the_workspace() {
+        use super::{BufferManager, CoreError};
+        let outside = tempfile::tempdir().unwrap();
+        // Saving is confined to the workspace, which is the crate directory under `cargo test`
+        let workspace = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let escape = workspace.path().join("escape.txt");
+        std::os::unix::fs::symlink(outside.path().join("planted.txt"), &escape).unwrap();
+        let inside = workspace.path().join("notes.txt");
+        std::os::unix::fs::symlink("new.txt", &inside).unwrap();
+
+        let mut bm = BufferManager::new(atom_settings::Settings::default());
+        let id = bm.new_buffer();
+        bm.apply_edit(&id, edit((0, 0), (0, 0), "text")).await.unwrap();
+        let err = bm.save_buffer(&id, Some(&escape), false).await.unwrap_err();
+        assert!(matches!(err, CoreError::PathOutsideWorkspace { .. }), "got {:?}", err);
+        assert!(!outside.path().join("planted.txt").exists());
+
+        // A dangling link inside the workspace gets its target created
+        bm.save_buffer(&id, Some(&inside), false).await.unwrap();
+        assert_eq!(std::fs::read_to_string(workspace.path().join("new.txt")).unwrap(), "text\n");
+        assert!(std::fs::symlink_metadata(&inside).unwrap().file_type().is_symlink());
+    }
+
+    #[tokio::test]
+    async fn large_files_are_read_in_chunks() {
+        use super::{BufferManager, LOAD_CHUNK_SIZE};
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.txt");
+        // Over a chunk of Windows-1251: the charset is guessed on a second pass
+        let text = "Отчёт за квартал\n".repeat(LOAD_CHUNK_SIZE / 16 + 1);
+        let (bytes, _, _) = encoding_rs::WINDOWS_1251.encode(&text);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut settings = atom_settings::Settings::default();
+        settings.editor.max_highlight_size = 1024;
+        let mut bm = BufferManager::new(settings);
+        let id = bm.open_file(&path).await.unwrap();
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert_eq!(buffer.mode, atom_ipc::BufferMode::LargeFile);
+        assert_eq!(buffer.encoding.name(), "windows-1251");
+        assert!(buffer.content == text.as_str());
+        assert!(buffer.disk.unwrap().matches_file(&path).await.unwrap());
+
+        let (version, line_count, lines) = bm.lines(&id, 1, 3).unwrap();
+        assert_eq!((version, line_count), (0, LOAD_CHUNK_SIZE / 16 + 2));
+        assert_eq!(lines, "Отчёт за квартал\n".repeat(2));
+        assert_eq!(bm.lines(&id, line_count - 1, usize::MAX).unwrap().2, "");
+
+        // Reloading swaps the text in without a whole-file undo step
+        let changed = text.replacen("Отчёт", "Сводка", 1);
+        std::fs::write(&path, encoding_rs::WINDOWS_1251.encode(&changed).0).unwrap();
+        assert!(matches!(bm.reload_buffer(&id).await.unwrap(), super::Reloaded::Replaced));
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert!(buffer.content == changed.as_str());
+        assert_eq!((buffer.version, buffer.is_dirty, buffer.encoding.name()), (1, false, "windows-1251"));
+        assert!(buffer.disk.unwrap().matches_file(&path).await.unwrap());
+        assert!(bm.undo(&id).await.unwrap().is_empty());
+        assert!(matches!(bm.reload_buffer(&id).await.unwrap(), super::Reloaded::Unchanged));
+    }
+
+    #[tokio::test]
+    async fn reopen_keeps_unsaved_changes_without_force() {
+        use super::{BufferManager, CoreError};
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\n").unwrap();
+
+        let mut bm = BufferManager::new(atom_settings::Settings::default());
+        let id = bm.open_file(&path).await.unwrap();
+        bm.apply_edit(&id, edit((0, 3), (0, 3), " two")).await.unwrap();
+        assert!(matches!(bm.reopen_with_encoding(&id, "latin1", false).await, Err(CoreError::UnsavedChanges(_))));
+        assert_eq!(bm.get_buffer(&id).unwrap().content.to_string(), "one two\n");
+        bm.reopen_with_encoding(&id, "latin1", true).await.unwrap();
+        assert_eq!(bm.get_buffer(&id).unwrap().content.to_string(), "one\n");
+
+        let untitled = bm.new_buffer();
+        let err = bm.reopen_with_encoding(&untitled, "latin1", false).await.unwrap_err();
+        assert_eq!(err.error_code(), atom_ipc::ErrorCode::NoFile);
+    }
+
+    #[tokio::test]
+    async fn undo_redo_survive_save() {
+        use super::BufferManager;
+        // Saving is confined to the workspace, which is the crate directory under `cargo test`
+        let dir = tempfile::tempdir_in(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one\n").unwrap();
+
+        let mut bm = BufferManager::new(atom_settings::Settings::default());
+        let id = bm.open_file(&path).await.unwrap();
+        // Typed within the grouping window: one undo step
+        bm.apply_edit(&id, edit((0, 3), (0, 3), " two")).await.unwrap();
+        bm.apply_edit(&id, edit((0, 7), (0, 7), " three")).await.unwrap();
+        assert!(bm.save_buffer(&id, None, false).await.unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one two three\n");
+        assert!(!bm.get_buffer(&id).unwrap().is_dirty);
+
+        let undone = bm.undo(&id).await.unwrap();
+        assert_eq!(undone.len(), 2);
+        assert_eq!(undone[0].old_text, " three");
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert_eq!(buffer.content.to_string(), "one\n");
+        assert!(buffer.is_dirty);
+        assert!(bm.undo(&id).await.unwrap().is_empty());
+
+        bm.redo(&id).await.unwrap();
+        let buffer = bm.get_buffer(&id).unwrap();
+        assert_eq!(buffer.content.to_string(), "one two three\n");
+        assert!(!buffer.is_dirty, "back at the saved state");
+        assert_eq!(buffer.version, 4);
+    }
 }
 
 /// Line ending styles
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum LineEnding {
     /// Unix-style (LF)
     Unix,
@@ -73,8 +359,19 @@ pub enum LineEnding {
     Mac,
 }
 
-/// Text position in buffer
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+impl LineEnding {
+    /// The line break characters
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Windows => "\r\n",
+            LineEnding::Mac => "\r",
+        }
+    }
+}
+
+/// Text position in buffer; the column counts chars (`PositionEncoding::Char`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -96,14 +393,56 @@ pub struct TextEdit {
     pub new_text: String,
 }
 
+/// Length in chars of the line terminator at the end of `line` (0 for the last line)
+fn line_terminator_len(line: &ropey::RopeSlice) -> usize {
+    let len = line.len_chars();
+    match (len.checked_sub(2).map(|i| line.char(i)), len.checked_sub(1).map(|i| line.char(i))) {
+        (Some('\r'), Some('\n')) => 2,
+        (_, Some('\n' | '\r' | '\u{0085}' | '\u{2028}' | '\u{2029}' | '\u{000B}' | '\u{000C}')) => 1,
+        _ => 0,
+    }
+}
+
+/// Chunk size for reading large files
+const LOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// File content loaded for a new buffer
+struct LoadedFile {
+    content: Rope,
+    encoding: FileEncoding,
+    mode: BufferMode,
+    disk: DiskFingerprint,
+}
+
+/// Edit applied to a buffer together with the text it replaced
+#[derive(Debug, Clone)]
+pub struct AppliedEdit {
+    pub edit: TextEdit,
+    pub old_text: String,
+}
+
+/// What re-reading a buffer's file changed
+#[derive(Debug, Clone)]
+pub enum Reloaded {
+    /// The file has the buffer's text
+    Unchanged,
+    /// The text was replaced as one undo step
+    Edited(AppliedEdit),
+    /// A large file's text was swapped without an undo step (its history
+    /// is cleared); holders have to refetch it
+    Replaced,
+}
+
 /// Buffer manager for handling multiple text buffers
 pub struct BufferManager {
     buffers: HashMap<String, TextBuffer>,
     parsers: HashMap<String, Parser>,
-    #[allow(dead_code)]
-    languages: HashMap<String, Language>,
-    #[allow(dead_code)]
+    languages: LanguageRegistry,
     settings: atom_settings::Settings,
+    save_pipeline: SavePipeline,
+    highlighter: Highlighter,
+    folder: Folder,
+    symbols: SymbolExtractor,
     next_buffer_id: usize,
 }
 
@@ -113,8 +452,12 @@ impl BufferManager {
         Self {
             buffers: HashMap::new(),
             parsers: HashMap::new(),
-            languages: HashMap::new(),
+            languages: LanguageRegistry::with_settings(&settings),
             settings,
+            save_pipeline: SavePipeline::default(),
+            highlighter: Highlighter::default(),
+            folder: Folder::default(),
+            symbols: SymbolExtractor::default(),
             next_buffer_id: 1,
         }
     }
@@ -133,30 +476,51 @@ impl BufferManager {
             }
         }
 
-        // Read file content
-        let content = fs::read_to_string(&path).await?;
+        // Read file content, detecting its encoding and binary data
+        let loaded = Self::load_file(path, u64::from(self.settings.editor.max_highlight_size)).await?;
 
-        // Detect line endings
-        let line_ending = Self::detect_line_ending(&content);
+        // `.editorconfig` decides the line ending; otherwise keep the file's own
+        let file_settings = self.settings.for_file(path).await;
+        let line_ending = match file_settings.end_of_line {
+            Some(end_of_line) => LineEnding::from(end_of_line),
+            None => Self::detect_line_ending_in(&loaded.content),
+        };
+        // Plain ASCII decodes the same in most charsets: take the configured one
+        let encoding = match file_settings.charset {
+            Some(charset)
+                if loaded.mode != BufferMode::Binary
+                    && loaded.encoding == FileEncoding::default()
+                    && loaded.content.len_bytes() == loaded.content.len_chars() =>
+            {
+                FileEncoding::from(charset)
+            }
+            _ => loaded.encoding,
+        };
 
-        // Detect language from file extension
-        let language = Self::detect_language(&path_buf);
+        // Detect language from file name, modeline or shebang
+        let language = self.languages.detect(path, &loaded.content).map(|language| language.id.clone());
 
         // Create buffer
         let buffer_id = self.generate_buffer_id();
         let mut buffer = TextBuffer {
             id: buffer_id.clone(),
             path: Some(path_buf),
-            content: Rope::from_str(&content),
+            content: loaded.content,
             language: language.clone(),
             is_dirty: false,
             syntax_tree: None,
-            encoding: "UTF-8".to_string(),
+            encoding,
             line_ending,
+            version: 0,
+            history: self.new_history(),
+            mode: loaded.mode,
+            disk: Some(loaded.disk),
         };
 
-        // Parse syntax if language is supported
-        if let Some(ref lang) = language {
+        // Parse syntax if language is supported (large and binary files are not parsed)
+        if buffer.mode != BufferMode::Normal {
+            tracing::info!("Opening {} in {:?} mode", path.display(), buffer.mode);
+        } else if let Some(ref lang) = language {
             match self.parse_buffer(&mut buffer, lang).await {
                 Ok(_) => {}
                 Err(e) => {
@@ -181,8 +545,12 @@ impl BufferManager {
             language: None,
             is_dirty: false,
             syntax_tree: None,
-            encoding: "UTF-8".to_string(),
+            encoding: FileEncoding::default(),
             line_ending: LineEnding::Unix,
+            version: 0,
+            history: self.new_history(),
+            mode: BufferMode::Normal,
+            disk: None,
         };
 
         self.buffers.insert(buffer_id.clone(), buffer);
@@ -190,39 +558,93 @@ impl BufferManager {
         buffer_id
     }
 
-    /// Save buffer to file
+    /// Fail with `ModifiedOnDisk` if the buffer's file changed or was deleted
+    /// since it was opened, saved or reloaded. Buffers without a file have
+    /// nothing to compare against.
+    pub async fn check_disk(&self, buffer_id: &str) -> Result<(), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        match (&buffer.path, buffer.disk) {
+            (Some(path), Some(disk)) if !disk.matches_file(path).await? => {
+                Err(CoreError::ModifiedOnDisk { path: path.clone() })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Save buffer to file.
+    ///
+    /// The content first runs through the save pipeline (whitespace, final
+    /// newline, line endings); its edits are applied to the buffer as one undo
+    /// step after the file is written and are returned. Saving over the
+    /// buffer's own file fails with `ModifiedOnDisk` if the file changed or was
+    /// deleted since it was opened, saved or reloaded, unless `force` is set.
     pub async fn save_buffer(
         &mut self,
         buffer_id: &str,
         path: Option<&Path>,
-    ) -> Result<(), CoreError> {
-        let (save_path, content, line_ending) = {
+        force: bool,
+    ) -> Result<Vec<AppliedEdit>, CoreError> {
+        let (save_path, same_file) = {
             let buffer = self
                 .buffers
                 .get(buffer_id)
                 .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+            if buffer.mode == BufferMode::Binary {
+                return Err(CoreError::ReadOnly(buffer_id.to_string()));
+            }
 
             let requested_path = match path {
                 Some(p) => p.to_path_buf(),
                 None => buffer
                     .path
                     .as_ref()
-                    .ok_or_else(|| CoreError::BufferNotFound("No path specified".to_string()))?
+                    .ok_or_else(|| CoreError::NoFile(buffer_id.to_string()))?
                     .clone(),
             };
 
             // Security: Validate and canonicalize path to prevent path traversal
             let save_path = self.validate_save_path(&requested_path)?;
+            let same_file = buffer.path.as_deref().and_then(|p| self.validate_save_path(p).ok()).as_ref() == Some(&save_path);
+            (save_path, same_file)
+        };
 
-            // Extract needed data to avoid borrow conflicts
-            let content = buffer.content.clone();
-            let line_ending = buffer.line_ending.clone();
+        let file_settings = self.settings.for_file(&save_path).await;
+        let (content, edits, version, line_ending, file_encoding, disk) = {
+            let buffer = self
+                .buffers
+                .get(buffer_id)
+                .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+            let line_ending = file_settings.end_of_line.map_or_else(|| buffer.line_ending.clone(), LineEnding::from);
+            // The charset of an existing file was settled when it was opened
+            let file_encoding = match file_settings.charset {
+                Some(charset) if !same_file => FileEncoding::from(charset),
+                _ => buffer.encoding,
+            };
+            let options = SaveOptions {
+                trim_trailing_whitespace: file_settings.trim_trailing_whitespace,
+                insert_final_newline: file_settings.insert_final_newline,
+                line_ending: line_ending.clone(),
+            };
+            let (content, edits) = self.save_pipeline.run(&buffer.content, &options);
+            // "Save as" to another file has nothing to compare against
+            let disk = buffer.disk.filter(|_| same_file);
 
-            (save_path, content, line_ending)
+            (content, edits, buffer.version, line_ending, file_encoding, disk)
         };
 
-        // Convert rope to string with appropriate line endings
-        let content_str = self.rope_to_string_with_endings(&content, &line_ending);
+        if let Some(disk) = disk.filter(|_| !force) {
+            if !disk.matches_file(&save_path).await? {
+                return Err(CoreError::ModifiedOnDisk { path: save_path });
+            }
+        }
+
+        let bytes = encoding::encode(&content.to_string(), file_encoding).map_err(|character| CoreError::Unencodable {
+            encoding: file_encoding.name().to_string(),
+            character,
+        })?;
 
         // Create parent directory if needed (within workspace bounds)
         if let Some(parent) = save_path.parent() {
@@ -237,10 +659,25 @@ impl BufferManager {
             }
         }
 
-        // Write file with proper error handling
-        fs::write(&save_path, content_str).await.map_err(|e| {
-            CoreError::IoErrorString(format!("Cannot write to {}: {}", save_path.display(), e))
-        })?;
+        // Write through a temp file and rename, so a failed write never truncates the file
+        let target = save_path.clone();
+        let disk = tokio::task::spawn_blocking(move || {
+            save::write_atomic(&target, &bytes)?;
+            Ok(DiskFingerprint::new(&bytes, &std::fs::metadata(&target)?))
+        })
+        .await
+        .map_err(|e| CoreError::IoErrorString(format!("Saving {} failed: {}", save_path.display(), e)))?
+        .map_err(|source| CoreError::WriteFailed { path: save_path.clone(), source })?;
+
+        // Bring the buffer in line with what was written
+        let applied = if edits.is_empty() {
+            Vec::new()
+        } else {
+            if let Some(buffer) = self.buffers.get_mut(buffer_id) {
+                buffer.history.seal();
+            }
+            self.apply_edits(buffer_id, version, edits).await?
+        };
 
         // Update buffer state after successful write
         let buffer = self
@@ -248,10 +685,210 @@ impl BufferManager {
             .get_mut(buffer_id)
             .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
         buffer.path = Some(save_path.clone());
+        buffer.line_ending = line_ending;
+        buffer.encoding = file_encoding;
         buffer.is_dirty = false;
+        buffer.history.mark_saved();
+        buffer.disk = Some(disk);
 
         tracing::info!("Saved buffer {} to {}", buffer_id, save_path.display());
-        Ok(())
+        Ok(applied)
+    }
+
+    /// Transforms run on every save, in order
+    pub fn save_pipeline_mut(&mut self) -> &mut SavePipeline {
+        &mut self.save_pipeline
+    }
+
+    /// Known languages
+    pub fn languages(&self) -> &LanguageRegistry {
+        &self.languages
+    }
+
+    /// Add or replace a language (from a plugin). Files opened afterwards
+    /// use it; open buffers keep their language.
+    pub fn register_language(&mut self, definition: LanguageDefinition) {
+        // Parsers and compiled queries of a replaced definition are stale
+        self.parsers.remove(&definition.id);
+        self.highlighter = Highlighter::default();
+        self.folder = Folder::default();
+        self.symbols = SymbolExtractor::default();
+        tracing::info!("Registered language {}", definition.id);
+        self.languages.register(definition);
+    }
+
+    /// Highlight spans for lines `start_line..end_line` of a buffer, with the
+    /// buffer version they belong to. Buffers without a syntax tree (large
+    /// files, unknown languages) have none.
+    pub fn highlights(
+        &mut self,
+        buffer_id: &str,
+        start_line: usize,
+        end_line: usize,
+    ) -> Result<(u64, Vec<atom_ipc::HighlightSpan>), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let language = buffer.language.as_deref().and_then(|id| self.languages.get(id));
+        let spans = match (language, &buffer.syntax_tree) {
+            (Some(language), Some(tree)) => {
+                self.highlighter.highlight(language, &buffer.content, tree, start_line, end_line)?
+            }
+            _ => Vec::new(),
+        };
+        Ok((buffer.version, spans))
+    }
+
+    /// Text of lines `start_line..end_line` of a buffer with their line
+    /// breaks, the buffer version and its total line count. The range is
+    /// clamped to the buffer.
+    pub fn lines(&self, buffer_id: &str, start_line: usize, end_line: usize) -> Result<(u64, usize, String), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let line_count = buffer.content.len_lines();
+        let end_line = end_line.min(line_count);
+        let start_line = start_line.min(end_line);
+        let text = buffer.content.slice(buffer.content.line_to_char(start_line)..buffer.content.line_to_char(end_line));
+        Ok((buffer.version, line_count, text.to_string()))
+    }
+
+    /// Folding ranges of a buffer, with the buffer version they belong to.
+    /// Buffers without a syntax tree have none.
+    pub fn folding_ranges(&mut self, buffer_id: &str) -> Result<(u64, Vec<atom_ipc::FoldingRange>), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let language = buffer.language.as_deref().and_then(|id| self.languages.get(id));
+        let ranges = match (language, &buffer.syntax_tree) {
+            (Some(language), Some(tree)) => self.folder.folding_ranges(language, tree)?,
+            _ => Vec::new(),
+        };
+        Ok((buffer.version, ranges))
+    }
+
+    /// Outline of a buffer: its definitions, nested, with the buffer version
+    /// they belong to. Buffers without a syntax tree have none.
+    pub fn document_symbols(&mut self, buffer_id: &str) -> Result<(u64, Vec<atom_ipc::DocumentSymbol>), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let language = buffer.language.as_deref().and_then(|id| self.languages.get(id));
+        let symbols = match (language, &buffer.syntax_tree) {
+            (Some(language), Some(tree)) => self.symbols.document_symbols(language, &buffer.content, tree)?,
+            _ => Vec::new(),
+        };
+        Ok((buffer.version, symbols))
+    }
+
+    /// Grow each selection to the smallest syntax node strictly containing it.
+    ///
+    /// Returns the buffer version and the new selections; without a syntax
+    /// tree the selections stay as they are.
+    pub fn expand_selection(&self, buffer_id: &str, ranges: &[Range]) -> Result<(u64, Vec<Range>), CoreError> {
+        self.select_structurally(buffer_id, ranges, selection::expand)
+    }
+
+    /// Narrow each selection to the first named syntax node inside it
+    pub fn shrink_selection(&self, buffer_id: &str, ranges: &[Range]) -> Result<(u64, Vec<Range>), CoreError> {
+        self.select_structurally(buffer_id, ranges, selection::shrink)
+    }
+
+    fn select_structurally(
+        &self,
+        buffer_id: &str,
+        ranges: &[Range],
+        step: fn(&Tree, std::ops::Range<usize>) -> std::ops::Range<usize>,
+    ) -> Result<(u64, Vec<Range>), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let Some(tree) = &buffer.syntax_tree else {
+            return Ok((buffer.version, ranges.to_vec()));
+        };
+        let content = &buffer.content;
+        let selected = ranges
+            .iter()
+            .map(|range| {
+                let start = content.char_to_byte(Self::position_to_char_idx_checked(content, range.start)?);
+                let end = content.char_to_byte(Self::position_to_char_idx_checked(content, range.end)?);
+                let bytes = step(tree, start.min(end)..start.max(end));
+                Ok(Range {
+                    start: Self::char_idx_to_position(content, content.byte_to_char(bytes.start)),
+                    end: Self::char_idx_to_position(content, content.byte_to_char(bytes.end)),
+                })
+            })
+            .collect::<Result<_, CoreError>>()?;
+        Ok((buffer.version, selected))
+    }
+
+    /// Apply one action at every selection as a single batch of edits: one
+    /// version and one undo step, or nothing if any selection is invalid.
+    ///
+    /// Returns the applied edits (none if there is nothing to change, e.g.
+    /// deleting backwards at the start of the text) and the selections after them.
+    pub async fn edit_selections(
+        &mut self,
+        buffer_id: &str,
+        base_version: u64,
+        selections: &SelectionSet,
+        action: &atom_ipc::SelectionEdit,
+    ) -> Result<(Vec<AppliedEdit>, SelectionSet), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        if buffer.version != base_version {
+            return Err(CoreError::VersionConflict {
+                buffer_id: buffer_id.to_string(),
+                expected: base_version,
+                actual: buffer.version,
+            });
+        }
+
+        let (insert_spaces, tab_size) = match &buffer.path {
+            Some(path) => {
+                let file_settings = self.settings.for_file(path).await;
+                (file_settings.insert_spaces, file_settings.tab_size)
+            }
+            None => (self.settings.ui.insert_spaces, self.settings.ui.tab_size),
+        };
+        let language = buffer.language.as_deref().and_then(|id| self.languages.get(id));
+        let style = cursors::LineStyle {
+            insert_spaces,
+            tab_size: usize::from(tab_size),
+            line_comment: language.and_then(|language| language.line_comment.as_deref()),
+            block_comment: language
+                .and_then(|language| language.block_comment.as_ref())
+                .map(|(open, close)| (open.as_str(), close.as_str())),
+        };
+        let (edits, selections) = cursors::edit(&buffer.content, selections, action, style)?;
+        if edits.is_empty() {
+            return Ok((Vec::new(), selections));
+        }
+        let applied = self.apply_edits(buffer_id, base_version, edits).await?;
+        Ok((applied, selections))
+    }
+
+    /// Add a selection at the next occurrence of the primary selection's
+    /// text, or grow an empty primary selection to the word under it.
+    ///
+    /// Returns the buffer version and the new selections.
+    pub fn add_cursor_at_next_match(
+        &self,
+        buffer_id: &str,
+        selections: &SelectionSet,
+    ) -> Result<(u64, SelectionSet), CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        Ok((buffer.version, cursors::add_next_match(&buffer.content, selections)?))
     }
 
     /// Get buffer by ID
@@ -264,108 +901,359 @@ impl BufferManager {
         self.buffers.get_mut(buffer_id)
     }
 
-    /// Apply text edit to buffer
-    pub async fn apply_edit(&mut self, buffer_id: &str, edit: TextEdit) -> Result<(), CoreError> {
-        // Get language first to avoid borrow conflicts
-        let language = {
+    /// Apply text edit to buffer.
+    ///
+    /// Returns the ranges whose syntax changed (empty if the buffer is not parsed).
+    pub async fn apply_edit(&mut self, buffer_id: &str, edit: TextEdit) -> Result<Vec<tree_sitter::Range>, CoreError> {
+        let version = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?
+            .version;
+        let (_, changed_ranges) = self.edit_buffer(buffer_id, version, vec![edit], PositionEncoding::Char).await?;
+        Ok(changed_ranges)
+    }
+
+    /// Apply a batch of edits to a buffer at `base_version`.
+    ///
+    /// Edits are applied in order, each against the text produced by the
+    /// previous one. The batch is atomic: if any edit is invalid the buffer
+    /// is left untouched. On success the buffer version is incremented once.
+    pub async fn apply_edits(
+        &mut self,
+        buffer_id: &str,
+        base_version: u64,
+        edits: Vec<TextEdit>,
+    ) -> Result<Vec<AppliedEdit>, CoreError> {
+        self.apply_edits_with_encoding(buffer_id, base_version, edits, PositionEncoding::Char).await
+    }
+
+    /// `apply_edits` with columns counted in `encoding` units (UTF-16 for
+    /// edits from language servers). The applied edits report char columns.
+    pub async fn apply_edits_with_encoding(
+        &mut self,
+        buffer_id: &str,
+        base_version: u64,
+        edits: Vec<TextEdit>,
+        encoding: PositionEncoding,
+    ) -> Result<Vec<AppliedEdit>, CoreError> {
+        let (applied, _) = self.edit_buffer(buffer_id, base_version, edits, encoding).await?;
+        Ok(applied)
+    }
+
+    /// `apply_edits_with_encoding` that also returns the ranges whose syntax changed
+    async fn edit_buffer(
+        &mut self,
+        buffer_id: &str,
+        base_version: u64,
+        edits: Vec<TextEdit>,
+        encoding: PositionEncoding,
+    ) -> Result<(Vec<AppliedEdit>, Vec<tree_sitter::Range>), CoreError> {
+        let (language, applied) = {
             let buffer = self
                 .buffers
-                .get(buffer_id)
+                .get_mut(buffer_id)
                 .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
-            buffer.language.clone()
+
+            if buffer.mode == BufferMode::Binary {
+                return Err(CoreError::ReadOnly(buffer_id.to_string()));
+            }
+            if buffer.version != base_version {
+                return Err(CoreError::VersionConflict {
+                    buffer_id: buffer_id.to_string(),
+                    expected: base_version,
+                    actual: buffer.version,
+                });
+            }
+
+            // Work on a copy so that a bad edit in the middle leaves the buffer intact
+            let mut content = buffer.content.clone();
+            let mut applied = Vec::with_capacity(edits.len());
+            let mut changes = Vec::with_capacity(edits.len());
+            let mut input_edits = Vec::with_capacity(edits.len());
+            for edit in edits {
+                let start_idx = edit.range.start.to_char_idx(&content, encoding)?;
+                let end_idx = edit.range.end.to_char_idx(&content, encoding)?;
+                if start_idx > end_idx {
+                    return Err(CoreError::InvalidEdit(format!(
+                        "range start {:?} is after end {:?}",
+                        edit.range.start, edit.range.end
+                    )));
+                }
+                let range = Range {
+                    start: Self::char_idx_to_position(&content, start_idx),
+                    end: Self::char_idx_to_position(&content, end_idx),
+                };
+
+                let change = Change {
+                    start: start_idx,
+                    old_text: content.slice(start_idx..end_idx).to_string(),
+                    new_text: edit.new_text.clone(),
+                };
+                input_edits.push(syntax::apply_change(&mut content, &change));
+                let edit = TextEdit { range, new_text: edit.new_text };
+                applied.push(AppliedEdit { edit, old_text: change.old_text.clone() });
+                changes.push(change);
+            }
+
+            buffer.content = content;
+            Self::edit_syntax_tree(buffer, &input_edits);
+            buffer.history.record(changes, Instant::now());
+            buffer.is_dirty = !buffer.history.is_at_saved();
+            buffer.version += 1;
+            (buffer.language.clone().filter(|_| buffer.mode == BufferMode::Normal), applied)
+        };
+
+        // Re-parse syntax if needed
+        let changed_ranges = match language {
+            Some(language) => self.reparse_buffer(buffer_id, &language)?,
+            None => Vec::new(),
         };
 
-        // Apply edit to buffer
-        {
+        Ok((applied, changed_ranges))
+    }
+
+    /// Replace the whole buffer content as a single undo step
+    pub async fn set_content(&mut self, buffer_id: &str, text: &str) -> Result<AppliedEdit, CoreError> {
+        let (version, end) = {
             let buffer = self
                 .buffers
                 .get_mut(buffer_id)
                 .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+            // Do not merge the replacement with edits typed just before it
+            buffer.history.seal();
+            let end = Self::char_idx_to_position(&buffer.content, buffer.content.len_chars());
+            (buffer.version, end)
+        };
+        let edit = TextEdit { range: Range { start: Position { line: 0, column: 0 }, end }, new_text: text.to_string() };
+        let mut applied = self.apply_edits(buffer_id, version, vec![edit]).await?;
+        if let Some(buffer) = self.buffers.get_mut(buffer_id) {
+            buffer.history.seal();
+        }
+        Ok(applied.remove(0))
+    }
+
+    /// Re-read a buffer's file from disk, decoding it with `encoding`.
+    ///
+    /// The new content replaces the old one as an undo step (see
+    /// `read_from_disk` for large files), and later saves use this encoding.
+    /// Buffers with unsaved changes are refused with `UnsavedChanges` unless
+    /// `force` is set.
+    pub async fn reopen_with_encoding(
+        &mut self,
+        buffer_id: &str,
+        encoding: &str,
+        force: bool,
+    ) -> Result<Reloaded, CoreError> {
+        let file_encoding =
+            FileEncoding::for_label(encoding).ok_or_else(|| CoreError::UnknownEncoding(encoding.to_string()))?;
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        if buffer.is_dirty && !force {
+            return Err(CoreError::UnsavedChanges(buffer_id.to_string()));
+        }
+        let applied = self.read_from_disk(buffer_id, file_encoding).await?;
+        tracing::info!("Reopened buffer {} as {}", buffer_id, file_encoding.name());
+        Ok(applied)
+    }
 
-            // Convert positions to byte indices using static helper
-            let content_clone = buffer.content.clone();
-            let start_idx = Self::position_to_byte_idx_static(&content_clone, edit.range.start);
-            let end_idx = Self::position_to_byte_idx_static(&content_clone, edit.range.end);
+    /// Replace the content of a clean buffer with the current content of its
+    /// file, keeping the buffer's encoding.
+    ///
+    /// Buffers with unsaved changes are refused with `UnsavedChanges`. The
+    /// reload is an undo step, except for large files (see `read_from_disk`).
+    pub async fn reload_buffer(&mut self, buffer_id: &str) -> Result<Reloaded, CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        if buffer.is_dirty {
+            return Err(CoreError::UnsavedChanges(buffer_id.to_string()));
+        }
+        if buffer.mode == BufferMode::Binary {
+            return Err(CoreError::ReadOnly(buffer_id.to_string()));
+        }
+        let file_encoding = buffer.encoding;
+        let applied = self.read_from_disk(buffer_id, file_encoding).await?;
+        tracing::info!("Reloaded buffer {} from disk", buffer_id);
+        Ok(applied)
+    }
 
-            // Apply edit to rope
-            buffer.content.remove(start_idx..end_idx);
-            buffer.content.insert(start_idx, &edit.new_text);
-            buffer.is_dirty = true;
+    /// Replace a buffer's content with its file decoded as `file_encoding`
+    /// and mark the buffer as matching the file.
+    ///
+    /// A large file is read in chunks like on open and its rope is swapped
+    /// in: a whole-file undo step would keep copies of both texts.
+    async fn read_from_disk(&mut self, buffer_id: &str, file_encoding: FileEncoding) -> Result<Reloaded, CoreError> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let path = buffer.path.clone().ok_or_else(|| CoreError::NoFile(buffer_id.to_string()))?;
+        if buffer.mode == BufferMode::LargeFile {
+            return self.swap_from_disk(buffer_id, path, file_encoding).await;
         }
 
-        // Re-parse syntax if needed
-        if let Some(language) = language {
-            // Extract content and old tree before parsing to avoid borrow conflicts
-            let (content_str, old_tree) = {
-                let buffer = self
-                    .buffers
-                    .get(buffer_id)
-                    .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
-                (buffer.content.to_string(), buffer.syntax_tree.clone())
+        let meta = fs::metadata(&path).await?;
+        let bytes = fs::read(&path).await?;
+        let decoded = encoding::decode_with(&bytes, file_encoding);
+        if decoded.had_errors {
+            tracing::warn!("{} is not valid {}, some bytes were replaced", path.display(), file_encoding.name());
+        }
+        let unchanged = self.buffers.get(buffer_id).is_some_and(|b| b.content == decoded.text.as_ref());
+        let reloaded =
+            if unchanged { Reloaded::Unchanged } else { Reloaded::Edited(self.set_content(buffer_id, &decoded.text).await?) };
+
+        let buffer = self
+            .buffers
+            .get_mut(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        buffer.encoding = decoded.encoding;
+        buffer.line_ending = Self::detect_line_ending(&decoded.text);
+        buffer.disk = Some(DiskFingerprint::new(&bytes, &meta));
+        // The buffer now matches the file again
+        buffer.history.mark_saved();
+        buffer.is_dirty = false;
+        Ok(reloaded)
+    }
+
+    /// `read_from_disk` for a large-file buffer
+    async fn swap_from_disk(
+        &mut self,
+        buffer_id: &str,
+        path: PathBuf,
+        file_encoding: FileEncoding,
+    ) -> Result<Reloaded, CoreError> {
+        let shown = path.display().to_string();
+        let loaded = tokio::task::spawn_blocking(move || Self::read_large_file(&path, Some(file_encoding)))
+            .await
+            .map_err(|e| CoreError::IoErrorString(format!("Loading {} failed: {}", shown, e)))??;
+        let history = self.new_history();
+
+        let buffer = self
+            .buffers
+            .get_mut(buffer_id)
+            .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+        let reloaded = if buffer.content == loaded.content {
+            buffer.history.mark_saved();
+            Reloaded::Unchanged
+        } else {
+            buffer.line_ending = Self::detect_line_ending_in(&loaded.content);
+            buffer.content = loaded.content;
+            buffer.version += 1;
+            // Recorded steps describe the old text
+            buffer.history = history;
+            Reloaded::Replaced
+        };
+        buffer.encoding = loaded.encoding;
+        buffer.disk = Some(loaded.disk);
+        buffer.is_dirty = false;
+        Ok(reloaded)
+    }
+
+    /// Undo the last edit step of a buffer.
+    ///
+    /// Returns the edits that were applied to revert it (empty if there is
+    /// nothing to undo).
+    pub async fn undo(&mut self, buffer_id: &str) -> Result<Vec<AppliedEdit>, CoreError> {
+        self.travel_history(buffer_id, History::undo).await
+    }
+
+    /// Redo the most recently undone edit step of a buffer
+    pub async fn redo(&mut self, buffer_id: &str) -> Result<Vec<AppliedEdit>, CoreError> {
+        self.travel_history(buffer_id, History::redo).await
+    }
+
+    async fn travel_history(
+        &mut self,
+        buffer_id: &str,
+        step: fn(&mut History) -> Option<Vec<Change>>,
+    ) -> Result<Vec<AppliedEdit>, CoreError> {
+        let (language, applied) = {
+            let buffer = self
+                .buffers
+                .get_mut(buffer_id)
+                .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+            let Some(changes) = step(&mut buffer.history) else {
+                return Ok(Vec::new());
             };
 
-            // Create or get parser for language
-            let mut parser = Parser::new();
-            match language.to_lowercase().as_str() {
-                "rust" => {
-                    let rust_language = tree_sitter_rust::LANGUAGE.into();
-                    parser.set_language(&rust_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set Rust language: {}", e))
-                    })?;
-                }
-                "javascript" => {
-                    let js_language = tree_sitter_javascript::LANGUAGE.into();
-                    parser.set_language(&js_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set JavaScript language: {}", e))
-                    })?;
-                }
-                "typescript" => {
-                    let ts_language = tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into();
-                    parser.set_language(&ts_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set TypeScript language: {}", e))
-                    })?;
-                }
-                "python" => {
-                    let py_language = tree_sitter_python::LANGUAGE.into();
-                    parser.set_language(&py_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set Python language: {}", e))
-                    })?;
-                }
-                "json" => {
-                    let json_language = tree_sitter_json::LANGUAGE.into();
-                    parser.set_language(&json_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set JSON language: {}", e))
-                    })?;
-                }
-                "markdown" => {
-                    // Markdown support temporarily disabled due to tree-sitter ABI incompatibility
-                    // Skip parsing but don't fail
-                    return Ok(());
-                }
-                _ => {
-                    // Language not supported, skip parsing
-                    return Ok(());
-                }
+            let mut applied = Vec::with_capacity(changes.len());
+            let mut input_edits = Vec::with_capacity(changes.len());
+            for change in &changes {
+                let old_end = change.start + change.old_text.chars().count();
+                let edit = TextEdit {
+                    range: Range {
+                        start: Self::char_idx_to_position(&buffer.content, change.start),
+                        end: Self::char_idx_to_position(&buffer.content, old_end),
+                    },
+                    new_text: change.new_text.clone(),
+                };
+                input_edits.push(syntax::apply_change(&mut buffer.content, change));
+                applied.push(AppliedEdit { edit, old_text: change.old_text.clone() });
             }
+            Self::edit_syntax_tree(buffer, &input_edits);
 
-            // Parse with the configured parser
-            match parser.parse(&content_str, old_tree.as_ref()) {
-                Some(tree) => {
-                    if let Some(buffer) = self.buffers.get_mut(buffer_id) {
-                        buffer.syntax_tree = Some(tree);
-                    }
-                }
-                None => {
-                    tracing::warn!(
-                        "Failed to parse buffer {} for language {}",
-                        buffer_id,
-                        language
-                    );
-                }
+            buffer.is_dirty = !buffer.history.is_at_saved();
+            buffer.version += 1;
+            (buffer.language.clone().filter(|_| buffer.mode == BufferMode::Normal), applied)
+        };
+
+        if let Some(language) = language {
+            self.reparse_buffer(buffer_id, &language)?;
+        }
+
+        Ok(applied)
+    }
+
+    /// Mirror edits applied to the buffer content into its syntax tree
+    fn edit_syntax_tree(buffer: &mut TextBuffer, input_edits: &[tree_sitter::InputEdit]) {
+        if let Some(tree) = buffer.syntax_tree.as_mut() {
+            for input_edit in input_edits {
+                tree.edit(input_edit);
             }
         }
+    }
 
-        Ok(())
+    /// Empty undo history with the grouping window and caps from editor settings
+    fn new_history(&self) -> History {
+        let editor = &self.settings.editor;
+        History::new(Duration::from_millis(editor.undo_group_ms))
+            .with_limits(editor.undo_max_steps, editor.undo_max_bytes)
+    }
+
+    /// Re-parse buffer syntax after its content changed, reusing the edited
+    /// old tree. Returns the ranges whose syntax changed.
+    fn reparse_buffer(&mut self, buffer_id: &str, language: &str) -> Result<Vec<tree_sitter::Range>, CoreError> {
+        // Ropes and trees are cheap to clone; the parser borrows `self` mutably
+        let (content, old_tree) = {
+            let buffer = self
+                .buffers
+                .get(buffer_id)
+                .ok_or_else(|| CoreError::BufferNotFound(buffer_id.to_string()))?;
+            (buffer.content.clone(), buffer.syntax_tree.clone())
+        };
+
+        if self.languages.get(language).is_none_or(|definition| definition.grammar.is_none()) {
+            // Language has no grammar, skip parsing
+            return Ok(Vec::new());
+        }
+        let parser = self.get_or_create_parser(language)?;
+        let Some(tree) = syntax::parse_rope(parser, &content, old_tree.as_ref()) else {
+            tracing::warn!("Failed to parse buffer {} for language {}", buffer_id, language);
+            return Ok(Vec::new());
+        };
+
+        let changed_ranges = match &old_tree {
+            Some(old_tree) => old_tree.changed_ranges(&tree).collect(),
+            None => vec![tree.root_node().range()],
+        };
+        if let Some(buffer) = self.buffers.get_mut(buffer_id) {
+            buffer.syntax_tree = Some(tree);
+        }
+        Ok(changed_ranges)
     }
 
     /// Close buffer
@@ -390,6 +1278,146 @@ impl BufferManager {
         id
     }
 
+    /// Read a file for a new buffer.
+    ///
+    /// Files up to `large_file_size` bytes are read in one go. Larger files are
+    /// read in chunks and decoded straight into the rope, without holding the
+    /// whole file in memory next to it, and open in large-file mode. Binary
+    /// files open as a read-only hex dump, or are refused when they are large.
+    async fn load_file(path: &Path, large_file_size: u64) -> Result<LoadedFile, CoreError> {
+        let meta = fs::metadata(path).await?;
+        if meta.len() <= large_file_size {
+            let bytes = fs::read(path).await?;
+            return Self::decode_file(path, &bytes, &meta, BufferMode::Normal);
+        }
+
+        let path = path.to_path_buf();
+        let shown = path.display().to_string();
+        tokio::task::spawn_blocking(move || Self::read_large_file(&path, None))
+            .await
+            .map_err(|e| CoreError::IoErrorString(format!("Loading {} failed: {}", shown, e)))?
+    }
+
+    /// Read a large file chunk by chunk, in `encoding` or else the detected
+    /// one. A file that is not UTF-8 and has no BOM is then read twice: once
+    /// to guess its charset, once to decode it.
+    fn read_large_file(path: &Path, encoding: Option<FileEncoding>) -> Result<LoadedFile, CoreError> {
+        let mut file = std::fs::File::open(path)?;
+        let mut chunk = vec![0; LOAD_CHUNK_SIZE];
+        let read = Self::read_chunk(&mut file, &mut chunk)?;
+        let decode_as = |file: &mut std::fs::File, chunk: &mut [u8], read: usize, encoding: FileEncoding| {
+            let mut decoder = EncodingDecoder::new(encoding);
+            let disk = Self::read_chunks(file, chunk, read, |bytes, last| decoder.feed(bytes, last))?;
+            Ok::<_, CoreError>((decoder.finish(), disk))
+        };
+
+        let ((content, file_encoding, had_errors), disk) = match encoding {
+            Some(encoding) => decode_as(&mut file, &mut chunk, read, encoding)?,
+            None => {
+                if encoding::looks_binary(&chunk[..read]) {
+                    return Err(CoreError::BinaryFile(path.to_path_buf()));
+                }
+                let mut decoder = ChunkDecoder::sniff(&chunk[..read]);
+                let disk = Self::read_chunks(&mut file, &mut chunk, read, |bytes, last| decoder.feed(bytes, last))?;
+                match decoder.finish() {
+                    Ok(decoded) => (decoded, disk),
+                    Err(guess) => {
+                        // The file may change between the passes: the fingerprint
+                        // describes the bytes that were decoded into the buffer
+                        let mut file = std::fs::File::open(path)?;
+                        let read = Self::read_chunk(&mut file, &mut chunk)?;
+                        decode_as(&mut file, &mut chunk, read, guess)?
+                    }
+                }
+            }
+        };
+        if had_errors {
+            tracing::warn!("{} is not valid {}, some bytes were replaced", path.display(), file_encoding.name());
+        }
+        Ok(LoadedFile { content, encoding: file_encoding, mode: BufferMode::LargeFile, disk })
+    }
+
+    /// Feed the rest of `file` to `feed` chunk by chunk, starting with the
+    /// `read` bytes already in `chunk`, and fingerprint what was read
+    fn read_chunks(
+        file: &mut std::fs::File,
+        chunk: &mut [u8],
+        mut read: usize,
+        mut feed: impl FnMut(&[u8], bool),
+    ) -> Result<DiskFingerprint, CoreError> {
+        let meta = file.metadata()?;
+        let mut hasher = ContentHasher::default();
+        loop {
+            hasher.update(&chunk[..read]);
+            let last = read < chunk.len();
+            feed(&chunk[..read], last);
+            if last {
+                return Ok(hasher.finish(&meta));
+            }
+            read = Self::read_chunk(file, chunk)?;
+        }
+    }
+
+    /// Fill `chunk` from `file`; fewer bytes than its length means end of file
+    fn read_chunk(file: &mut std::fs::File, chunk: &mut [u8]) -> std::io::Result<usize> {
+        use std::io::Read;
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match file.read(&mut chunk[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    fn decode_file(
+        path: &Path,
+        bytes: &[u8],
+        meta: &std::fs::Metadata,
+        mode: BufferMode,
+    ) -> Result<LoadedFile, CoreError> {
+        let disk = DiskFingerprint::new(bytes, meta);
+        if encoding::looks_binary(bytes) {
+            if mode == BufferMode::LargeFile {
+                return Err(CoreError::BinaryFile(path.to_path_buf()));
+            }
+            return Ok(LoadedFile {
+                content: Rope::from_str(&encoding::hex_dump(bytes)),
+                encoding: FileEncoding::default(),
+                mode: BufferMode::Binary,
+                disk,
+            });
+        }
+
+        let decoded = encoding::decode(bytes);
+        if decoded.had_errors {
+            tracing::warn!("{} is not valid {}, some bytes were replaced", path.display(), decoded.encoding.name());
+        }
+        Ok(LoadedFile { content: Rope::from_str(&decoded.text), encoding: decoded.encoding, mode, disk })
+    }
+
+    /// Detect line ending style from rope content
+    fn detect_line_ending_in(content: &Rope) -> LineEnding {
+        let mut has_cr = false;
+        let mut prev_cr = false;
+        for chunk in content.chunks() {
+            // A CRLF pair may be split between chunks
+            if prev_cr && chunk.starts_with('\n') || chunk.contains("\r\n") {
+                return LineEnding::Windows;
+            }
+            has_cr |= chunk.contains('\r');
+            prev_cr = chunk.ends_with('\r');
+        }
+        if has_cr {
+            LineEnding::Mac
+        } else {
+            LineEnding::Unix
+        }
+    }
+
     /// Detect line ending style from content
     fn detect_line_ending(content: &str) -> LineEnding {
         if content.contains("\r\n") {
@@ -401,44 +1429,21 @@ impl BufferManager {
         }
     }
 
-    /// Detect language from file path
-    fn detect_language(path: &Path) -> Option<String> {
-        path.extension()
-            .and_then(|ext| ext.to_str())
-            .and_then(|ext| match ext.to_lowercase().as_str() {
-                "rs" => Some("rust"),
-                "js" | "jsx" => Some("javascript"),
-                "ts" | "tsx" => Some("typescript"),
-                "py" => Some("python"),
-                "go" => Some("go"),
-                "c" => Some("c"),
-                "cpp" | "cxx" | "cc" => Some("cpp"),
-                "h" | "hpp" => Some("c"),
-                "java" => Some("java"),
-                "json" => Some("json"),
-                "toml" => Some("toml"),
-                "yaml" | "yml" => Some("yaml"),
-                "html" => Some("html"),
-                "css" => Some("css"),
-                "md" => Some("markdown"),
-                _ => None,
-            })
-            .map(|s| s.to_string())
-    }
-
     /// Parse buffer syntax with tree-sitter
     async fn parse_buffer(
         &mut self,
         buffer: &mut TextBuffer,
         language: &str,
     ) -> Result<(), CoreError> {
+        if self.languages.get(language).is_none_or(|definition| definition.grammar.is_none()) {
+            // Language has no grammar, nothing to parse
+            return Ok(());
+        }
         // Get or create parser for this language
         let parser = self.get_or_create_parser(language)?;
 
         // Parse the buffer content
-        let content_str = buffer.content.to_string();
-        let tree = parser
-            .parse(&content_str, buffer.syntax_tree.as_ref())
+        let tree = syntax::parse_rope(parser, &buffer.content, buffer.syntax_tree.as_ref())
             .ok_or_else(|| CoreError::ParseError("Failed to parse buffer".to_string()))?;
 
         buffer.syntax_tree = Some(tree);
@@ -448,56 +1453,16 @@ impl BufferManager {
     /// Get or create parser for language
     fn get_or_create_parser(&mut self, language: &str) -> Result<&mut Parser, CoreError> {
         if !self.parsers.contains_key(language) {
+            let grammar = self
+                .languages
+                .get(language)
+                .and_then(|definition| definition.grammar.as_ref())
+                .ok_or_else(|| CoreError::UnsupportedLanguage(language.to_string()))?;
             let mut parser = Parser::new();
-
-            // Set language-specific tree-sitter parser
-            match language.to_lowercase().as_str() {
-                "rust" => {
-                    let rust_language = tree_sitter_rust::LANGUAGE.into();
-                    parser.set_language(&rust_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set Rust language: {}", e))
-                    })?;
-                    tracing::info!("Initialized Rust parser");
-                }
-                "javascript" => {
-                    let js_language = tree_sitter_javascript::LANGUAGE.into();
-                    parser.set_language(&js_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set JavaScript language: {}", e))
-                    })?;
-                    tracing::info!("Initialized JavaScript parser");
-                }
-                "typescript" => {
-                    let ts_language = tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into();
-                    parser.set_language(&ts_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set TypeScript language: {}", e))
-                    })?;
-                    tracing::info!("Initialized TypeScript parser");
-                }
-                "python" => {
-                    let py_language = tree_sitter_python::LANGUAGE.into();
-                    parser.set_language(&py_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set Python language: {}", e))
-                    })?;
-                    tracing::info!("Initialized Python parser");
-                }
-                "json" => {
-                    let json_language = tree_sitter_json::LANGUAGE.into();
-                    parser.set_language(&json_language).map_err(|e| {
-                        CoreError::ParseError(format!("Failed to set JSON language: {}", e))
-                    })?;
-                    tracing::info!("Initialized JSON parser");
-                }
-                "markdown" => {
-                    // Markdown support temporarily disabled due to tree-sitter ABI incompatibility
-                    tracing::warn!("Markdown syntax highlighting temporarily disabled");
-                    // Don't fail, just use parser without language set
-                }
-                _ => {
-                    tracing::warn!("Language '{}' not supported, using generic parser without syntax highlighting", language);
-                    // Don't set a language for unsupported types - parser will work as plain text
-                }
-            }
-
+            parser.set_language(grammar).map_err(|e| {
+                CoreError::ParseError(format!("Failed to set {} language: {}", language, e))
+            })?;
+            tracing::info!("Initialized {} parser", language);
             self.parsers.insert(language.to_string(), parser);
         }
 
@@ -507,20 +1472,14 @@ impl BufferManager {
             .expect("Parser must exist after successful insertion"))
     }
 
-    /// Convert position to byte index in rope
-    #[allow(dead_code)]
-    fn position_to_byte_idx(&self, rope: &Rope, position: Position) -> usize {
-        Self::position_to_byte_idx_static(rope, position)
+    /// Convert position to char index in rope, rejecting positions outside the text
+    fn position_to_char_idx_checked(rope: &Rope, position: Position) -> Result<usize, CoreError> {
+        position.to_char_idx(rope, PositionEncoding::Char)
     }
 
-    /// Convert position to byte index in rope (static version)
-    fn position_to_byte_idx_static(rope: &Rope, position: Position) -> usize {
-        let line_start = rope.line_to_byte(position.line.min(rope.len_lines().saturating_sub(1)));
-        let line = rope.line(position.line);
-        let column_bytes = line
-            .byte_slice(0..position.column.min(line.len_chars()))
-            .len_bytes();
-        line_start + column_bytes
+    /// Convert char index in rope to position
+    fn char_idx_to_position(rope: &Rope, char_idx: usize) -> Position {
+        Position::from_char_idx(rope, char_idx, PositionEncoding::Char)
     }
 
     /// Validate and canonicalize save path to prevent path traversal attacks
@@ -538,6 +1497,10 @@ impl BufferManager {
             workspace_root.join(requested_path)
         };
 
+        // The file actually written is the end of a symlink chain. A dangling
+        // link does not canonicalize, so follow it before the check below.
+        let resolved_path = save::resolve_symlinks(&resolved_path)?;
+
         // Canonicalize to resolve .. and symlinks
         let canonical_path = resolved_path.canonicalize().or_else(|_| {
             // If file doesn't exist yet, canonicalize parent and append filename
@@ -564,26 +1527,22 @@ impl BufferManager {
         })?;
 
         if !canonical_path.starts_with(&canonical_workspace) {
-            return Err(CoreError::IoErrorString(format!(
-                "Path traversal detected: {} is outside workspace {}",
-                canonical_path.display(),
-                canonical_workspace.display()
-            )));
+            return Err(CoreError::PathOutsideWorkspace {
+                path: canonical_path,
+                workspace: canonical_workspace,
+            });
         }
 
         Ok(canonical_path)
     }
+}
 
-    /// Convert rope to string with specific line endings
-    fn rope_to_string_with_endings(&self, rope: &Rope, line_ending: &LineEnding) -> String {
-        let content = rope.to_string();
-        match line_ending {
-            LineEnding::Unix => content.replace("\r\n", "\n").replace('\r', "\n"),
-            LineEnding::Windows => content
-                .replace("\r\n", "\n")
-                .replace('\r', "\n")
-                .replace('\n', "\r\n"),
-            LineEnding::Mac => content.replace("\r\n", "\n").replace('\n', "\r"),
+impl From<atom_settings::EndOfLine> for LineEnding {
+    fn from(end_of_line: atom_settings::EndOfLine) -> Self {
+        match end_of_line {
+            atom_settings::EndOfLine::Lf => LineEnding::Unix,
+            atom_settings::EndOfLine::Crlf => LineEnding::Windows,
+            atom_settings::EndOfLine::Cr => LineEnding::Mac,
         }
     }
 }
@@ -0,0 +1,209 @@
+//! Column conversions between position encodings
+//!
+//! `Position::column` counts chars. Language servers count UTF-16 code units
+//! (or UTF-8 bytes, if negotiated), tree-sitter counts bytes and the cursor
+//! steps over grapheme clusters. Columns convert through the char column on
+//! the same line: a column inside a char or cluster (the low half of a
+//! surrogate pair, a continuation byte, a combining mark) rounds down to its
+//! start. Columns past the end of the line content are errors.
+
+use std::borrow::Cow;
+
+use ropey::{Rope, RopeSlice};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{line_terminator_len, CoreError, Position};
+
+/// Unit columns are counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PositionEncoding {
+    /// UTF-8 bytes (tree-sitter, LSP `utf-8`)
+    Utf8,
+    /// UTF-16 code units (the LSP default)
+    Utf16,
+    /// Unicode scalar values (LSP `utf-32`): the columns of `Position` and IPC ranges
+    #[default]
+    Char,
+    /// Grapheme clusters: what the user sees as one character
+    Grapheme,
+}
+
+/// Line `index` of `text` without its line break
+fn line_content(text: &Rope, index: usize) -> RopeSlice<'_> {
+    let line = text.line(index);
+    line.slice(..line.len_chars() - line_terminator_len(&line))
+}
+
+/// Length of a line's content in `encoding` units
+fn len(line: RopeSlice, encoding: PositionEncoding) -> usize {
+    match encoding {
+        PositionEncoding::Utf8 => line.len_bytes(),
+        PositionEncoding::Utf16 => line.len_utf16_cu(),
+        PositionEncoding::Char => line.len_chars(),
+        PositionEncoding::Grapheme => Cow::<str>::from(line).graphemes(true).count(),
+    }
+}
+
+/// Char column of `column`, counted in `encoding` units on a line's content
+fn char_column(line: RopeSlice, column: usize, encoding: PositionEncoding) -> usize {
+    match encoding {
+        PositionEncoding::Utf8 => line.byte_to_char(column),
+        PositionEncoding::Utf16 => line.utf16_cu_to_char(column),
+        PositionEncoding::Char => column,
+        PositionEncoding::Grapheme => {
+            let content = Cow::<str>::from(line);
+            let byte = content.grapheme_indices(true).nth(column).map_or(content.len(), |(byte, _)| byte);
+            line.byte_to_char(byte)
+        }
+    }
+}
+
+/// Column in `encoding` units of char column `column` on a line's content
+fn encoded_column(line: RopeSlice, column: usize, encoding: PositionEncoding) -> usize {
+    match encoding {
+        PositionEncoding::Utf8 => line.char_to_byte(column),
+        PositionEncoding::Utf16 => line.char_to_utf16_cu(column),
+        PositionEncoding::Char => column,
+        PositionEncoding::Grapheme => {
+            let byte = line.char_to_byte(column);
+            let content = Cow::<str>::from(line);
+            content.grapheme_indices(true).take_while(|(start, grapheme)| start + grapheme.len() <= byte).count()
+        }
+    }
+}
+
+fn unit_name(encoding: PositionEncoding) -> &'static str {
+    match encoding {
+        PositionEncoding::Utf8 => "bytes",
+        PositionEncoding::Utf16 => "UTF-16 units",
+        PositionEncoding::Char => "chars",
+        PositionEncoding::Grapheme => "graphemes",
+    }
+}
+
+impl Position {
+    /// Char offset in `text` of this position, its column counted in
+    /// `encoding` units; positions outside the text are rejected
+    pub fn to_char_idx(self, text: &Rope, encoding: PositionEncoding) -> Result<usize, CoreError> {
+        if self.line >= text.len_lines() {
+            return Err(CoreError::InvalidEdit(format!(
+                "line {} is out of range ({} lines)",
+                self.line,
+                text.len_lines()
+            )));
+        }
+        let line = line_content(text, self.line);
+        let line_len = len(line, encoding);
+        if self.column > line_len {
+            return Err(CoreError::InvalidEdit(format!(
+                "column {} is out of range on line {} ({} {})",
+                self.column,
+                self.line,
+                line_len,
+                unit_name(encoding)
+            )));
+        }
+        Ok(text.line_to_char(self.line) + char_column(line, self.column, encoding))
+    }
+
+    /// Position of char offset `char_idx` in `text`, its column counted in
+    /// `encoding` units
+    pub fn from_char_idx(text: &Rope, char_idx: usize, encoding: PositionEncoding) -> Position {
+        let line = text.char_to_line(char_idx);
+        let column = char_idx - text.line_to_char(line);
+        // Line breaks count one unit per char, like in every encoding
+        Position { line, column: encoded_column(text.line(line), column, encoding) }
+    }
+
+    /// The same position with its column counted in `to` units instead of `from` units
+    pub fn convert(self, text: &Rope, from: PositionEncoding, to: PositionEncoding) -> Result<Position, CoreError> {
+        if from == to {
+            // Still validated, like every conversion
+            self.to_char_idx(text, from)?;
+            return Ok(self);
+        }
+        Ok(Position::from_char_idx(text, self.to_char_idx(text, from)?, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const ALL: [PositionEncoding; 4] =
+        [PositionEncoding::Utf8, PositionEncoding::Utf16, PositionEncoding::Char, PositionEncoding::Grapheme];
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn counts_columns_in_each_encoding() {
+        // é is 2 bytes, 中 3 bytes, 😀 4 bytes and 2 UTF-16 units, e + U+0301 one grapheme
+        let text = Rope::from_str("aé中😀e\u{301}x\r\nnext");
+        let end_of_x = at(0, 7);
+        let columns: Vec<_> =
+            ALL.iter().map(|&encoding| end_of_x.convert(&text, PositionEncoding::Char, encoding).unwrap().column).collect();
+        assert_eq!(columns, [14, 8, 7, 6]);
+        for (&encoding, column) in ALL.iter().zip(columns) {
+            assert_eq!(at(0, column).to_char_idx(&text, encoding).unwrap(), 7);
+        }
+
+        // Inside the emoji and the cluster: rounded down to their start
+        assert_eq!(at(0, 4).to_char_idx(&text, PositionEncoding::Utf16).unwrap(), 3);
+        assert_eq!(at(0, 8).to_char_idx(&text, PositionEncoding::Utf8).unwrap(), 3);
+        assert_eq!(at(0, 5).convert(&text, PositionEncoding::Char, PositionEncoding::Grapheme).unwrap(), at(0, 4));
+        // The line break is not part of the line
+        assert!(at(0, 9).to_char_idx(&text, PositionEncoding::Utf16).is_err());
+        assert!(at(2, 0).to_char_idx(&text, PositionEncoding::Char).is_err());
+        // Between CR and LF
+        assert_eq!(Position::from_char_idx(&text, 8, PositionEncoding::Utf16), at(0, 9));
+    }
+
+    /// Lines mixing ASCII, accents, CJK, emoji (with ZWJ sequences and
+    /// skin tones) and combining marks
+    fn mixed_text() -> impl Strategy<Value = String> {
+        let piece = prop::sample::select(vec![
+            "a", " ", "é", "e\u{301}", "中", "文", "😀", "👍🏽", "👨\u{200d}👩\u{200d}👧", "🇷🇺", "\t", "\n", "\r\n",
+        ]);
+        prop::collection::vec(piece, 0..40).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn columns_round_trip_through_every_encoding(source in mixed_text()) {
+            let text = Rope::from_str(&source);
+            for line in 0..text.len_lines() {
+                for column in 0..=line_content(&text, line).len_chars() {
+                    let position = Position { line, column };
+                    let char_idx = position.to_char_idx(&text, PositionEncoding::Char).unwrap();
+                    for encoding in ALL {
+                        let encoded = position.convert(&text, PositionEncoding::Char, encoding).unwrap();
+                        let back = encoded.to_char_idx(&text, encoding).unwrap();
+                        if encoding == PositionEncoding::Grapheme {
+                            // Rounded down to the start of the cluster
+                            prop_assert!(back <= char_idx);
+                            prop_assert_eq!(Position::from_char_idx(&text, back, encoding), encoded);
+                        } else {
+                            prop_assert_eq!(back, char_idx);
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn utf16_columns_match_std(source in mixed_text()) {
+            let text = Rope::from_str(&source);
+            for (index, line) in source.split('\n').enumerate() {
+                let content = line.strip_suffix('\r').unwrap_or(line);
+                let end = Position { line: index, column: content.chars().count() };
+                let utf16 = end.convert(&text, PositionEncoding::Char, PositionEncoding::Utf16).unwrap();
+                prop_assert_eq!(utf16.column, content.encode_utf16().count());
+                let utf8 = end.convert(&text, PositionEncoding::Char, PositionEncoding::Utf8).unwrap();
+                prop_assert_eq!(utf8.column, content.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Crash-safe file writes
+//!
+//! The new content goes to a temporary file next to the target, is flushed
+//! to disk, and then renamed over the target in one step. A crash or a full
+//! disk at any point leaves either the old file or the new one, never a
+//! truncated mix. Permissions and (on Unix) ownership of the old file carry
+//! over, and a symlinked target is replaced at the end of the link, keeping
+//! the link itself.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Atomically replace the contents of `path` with `bytes`
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_atomic_with(path, |file| file.write_all(bytes))
+}
+
+/// Atomically replace the contents of `path` with whatever `write` puts into
+/// the temporary file. If `write` fails, the target is left untouched.
+pub fn write_atomic_with(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let existing = fs::metadata(&target).ok();
+
+    let (tmp_path, mut file) = create_temp(&dir, &target)?;
+    let result = (|| {
+        write(&mut file)?;
+        file.sync_all()?;
+        if let Some(meta) = &existing {
+            file.set_permissions(meta.permissions())?;
+            #[cfg(unix)]
+            keep_owner(&file, meta)?;
+        }
+        drop(file);
+        fs::rename(&tmp_path, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    File::open(&dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Follow a chain of symlinks to the file that should actually be replaced
+pub(crate) fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut current = path.to_path_buf();
+    // Same limit as the kernel's ELOOP check
+    for _ in 0..40 {
+        match fs::symlink_metadata(&current) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let link = fs::read_link(&current)?;
+                current = match current.parent() {
+                    Some(parent) if link.is_relative() => parent.join(link),
+                    _ => link,
+                };
+            }
+            // A missing target is created; a dangling link gets its target created
+            Ok(_) | Err(_) => return Ok(current),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Too many levels of symbolic links: {}", path.display())))
+}
+
+fn create_temp(dir: &Path, target: &Path) -> io::Result<(PathBuf, File)> {
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    loop {
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = dir.join(format!(".{}.{}-{}.atom-save", name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Give the new file the owner and group of the old one. Without the right
+/// to do so (a user saving someone else's file in a shared directory) the
+/// file stays owned by the current user.
+#[cfg(unix)]
+fn keep_owner(file: &File, meta: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    match std::os::unix::fs::fchown(file, Some(meta.uid()), Some(meta.gid())) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".atom-save"))
+            .collect()
+    }
+
+    #[test]
+    fn failed_write_keeps_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        fs::write(&path, "fn main() {}\n").unwrap();
+
+        let err = write_atomic_with(&path, |file| {
+            file.write_all(b"fn ma")?;
+            Err(io::Error::other("no space left on device"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "no space left on device");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {}\n");
+        assert!(leftovers(dir.path()).is_empty());
+
+        write_atomic(&path, b"fn main() { run() }\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() { run() }\n");
+        assert!(leftovers(dir.path()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("deploy.sh");
+        fs::write(&real, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o750)).unwrap();
+        let link = dir.path().join("current.sh");
+        std::os::unix::fs::symlink("deploy.sh", &link).unwrap();
+
+        write_atomic(&link, b"#!/bin/sh\nexit 0\n").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "#!/bin/sh\nexit 0\n");
+        assert_eq!(fs::metadata(&real).unwrap().permissions().mode() & 0o777, 0o750);
+    }
+}
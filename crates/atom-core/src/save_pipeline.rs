@@ -0,0 +1,210 @@
+//! Transforms applied to a buffer when it is saved
+//!
+//! Before writing, the buffer content runs through a pipeline of transforms
+//! (trailing whitespace, final newline, line endings). Each transform looks
+//! at the text left by the previous one and returns the replacements it
+//! wants. The collected edits are applied to the buffer once the file is
+//! written, so the buffer and its clients end up with exactly what is on disk.
+
+use ropey::Rope;
+
+use crate::{line_terminator_len, Change, LineEnding, Position, Range, TextEdit};
+
+/// Per-buffer options the transforms act on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveOptions {
+    pub trim_trailing_whitespace: bool,
+    pub insert_final_newline: bool,
+    pub line_ending: LineEnding,
+}
+
+/// One step of the save pipeline
+pub trait SaveTransform: Send + Sync {
+    /// Name for logs
+    fn name(&self) -> &'static str;
+
+    /// Replacements to make in `text`, in application order. Offsets of each
+    /// change refer to the text with the previous changes already applied.
+    fn changes(&self, text: &Rope, options: &SaveOptions) -> Vec<Change>;
+}
+
+/// Ordered list of save transforms
+pub struct SavePipeline {
+    transforms: Vec<Box<dyn SaveTransform>>,
+}
+
+impl Default for SavePipeline {
+    /// Whitespace trimming, final newline and line ending conversion
+    fn default() -> Self {
+        Self {
+            transforms: vec![
+                Box::new(TrimTrailingWhitespace),
+                Box::new(InsertFinalNewline),
+                Box::new(NormalizeLineEndings),
+            ],
+        }
+    }
+}
+
+impl SavePipeline {
+    /// Pipeline without transforms: buffers are written as they are
+    pub fn empty() -> Self {
+        Self { transforms: Vec::new() }
+    }
+
+    /// Append a transform to run after the existing ones
+    pub fn push(&mut self, transform: impl SaveTransform + 'static) {
+        self.transforms.push(Box::new(transform));
+    }
+
+    /// Run all transforms over `text`. Returns the text to write and the
+    /// edits that turn `text` into it, in application order.
+    pub fn run(&self, text: &Rope, options: &SaveOptions) -> (Rope, Vec<TextEdit>) {
+        let mut text = text.clone();
+        let mut edits = Vec::new();
+        for transform in &self.transforms {
+            let changes = transform.changes(&text, options);
+            if !changes.is_empty() {
+                tracing::debug!("Save transform {} made {} changes", transform.name(), changes.len());
+            }
+            for change in changes {
+                let end = change.start + change.old_text.chars().count();
+                edits.push(TextEdit {
+                    range: Range { start: position(&text, change.start), end: position(&text, end) },
+                    new_text: change.new_text.clone(),
+                });
+                text.remove(change.start..end);
+                text.insert(change.start, &change.new_text);
+            }
+        }
+        (text, edits)
+    }
+}
+
+fn position(text: &Rope, char_idx: usize) -> Position {
+    let line = text.char_to_line(char_idx);
+    Position { line, column: char_idx - text.line_to_char(line) }
+}
+
+/// Removes whitespace at the end of every line
+pub struct TrimTrailingWhitespace;
+
+impl SaveTransform for TrimTrailingWhitespace {
+    fn name(&self) -> &'static str {
+        "trim_trailing_whitespace"
+    }
+
+    fn changes(&self, text: &Rope, options: &SaveOptions) -> Vec<Change> {
+        if !options.trim_trailing_whitespace {
+            return Vec::new();
+        }
+        // Bottom-up, so that earlier offsets stay valid
+        let mut changes = Vec::new();
+        for line_idx in (0..text.len_lines()).rev() {
+            let line = text.line(line_idx);
+            let len = line.len_chars() - line_terminator_len(&line);
+            let trailing = line.slice(..len).chars_at(len).reversed().take_while(|c| c.is_whitespace()).count();
+            if trailing > 0 {
+                changes.push(Change {
+                    start: text.line_to_char(line_idx) + len - trailing,
+                    old_text: line.slice(len - trailing..len).to_string(),
+                    new_text: String::new(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// Ends a non-empty file with a line break
+pub struct InsertFinalNewline;
+
+impl SaveTransform for InsertFinalNewline {
+    fn name(&self) -> &'static str {
+        "insert_final_newline"
+    }
+
+    fn changes(&self, text: &Rope, options: &SaveOptions) -> Vec<Change> {
+        // The last line is empty exactly when the text ends with a line break
+        if !options.insert_final_newline || text.line(text.len_lines() - 1).len_chars() == 0 {
+            return Vec::new();
+        }
+        vec![Change {
+            start: text.len_chars(),
+            old_text: String::new(),
+            new_text: options.line_ending.as_str().to_string(),
+        }]
+    }
+}
+
+/// Converts CR, LF and CRLF line breaks to the buffer's line ending
+pub struct NormalizeLineEndings;
+
+impl SaveTransform for NormalizeLineEndings {
+    fn name(&self) -> &'static str {
+        "normalize_line_endings"
+    }
+
+    fn changes(&self, text: &Rope, options: &SaveOptions) -> Vec<Change> {
+        let target = options.line_ending.as_str();
+        let mut changes = Vec::new();
+        let mut chars = text.chars().enumerate().peekable();
+        while let Some((idx, c)) = chars.next() {
+            let found = match c {
+                '\r' if chars.next_if(|&(_, next)| next == '\n').is_some() => "\r\n",
+                '\r' => "\r",
+                '\n' => "\n",
+                _ => continue,
+            };
+            if found != target {
+                changes.push(Change { start: idx, old_text: found.to_string(), new_text: target.to_string() });
+            }
+        }
+        // Bottom-up, so that earlier offsets stay valid
+        changes.reverse();
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(line_ending: LineEnding) -> SaveOptions {
+        SaveOptions { trim_trailing_whitespace: true, insert_final_newline: true, line_ending }
+    }
+
+    /// Apply the reported edits to the original text the way a client would
+    fn replay(original: &str, edits: &[TextEdit]) -> String {
+        let mut text = Rope::from_str(original);
+        for edit in edits {
+            let start = text.line_to_char(edit.range.start.line) + edit.range.start.column;
+            let end = text.line_to_char(edit.range.end.line) + edit.range.end.column;
+            text.remove(start..end);
+            text.insert(start, &edit.new_text);
+        }
+        text.to_string()
+    }
+
+    #[test]
+    fn default_pipeline_cleans_up_and_reports_edits() {
+        let original = "fn main() {  \r\n\tlet x = 1;\t\n  \n}";
+        let (text, edits) = SavePipeline::default().run(&Rope::from_str(original), &options(LineEnding::Unix));
+        assert_eq!(text.to_string(), "fn main() {\n\tlet x = 1;\n\n}\n");
+        assert_eq!(replay(original, &edits), text.to_string());
+
+        let (text, edits) = SavePipeline::default().run(&Rope::from_str(original), &options(LineEnding::Windows));
+        assert_eq!(text.to_string(), "fn main() {\r\n\tlet x = 1;\r\n\r\n}\r\n");
+        assert_eq!(replay(original, &edits), text.to_string());
+    }
+
+    #[test]
+    fn disabled_options_keep_text() {
+        let original = "a \nb";
+        let options = SaveOptions { trim_trailing_whitespace: false, insert_final_newline: false, line_ending: LineEnding::Unix };
+        let (text, edits) = SavePipeline::default().run(&Rope::from_str(original), &options);
+        assert_eq!(text.to_string(), original);
+        assert!(edits.is_empty());
+        assert!(SavePipeline::default().run(&Rope::new(), &self::options(LineEnding::Unix)).1.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+//! Structural selection over syntax trees
+//!
+//! Expanding selects the smallest node that strictly contains the selection;
+//! shrinking selects the first named child of the node the selection covers.
+//! Both work on byte ranges; the buffer manager converts them to positions.
+
+use std::ops::Range;
+
+use tree_sitter::{Node, Tree};
+
+/// Smallest node covering `range`, if the tree has one
+fn covering_node<'tree>(tree: &'tree Tree, range: &Range<usize>) -> Option<Node<'tree>> {
+    tree.root_node().descendant_for_byte_range(range.start, range.end)
+}
+
+/// Range of the smallest syntax node strictly containing `range`; `range`
+/// itself once the whole tree is selected
+pub(crate) fn expand(tree: &Tree, range: Range<usize>) -> Range<usize> {
+    let mut node = covering_node(tree, &range);
+    while let Some(current) = node {
+        if current.byte_range() != range {
+            return current.byte_range();
+        }
+        node = current.parent();
+    }
+    range
+}
+
+/// Range of the first named child of the node `range` covers; `range` itself
+/// if there is nothing smaller to select
+pub(crate) fn shrink(tree: &Tree, range: Range<usize>) -> Range<usize> {
+    let Some(node) = covering_node(tree, &range) else {
+        return range;
+    };
+    // A selection not aligned to nodes shrinks to the first node inside it
+    let node = if node.byte_range() == range { node.named_child(0) } else { first_named_inside(node, &range) };
+    node.map_or(range, |node| node.byte_range())
+}
+
+/// First named node of `node`'s subtree lying inside `range`
+fn first_named_inside<'tree>(node: Node<'tree>, range: &Range<usize>) -> Option<Node<'tree>> {
+    let mut cursor = node.walk();
+    let children: Vec<_> = node.named_children(&mut cursor).collect();
+    children.into_iter().find_map(|child| {
+        if range.start <= child.start_byte() && child.end_byte() <= range.end {
+            Some(child)
+        } else if child.start_byte() < range.end && range.start < child.end_byte() {
+            first_named_inside(child, range)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rust_tree(source: &str) -> Tree {
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(&tree_sitter_rust::LANGUAGE.into()).unwrap();
+        parser.parse(source, None).unwrap()
+    }
+
+    #[test]
+    fn expands_and_shrinks_through_nodes() {
+        let source = "fn main() { let x = foo(1, 2); }";
+        let tree = rust_tree(source);
+        let text = |range: &Range<usize>| &source[range.clone()];
+
+        // From a cursor inside `let`
+        let mut selection = 13..13;
+        let mut texts = Vec::new();
+        for _ in 0..4 {
+            selection = expand(&tree, selection);
+            texts.push(text(&selection));
+        }
+        assert_eq!(texts, ["let", "let x = foo(1, 2);", "{ let x = foo(1, 2); }", source]);
+        assert_eq!(expand(&tree, 0..source.len()), 0..source.len());
+
+        let call = source.find("foo(1, 2)").unwrap();
+        let call = call..call + "foo(1, 2)".len();
+        assert_eq!(text(&shrink(&tree, call)), "foo");
+        // Unaligned selection: the first whole node inside it
+        assert_eq!(text(&shrink(&tree, 24..29)), "1");
+        assert_eq!(shrink(&tree, 0..2), 0..2);
+    }
+}
@@ -0,0 +1,271 @@
+//! Document symbols from tree-sitter `tags.scm` queries
+//!
+//! Every `@definition.<kind>` capture with its `@name` becomes a symbol;
+//! references are ignored. Symbols nest by the ranges of their definition
+//! nodes, so methods end up inside their classes. The queries do not always
+//! tell functions and methods apart (Python has no methods, Rust calls every
+//! function in a `mod` a method), so the kind follows the parent: a method
+//! only inside a class, interface or impl. For one node matched by several
+//! patterns, the first wins.
+
+use std::collections::HashMap;
+
+use atom_ipc::{DocumentSymbol, SymbolKind, TextRange};
+use ropey::Rope;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Parser, Query, QueryCursor, Tree};
+
+use crate::{syntax, CoreError, LanguageDefinition};
+
+/// Impl blocks, which the Rust query only has as references, hold methods
+pub(crate) const RUST_IMPL_TAGS: &str = "(impl_item type: (_) @name) @definition.implementation";
+
+/// Symbol kind of a `definition.<kind>` capture name
+fn definition_kind(name: &str) -> Option<SymbolKind> {
+    match name.strip_prefix("definition.")? {
+        "class" => Some(SymbolKind::Class),
+        "constant" => Some(SymbolKind::Constant),
+        "function" => Some(SymbolKind::Function),
+        "implementation" => Some(SymbolKind::Implementation),
+        "interface" => Some(SymbolKind::Interface),
+        "macro" => Some(SymbolKind::Macro),
+        "method" => Some(SymbolKind::Method),
+        "module" => Some(SymbolKind::Module),
+        _ => None,
+    }
+}
+
+struct TagsQuery {
+    query: Query,
+    /// Index of the `@name` capture
+    name: u32,
+    /// Symbol kind per capture index, for definition captures
+    kinds: Vec<Option<SymbolKind>>,
+}
+
+/// A definition found by the query, before nesting
+struct Definition {
+    node: std::ops::Range<usize>,
+    name: std::ops::Range<usize>,
+    pattern: usize,
+    kind: SymbolKind,
+}
+
+/// Compiled tags queries and parsers, per language
+#[derive(Default)]
+pub struct SymbolExtractor {
+    queries: HashMap<String, Option<TagsQuery>>,
+    parsers: HashMap<String, Parser>,
+}
+
+impl SymbolExtractor {
+    /// Symbols of `text`, parsed as `tree`, as a tree of nested definitions
+    pub fn document_symbols(
+        &mut self,
+        language: &LanguageDefinition,
+        text: &Rope,
+        tree: &Tree,
+    ) -> Result<Vec<DocumentSymbol>, CoreError> {
+        let Some(query) = self.query(language, tree)? else {
+            return Ok(Vec::new());
+        };
+
+        let mut definitions = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let node_text = |node: tree_sitter::Node| text.byte_slice(node.byte_range()).chunks().map(str::as_bytes);
+        let mut matches = cursor.matches(&query.query, tree.root_node(), node_text);
+        while let Some(m) = matches.next() {
+            let name = m.captures.iter().find(|c| c.index == query.name);
+            let definition = m.captures.iter().find_map(|c| Some((c.node, query.kinds[c.index as usize]?)));
+            if let (Some(name), Some((node, kind))) = (name, definition) {
+                definitions.push(Definition {
+                    node: node.byte_range(),
+                    name: name.node.byte_range(),
+                    pattern: m.pattern_index,
+                    kind,
+                });
+            }
+        }
+        // Outer definitions before the ones inside them; for one node, the first pattern
+        definitions.sort_by_key(|d| (d.node.start, std::cmp::Reverse(d.node.end), d.pattern));
+        definitions.dedup_by_key(|d| d.node.clone());
+
+        Ok(nest(text, definitions))
+    }
+
+    /// Symbols of a text that has no buffer (workspace indexing).
+    /// Languages without a grammar or tags query have none.
+    pub fn file_symbols(&mut self, language: &LanguageDefinition, text: &Rope) -> Result<Vec<DocumentSymbol>, CoreError> {
+        let (Some(grammar), Some(_)) = (&language.grammar, &language.tags_query) else {
+            return Ok(Vec::new());
+        };
+        let parser = match self.parsers.entry(language.id.clone()) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let mut parser = Parser::new();
+                parser
+                    .set_language(grammar)
+                    .map_err(|e| CoreError::ParseError(format!("Failed to set {} language: {}", language.id, e)))?;
+                entry.insert(parser)
+            }
+        };
+        let tree = syntax::parse_rope(parser, text, None)
+            .ok_or_else(|| CoreError::ParseError(format!("Failed to parse {} source", language.id)))?;
+        self.document_symbols(language, text, &tree)
+    }
+
+    /// Compiled query of a language, `None` if the language has no tags
+    fn query(&mut self, language: &LanguageDefinition, tree: &Tree) -> Result<Option<&TagsQuery>, CoreError> {
+        if !self.queries.contains_key(&language.id) {
+            let compiled = match &language.tags_query {
+                Some(source) => {
+                    let query = Query::new(&tree.language(), source).map_err(|e| {
+                        CoreError::ParseError(format!("Invalid {} tags query: {}", language.id, e))
+                    })?;
+                    let name = query
+                        .capture_index_for_name("name")
+                        .ok_or_else(|| CoreError::ParseError(format!("{} tags query has no @name", language.id)))?;
+                    let kinds = query.capture_names().iter().map(|name| definition_kind(name)).collect();
+                    Some(TagsQuery { query, name, kinds })
+                }
+                None => None,
+            };
+            self.queries.insert(language.id.clone(), compiled);
+        }
+        Ok(self.queries[&language.id].as_ref())
+    }
+}
+
+/// Build the symbol tree from definitions sorted outermost first
+fn nest(text: &Rope, definitions: Vec<Definition>) -> Vec<DocumentSymbol> {
+    let mut roots = Vec::new();
+    // Open definitions, innermost last, with the byte their node ends at
+    let mut open: Vec<(usize, DocumentSymbol)> = Vec::new();
+    for definition in definitions {
+        while open.last().is_some_and(|(end, _)| *end <= definition.node.start) {
+            close(&mut open, &mut roots);
+        }
+        let in_type = matches!(
+            open.last().map(|(_, parent)| parent.kind),
+            Some(SymbolKind::Class | SymbolKind::Interface | SymbolKind::Implementation)
+        );
+        let kind = match definition.kind {
+            SymbolKind::Function if in_type => SymbolKind::Method,
+            SymbolKind::Method if !in_type => SymbolKind::Function,
+            kind => kind,
+        };
+        let symbol = DocumentSymbol {
+            name: text.byte_slice(definition.name.clone()).to_string(),
+            kind,
+            range: text_range(text, &definition.node),
+            selection_range: text_range(text, &definition.name),
+            children: Vec::new(),
+        };
+        open.push((definition.node.end, symbol));
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
+
+/// Attach the innermost open symbol to its parent, or to the roots
+fn close(open: &mut Vec<(usize, DocumentSymbol)>, roots: &mut Vec<DocumentSymbol>) {
+    if let Some((_, symbol)) = open.pop() {
+        match open.last_mut() {
+            Some((_, parent)) => parent.children.push(symbol),
+            None => roots.push(symbol),
+        }
+    }
+}
+
+/// Range of bytes in char columns
+fn text_range(text: &Rope, bytes: &std::ops::Range<usize>) -> TextRange {
+    let position = |byte: usize| {
+        let line = text.byte_to_line(byte);
+        (line, text.byte_to_char(byte) - text.line_to_char(line))
+    };
+    let (start_line, start_column) = position(bytes.start);
+    let (end_line, end_column) = position(bytes.end);
+    TextRange { start_line, start_column, end_line, end_column }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_symbols(name: &str, source: &str) -> Vec<DocumentSymbol> {
+        let language = crate::LanguageRegistry::default().get(name).unwrap().clone();
+        SymbolExtractor::default().file_symbols(&language, &Rope::from_str(source)).unwrap()
+    }
+
+    /// (depth, name, kind) of every symbol, depth first
+    fn outline(language: &str, source: &str) -> Vec<(usize, String, SymbolKind)> {
+        fn walk(symbols: &[DocumentSymbol], depth: usize, out: &mut Vec<(usize, String, SymbolKind)>) {
+            for symbol in symbols {
+                out.push((depth, symbol.name.clone(), symbol.kind));
+                walk(&symbol.children, depth + 1, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(&file_symbols(language, source), 0, &mut out);
+        out
+    }
+
+    #[test]
+    fn nests_python_methods_in_classes() {
+        let source = "LIMIT = 3\n\nclass Greeter:\n    def hello(self):\n        def inner():\n            pass\n\ndef main():\n    Greeter().hello()\n";
+        assert_eq!(
+            outline("python", source),
+            [
+                (0, "LIMIT".into(), SymbolKind::Constant),
+                (0, "Greeter".into(), SymbolKind::Class),
+                (1, "hello".into(), SymbolKind::Method),
+                (2, "inner".into(), SymbolKind::Function),
+                (0, "main".into(), SymbolKind::Function),
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_javascript_and_typescript() {
+        let source = "class Shape {\n  constructor() {}\n  area() { return 0; }\n}\nconst make = () => new Shape();\n";
+        let expected = [
+            (0, "Shape".to_string(), SymbolKind::Class),
+            (1, "area".to_string(), SymbolKind::Method),
+            (0, "make".to_string(), SymbolKind::Function),
+        ];
+        assert_eq!(outline("javascript", source), expected);
+        assert_eq!(outline("typescript", source), expected);
+        assert_eq!(
+            outline("typescript", "interface Named {\n  name(): string;\n}\n"),
+            [(0, "Named".into(), SymbolKind::Interface), (1, "name".into(), SymbolKind::Method)]
+        );
+    }
+
+    #[test]
+    fn nests_rust_methods_in_impls() {
+        let source = "struct Point;\nimpl Point {\n    fn new() -> Self { Point }\n}\ntrait Draw {\n    fn draw(&self);\n}\nfn main() {}\n";
+        assert_eq!(
+            outline("rust", source),
+            [
+                (0, "Point".into(), SymbolKind::Class),
+                (0, "Point".into(), SymbolKind::Implementation),
+                (1, "new".into(), SymbolKind::Method),
+                (0, "Draw".into(), SymbolKind::Interface),
+                (0, "main".into(), SymbolKind::Function),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_ranges_in_char_columns() {
+        let source = "// héllo\nmod outer {\n    fn ünïcode() {}\n}\n";
+        let symbols = file_symbols("rust", source);
+        assert_eq!(symbols.len(), 1);
+        let function = &symbols[0].children[0];
+        assert_eq!((function.name.as_str(), function.kind), ("ünïcode", SymbolKind::Function));
+        assert_eq!(function.selection_range, TextRange { start_line: 2, start_column: 7, end_line: 2, end_column: 14 });
+        assert_eq!(function.range, TextRange { start_line: 2, start_column: 4, end_line: 2, end_column: 19 });
+    }
+}
@@ -0,0 +1,88 @@
+//! Incremental tree-sitter parsing over ropes
+//!
+//! Edits to a buffer are mirrored into its syntax tree as `InputEdit`s, so
+//! that the next parse reuses every subtree outside the edited ranges. The
+//! parser reads the rope chunk by chunk instead of a flattened copy.
+
+use ropey::Rope;
+use tree_sitter::{InputEdit, Parser, Point, Tree};
+
+use crate::Change;
+
+/// Tree-sitter point of a byte offset.
+///
+/// Rows are the rope's lines. They differ from tree-sitter's own rows only
+/// for line breaks other than LF and CRLF (a lone CR, U+2028, ...), where the
+/// reported rows of nodes shift; byte offsets, which drive reuse, stay exact.
+pub(crate) fn point_at(rope: &Rope, byte: usize) -> Point {
+    let row = rope.byte_to_line(byte);
+    Point { row, column: byte - rope.line_to_byte(row) }
+}
+
+/// Apply `change` to `content` and describe it for `Tree::edit`
+pub(crate) fn apply_change(content: &mut Rope, change: &Change) -> InputEdit {
+    let end = change.start + change.old_text.chars().count();
+    let start_byte = content.char_to_byte(change.start);
+    let old_end_byte = content.char_to_byte(end);
+    let start_position = point_at(content, start_byte);
+    let old_end_position = point_at(content, old_end_byte);
+
+    content.remove(change.start..end);
+    content.insert(change.start, &change.new_text);
+
+    let new_end_byte = start_byte + change.new_text.len();
+    InputEdit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start_position,
+        old_end_position,
+        new_end_position: point_at(content, new_end_byte),
+    }
+}
+
+/// Parse `rope`, reusing `old_tree` if it was edited to match the rope
+pub(crate) fn parse_rope(parser: &mut Parser, rope: &Rope, old_tree: Option<&Tree>) -> Option<Tree> {
+    let mut read = |byte: usize, _: Point| -> &[u8] {
+        if byte >= rope.len_bytes() {
+            return &[];
+        }
+        let (chunk, chunk_start, _, _) = rope.chunk_at_byte(byte);
+        &chunk.as_bytes()[byte - chunk_start..]
+    };
+    parser.parse_with_options(&mut read, old_tree, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rust_parser() -> Parser {
+        let mut parser = Parser::new();
+        parser.set_language(&tree_sitter_rust::LANGUAGE.into()).unwrap();
+        parser
+    }
+
+    #[test]
+    fn edited_tree_matches_fresh_parse() {
+        let mut parser = rust_parser();
+        // Several chunks worth of text, so the reader crosses chunk boundaries
+        let source = (0..200).map(|i| format!("fn f{}() -> u32 {{ {} }}", i, i)).collect::<Vec<_>>().join("\n");
+        let mut rope = Rope::from_str(&source);
+        let mut tree = parse_rope(&mut parser, &rope, None).unwrap();
+        assert!(!tree.root_node().has_error());
+
+        // The body of `fn f150` becomes a field access
+        let start = rope.line_to_char(150) + 19;
+        let change = Change { start, old_text: "150".into(), new_text: "ñame.len".into() };
+        let edit = apply_change(&mut rope, &change);
+        tree.edit(&edit);
+        let new_tree = parse_rope(&mut parser, &rope, Some(&tree)).unwrap();
+
+        let fresh = parse_rope(&mut parser, &rope, None).unwrap();
+        assert_eq!(new_tree.root_node().to_sexp(), fresh.root_node().to_sexp());
+        let changed: Vec<_> = tree.changed_ranges(&new_tree).collect();
+        assert!(!changed.is_empty());
+        assert!(changed.iter().all(|r| r.start_point.row == 150), "{:?}", changed);
+    }
+}
@@ -17,6 +17,10 @@ use tantivy::{
 use tokio::process::Command;
 use tracing::{error, info, warn};
 
+mod symbols;
+
+pub use symbols::{WorkspaceSymbol, WorkspaceSymbols};
+
 /// Index-related errors
 #[derive(Debug, thiserror::Error)]
 pub enum IndexError {
@@ -32,6 +36,8 @@ pub enum IndexError {
     SettingsError(#[from] atom_settings::SettingsError),
     #[error("Directory error: {0}")]
     DirectoryError(#[from] tantivy::directory::error::OpenDirectoryError),
+    #[error("Symbol extraction error: {0}")]
+    SymbolError(#[from] atom_core::CoreError),
 }
 
 /// Search result from index
@@ -0,0 +1,137 @@
+//! Workspace symbol index
+//!
+//! Definitions of every indexed file, extracted with the same tags queries
+//! atom-core uses for document outlines, searchable by name.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use atom_core::{LanguageRegistry, SymbolExtractor};
+use atom_ipc::{DocumentSymbol, SymbolKind, TextRange};
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::IndexError;
+
+/// Definition found in a workspace file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub path: PathBuf,
+    /// Range of the name
+    pub range: TextRange,
+    /// Name of the enclosing symbol (class of a method)
+    pub container: Option<String>,
+}
+
+/// In-memory symbol index of workspace files
+#[derive(Default)]
+pub struct WorkspaceSymbols {
+    languages: LanguageRegistry,
+    extractor: SymbolExtractor,
+    files: HashMap<PathBuf, Vec<WorkspaceSymbol>>,
+}
+
+impl WorkspaceSymbols {
+    /// Create empty symbol index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Symbol index detecting file languages with `languages`
+    pub fn with_languages(languages: LanguageRegistry) -> Self {
+        Self { languages, ..Self::default() }
+    }
+
+    /// Index (or re-index) a file; returns the number of symbols found.
+    /// Unreadable files are skipped like in `IndexEngine::index_file`.
+    pub async fn index_file<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, IndexError> {
+        let path = path.as_ref();
+        let source = match tokio::fs::read_to_string(path).await {
+            Ok(source) => source,
+            Err(e) => {
+                warn!("Failed to read file {:?}: {}", path, e);
+                return Ok(0);
+            }
+        };
+        self.index_source(path, &source)
+    }
+
+    /// Index the given content of a file
+    pub fn index_source(&mut self, path: &Path, source: &str) -> Result<usize, IndexError> {
+        let text = Rope::from_str(source);
+        let symbols = match self.languages.detect(path, &text) {
+            Some(language) => self.extractor.file_symbols(language, &text)?,
+            None => Vec::new(),
+        };
+        let mut flat = Vec::new();
+        flatten(path, &symbols, None, &mut flat);
+        let count = flat.len();
+        self.files.insert(path.to_path_buf(), flat);
+
+        info!("Indexed symbols: {:?} ({} symbols)", path, count);
+        Ok(count)
+    }
+
+    /// Drop the symbols of a file
+    pub fn remove_file(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Symbols whose name contains `query` (case-insensitive), names starting
+    /// with it first, at most `max_results`
+    pub fn search(&self, query: &str, max_results: usize) -> Vec<&WorkspaceSymbol> {
+        let query = query.to_lowercase();
+        let mut found: Vec<_> = self
+            .files
+            .values()
+            .flatten()
+            .filter_map(|symbol| {
+                let position = symbol.name.to_lowercase().find(&query)?;
+                Some((position > 0, symbol))
+            })
+            .collect();
+        found.sort_by(|(a_inner, a), (b_inner, b)| {
+            (a_inner, a.name.len(), &a.name, &a.path).cmp(&(b_inner, b.name.len(), &b.name, &b.path))
+        });
+        found.into_iter().take(max_results).map(|(_, symbol)| symbol).collect()
+    }
+}
+
+/// Append the symbol tree of a file as a flat list
+fn flatten(path: &Path, symbols: &[DocumentSymbol], container: Option<&str>, out: &mut Vec<WorkspaceSymbol>) {
+    for symbol in symbols {
+        out.push(WorkspaceSymbol {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            path: path.to_path_buf(),
+            range: symbol.selection_range.clone(),
+            container: container.map(str::to_string),
+        });
+        flatten(path, &symbol.children, Some(&symbol.name), out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_symbols_across_files() {
+        let mut index = WorkspaceSymbols::new();
+        index.index_source(Path::new("app.py"), "class Parser:\n    def parse(self):\n        pass\n").unwrap();
+        index.index_source(Path::new("lib.rs"), "fn parse_args() {}\nfn run() {}\n").unwrap();
+        assert_eq!(index.index_source(Path::new("notes.txt"), "parse").unwrap(), 0);
+
+        let names: Vec<_> = index.search("PARSE", 10).iter().map(|s| (s.name.as_str(), s.container.as_deref())).collect();
+        assert_eq!(names, [("parse", Some("Parser")), ("Parser", None), ("parse_args", None)]);
+
+        // Re-indexing replaces the old symbols of the file
+        index.index_source(Path::new("lib.rs"), "fn run() {}\n").unwrap();
+        assert_eq!(index.search("parse_", 10).len(), 0);
+        index.remove_file(Path::new("app.py"));
+        assert!(index.search("parse", 10).is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+//! Client connection lifecycle
+//!
+//! Задача соединения владеет очередью исходящих сообщений и переживает разрывы:
+//! при потере связи клиент переходит в `ConnectionState::Reconnecting`, повторяет
+//! подключение с экспоненциальной задержкой, заново выполняет рукопожатие и
+//! повторно отправляет идемпотентные запросы, ожидающие ответа. Неидемпотентные
+//! запросы, которые могли дойти до демона, завершаются `IpcError::ConnectionLost`.
+//! Подписки на уведомления восстанавливаются отдельным запросом `Subscribe`.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
+
+use crate::{
+    capabilities, now_millis, read_ipc_frame, write_ipc_frame, ConnectionState, CoreRequest,
+    FrameOptions, IpcClient, IpcConfig, IpcEndpoint, IpcError, IpcMessage, IpcPayload, IpcStream,
+    NegotiatedProtocol, Notification, PendingMap, RequestId, StreamMap, SubscriptionTopic,
+    MAX_RECONNECT_DELAY,
+};
+
+/// Чем закончился сеанс одного соединения
+enum SessionEnd {
+    /// Клиент закрыт (все отправители очереди удалены)
+    ClientClosed,
+    /// Связь с демоном потеряна
+    ConnectionLost,
+}
+
+pub(crate) struct ConnectionTask {
+    pub(crate) endpoint: IpcEndpoint,
+    pub(crate) config: IpcConfig,
+    pub(crate) state: Arc<watch::Sender<ConnectionState>>,
+    pub(crate) protocol: Arc<RwLock<NegotiatedProtocol>>,
+    pub(crate) pending_requests: Arc<Mutex<PendingMap>>,
+    pub(crate) pending_streams: Arc<Mutex<StreamMap>>,
+    pub(crate) notification_tx: Arc<Mutex<Option<mpsc::UnboundedSender<Notification>>>>,
+    pub(crate) subscriptions: Arc<Mutex<HashSet<SubscriptionTopic>>>,
+}
+
+impl ConnectionTask {
+    pub(crate) async fn run(self, mut stream: IpcStream, mut receiver: mpsc::UnboundedReceiver<IpcMessage>) {
+        // Сообщения, которые нужно отправить сразу после (пере)подключения
+        let mut backlog: VecDeque<IpcMessage> = VecDeque::new();
+
+        loop {
+            if let SessionEnd::ClientClosed = self.session(stream, &mut receiver, &mut backlog).await {
+                self.close(ConnectionState::Closed).await;
+                return;
+            }
+
+            self.state.send_replace(ConnectionState::Reconnecting);
+            // Всё, что ещё лежит в очереди, до демона точно не дошло
+            while let Ok(message) = receiver.try_recv() {
+                backlog.push_back(message);
+            }
+            self.fail_unreplayable(&backlog).await;
+
+            match self.reconnect(&mut receiver, &mut backlog).await {
+                Ok(new_stream) => {
+                    self.queue_replays(&mut backlog).await;
+                    self.queue_resubscribe(&mut backlog).await;
+                    stream = new_stream;
+                    self.state.send_replace(ConnectionState::Connected);
+                }
+                Err(state) => {
+                    self.close(state).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Обслуживать одно соединение до разрыва или закрытия клиента
+    async fn session(
+        &self,
+        stream: IpcStream,
+        receiver: &mut mpsc::UnboundedReceiver<IpcMessage>,
+        backlog: &mut VecDeque<IpcMessage>,
+    ) -> SessionEnd {
+        // Согласованная версия и лимиты кадра из конфигурации клиента
+        let frame = self.protocol.read().await.frame_options(&FrameOptions {
+            max_decompressed_size: self.config.max_decompressed_size,
+            max_reassembled_size: self.config.max_reassembled_size,
+            ..FrameOptions::with_limit(self.config.max_message_size)
+        });
+
+        let (read_stream, write_stream) = stream.into_split();
+        let mut reader = BufReader::new(read_stream);
+        let mut writer = BufWriter::new(write_stream);
+
+        let pending_requests = Arc::clone(&self.pending_requests);
+        let pending_streams = Arc::clone(&self.pending_streams);
+        let notification_tx = Arc::clone(&self.notification_tx);
+        let mut reader_task = tokio::spawn(async move {
+            loop {
+                match read_ipc_frame(&mut reader, &frame).await {
+                    Ok(message) => {
+                        IpcClient::handle_message(message, &pending_requests, &pending_streams, &notification_tx).await;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Read error: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let end = 'session: {
+            while let Some(message) = backlog.pop_front() {
+                if !self.write(&mut writer, &message, &frame).await {
+                    break 'session SessionEnd::ConnectionLost;
+                }
+            }
+            loop {
+                tokio::select! {
+                    message = receiver.recv() => match message {
+                        Some(message) => {
+                            if !self.write(&mut writer, &message, &frame).await {
+                                break 'session SessionEnd::ConnectionLost;
+                            }
+                        }
+                        None => break 'session SessionEnd::ClientClosed,
+                    },
+                    _ = &mut reader_task => break 'session SessionEnd::ConnectionLost,
+                }
+            }
+        };
+        reader_task.abort();
+        end
+    }
+
+    /// Записать сообщение; `false` — связь потеряна.
+    ///
+    /// Запрос, которого нет в согласованной версии протокола, до провода не
+    /// доходит: он сразу завершается `IpcError::Unsupported`, соединение остаётся.
+    async fn write<W: AsyncWriteExt + Unpin>(&self, writer: &mut W, message: &IpcMessage, frame: &FrameOptions) -> bool {
+        match write_ipc_frame(writer, message, frame).await {
+            Ok(()) => true,
+            Err(IpcError::Unsupported(what)) => {
+                tracing::debug!("Not sent to {}: {}", self.endpoint, what);
+                if let Some(p) = self.pending_requests.lock().await.remove(&message.id) {
+                    let _ = p.tx.send(Err(IpcError::Unsupported(what.clone())));
+                }
+                if let Some(tx) = self.pending_streams.lock().await.remove(&message.id) {
+                    let _ = tx.send(Err(IpcError::Unsupported(what)));
+                }
+                true
+            }
+            Err(e) => {
+                tracing::debug!("Write error: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Переподключиться с экспоненциальной задержкой.
+    ///
+    /// Запросы, поступающие во время ожидания, копятся в `backlog`.
+    /// При неудаче возвращает итоговое состояние клиента.
+    async fn reconnect(
+        &self,
+        receiver: &mut mpsc::UnboundedReceiver<IpcMessage>,
+        backlog: &mut VecDeque<IpcMessage>,
+    ) -> Result<IpcStream, ConnectionState> {
+        let mut delay = self.config.reconnect_base_delay;
+        for attempt in 1..=self.config.max_reconnect_attempts {
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    message = receiver.recv() => match message {
+                        Some(message) => backlog.push_back(message),
+                        None => return Err(ConnectionState::Closed),
+                    },
+                }
+            }
+
+            match IpcClient::establish(&self.endpoint, &self.config, 1).await {
+                Ok((stream, protocol)) => {
+                    tracing::info!("Reconnected to {} (attempt {}, protocol v{})", self.endpoint, attempt, protocol.version);
+                    *self.protocol.write().await = protocol;
+                    return Ok(stream);
+                }
+                Err(e @ IpcError::IncompatibleVersion { .. }) => {
+                    tracing::error!("Reconnect to {} failed: {}", self.endpoint, e);
+                    return Err(ConnectionState::Disconnected);
+                }
+                Err(e) => {
+                    tracing::debug!("Reconnect attempt {} to {} failed: {}", attempt, self.endpoint, e);
+                }
+            }
+            delay = std::cmp::min(delay * 2, MAX_RECONNECT_DELAY);
+        }
+        tracing::warn!("Giving up reconnecting to {}", self.endpoint);
+        Err(ConnectionState::Disconnected)
+    }
+
+    /// Завершить запросы, которые могли дойти до демона и не могут быть повторены:
+    /// неидемпотентные запросы и потоки (часть кадров уже доставлена потребителю)
+    async fn fail_unreplayable(&self, backlog: &VecDeque<IpcMessage>) {
+        let unsent: HashSet<RequestId> = backlog.iter().map(|m| m.id).collect();
+        let mut pending = self.pending_requests.lock().await;
+        let lost: Vec<RequestId> = pending
+            .iter()
+            .filter(|(id, p)| p.replay.is_none() && !unsent.contains(id))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in lost {
+            if let Some(p) = pending.remove(&id) {
+                let _ = p.tx.send(Err(IpcError::ConnectionLost));
+            }
+        }
+        let mut streams = self.pending_streams.lock().await;
+        streams.retain(|id, tx| {
+            let keep = unsent.contains(id);
+            if !keep {
+                let _ = tx.send(Err(IpcError::ConnectionLost));
+            }
+            keep
+        });
+    }
+
+    /// Поставить в начало очереди повтор идемпотентных запросов, отправленных до разрыва
+    async fn queue_replays(&self, backlog: &mut VecDeque<IpcMessage>) {
+        let unsent: HashSet<RequestId> = backlog.iter().map(|m| m.id).collect();
+        let deadline = now_millis() + self.config.request_timeout.as_millis() as u64;
+        let pending = self.pending_requests.lock().await;
+        let replays: Vec<IpcMessage> = pending
+            .iter()
+            .filter(|(id, _)| !unsent.contains(id))
+            .filter_map(|(id, p)| {
+                p.replay.as_ref().map(|request| IpcMessage {
+                    id: *id,
+                    deadline_millis: deadline,
+                    payload: IpcPayload::Request(request.clone()),
+                })
+            })
+            .collect();
+        if !replays.is_empty() {
+            tracing::info!("Replaying {} idempotent request(s) after reconnect", replays.len());
+        }
+        for message in replays {
+            backlog.push_front(message);
+        }
+    }
+
+    /// Восстановить подписки нового соединения раньше всех остальных запросов.
+    ///
+    /// Ответ на этот запрос никто не ждёт — он отбрасывается как неизвестный.
+    async fn queue_resubscribe(&self, backlog: &mut VecDeque<IpcMessage>) {
+        if !self.protocol.read().await.supports(capabilities::SUBSCRIPTIONS) {
+            return;
+        }
+        let topics: Vec<SubscriptionTopic> = self.subscriptions.lock().await.iter().cloned().collect();
+        if topics.is_empty() {
+            return;
+        }
+        backlog.push_front(IpcMessage {
+            id: RequestId::new(),
+            deadline_millis: now_millis() + self.config.request_timeout.as_millis() as u64,
+            payload: IpcPayload::Request(CoreRequest::Subscribe { topics }),
+        });
+    }
+
+    /// Окончательно закрыть клиент: выставить состояние и завершить все ожидания
+    async fn close(&self, state: ConnectionState) {
+        self.state.send_replace(state);
+        for (_, p) in self.pending_requests.lock().await.drain() {
+            let _ = p.tx.send(Err(IpcError::ChannelClosed));
+        }
+        // Потоки не имеют таймаута — закрываем их явно, чтобы потребители не зависли
+        for (_, tx) in self.pending_streams.lock().await.drain() {
+            let _ = tx.send(Err(IpcError::ChannelClosed));
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Protocol version negotiation
+//!
+//! В начале каждого соединения клиент отправляет `Hello` с диапазоном
+//! поддерживаемых версий и списком возможностей, сервер отвечает `Welcome`
+//! с выбранной версией (наибольшей общей) и пересечением возможностей.
+//! Оба кадра рукопожатия помечаются `MIN_PROTOCOL_VERSION`, чтобы их заголовок
+//! мог разобрать любой поддерживаемый пир. Сервер, получивший первым сообщением
+//! не `Hello`, работает с клиентом в режиме версии 1 (legacy).
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    read_ipc_frame, write_ipc_frame, FrameOptions, IpcError, IpcMessage, IpcPayload, RequestId,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+/// Имена возможностей, согласуемых при рукопожатии.
+///
+/// Неизвестные имена игнорируются, поэтому список можно расширять без смены версии.
+pub mod capabilities {
+    /// Потоковые ответы (`IpcPayload::StreamRequest`, `SearchResultsChunk`)
+    pub const STREAMING: &str = "streaming";
+    /// Сжатие крупных кадров LZ4 (`FRAME_FLAG_LZ4`)
+    pub const LZ4: &str = "lz4";
+    /// Сообщения из нескольких кадров (`FRAME_FLAG_CONTINUED`)
+    pub const CHUNKED: &str = "chunked";
+    /// Подписки на уведомления (`CoreRequest::Subscribe` / `Unsubscribe`)
+    pub const SUBSCRIPTIONS: &str = "subscriptions";
+
+    /// Все возможности, реализованные этой сборкой atom-ipc
+    pub const ALL: &[&str] = &[STREAMING, LZ4, CHUNKED, SUBSCRIPTIONS];
+}
+
+/// Первое сообщение клиента
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub min_version: u8,
+    pub max_version: u8,
+    pub capabilities: Vec<String>,
+}
+
+impl Hello {
+    /// Hello с диапазоном версий этой сборки
+    pub fn new<S: AsRef<str>>(capabilities: &[S]) -> Self {
+        Self {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: capabilities.iter().map(|c| c.as_ref().to_string()).collect(),
+        }
+    }
+}
+
+/// Ответ сервера на `Hello`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Welcome {
+    /// Выбранная версия; 0 — общих версий нет, сервер закрывает соединение
+    pub version: u8,
+    /// Диапазон версий сервера (для диагностики несовместимости)
+    pub min_version: u8,
+    pub max_version: u8,
+    /// Возможности, поддерживаемые обеими сторонами
+    pub capabilities: Vec<String>,
+}
+
+/// Результат рукопожатия для одного соединения
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedProtocol {
+    pub version: u8,
+    pub capabilities: Vec<String>,
+}
+
+impl NegotiatedProtocol {
+    /// Пир без рукопожатия: версия 1, никаких возможностей
+    pub fn legacy() -> Self {
+        Self { version: MIN_PROTOCOL_VERSION, capabilities: Vec::new() }
+    }
+
+    /// Поддерживает ли соединение возможность `name`
+    pub fn supports(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|c| c == name)
+    }
+
+    /// Параметры кадрирования после рукопожатия: лимиты берутся из `limits`,
+    /// версия и флаги возможностей — из результата согласования
+    pub fn frame_options(&self, limits: &FrameOptions) -> FrameOptions {
+        FrameOptions {
+            version: self.version,
+            compression: self.supports(capabilities::LZ4),
+            chunking: self.supports(capabilities::CHUNKED),
+            ..*limits
+        }
+    }
+}
+
+/// Выбрать наибольшую версию из пересечения двух диапазонов
+pub fn negotiate_version(
+    local_min: u8,
+    local_max: u8,
+    remote_min: u8,
+    remote_max: u8,
+) -> Result<u8, IpcError> {
+    let low = local_min.max(remote_min);
+    let high = local_max.min(remote_max);
+    if low > high {
+        return Err(IpcError::IncompatibleVersion { local_min, local_max, remote_min, remote_max });
+    }
+    Ok(high)
+}
+
+fn intersect_capabilities<S: AsRef<str>>(local: &[S], remote: &[String]) -> Vec<String> {
+    remote
+        .iter()
+        .filter(|c| local.iter().any(|l| l.as_ref() == c.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Итог серверной стороны рукопожатия
+#[derive(Debug)]
+pub enum ServerHandshake {
+    /// Клиент прислал `Hello`, версия согласована
+    Negotiated(NegotiatedProtocol),
+    /// Клиент без рукопожатия: первое сообщение нужно обработать как обычное
+    Legacy(IpcMessage),
+}
+
+/// Серверная сторона рукопожатия: прочитать первое сообщение и ответить на `Hello`.
+///
+/// При отсутствии общей версии клиенту отправляется `Welcome { version: 0, .. }`
+/// и возвращается `IpcError::IncompatibleVersion` — соединение следует закрыть.
+pub async fn server_handshake<R, W, S>(
+    reader: &mut R,
+    writer: &mut W,
+    max_message_size: u32,
+    capabilities: &[S],
+) -> Result<ServerHandshake, IpcError>
+where
+    R: AsyncReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+    S: AsRef<str>,
+{
+    let options = FrameOptions::handshake(max_message_size);
+    let message = read_ipc_frame(reader, &options).await?;
+    let hello = match message.payload {
+        IpcPayload::Hello(hello) => hello,
+        _ => return Ok(ServerHandshake::Legacy(message)),
+    };
+
+    let negotiated = negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, hello.min_version, hello.max_version);
+    let welcome = Welcome {
+        version: negotiated.as_ref().copied().unwrap_or(0),
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+        capabilities: match negotiated {
+            Ok(_) => intersect_capabilities(capabilities, &hello.capabilities),
+            Err(_) => Vec::new(),
+        },
+    };
+    let reply = IpcMessage { id: message.id, deadline_millis: 0, payload: IpcPayload::Welcome(welcome.clone()) };
+    write_ipc_frame(writer, &reply, &options).await?;
+
+    let version = negotiated?;
+    Ok(ServerHandshake::Negotiated(NegotiatedProtocol { version, capabilities: welcome.capabilities }))
+}
+
+/// Клиентская сторона рукопожатия: отправить `Hello` и дождаться `Welcome`.
+///
+/// Ошибка чтения (пир закрыл соединение) означает сервер без поддержки рукопожатия;
+/// решение о переходе в legacy‑режим принимает вызывающий.
+pub(crate) async fn client_handshake<T, S>(
+    stream: &mut T,
+    max_message_size: u32,
+    capabilities: &[S],
+) -> Result<NegotiatedProtocol, IpcError>
+where
+    T: AsyncReadExt + AsyncWriteExt + Unpin,
+    S: AsRef<str>,
+{
+    let options = FrameOptions::handshake(max_message_size);
+    let hello = Hello::new(capabilities);
+    let id = RequestId::new();
+    let message = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Hello(hello.clone()) };
+    write_ipc_frame(stream, &message, &options).await?;
+
+    let reply = read_ipc_frame(stream, &options).await?;
+    let welcome = match reply.payload {
+        IpcPayload::Welcome(welcome) if reply.id == id => welcome,
+        other => {
+            return Err(IpcError::InvalidFrame(format!("Expected Welcome, got {:?}", other)));
+        }
+    };
+    if welcome.version == 0 {
+        return Err(IpcError::IncompatibleVersion {
+            local_min: hello.min_version,
+            local_max: hello.max_version,
+            remote_min: welcome.min_version,
+            remote_max: welcome.max_version,
+        });
+    }
+    // Сервер обязан выбрать версию из нашего диапазона
+    if !(hello.min_version..=hello.max_version).contains(&welcome.version) {
+        return Err(IpcError::InvalidFrame(format!(
+            "Server selected unsupported protocol version {}",
+            welcome.version
+        )));
+    }
+    Ok(NegotiatedProtocol {
+        version: welcome.version,
+        capabilities: intersect_capabilities(capabilities, &welcome.capabilities),
+    })
+}
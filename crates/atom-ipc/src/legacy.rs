@@ -0,0 +1,238 @@
+//! Проводной формат версий 1 и 2
+//!
+//! В версии 3 у нескольких вариантов появились поля: `CoreRequest::SaveBuffer`
+//! (`force`), `CoreResponse::BufferOpened` (`version`, `mode`),
+//! `CoreResponse::BufferSaved` (`version`, `changes`), `CoreResponse::Error`
+//! (`code`, `detail`) и `Notification::BufferChanged` (`version`). Пиры версий
+//! 1–2 кодируют их по-старому, поэтому кадры этих версий пишутся и читаются
+//! через копии прежних перечислений. Порядок вариантов здесь тот же, что в
+//! версии 2, — менять его нельзя.
+//!
+//! При чтении отсутствующие поля получают значения по умолчанию (версия 0,
+//! `ErrorCode::Internal`), при записи новые поля отбрасываются. Вариантов,
+//! появившихся в версии 3, в старом формате нет: запись такого сообщения
+//! завершается `IpcError::Unsupported`, соединение при этом не страдает.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BufferMode, CoreRequest, CoreResponse, ErrorCode, FileChangeType, Hello, IpcError, IpcMessage,
+    IpcPayload, RequestId, SearchOptions, SearchResult, SubscriptionTopic, TextChange, Welcome,
+};
+
+/// Первая версия протокола с новыми полями
+pub(crate) const FIELDS_VERSION: u8 = 3;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageV2 {
+    id: RequestId,
+    deadline_millis: u64,
+    payload: PayloadV2,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum PayloadV2 {
+    Request(RequestV2),
+    Response(ResponseV2),
+    Notification(NotificationV2),
+    Cancel(RequestId),
+    StreamRequest(RequestV2),
+    Hello(Hello),
+    Welcome(Welcome),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum RequestV2 {
+    Ping,
+    Sleep { millis: u64 },
+    OpenBuffer { path: String },
+    SaveBuffer { buffer_id: String, content: String },
+    CloseBuffer { buffer_id: String },
+    Search { query: String, options: SearchOptions },
+    LspRequest { server: String, method: String, params: serde_json::Value },
+    GetProjectFiles { root_path: String },
+    GetStats,
+    Subscribe { topics: Vec<SubscriptionTopic> },
+    Unsubscribe { topics: Vec<SubscriptionTopic> },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ResponseV2 {
+    Pong,
+    BufferOpened { buffer_id: String, content: String },
+    BufferSaved { buffer_id: String },
+    BufferClosed { buffer_id: String },
+    SearchResults { results: Vec<SearchResult> },
+    LspResponse { result: serde_json::Value },
+    ProjectFiles { files: Vec<String> },
+    Stats { cancels: u64, deadlines: u64, backpressure: u64 },
+    Success,
+    Error { message: String },
+    SearchResultsChunk { results: Vec<SearchResult> },
+    SearchComplete { total: usize },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum NotificationV2 {
+    BufferChanged { buffer_id: String, changes: Vec<TextChange> },
+    DiagnosticsUpdate { uri: String, diagnostics: Vec<serde_json::Value> },
+    FileSystemChanged { path: String, change_type: FileChangeType },
+}
+
+/// Сериализовать сообщение в формате версий 1–2
+pub(crate) fn serialize(message: &IpcMessage) -> Result<Vec<u8>, IpcError> {
+    let legacy = MessageV2 { id: message.id, deadline_millis: message.deadline_millis, payload: payload(&message.payload)? };
+    Ok(bincode::serialize(&legacy)?)
+}
+
+/// Разобрать сообщение в формате версий 1–2
+pub(crate) fn deserialize(bytes: &[u8]) -> Result<IpcMessage, IpcError> {
+    let legacy: MessageV2 = bincode::deserialize(bytes)?;
+    let payload = match legacy.payload {
+        PayloadV2::Request(request) => IpcPayload::Request(request.into()),
+        PayloadV2::Response(response) => IpcPayload::Response(response.into()),
+        PayloadV2::Notification(notification) => IpcPayload::Notification(notification.into()),
+        PayloadV2::Cancel(id) => IpcPayload::Cancel(id),
+        PayloadV2::StreamRequest(request) => IpcPayload::StreamRequest(request.into()),
+        PayloadV2::Hello(hello) => IpcPayload::Hello(hello),
+        PayloadV2::Welcome(welcome) => IpcPayload::Welcome(welcome),
+    };
+    Ok(IpcMessage { id: legacy.id, deadline_millis: legacy.deadline_millis, payload })
+}
+
+/// Ошибка для варианта, которого нет в версиях 1–2 (в тексте — только имя варианта)
+fn unsupported(variant: &impl std::fmt::Debug) -> IpcError {
+    let debug = format!("{:?}", variant);
+    let name = debug.split([' ', '{', '(']).next().unwrap_or_default();
+    IpcError::Unsupported(format!("{} needs protocol version {}", name, FIELDS_VERSION))
+}
+
+fn payload(payload: &IpcPayload) -> Result<PayloadV2, IpcError> {
+    Ok(match payload {
+        IpcPayload::Request(request) => PayloadV2::Request(request.try_into()?),
+        IpcPayload::Response(response) => PayloadV2::Response(response.try_into()?),
+        IpcPayload::Notification(notification) => PayloadV2::Notification(notification.into()),
+        IpcPayload::Cancel(id) => PayloadV2::Cancel(*id),
+        IpcPayload::StreamRequest(request) => PayloadV2::StreamRequest(request.try_into()?),
+        IpcPayload::Hello(hello) => PayloadV2::Hello(hello.clone()),
+        IpcPayload::Welcome(welcome) => PayloadV2::Welcome(welcome.clone()),
+    })
+}
+
+impl TryFrom<&CoreRequest> for RequestV2 {
+    type Error = IpcError;
+
+    fn try_from(request: &CoreRequest) -> Result<Self, IpcError> {
+        Ok(match request.clone() {
+            CoreRequest::Ping => RequestV2::Ping,
+            CoreRequest::Sleep { millis } => RequestV2::Sleep { millis },
+            CoreRequest::OpenBuffer { path } => RequestV2::OpenBuffer { path },
+            // Старый демон не проверяет файл на диске: `force` ему не нужен
+            CoreRequest::SaveBuffer { buffer_id, content, force: _ } => RequestV2::SaveBuffer { buffer_id, content },
+            CoreRequest::CloseBuffer { buffer_id } => RequestV2::CloseBuffer { buffer_id },
+            CoreRequest::Search { query, options } => RequestV2::Search { query, options },
+            CoreRequest::LspRequest { server, method, params } => RequestV2::LspRequest { server, method, params },
+            CoreRequest::GetProjectFiles { root_path } => RequestV2::GetProjectFiles { root_path },
+            CoreRequest::GetStats => RequestV2::GetStats,
+            CoreRequest::Subscribe { topics } => RequestV2::Subscribe { topics },
+            CoreRequest::Unsubscribe { topics } => RequestV2::Unsubscribe { topics },
+            other => return Err(unsupported(&other)),
+        })
+    }
+}
+
+impl From<RequestV2> for CoreRequest {
+    fn from(request: RequestV2) -> Self {
+        match request {
+            RequestV2::Ping => CoreRequest::Ping,
+            RequestV2::Sleep { millis } => CoreRequest::Sleep { millis },
+            RequestV2::OpenBuffer { path } => CoreRequest::OpenBuffer { path },
+            RequestV2::SaveBuffer { buffer_id, content } => CoreRequest::SaveBuffer { buffer_id, content, force: false },
+            RequestV2::CloseBuffer { buffer_id } => CoreRequest::CloseBuffer { buffer_id },
+            RequestV2::Search { query, options } => CoreRequest::Search { query, options },
+            RequestV2::LspRequest { server, method, params } => CoreRequest::LspRequest { server, method, params },
+            RequestV2::GetProjectFiles { root_path } => CoreRequest::GetProjectFiles { root_path },
+            RequestV2::GetStats => CoreRequest::GetStats,
+            RequestV2::Subscribe { topics } => CoreRequest::Subscribe { topics },
+            RequestV2::Unsubscribe { topics } => CoreRequest::Unsubscribe { topics },
+        }
+    }
+}
+
+impl TryFrom<&CoreResponse> for ResponseV2 {
+    type Error = IpcError;
+
+    fn try_from(response: &CoreResponse) -> Result<Self, IpcError> {
+        Ok(match response {
+            CoreResponse::Pong => ResponseV2::Pong,
+            CoreResponse::BufferOpened { buffer_id, content, .. } => {
+                ResponseV2::BufferOpened { buffer_id: buffer_id.clone(), content: content.clone() }
+            }
+            CoreResponse::BufferSaved { buffer_id, .. } => ResponseV2::BufferSaved { buffer_id: buffer_id.clone() },
+            CoreResponse::BufferClosed { buffer_id } => ResponseV2::BufferClosed { buffer_id: buffer_id.clone() },
+            CoreResponse::SearchResults { results } => ResponseV2::SearchResults { results: results.clone() },
+            CoreResponse::LspResponse { result } => ResponseV2::LspResponse { result: result.clone() },
+            CoreResponse::ProjectFiles { files } => ResponseV2::ProjectFiles { files: files.clone() },
+            CoreResponse::Stats { cancels, deadlines, backpressure } => {
+                ResponseV2::Stats { cancels: *cancels, deadlines: *deadlines, backpressure: *backpressure }
+            }
+            CoreResponse::Success => ResponseV2::Success,
+            CoreResponse::Error { message, .. } => ResponseV2::Error { message: message.clone() },
+            CoreResponse::SearchResultsChunk { results } => ResponseV2::SearchResultsChunk { results: results.clone() },
+            CoreResponse::SearchComplete { total } => ResponseV2::SearchComplete { total: *total },
+            other => return Err(unsupported(&other)),
+        })
+    }
+}
+
+impl From<ResponseV2> for CoreResponse {
+    fn from(response: ResponseV2) -> Self {
+        match response {
+            ResponseV2::Pong => CoreResponse::Pong,
+            ResponseV2::BufferOpened { buffer_id, content } => {
+                CoreResponse::BufferOpened { buffer_id, content, version: 0, mode: BufferMode::Normal }
+            }
+            ResponseV2::BufferSaved { buffer_id } => CoreResponse::BufferSaved { buffer_id, version: 0, changes: Vec::new() },
+            ResponseV2::BufferClosed { buffer_id } => CoreResponse::BufferClosed { buffer_id },
+            ResponseV2::SearchResults { results } => CoreResponse::SearchResults { results },
+            ResponseV2::LspResponse { result } => CoreResponse::LspResponse { result },
+            ResponseV2::ProjectFiles { files } => CoreResponse::ProjectFiles { files },
+            ResponseV2::Stats { cancels, deadlines, backpressure } => CoreResponse::Stats { cancels, deadlines, backpressure },
+            ResponseV2::Success => CoreResponse::Success,
+            // Старый демон не сообщает класс ошибки
+            ResponseV2::Error { message } => CoreResponse::error(ErrorCode::Internal, message),
+            ResponseV2::SearchResultsChunk { results } => CoreResponse::SearchResultsChunk { results },
+            ResponseV2::SearchComplete { total } => CoreResponse::SearchComplete { total },
+        }
+    }
+}
+
+impl From<&crate::Notification> for NotificationV2 {
+    fn from(notification: &crate::Notification) -> Self {
+        match notification.clone() {
+            crate::Notification::BufferChanged { buffer_id, changes, version: _ } => {
+                NotificationV2::BufferChanged { buffer_id, changes }
+            }
+            crate::Notification::DiagnosticsUpdate { uri, diagnostics } => NotificationV2::DiagnosticsUpdate { uri, diagnostics },
+            crate::Notification::FileSystemChanged { path, change_type } => {
+                NotificationV2::FileSystemChanged { path, change_type }
+            }
+        }
+    }
+}
+
+impl From<NotificationV2> for crate::Notification {
+    fn from(notification: NotificationV2) -> Self {
+        match notification {
+            NotificationV2::BufferChanged { buffer_id, changes } => {
+                crate::Notification::BufferChanged { buffer_id, changes, version: 0 }
+            }
+            NotificationV2::DiagnosticsUpdate { uri, diagnostics } => {
+                crate::Notification::DiagnosticsUpdate { uri, diagnostics }
+            }
+            NotificationV2::FileSystemChanged { path, change_type } => {
+                crate::Notification::FileSystemChanged { path, change_type }
+            }
+        }
+    }
+}
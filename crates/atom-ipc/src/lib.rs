@@ -4,16 +4,29 @@
 //! between UI process and core daemon with framing, cancellation, and backpressure.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
-use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock};
 use tokio::time::{timeout, Duration};
 use tracing::error;
 use uuid::Uuid;
 
+mod connection;
+mod handshake;
+mod legacy;
+mod transport;
+
+pub use handshake::{
+    capabilities, negotiate_version, server_handshake, Hello, NegotiatedProtocol, ServerHandshake,
+    Welcome,
+};
+pub use transport::{IpcEndpoint, IpcListener, IpcReadHalf, IpcStream, IpcWriteHalf};
+
 /// IPC Protocol Errors
 #[derive(Error, Debug)]
 pub enum IpcError {
@@ -33,6 +46,34 @@ pub enum IpcError {
     InvalidFrame(String),
     #[error("Backpressure: too many pending requests")]
     Backpressure,
+    #[error("Connection lost before the response arrived")]
+    ConnectionLost,
+    #[error("Not supported by peer: {0}")]
+    Unsupported(String),
+    #[error("Incompatible protocol version: local {local_min}..={local_max}, remote {remote_min}..={remote_max}")]
+    IncompatibleVersion {
+        local_min: u8,
+        local_max: u8,
+        remote_min: u8,
+        remote_max: u8,
+    },
+}
+
+impl IpcError {
+    /// Класс ошибки клиента в тех же кодах, что и ответы демона
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            IpcError::Timeout => ErrorCode::DeadlineExceeded,
+            IpcError::Cancelled => ErrorCode::Cancelled,
+            IpcError::Backpressure => ErrorCode::Backpressure,
+            IpcError::Unsupported(_) | IpcError::IncompatibleVersion { .. } => ErrorCode::Unsupported,
+            IpcError::ConnectionFailed(_) | IpcError::ChannelClosed | IpcError::ConnectionLost => {
+                ErrorCode::Unavailable
+            }
+            IpcError::IoError(e) => ErrorCode::from_io_kind(e.kind()),
+            IpcError::SerializationError(_) | IpcError::InvalidFrame(_) => ErrorCode::Internal,
+        }
+    }
 }
 
 /// Request ID for tracking RPC calls
@@ -71,10 +112,17 @@ pub enum IpcPayload {
     Notification(Notification),
     /// Cancellation request
     Cancel(RequestId),
+    /// Request with a streamed response: zero or more partial chunk frames
+    /// (e.g. `SearchResultsChunk`) followed by one final response frame
+    StreamRequest(CoreRequest),
+    /// Handshake: first message of a client (supported versions and capabilities)
+    Hello(Hello),
+    /// Handshake: server reply to `Hello` with the negotiated version
+    Welcome(Welcome),
 }
 
 /// Requests from UI to Core daemon
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CoreRequest {
     /// Ping for health check
     Ping,
@@ -82,8 +130,8 @@ pub enum CoreRequest {
     Sleep { millis: u64 },
     /// Open a file buffer
     OpenBuffer { path: String },
-    /// Save buffer
-    SaveBuffer { buffer_id: String, content: String },
+    /// Save buffer; `force` overwrites the file even if it changed on disk
+    SaveBuffer { buffer_id: String, content: String, force: bool },
     /// Close buffer
     CloseBuffer { buffer_id: String },
     /// Search in workspace
@@ -101,6 +149,87 @@ pub enum CoreRequest {
     GetProjectFiles { root_path: String },
     /// Get daemon runtime stats (metrics snapshot)
     GetStats,
+    /// Подписать соединение на темы уведомлений
+    Subscribe { topics: Vec<SubscriptionTopic> },
+    /// Отписать соединение от тем уведомлений
+    Unsubscribe { topics: Vec<SubscriptionTopic> },
+    /// Применить правки к буферу версии `base_version`.
+    ///
+    /// Правки применяются последовательно (координаты каждой — в тексте после
+    /// предыдущих) и атомарно: либо все, либо ни одной.
+    ApplyEdits {
+        buffer_id: String,
+        base_version: u64,
+        edits: Vec<TextEdit>,
+    },
+    /// Отменить последний шаг правок буфера (история общая для всех клиентов)
+    Undo { buffer_id: String },
+    /// Повторить последний отменённый шаг правок буфера
+    Redo { buffer_id: String },
+    /// Перечитать файл буфера в указанной кодировке (`windows-1251`, `utf-16le`, ...);
+    /// ответ — `BufferOpened` с новым содержимым. Буфер с несохранёнными
+    /// изменениями не перечитывается (`Conflict`), если не задан `force`
+    ReopenWithEncoding { buffer_id: String, encoding: String, force: bool },
+    /// Перечитать файл буфера без несохранённых изменений (после изменения файла на диске);
+    /// ответ — `BufferOpened` с новым содержимым
+    ReloadBuffer { buffer_id: String },
+    /// Подсветка синтаксиса строк `start_line..end_line` (конец не включается) —
+    /// обычно видимой области; ответ — `Highlights`
+    GetHighlights { buffer_id: String, start_line: usize, end_line: usize },
+    /// Диапазоны сворачивания буфера по синтаксическому дереву; ответ — `FoldingRanges`
+    GetFoldingRanges { buffer_id: String },
+    /// Расширить каждое выделение до ближайшего объемлющего синтаксического узла;
+    /// ответ — `Selections`
+    ExpandSelection { buffer_id: String, ranges: Vec<TextRange> },
+    /// Сузить каждое выделение до первого вложенного синтаксического узла;
+    /// ответ — `Selections`
+    ShrinkSelection { buffer_id: String, ranges: Vec<TextRange> },
+    /// Структура документа (классы, функции, методы) без LSP; ответ — `DocumentSymbols`
+    GetDocumentSymbols { buffer_id: String },
+    /// Применить действие ко всем выделениям буфера версии `base_version` одной
+    /// атомарной пачкой правок (одна версия, один шаг истории). У выделений начало
+    /// диапазона — якорь, конец — курсор; последнее выделение — основное.
+    /// Ответ — `SelectionsEdited`
+    EditSelections { buffer_id: String, base_version: u64, selections: Vec<TextRange>, edit: SelectionEdit },
+    /// Добавить выделение на следующем вхождении текста основного выделения
+    /// (пустое основное выделение расширяется до слова под курсором); ответ — `Selections`
+    AddCursorAtNextMatch { buffer_id: String, selections: Vec<TextRange> },
+    /// Текст строк `start_line..end_line` (конец не включается) — видимой области
+    /// большого файла, содержимое которого не приходит в `BufferOpened`; ответ — `Lines`
+    GetLines { buffer_id: String, start_line: usize, end_line: usize },
+}
+
+impl CoreRequest {
+    /// Можно ли безопасно повторить запрос после переподключения
+    /// (запрос не меняет состояние демона и файлов)
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            CoreRequest::Ping
+            | CoreRequest::Sleep { .. }
+            | CoreRequest::OpenBuffer { .. }
+            | CoreRequest::Search { .. }
+            | CoreRequest::GetProjectFiles { .. }
+            | CoreRequest::GetStats
+            | CoreRequest::Subscribe { .. }
+            | CoreRequest::Unsubscribe { .. }
+            | CoreRequest::GetHighlights { .. }
+            | CoreRequest::GetFoldingRanges { .. }
+            | CoreRequest::ExpandSelection { .. }
+            | CoreRequest::ShrinkSelection { .. }
+            | CoreRequest::GetDocumentSymbols { .. }
+            | CoreRequest::AddCursorAtNextMatch { .. }
+            | CoreRequest::GetLines { .. } => true,
+            CoreRequest::SaveBuffer { .. }
+            | CoreRequest::CloseBuffer { .. }
+            | CoreRequest::LspRequest { .. }
+            | CoreRequest::ApplyEdits { .. }
+            | CoreRequest::Undo { .. }
+            | CoreRequest::Redo { .. }
+            | CoreRequest::ReopenWithEncoding { .. }
+            | CoreRequest::ReloadBuffer { .. }
+            | CoreRequest::EditSelections { .. } => false,
+        }
+    }
 }
 
 /// Responses from Core to UI
@@ -109,9 +238,10 @@ pub enum CoreResponse {
     /// Pong response
     Pong,
     /// Buffer opened successfully
-    BufferOpened { buffer_id: String, content: String },
-    /// Buffer saved
-    BufferSaved { buffer_id: String },
+    BufferOpened { buffer_id: String, content: String, version: u64, mode: BufferMode },
+    /// Buffer saved; `changes` are the edits made by the save pipeline
+    /// (trimmed whitespace, final newline, line endings), `version` the buffer version after them
+    BufferSaved { buffer_id: String, version: u64, changes: Vec<TextChange> },
     /// Buffer closed
     BufferClosed { buffer_id: String },
     /// Search results
@@ -125,16 +255,280 @@ pub enum CoreResponse {
     /// Generic success
     Success,
     /// Error occurred
-    Error { message: String },
+    Error {
+        /// Класс ошибки — по нему UI выбирает реакцию (сообщение остаётся для людей)
+        code: ErrorCode,
+        message: String,
+        detail: Option<ErrorDetail>,
+    },
+    /// Partial search results (streaming mode, more frames follow)
+    SearchResultsChunk { results: Vec<SearchResult> },
+    /// Final frame of a streamed search
+    SearchComplete { total: usize },
+    /// Правки применены; `version` — новая версия буфера
+    EditsApplied { buffer_id: String, version: u64 },
+    /// Результат `Undo`/`Redo`: применённые к буферу правки
+    /// (пусто, если отменять или повторять нечего)
+    HistoryApplied { buffer_id: String, version: u64, changes: Vec<TextChange> },
+    /// Подсветка запрошенных строк для версии буфера `version`
+    /// (пусто для буферов без разбора: большие файлы, неизвестные языки)
+    Highlights { buffer_id: String, version: u64, spans: Vec<HighlightSpan> },
+    /// Диапазоны сворачивания для версии буфера `version`, по возрастанию начальной строки
+    FoldingRanges { buffer_id: String, version: u64, ranges: Vec<FoldingRange> },
+    /// Новые выделения для версии буфера `version`: по одному на каждое из запроса
+    /// (`ExpandSelection`/`ShrinkSelection`) или весь набор, основное — последним
+    /// (`AddCursorAtNextMatch`)
+    Selections { buffer_id: String, version: u64, ranges: Vec<TextRange> },
+    /// Символы верхнего уровня документа (вложенные — в `children`) для версии буфера `version`
+    DocumentSymbols { buffer_id: String, version: u64, symbols: Vec<DocumentSymbol> },
+    /// Результат `EditSelections`: применённые правки (пусто, если менять нечего —
+    /// тогда версия прежняя) и выделения после них, основное — последним
+    SelectionsEdited { buffer_id: String, version: u64, changes: Vec<TextChange>, selections: Vec<TextRange> },
+    /// Запрошенные строки версии буфера `version` вместе с переводами строк
+    /// (диапазон обрезается по концу буфера); `line_count` — число строк в буфере
+    Lines { buffer_id: String, version: u64, start_line: usize, line_count: usize, text: String },
+}
+
+impl CoreResponse {
+    /// Является ли ответ промежуточным кадром потока (после него придут ещё кадры)
+    pub fn is_partial(&self) -> bool {
+        matches!(self, CoreResponse::SearchResultsChunk { .. })
+    }
+
+    /// Ошибка без дополнительных данных
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        CoreResponse::Error { code, message: message.into(), detail: None }
+    }
+
+    /// Ошибка со структурированными подробностями
+    pub fn error_with_detail(code: ErrorCode, message: impl Into<String>, detail: ErrorDetail) -> Self {
+        CoreResponse::Error { code, message: message.into(), detail: Some(detail) }
+    }
+
+    /// Класс ошибки, если это ответ `Error`
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            CoreResponse::Error { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+/// Режим открытого буфера
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BufferMode {
+    /// Обычный текстовый буфер
+    #[default]
+    Normal,
+    /// Большой файл: без разбора tree-sitter и подсветки. Содержимое в
+    /// `BufferOpened` пустое — видимые строки запрашиваются через `GetLines`
+    /// (пиры версий 1–2 получают содержимое целиком)
+    LargeFile,
+    /// Двоичный файл: содержимое — шестнадцатеричный дамп, только чтение
+    Binary,
+}
+
+/// Класс подсвеченного токена; соответствует именам захватов в `highlights.scm`
+/// (`function.method` → `FunctionMethod`). Новые классы добавляются только в конец.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HighlightKind {
+    Attribute,
+    Comment,
+    Constant,
+    ConstantBuiltin,
+    Constructor,
+    Embedded,
+    Escape,
+    Function,
+    FunctionBuiltin,
+    FunctionMacro,
+    FunctionMethod,
+    Keyword,
+    Label,
+    Module,
+    Number,
+    Operator,
+    Property,
+    Punctuation,
+    PunctuationBracket,
+    PunctuationDelimiter,
+    String,
+    StringSpecial,
+    Tag,
+    Type,
+    TypeBuiltin,
+    Variable,
+    VariableBuiltin,
+    VariableParameter,
+}
+
+/// Подсвеченный участок одной строки; колонки в символах, как в `TextRange`.
+/// Многострочные токены (комментарии, строки) разбиты по строкам
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighlightSpan {
+    pub line: u32,
+    pub start_column: u32,
+    pub length: u32,
+    pub kind: HighlightKind,
+}
+
+/// Что сворачивает диапазон (как `FoldingRangeKind` в LSP).
+/// Новые виды добавляются только в конец
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FoldingKind {
+    /// Синтаксическая конструкция: функция, блок, литерал
+    Region,
+    /// Комментарий или несколько комментариев подряд
+    Comment,
+    /// Группа импортов
+    Imports,
+}
+
+/// Сворачиваемые строки `start_line..=end_line`; первая строка остаётся видимой
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoldingRange {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub kind: FoldingKind,
+}
+
+/// Вид символа документа (по захватам `@definition.*` в `tags.scm`).
+/// Новые виды добавляются только в конец
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SymbolKind {
+    Class,
+    Constant,
+    Function,
+    Interface,
+    Macro,
+    Method,
+    Module,
+    /// Блок реализации (`impl` в Rust)
+    Implementation,
+}
+
+/// Символ документа; `range` — всё определение, `selection_range` — имя
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: TextRange,
+    pub selection_range: TextRange,
+    /// Вложенные символы (методы класса, функции модуля)
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// Стабильные классы ошибок `CoreResponse::Error`.
+///
+/// Новые коды добавляются только в конец (бинарная совместимость bincode);
+/// строковые имена из `as_str` не меняются.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// Ошибка без отдельного класса
+    Internal,
+    /// Дедлайн запроса истёк до начала или во время выполнения
+    DeadlineExceeded,
+    /// Превышен лимит одновременных запросов
+    Backpressure,
+    /// Запрос отменён клиентом
+    Cancelled,
+    /// Файл, каталог или буфер не найден
+    NotFound,
+    /// Нет прав доступа к файлу
+    PermissionDenied,
+    /// Путь вне рабочей области
+    PathOutsideWorkspace,
+    /// Не найден внешний инструмент (например, `rg`)
+    ToolMissing,
+    /// Операция не уложилась в собственный таймаут
+    Timeout,
+    /// Некорректные аргументы запроса
+    InvalidRequest,
+    /// Запрос пока не реализован демоном
+    NotImplemented,
+    /// Возможность не поддерживается (язык, протокол)
+    Unsupported,
+    /// Прочие ошибки ввода-вывода
+    Io,
+    /// Демон недоступен (соединение потеряно или закрыто)
+    Unavailable,
+    /// Запрос основан на устаревшей версии буфера
+    Conflict,
+    /// У буфера нет файла на диске
+    NoFile,
+}
+
+impl ErrorCode {
+    /// Стабильное строковое имя кода
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "internal",
+            ErrorCode::DeadlineExceeded => "deadline",
+            ErrorCode::Backpressure => "backpressure",
+            ErrorCode::Cancelled => "cancelled",
+            ErrorCode::NotFound => "not-found",
+            ErrorCode::PermissionDenied => "permission-denied",
+            ErrorCode::PathOutsideWorkspace => "path-outside-workspace",
+            ErrorCode::ToolMissing => "tool-missing",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::InvalidRequest => "invalid-request",
+            ErrorCode::NotImplemented => "not-implemented",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::Io => "io",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::NoFile => "no-file",
+        }
+    }
+
+    /// Класс ошибки ввода-вывода по её виду
+    pub fn from_io_kind(kind: std::io::ErrorKind) -> Self {
+        match kind {
+            std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            std::io::ErrorKind::TimedOut => ErrorCode::Timeout,
+            std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => ErrorCode::InvalidRequest,
+            _ => ErrorCode::Io,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Структурированные подробности ошибки
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorDetail {
+    /// Путь, к которому относится ошибка
+    Path { path: String },
+    /// Путь вне рабочей области и сама рабочая область
+    OutsideWorkspace { path: String, workspace: String },
+    /// Буфер, к которому относится ошибка
+    Buffer { buffer_id: String },
+    /// Отсутствующий внешний инструмент
+    Tool { name: String },
+    /// Превышенный лимит
+    Limit { limit: u64 },
+    /// Истёкший дедлайн (мс от UNIX EPOCH)
+    Deadline { deadline_millis: u64 },
+    /// Версия буфера, на которой основан запрос, и текущая версия
+    Version { buffer_id: String, expected: u64, actual: u64 },
 }
 
 /// Notifications (one-way messages)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Notification {
     /// Buffer content changed
     BufferChanged {
         buffer_id: String,
+        /// Empty when the changes were not delivered (the subscriber fell
+        /// behind): refetch the buffer at `version`
         changes: Vec<TextChange>,
+        /// Buffer version after the changes
+        version: u64,
     },
     /// LSP diagnostic update
     DiagnosticsUpdate {
@@ -148,8 +542,32 @@ pub enum Notification {
     },
 }
 
+impl Notification {
+    /// Тема подписки, к которой относится уведомление
+    pub fn topic(&self) -> SubscriptionTopic {
+        match self {
+            Notification::BufferChanged { buffer_id, .. } => {
+                SubscriptionTopic::Buffer { buffer_id: buffer_id.clone() }
+            }
+            Notification::DiagnosticsUpdate { .. } => SubscriptionTopic::Diagnostics,
+            Notification::FileSystemChanged { .. } => SubscriptionTopic::FileSystem,
+        }
+    }
+}
+
+/// Темы уведомлений, на которые подписывается соединение
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SubscriptionTopic {
+    /// Изменения файлов в отслеживаемых корнях рабочей области
+    FileSystem,
+    /// Обновления диагностик LSP
+    Diagnostics,
+    /// Изменения содержимого буфера
+    Buffer { buffer_id: String },
+}
+
 /// File change types
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileChangeType {
     Created,
     Modified,
@@ -158,7 +576,7 @@ pub enum FileChangeType {
 }
 
 /// Search options
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchOptions {
     pub case_sensitive: bool,
     pub whole_word: bool,
@@ -182,7 +600,7 @@ impl Default for SearchOptions {
 }
 
 /// Search result
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub path: String,
     pub line_number: usize,
@@ -191,16 +609,41 @@ pub struct SearchResult {
     pub match_text: String,
 }
 
+/// Действие `EditSelections`, применяемое сразу ко всем выделениям
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectionEdit {
+    /// Заменить каждое выделение текстом (ввод, вставка)
+    Insert { text: String },
+    /// Удалить выделенное, а у пустых выделений — символ перед курсором
+    DeleteBackward,
+    /// Удалить выделенное, а у пустых выделений — символ после курсора
+    DeleteForward,
+    /// Добавить уровень отступа строкам выделений (по `.editorconfig` и настройкам)
+    Indent,
+    /// Убрать уровень отступа у строк выделений
+    Outdent,
+    /// Закомментировать строки выделений или снять комментарий, если закомментированы
+    /// все; токены комментария берутся из языка буфера
+    ToggleComment,
+}
+
 /// Text change event
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextChange {
     pub range: TextRange,
     pub new_text: String,
     pub old_text: String,
 }
 
-/// Text range
-#[derive(Debug, Serialize, Deserialize)]
+/// Text edit sent by a client: replace `range` with `new_text`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub range: TextRange,
+    pub new_text: String,
+}
+
+/// Text range: lines from 0, columns in chars (Unicode scalar values)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TextRange {
     pub start_line: usize,
     pub start_column: usize,
@@ -214,78 +657,132 @@ pub struct TextRange {
 pub struct FrameHeader {
     magic: [u8; 4], // "ATOM" magic bytes
     version: u8,    // Protocol version
-    flags: u8,      // Reserved flags
+    flags: u8,      // FRAME_FLAG_* bits
     length: u32,    // Message length
     checksum: u32,  // CRC32 checksum
 }
 
 pub const MAGIC_BYTES: [u8; 4] = *b"ATOM";
-pub const PROTOCOL_VERSION: u8 = 1;
+/// Наибольшая поддерживаемая версия протокола (версия 2 — с рукопожатием Hello/Welcome,
+/// версия 3 — с версиями буферов, режимами файлов и кодами ошибок; кадры версий 1–2
+/// пишутся и читаются в прежнем формате, см. `legacy`)
+pub const PROTOCOL_VERSION: u8 = 3;
+/// Наименьшая поддерживаемая версия протокола (клиенты без рукопожатия)
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+/// Первая версия протокола, в которой большие файлы читаются по строкам (`GetLines`)
+pub const LINES_PROTOCOL_VERSION: u8 = 3;
 // Политика: лимит кадра по умолчанию 1 MiB (конфигурируемый в будущем)
 pub const MAX_MESSAGE_SIZE: u32 = 1024 * 1024; // 1 MiB limit
+/// Лимит размера сообщения после распаковки сжатого кадра
+pub const MAX_DECOMPRESSED_SIZE: u32 = 16 * 1024 * 1024; // 16 MiB limit
+/// Флаг кадра: полезная нагрузка сжата LZ4 (блок с 4‑байтовым LE‑префиксом исходного размера)
+pub const FRAME_FLAG_LZ4: u8 = 0x01;
+/// Флаг кадра: за ним следует продолжение того же логического сообщения
+pub const FRAME_FLAG_CONTINUED: u8 = 0x02;
+/// Лимит суммарного размера сообщения, собранного из нескольких кадров
+pub const MAX_REASSEMBLED_SIZE: u32 = 64 * 1024 * 1024; // 64 MiB limit
+/// Сообщения меньше порога не сжимаются — выигрыш не окупает накладные расходы
+const COMPRESSION_THRESHOLD: usize = 4 * 1024;
 const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
-#[allow(dead_code)]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 const MAX_RECONNECT_ATTEMPTS: usize = 5;
-#[allow(dead_code)]
 const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
 
 /// Connection state
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-enum ConnectionState {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Переподключение не удалось; новые запросы завершаются `ChannelClosed`
     Disconnected,
     Connecting,
     Connected,
+    /// Связь с демоном потеряна, идёт переподключение с backoff
     Reconnecting,
+    /// Клиент закрыт
     Closed,
 }
 
 /// IPC Client for UI process
 pub struct IpcClient {
-    state: Arc<RwLock<ConnectionState>>,
+    state: Arc<watch::Sender<ConnectionState>>,
     sender: Arc<Mutex<Option<mpsc::UnboundedSender<IpcMessage>>>>,
     pending_requests: Arc<Mutex<PendingMap>>,
+    pending_streams: Arc<Mutex<StreamMap>>,
     notification_tx: Arc<Mutex<Option<mpsc::UnboundedSender<Notification>>>>,
+    protocol: Arc<RwLock<NegotiatedProtocol>>,
+    /// Активные подписки; восстанавливаются на демоне после переподключения
+    subscriptions: Arc<Mutex<HashSet<SubscriptionTopic>>>,
     _socket_addr: String,
     config: IpcConfig,
 }
 
-type PendingMap = HashMap<RequestId, oneshot::Sender<Result<CoreResponse, IpcError>>>;
+/// Ожидающий ответа запрос
+struct PendingRequest {
+    tx: oneshot::Sender<Result<CoreResponse, IpcError>>,
+    /// Копия идемпотентного запроса для повтора после переподключения
+    replay: Option<CoreRequest>,
+}
+
+type PendingMap = HashMap<RequestId, PendingRequest>;
+type StreamMap = HashMap<RequestId, mpsc::UnboundedSender<Result<CoreResponse, IpcError>>>;
+
+/// Асинхронный поток кадров ответа на `IpcPayload::StreamRequest`.
+///
+/// Выдаёт промежуточные кадры (`CoreResponse::SearchResultsChunk`) и завершается
+/// после финального кадра, ошибки или отмены запроса.
+pub struct ResponseStream {
+    rx: mpsc::UnboundedReceiver<Result<CoreResponse, IpcError>>,
+}
+
+impl futures::Stream for ResponseStream {
+    type Item = Result<CoreResponse, IpcError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
 
 impl IpcClient {
     /// Connect to daemon with retry logic
-    pub async fn connect<A: ToSocketAddrs + Clone>(socket_addr: A) -> Result<Self, IpcError> {
+    ///
+    /// `socket_addr` is `host:port`, `tcp:host:port` or `unix:/path/to.sock`.
+    pub async fn connect<A: AsRef<str>>(socket_addr: A) -> Result<Self, IpcError> {
         Self::connect_with_config(socket_addr, IpcConfig::default()).await
     }
 
     /// Connect with explicit IPC configuration
-    pub async fn connect_with_config<A: ToSocketAddrs + Clone>(
+    pub async fn connect_with_config<A: AsRef<str>>(
         socket_addr: A,
         config: IpcConfig,
     ) -> Result<Self, IpcError> {
-        // Attempt initial connection with retries
-        let stream = Self::connect_with_retry(socket_addr.clone(), 3)
-            .await
-            .map_err(|e| IpcError::ConnectionFailed(format!("Failed to connect: {}", e)))?;
+        let endpoint = IpcEndpoint::parse(socket_addr.as_ref())?;
+        let (stream, protocol) = Self::establish(&endpoint, &config, 3).await?;
+        let legacy = protocol == NegotiatedProtocol::legacy();
 
         let (sender, receiver) = mpsc::unbounded_channel::<IpcMessage>();
-        let (notification_tx, notification_rx) = mpsc::unbounded_channel::<Notification>();
+        let (notification_tx, _notification_rx) = mpsc::unbounded_channel::<Notification>();
+        let (state, _) = watch::channel(ConnectionState::Connected);
 
         let client = Self {
-            state: Arc::new(RwLock::new(ConnectionState::Connected)),
+            state: Arc::new(state),
             sender: Arc::new(Mutex::new(Some(sender))),
             pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            pending_streams: Arc::new(Mutex::new(HashMap::new())),
             notification_tx: Arc::new(Mutex::new(Some(notification_tx))),
-            _socket_addr: "ipc-client".to_string(),
+            protocol: Arc::new(RwLock::new(protocol)),
+            subscriptions: Arc::new(Mutex::new(HashSet::new())),
+            _socket_addr: endpoint.to_string(),
             config,
         };
 
         // Start connection handler task
-        client
-            .start_connection_handler(stream, receiver, notification_rx)
-            .await;
+        client.start_connection_handler(stream, endpoint, receiver);
+
+        if !legacy {
+            return Ok(client);
+        }
 
-        // Test connection with ping
+        // Legacy‑сервер: проверяем соединение пингом
         match timeout(Duration::from_secs(5), client.ping()).await {
             Ok(Ok(_)) => Ok(client),
             Ok(Err(e)) => Err(IpcError::ConnectionFailed(format!("Ping failed: {}", e))),
@@ -293,18 +790,54 @@ impl IpcClient {
         }
     }
 
+    /// Установить соединение и выполнить рукопожатие.
+    ///
+    /// Сервер без поддержки Hello закрывает соединение — переподключаемся
+    /// и работаем как клиент версии 1.
+    async fn establish(
+        endpoint: &IpcEndpoint,
+        config: &IpcConfig,
+        max_retries: usize,
+    ) -> Result<(IpcStream, NegotiatedProtocol), IpcError> {
+        let mut stream = Self::connect_with_retry(endpoint, max_retries)
+            .await
+            .map_err(|e| IpcError::ConnectionFailed(format!("Failed to connect: {}", e)))?;
+
+        let offered: Vec<&str> = capabilities::ALL
+            .iter()
+            .copied()
+            .filter(|c| config.compression || *c != capabilities::LZ4)
+            .collect();
+        let handshake = timeout(
+            HANDSHAKE_TIMEOUT,
+            handshake::client_handshake(&mut stream, config.max_message_size, &offered),
+        )
+        .await;
+        match handshake {
+            Ok(Ok(protocol)) => Ok((stream, protocol)),
+            Ok(Err(e @ IpcError::IncompatibleVersion { .. })) => Err(e),
+            Ok(Err(IpcError::IoError(e))) => {
+                tracing::info!("Handshake not supported by {} ({}), using legacy protocol", endpoint, e);
+                let stream = Self::connect_with_retry(endpoint, max_retries)
+                    .await
+                    .map_err(|e| IpcError::ConnectionFailed(format!("Failed to connect: {}", e)))?;
+                Ok((stream, NegotiatedProtocol::legacy()))
+            }
+            Ok(Err(e)) => Err(IpcError::ConnectionFailed(format!("Handshake failed: {}", e))),
+            Err(_) => Err(IpcError::ConnectionFailed("Handshake timeout".to_string())),
+        }
+    }
+
     /// Attempt connection with exponential backoff retry
-    async fn connect_with_retry<A: ToSocketAddrs + Clone>(
-        socket_addr: A,
+    async fn connect_with_retry(
+        endpoint: &IpcEndpoint,
         max_retries: usize,
-    ) -> Result<TcpStream, IpcError> {
+    ) -> Result<IpcStream, IpcError> {
         let mut delay = Duration::from_millis(100);
 
         for attempt in 0..max_retries {
-            match TcpStream::connect(socket_addr.clone()).await {
+            match IpcStream::connect(endpoint).await {
                 Ok(stream) => {
-                    // Configure TCP socket
-                    stream.set_nodelay(true)?;
                     return Ok(stream);
                 }
                 Err(e) if attempt == max_retries - 1 => {
@@ -320,153 +853,47 @@ impl IpcClient {
         unreachable!()
     }
 
-    /// Start the connection handler task
-    async fn start_connection_handler(
+    /// Start the connection handler task (переживает разрывы: переподключается сам)
+    fn start_connection_handler(
         &self,
-        stream: TcpStream,
-        mut receiver: mpsc::UnboundedReceiver<IpcMessage>,
-        _notification_rx: mpsc::UnboundedReceiver<Notification>,
+        stream: IpcStream,
+        endpoint: IpcEndpoint,
+        receiver: mpsc::UnboundedReceiver<IpcMessage>,
     ) {
-        let (read_stream, write_stream) = stream.into_split();
-        let mut reader = BufReader::new(read_stream);
-        let mut writer = BufWriter::new(write_stream);
-
-        let pending_requests = Arc::clone(&self.pending_requests);
-        let state = Arc::clone(&self.state);
-        let notification_tx = Arc::clone(&self.notification_tx);
-
-        // Writer task (используем лимит кадра из конфигурации клиента)
-        let max_frame = self.config.max_message_size;
-        let writer_task = tokio::spawn(async move {
-            while let Some(message) = receiver.recv().await {
-                if let Err(e) = Self::write_message_with_limit(&mut writer, &message, max_frame).await {
-                    eprintln!("Write error: {}", e);
-                    break;
-                }
-            }
-        });
-
-        // Reader task
-        let reader_task = tokio::spawn(async move {
-            loop {
-                match Self::read_message_with_limit(&mut reader, MAX_MESSAGE_SIZE).await {
-                    Ok(message) => {
-                        Self::handle_message(message, &pending_requests, &notification_tx).await;
-                    }
-                    Err(e) => {
-                        eprintln!("Read error: {}", e);
-                        break;
-                    }
-                }
-            }
-        });
-
-        // Detach a supervisor and return immediately (do not block connect())
-        let state_detached = Arc::clone(&state);
-        tokio::spawn(async move {
-            tokio::select! {
-                _ = writer_task => {},
-                _ = reader_task => {},
-            }
-            *state_detached.write().await = ConnectionState::Disconnected;
-        });
-    }
-
-    /// Write framed message to stream
-    /// Низкоуровневая запись сообщения в поток (внутри клиента)
-    // Внутренний helper с параметром лимита кадра
-    async fn write_message_with_limit<W: AsyncWriteExt + Unpin>(
-        writer: &mut W,
-        message: &IpcMessage,
-        max_message_size: u32,
-    ) -> Result<(), IpcError> {
-        let payload = bincode::serialize(message)?;
-
-        if payload.len() > max_message_size as usize {
-            return Err(IpcError::InvalidFrame(format!(
-                "Message too large: {} bytes",
-                payload.len()
-            )));
-        }
-
-        let checksum = crc32fast::hash(&payload);
-
-        let header = FrameHeader {
-            magic: MAGIC_BYTES,
-            version: PROTOCOL_VERSION,
-            flags: 0,
-            length: payload.len() as u32,
-            checksum,
+        let task = connection::ConnectionTask {
+            endpoint,
+            config: self.config.clone(),
+            state: Arc::clone(&self.state),
+            protocol: Arc::clone(&self.protocol),
+            pending_requests: Arc::clone(&self.pending_requests),
+            pending_streams: Arc::clone(&self.pending_streams),
+            notification_tx: Arc::clone(&self.notification_tx),
+            subscriptions: Arc::clone(&self.subscriptions),
         };
-
-        // Write header
-        let header_bytes = bincode::serialize(&header)?;
-        writer.write_all(&header_bytes).await?;
-
-        // Write payload
-        writer.write_all(&payload).await?;
-        writer.flush().await?;
-
-        Ok(())
-    }
-
-    /// Read framed message from stream
-    /// Низкоуровневое чтение сообщения из потока (внутри клиента)
-    // Внутренний helper чтения с параметром лимита кадра
-    async fn read_message_with_limit<R: AsyncReadExt + Unpin>(
-        reader: &mut R,
-        max_message_size: u32,
-    ) -> Result<IpcMessage, IpcError> {
-        // Read header (фиксированный сериализованный размер 14 байт: 4+1+1+4+4)
-        let mut header_buf = [0u8; 14];
-        reader.read_exact(&mut header_buf).await?;
-
-        let header: FrameHeader = bincode::deserialize(&header_buf)?;
-
-        // Validate header
-        if header.magic != MAGIC_BYTES {
-            return Err(IpcError::InvalidFrame("Invalid magic bytes".to_string()));
-        }
-
-        if header.version != PROTOCOL_VERSION {
-            return Err(IpcError::InvalidFrame(format!(
-                "Unsupported protocol version: {}",
-                header.version
-            )));
-        }
-
-        if header.length > max_message_size {
-            return Err(IpcError::InvalidFrame(format!(
-                "Message too large: {} bytes",
-                header.length
-            )));
-        }
-
-        // Read payload
-        let mut payload_buf = vec![0u8; header.length as usize];
-        reader.read_exact(&mut payload_buf).await?;
-
-        // Verify checksum
-        let actual_checksum = crc32fast::hash(&payload_buf);
-        if actual_checksum != header.checksum {
-            return Err(IpcError::InvalidFrame("Checksum mismatch".to_string()));
-        }
-
-        // Deserialize message
-        let message: IpcMessage = bincode::deserialize(&payload_buf)?;
-        Ok(message)
+        tokio::spawn(task.run(stream, receiver));
     }
 
     /// Handle received message
     async fn handle_message(
         message: IpcMessage,
         pending_requests: &Arc<Mutex<PendingMap>>,
+        pending_streams: &Arc<Mutex<StreamMap>>,
         notification_tx: &Arc<Mutex<Option<mpsc::UnboundedSender<Notification>>>>,
     ) {
         match message.payload {
             IpcPayload::Response(response) => {
-                if let Some(sender) = pending_requests.lock().await.remove(&message.id) {
-                    let _ = sender.send(Ok(response));
+                if let Some(pending) = pending_requests.lock().await.remove(&message.id) {
+                    let _ = pending.tx.send(Ok(response));
+                    return;
+                }
+                let mut streams = pending_streams.lock().await;
+                if response.is_partial() {
+                    if let Some(tx) = streams.get(&message.id) {
+                        let _ = tx.send(Ok(response));
+                    }
+                } else if let Some(tx) = streams.remove(&message.id) {
+                    // Финальный кадр: после него отправитель удаляется и поток завершается
+                    let _ = tx.send(Ok(response));
                 }
             }
             IpcPayload::Notification(notification) => {
@@ -506,10 +933,13 @@ impl IpcClient {
         // Register pending request
         {
             let mut pending = self.pending_requests.lock().await;
-            if pending.len() >= self.config.max_pending_requests {
+            if pending.len() + self.pending_streams.lock().await.len()
+                >= self.config.max_pending_requests
+            {
                 return Err(IpcError::Backpressure);
             }
-            pending.insert(id, response_tx);
+            let replay = request.is_idempotent().then(|| request.clone());
+            pending.insert(id, PendingRequest { tx: response_tx, replay });
         }
 
         let message = IpcMessage {
@@ -519,15 +949,63 @@ impl IpcClient {
         };
 
         // Send message
-        if let Some(sender) = self.sender.lock().await.as_ref() {
-            sender.send(message).map_err(|_| IpcError::ChannelClosed)?;
-        } else {
-            return Err(IpcError::ChannelClosed);
+        if let Err(e) = self.send_message(message).await {
+            self.pending_requests.lock().await.remove(&id);
+            return Err(e);
         }
 
         Ok((id, response_rx))
     }
 
+    /// Отправить запрос в потоковом режиме и получить идентификатор + поток кадров ответа.
+    ///
+    /// Поток завершается после финального кадра; `cancel(id)` останавливает его
+    /// с ошибкой `IpcError::Cancelled`.
+    pub async fn start_stream(
+        &self,
+        request: CoreRequest,
+    ) -> Result<(RequestId, ResponseStream), IpcError> {
+        let id = RequestId::new();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        {
+            // Порядок блокировок тот же, что и в start_request: pending_requests → pending_streams
+            let pending = self.pending_requests.lock().await;
+            let mut streams = self.pending_streams.lock().await;
+            if pending.len() + streams.len() >= self.config.max_pending_requests {
+                return Err(IpcError::Backpressure);
+            }
+            streams.insert(id, tx);
+        }
+
+        // Без возможности STREAMING сервер вернёт один финальный кадр на обычный запрос
+        let payload = if self.protocol.read().await.supports(capabilities::STREAMING) {
+            IpcPayload::StreamRequest(request)
+        } else {
+            IpcPayload::Request(request)
+        };
+        let message = IpcMessage {
+            id,
+            deadline_millis: now_millis() + self.config.request_timeout.as_millis() as u64,
+            payload,
+        };
+
+        if let Err(e) = self.send_message(message).await {
+            self.pending_streams.lock().await.remove(&id);
+            return Err(e);
+        }
+
+        Ok((id, ResponseStream { rx }))
+    }
+
+    /// Передать сообщение задаче‑писателю
+    async fn send_message(&self, message: IpcMessage) -> Result<(), IpcError> {
+        match self.sender.lock().await.as_ref() {
+            Some(sender) => sender.send(message).map_err(|_| IpcError::ChannelClosed),
+            None => Err(IpcError::ChannelClosed),
+        }
+    }
+
     /// Send ping to test connection
     pub async fn ping(&self) -> Result<(), IpcError> {
         match self.request(CoreRequest::Ping).await? {
@@ -542,8 +1020,11 @@ impl IpcClient {
     /// Cancel a pending request
     pub async fn cancel(&self, request_id: RequestId) -> Result<(), IpcError> {
         // Remove from pending requests
-        if let Some(sender) = self.pending_requests.lock().await.remove(&request_id) {
-            let _ = sender.send(Err(IpcError::Cancelled));
+        if let Some(pending) = self.pending_requests.lock().await.remove(&request_id) {
+            let _ = pending.tx.send(Err(IpcError::Cancelled));
+        }
+        if let Some(tx) = self.pending_streams.lock().await.remove(&request_id) {
+            let _ = tx.send(Err(IpcError::Cancelled));
         }
 
         // Send cancellation message
@@ -560,10 +1041,56 @@ impl IpcClient {
         Ok(())
     }
 
+    /// Версия протокола и возможности, согласованные с сервером
+    pub async fn protocol(&self) -> NegotiatedProtocol {
+        self.protocol.read().await.clone()
+    }
+
     /// Get connection state
-    #[allow(dead_code)]
-    pub(crate) async fn state(&self) -> ConnectionState {
-        self.state.read().await.clone()
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Подписаться на изменения состояния соединения (индикация «reconnecting…» в UI)
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Подписаться на темы уведомлений демона.
+    ///
+    /// Уведомления приходят в приёмник из `notifications()`. Подписки
+    /// переживают переподключение: задача соединения повторяет их сама.
+    pub async fn subscribe(&self, topics: Vec<SubscriptionTopic>) -> Result<(), IpcError> {
+        self.require(capabilities::SUBSCRIPTIONS).await?;
+        self.subscriptions.lock().await.extend(topics.iter().cloned());
+        match self.request(CoreRequest::Subscribe { topics }).await? {
+            CoreResponse::Success => Ok(()),
+            other => Err(IpcError::ConnectionFailed(format!("Unexpected response to subscribe: {:?}", other))),
+        }
+    }
+
+    /// Отписаться от тем уведомлений
+    pub async fn unsubscribe(&self, topics: Vec<SubscriptionTopic>) -> Result<(), IpcError> {
+        self.require(capabilities::SUBSCRIPTIONS).await?;
+        {
+            let mut subscriptions = self.subscriptions.lock().await;
+            for topic in &topics {
+                subscriptions.remove(topic);
+            }
+        }
+        match self.request(CoreRequest::Unsubscribe { topics }).await? {
+            CoreResponse::Success => Ok(()),
+            other => Err(IpcError::ConnectionFailed(format!("Unexpected response to unsubscribe: {:?}", other))),
+        }
+    }
+
+    /// Проверить, что возможность согласована с сервером
+    async fn require(&self, capability: &str) -> Result<(), IpcError> {
+        if self.protocol.read().await.supports(capability) {
+            Ok(())
+        } else {
+            Err(IpcError::Unsupported(capability.to_string()))
+        }
     }
 
     /// Subscribe to notifications
@@ -581,54 +1108,94 @@ impl IpcClient {
 
 // === Публичные функции для серверной стороны (atomd) ===
 
-/// Прочитать фреймированное IPC‑сообщение из потока (сервер/клиент)
-pub async fn read_ipc_message<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<IpcMessage, IpcError> {
-    // Read header from wire: magic[4], version[1], flags[1], length[4], checksum[4] = 14 bytes
-    // Do not use size_of::<FrameHeader>() here due to potential struct padding.
-    let mut header_buf = [0u8; 14];
-    reader.read_exact(&mut header_buf).await?;
-
-    let header: FrameHeader = bincode::deserialize(&header_buf)?;
+/// Параметры кадрирования одного соединения
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Версия протокола в заголовке исходящих кадров
+    pub version: u8,
+    /// Лимит размера кадра на проводе (байт)
+    pub max_message_size: u32,
+    /// Лимит размера сообщения после распаковки (байт)
+    pub max_decompressed_size: u32,
+    /// Сжатие LZ4 согласовано с пиром (`capabilities::LZ4`)
+    pub compression: bool,
+    /// Лимит суммарного размера сообщения из нескольких кадров (байт)
+    pub max_reassembled_size: u32,
+    /// Многокадровые сообщения согласованы с пиром (`capabilities::CHUNKED`)
+    pub chunking: bool,
+}
 
-    // Validate header
-    if header.magic != MAGIC_BYTES {
-        return Err(IpcError::InvalidFrame("Invalid magic bytes".to_string()));
+impl FrameOptions {
+    /// Текущая версия протокола с указанным лимитом кадра, без сжатия и продолжений
+    pub fn with_limit(max_message_size: u32) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            max_message_size,
+            max_decompressed_size: MAX_DECOMPRESSED_SIZE,
+            compression: false,
+            max_reassembled_size: MAX_REASSEMBLED_SIZE,
+            chunking: false,
+        }
     }
 
-    if header.version != PROTOCOL_VERSION {
-        return Err(IpcError::InvalidFrame(format!(
-            "Unsupported protocol version: {}",
-            header.version
-        )));
+    /// Параметры до завершения рукопожатия: кадры помечаются минимальной версией,
+    /// чтобы их заголовок мог разобрать и более старый пир
+    pub fn handshake(max_message_size: u32) -> Self {
+        Self { version: MIN_PROTOCOL_VERSION, ..Self::with_limit(max_message_size) }
     }
+}
 
-    if header.length > MAX_MESSAGE_SIZE {
-        return Err(IpcError::InvalidFrame(format!(
-            "Message too large: {} bytes",
-            header.length
-        )));
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self::with_limit(MAX_MESSAGE_SIZE)
     }
+}
 
-    // Read payload
-    let mut payload_buf = vec![0u8; header.length as usize];
-    reader.read_exact(&mut payload_buf).await?;
+/// Прочитать фреймированное IPC‑сообщение с параметрами соединения.
+///
+/// Принимаются кадры любой поддерживаемой версии (`MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION`).
+/// Логическое сообщение из нескольких кадров (`FRAME_FLAG_CONTINUED`) собирается
+/// целиком; фрагменты одного сообщения идут подряд, без чередования с другими.
+pub async fn read_ipc_frame<R: AsyncReadExt + Unpin>(
+    reader: &mut R,
+    options: &FrameOptions,
+) -> Result<IpcMessage, IpcError> {
+    let (first, mut payload_buf) = read_fragment(reader, options).await?;
+    let compressed = first.flags & FRAME_FLAG_LZ4 != 0;
+    let mut continued = first.flags & FRAME_FLAG_CONTINUED != 0;
+
+    while continued {
+        let (header, fragment) = read_fragment(reader, options).await?;
+        if (header.flags & FRAME_FLAG_LZ4 != 0) != compressed {
+            return Err(IpcError::InvalidFrame("Inconsistent flags in continuation frame".to_string()));
+        }
+        if payload_buf.len() + fragment.len() > options.max_reassembled_size as usize {
+            return Err(IpcError::InvalidFrame(format!(
+                "Reassembled message too large: more than {} bytes",
+                options.max_reassembled_size
+            )));
+        }
+        payload_buf.extend_from_slice(&fragment);
+        continued = header.flags & FRAME_FLAG_CONTINUED != 0;
+    }
 
-    // Verify checksum
-    let actual_checksum = crc32fast::hash(&payload_buf);
-    if actual_checksum != header.checksum {
-        return Err(IpcError::InvalidFrame("Checksum mismatch".to_string()));
+    if compressed {
+        payload_buf = decompress_payload(&payload_buf, options.max_decompressed_size)?;
     }
 
-    // Deserialize message
+    // Deserialize message (формат определяется версией кадра)
+    if first.version < legacy::FIELDS_VERSION {
+        return legacy::deserialize(&payload_buf);
+    }
     let message: IpcMessage = bincode::deserialize(&payload_buf)?;
     Ok(message)
 }
 
-/// Прочитать фреймированное IPC‑сообщение с указанным лимитом кадра
-pub async fn read_ipc_message_cfg<R: AsyncReadExt + Unpin>(
+/// Прочитать один кадр: проверить заголовок и контрольную сумму
+async fn read_fragment<R: AsyncReadExt + Unpin>(
     reader: &mut R,
-    max_message_size: u32,
-) -> Result<IpcMessage, IpcError> {
+    options: &FrameOptions,
+) -> Result<(FrameHeader, Vec<u8>), IpcError> {
     // Read header from wire: magic[4], version[1], flags[1], length[4], checksum[4] = 14 bytes
     // Do not use size_of::<FrameHeader>() here due to potential struct padding.
     let mut header_buf = [0u8; 14];
@@ -641,14 +1208,24 @@ pub async fn read_ipc_message_cfg<R: AsyncReadExt + Unpin>(
         return Err(IpcError::InvalidFrame("Invalid magic bytes".to_string()));
     }
 
-    if header.version != PROTOCOL_VERSION {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&header.version) {
         return Err(IpcError::InvalidFrame(format!(
             "Unsupported protocol version: {}",
             header.version
         )));
     }
 
-    if header.length > max_message_size {
+    if header.flags & !(FRAME_FLAG_LZ4 | FRAME_FLAG_CONTINUED) != 0 {
+        return Err(IpcError::InvalidFrame(format!("Unknown frame flags: {:#04x}", header.flags)));
+    }
+    if header.flags & FRAME_FLAG_LZ4 != 0 && !options.compression {
+        return Err(IpcError::InvalidFrame("Compressed frame without negotiated compression".to_string()));
+    }
+    if header.flags & FRAME_FLAG_CONTINUED != 0 && !options.chunking {
+        return Err(IpcError::InvalidFrame("Continuation frame without negotiated chunking".to_string()));
+    }
+
+    if header.length > options.max_message_size {
         return Err(IpcError::InvalidFrame(format!(
             "Message too large: {} bytes",
             header.length
@@ -659,78 +1236,140 @@ pub async fn read_ipc_message_cfg<R: AsyncReadExt + Unpin>(
     let mut payload_buf = vec![0u8; header.length as usize];
     reader.read_exact(&mut payload_buf).await?;
 
-    // Verify checksum
+    // Verify checksum (по байтам на проводе, т.е. до распаковки)
     let actual_checksum = crc32fast::hash(&payload_buf);
     if actual_checksum != header.checksum {
         return Err(IpcError::InvalidFrame("Checksum mismatch".to_string()));
     }
 
-    // Deserialize message
-    let message: IpcMessage = bincode::deserialize(&payload_buf)?;
-    Ok(message)
+    Ok((header, payload_buf))
 }
 
-/// Записать фреймированное IPC‑сообщение в поток (сервер/клиент)
-pub async fn write_ipc_message<W: AsyncWriteExt + Unpin>(
+/// Записать фреймированное IPC‑сообщение с параметрами соединения.
+///
+/// При согласованном chunking сообщение больше `max_message_size` делится на
+/// кадры с `FRAME_FLAG_CONTINUED` (кроме последнего); все кадры пишутся подряд.
+pub async fn write_ipc_frame<W: AsyncWriteExt + Unpin>(
     writer: &mut W,
     message: &IpcMessage,
+    options: &FrameOptions,
 ) -> Result<(), IpcError> {
-    let payload = bincode::serialize(message)?;
+    let mut payload = if options.version < legacy::FIELDS_VERSION {
+        legacy::serialize(message)?
+    } else {
+        bincode::serialize(message)?
+    };
+    let mut flags = 0;
+
+    // Сообщение больше лимита распаковки пир не примет сжатым — отправляем как есть
+    if options.compression
+        && payload.len() >= COMPRESSION_THRESHOLD
+        && payload.len() <= options.max_decompressed_size as usize
+    {
+        let compressed = lz4_flex::block::compress_prepend_size(&payload);
+        // Несжимаемые данные отправляем как есть
+        if compressed.len() < payload.len() {
+            payload = compressed;
+            flags |= FRAME_FLAG_LZ4;
+        }
+    }
 
-    if payload.len() > MAX_MESSAGE_SIZE as usize {
+    let limit = if options.chunking { options.max_reassembled_size } else { options.max_message_size };
+    if payload.len() > limit as usize {
         return Err(IpcError::InvalidFrame(format!(
             "Message too large: {} bytes",
             payload.len()
         )));
     }
 
-    let checksum = crc32fast::hash(&payload);
+    let fragment_size = options.max_message_size.max(1) as usize;
+    let mut offset = 0;
+    loop {
+        let end = (offset + fragment_size).min(payload.len());
+        let last = end == payload.len();
+        let fragment_flags = if last { flags } else { flags | FRAME_FLAG_CONTINUED };
+        write_fragment(writer, options.version, fragment_flags, &payload[offset..end]).await?;
+        if last {
+            break;
+        }
+        offset = end;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Записать один кадр (заголовок + полезная нагрузка) без flush
+async fn write_fragment<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    version: u8,
+    flags: u8,
+    payload: &[u8],
+) -> Result<(), IpcError> {
+    let checksum = crc32fast::hash(payload);
 
     let header = FrameHeader {
         magic: MAGIC_BYTES,
-        version: PROTOCOL_VERSION,
-        flags: 0,
+        version,
+        flags,
         length: payload.len() as u32,
         checksum,
     };
 
     let header_bytes = bincode::serialize(&header)?;
     writer.write_all(&header_bytes).await?;
-    writer.write_all(&payload).await?;
-    writer.flush().await?;
+    writer.write_all(payload).await?;
     Ok(())
 }
 
-/// Записать фреймированное IPC‑сообщение в поток с указанным лимитом кадра
-pub async fn write_ipc_message_cfg<W: AsyncWriteExt + Unpin>(
-    writer: &mut W,
-    message: &IpcMessage,
-    max_message_size: u32,
-) -> Result<(), IpcError> {
-    let payload = bincode::serialize(message)?;
-
-    if payload.len() > max_message_size as usize {
+/// Распаковать LZ4‑блок; заявленный размер проверяется до выделения памяти
+fn decompress_payload(payload: &[u8], max_decompressed_size: u32) -> Result<Vec<u8>, IpcError> {
+    if payload.len() < 4 {
+        return Err(IpcError::InvalidFrame("Compressed frame is truncated".to_string()));
+    }
+    let (size, block) = payload.split_at(4);
+    let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+    if size > max_decompressed_size {
         return Err(IpcError::InvalidFrame(format!(
-            "Message too large: {} bytes",
-            payload.len()
+            "Decompressed message too large: {} bytes",
+            size
         )));
     }
+    let data = lz4_flex::block::decompress(block, size as usize)
+        .map_err(|e| IpcError::InvalidFrame(format!("LZ4 decompression failed: {}", e)))?;
+    if data.len() != size as usize {
+        return Err(IpcError::InvalidFrame("Decompressed size mismatch".to_string()));
+    }
+    Ok(data)
+}
 
-    let checksum = crc32fast::hash(&payload);
+/// Прочитать фреймированное IPC‑сообщение из потока (сервер/клиент)
+pub async fn read_ipc_message<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<IpcMessage, IpcError> {
+    read_ipc_frame(reader, &FrameOptions::default()).await
+}
 
-    let header = FrameHeader {
-        magic: MAGIC_BYTES,
-        version: PROTOCOL_VERSION,
-        flags: 0,
-        length: payload.len() as u32,
-        checksum,
-    };
+/// Прочитать фреймированное IPC‑сообщение с указанным лимитом кадра
+pub async fn read_ipc_message_cfg<R: AsyncReadExt + Unpin>(
+    reader: &mut R,
+    max_message_size: u32,
+) -> Result<IpcMessage, IpcError> {
+    read_ipc_frame(reader, &FrameOptions::with_limit(max_message_size)).await
+}
 
-    let header_bytes = bincode::serialize(&header)?;
-    writer.write_all(&header_bytes).await?;
-    writer.write_all(&payload).await?;
-    writer.flush().await?;
-    Ok(())
+/// Записать фреймированное IPC‑сообщение в поток (сервер/клиент)
+pub async fn write_ipc_message<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    message: &IpcMessage,
+) -> Result<(), IpcError> {
+    write_ipc_frame(writer, message, &FrameOptions::default()).await
+}
+
+/// Записать фреймированное IPC‑сообщение в поток с указанным лимитом кадра
+pub async fn write_ipc_message_cfg<W: AsyncWriteExt + Unpin>(
+    writer: &mut W,
+    message: &IpcMessage,
+    max_message_size: u32,
+) -> Result<(), IpcError> {
+    write_ipc_frame(writer, message, &FrameOptions::with_limit(max_message_size)).await
 }
 
 fn now_millis() -> u64 {
@@ -747,6 +1386,16 @@ pub struct IpcConfig {
     pub request_timeout: Duration,
     pub max_message_size: u32,
     pub max_pending_requests: usize,
+    /// Лимит размера сообщения после распаковки сжатого кадра
+    pub max_decompressed_size: u32,
+    /// Предлагать серверу сжатие LZ4
+    pub compression: bool,
+    /// Лимит суммарного размера сообщения из нескольких кадров
+    pub max_reassembled_size: u32,
+    /// Число попыток переподключения после разрыва (0 — не переподключаться)
+    pub max_reconnect_attempts: usize,
+    /// Начальная задержка между попытками (удваивается, не более 10 с)
+    pub reconnect_base_delay: Duration,
 }
 
 impl Default for IpcConfig {
@@ -755,6 +1404,11 @@ impl Default for IpcConfig {
             request_timeout: DEFAULT_REQUEST_TIMEOUT,
             max_message_size: MAX_MESSAGE_SIZE,
             max_pending_requests: 10_000,
+            max_decompressed_size: MAX_DECOMPRESSED_SIZE,
+            compression: true,
+            max_reassembled_size: MAX_REASSEMBLED_SIZE,
+            max_reconnect_attempts: MAX_RECONNECT_ATTEMPTS,
+            reconnect_base_delay: RECONNECT_BASE_DELAY,
         }
     }
 }
@@ -787,4 +1441,24 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "ipc_test");
     }
+
+    #[test]
+    fn error_response_keeps_code_and_detail() {
+        let response = CoreResponse::error_with_detail(
+            ErrorCode::ToolMissing,
+            "Search failed: rg not found in PATH",
+            ErrorDetail::Tool { name: "rg".into() },
+        );
+        let bytes = bincode::serialize(&response).unwrap();
+        match bincode::deserialize::<CoreResponse>(&bytes).unwrap() {
+            CoreResponse::Error { code, detail, .. } => {
+                assert_eq!(code, ErrorCode::ToolMissing);
+                assert_eq!(code.to_string(), "tool-missing");
+                assert_eq!(detail, Some(ErrorDetail::Tool { name: "rg".into() }));
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert_eq!(IpcError::Timeout.code(), ErrorCode::DeadlineExceeded);
+        assert_eq!(CoreResponse::Pong.error_code(), None);
+    }
 }
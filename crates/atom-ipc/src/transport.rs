@@ -0,0 +1,315 @@
+//! IPC transports
+//!
+//! Абстракция над TCP и Unix domain sockets. Адрес демона задаётся строкой
+//! (`DaemonSettings::daemon_socket`):
+//! - `127.0.0.1:8877` или `tcp:127.0.0.1:8877` — TCP;
+//! - `unix:/run/user/1000/atomd.sock` — Unix domain socket (только Unix‑платформы).
+
+use std::fmt;
+use std::io;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::IpcError;
+
+const TCP_PREFIX: &str = "tcp:";
+const UNIX_PREFIX: &str = "unix:";
+
+/// Адрес IPC‑сервера
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcEndpoint {
+    /// TCP `host:port`
+    Tcp(String),
+    /// Путь к Unix domain socket
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl IpcEndpoint {
+    /// Разобрать адрес вида `host:port`, `tcp:host:port` или `unix:/path`
+    pub fn parse(addr: &str) -> Result<Self, IpcError> {
+        let addr = addr.trim();
+        if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                return Err(IpcError::ConnectionFailed("Empty unix socket path".to_string()));
+            }
+            #[cfg(unix)]
+            return Ok(IpcEndpoint::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(IpcError::ConnectionFailed(format!(
+                "Unix domain sockets are not supported on this platform: {}",
+                addr
+            )));
+        }
+        let tcp = addr.strip_prefix(TCP_PREFIX).unwrap_or(addr);
+        if tcp.is_empty() {
+            return Err(IpcError::ConnectionFailed("Empty socket address".to_string()));
+        }
+        Ok(IpcEndpoint::Tcp(tcp.to_string()))
+    }
+}
+
+impl FromStr for IpcEndpoint {
+    type Err = IpcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for IpcEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcEndpoint::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            IpcEndpoint::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Половина соединения для чтения (транспорт‑независимая)
+pub type IpcReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+/// Половина соединения для записи (транспорт‑независимая)
+pub type IpcWriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Установленное IPC‑соединение
+#[derive(Debug)]
+pub enum IpcStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl IpcStream {
+    /// Подключиться к адресу (одна попытка, без ретраев)
+    pub async fn connect(endpoint: &IpcEndpoint) -> io::Result<Self> {
+        match endpoint {
+            IpcEndpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr.as_str()).await?;
+                // Configure TCP socket
+                stream.set_nodelay(true)?;
+                Ok(IpcStream::Tcp(stream))
+            }
+            #[cfg(unix)]
+            IpcEndpoint::Unix(path) => Ok(IpcStream::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+
+    /// Разделить соединение на независимые половины чтения и записи
+    pub fn into_split(self) -> (IpcReadHalf, IpcWriteHalf) {
+        match self {
+            IpcStream::Tcp(stream) => {
+                let (r, w) = stream.into_split();
+                (Box::new(r), Box::new(w))
+            }
+            #[cfg(unix)]
+            IpcStream::Unix(stream) => {
+                let (r, w) = stream.into_split();
+                (Box::new(r), Box::new(w))
+            }
+        }
+    }
+}
+
+impl AsyncRead for IpcStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IpcStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            IpcStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for IpcStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            IpcStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            IpcStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IpcStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            IpcStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            IpcStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            IpcStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Слушающий IPC‑сокет сервера.
+///
+/// Для Unix domain socket файл сокета сразу создаётся с правами 0600, «протухший»
+/// файл от упавшего демона удаляется при старте, а при остановке сервера файл
+/// удаляется в `Drop`.
+#[derive(Debug)]
+pub enum IpcListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix { listener: UnixListener, path: PathBuf },
+}
+
+impl IpcListener {
+    /// Начать слушать адрес
+    pub async fn bind(endpoint: &IpcEndpoint) -> Result<Self, IpcError> {
+        match endpoint {
+            IpcEndpoint::Tcp(addr) => Ok(IpcListener::Tcp(TcpListener::bind(addr.as_str()).await?)),
+            #[cfg(unix)]
+            IpcEndpoint::Unix(path) => {
+                remove_stale_socket(path).await?;
+                if let Some(parent) = path.parent() {
+                    if !parent.as_os_str().is_empty() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                }
+                let listener = bind_private(path)?;
+                Ok(IpcListener::Unix { listener, path: path.clone() })
+            }
+        }
+    }
+
+    /// Принять новое соединение; возвращает поток и описание удалённой стороны
+    pub async fn accept(&self) -> io::Result<(IpcStream, String)> {
+        match self {
+            IpcListener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok((IpcStream::Tcp(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            IpcListener::Unix { listener, path } => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok((IpcStream::Unix(stream), format!("{}{}", UNIX_PREFIX, path.display())))
+            }
+        }
+    }
+
+    /// Фактический адрес прослушивания (для TCP с портом 0 — назначенный порт)
+    pub fn local_endpoint(&self) -> io::Result<IpcEndpoint> {
+        match self {
+            IpcListener::Tcp(listener) => Ok(IpcEndpoint::Tcp(listener.local_addr()?.to_string())),
+            #[cfg(unix)]
+            IpcListener::Unix { path, .. } => Ok(IpcEndpoint::Unix(path.clone())),
+        }
+    }
+}
+
+/// Создать сокет сразу с правами 0600.
+///
+/// Сокет создаётся во временном каталоге 0700 рядом с `path`, получает права
+/// 0600 и только затем переименовывается в `path`: до этого момента к нему не
+/// может подключиться никто, кроме владельца. umask не трогаем — он общий для
+/// всех потоков процесса.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("no socket file name in {}", path.display()))
+    })?;
+    let mut staging_name = std::ffi::OsString::from(".");
+    staging_name.push(file_name);
+    staging_name.push(format!(".{}.tmp", std::process::id()));
+    let staging = path.with_file_name(staging_name);
+    if staging.exists() {
+        // Остаток от упавшего процесса с тем же pid
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join(file_name);
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        // Только владелец может подключаться к демону
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+#[cfg(unix)]
+impl Drop for IpcListener {
+    fn drop(&mut self) {
+        if let IpcListener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Удалить файл сокета, оставшийся от не завершившегося корректно демона.
+/// Если на сокете кто‑то слушает — это ошибка (второй демон на том же адресе).
+#[cfg(unix)]
+async fn remove_stale_socket(path: &Path) -> Result<(), IpcError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(IpcError::ConnectionFailed(format!(
+            "{} exists and is not a socket",
+            path.display()
+        )));
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(IpcError::IoError(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another daemon is already listening on {}", path.display()),
+        )));
+    }
+    tracing::info!("Removing stale IPC socket {}", path.display());
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn unix_socket_is_private_and_connectable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("atomd.sock");
+        let listener = IpcListener::bind(&IpcEndpoint::Unix(path.clone())).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "socket mode: {:o}", mode);
+        // Временный каталог убран
+        let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(entries, vec![std::ffi::OsString::from("atomd.sock")]);
+
+        let (accepted, connected) = tokio::join!(listener.accept(), UnixStream::connect(&path));
+        accepted.unwrap();
+        connected.unwrap();
+    }
+}
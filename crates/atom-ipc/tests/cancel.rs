@@ -1,11 +1,11 @@
-use atom_ipc::{CoreRequest, IpcClient, IpcConfig, IpcMessage, IpcPayload, read_ipc_message, write_ipc_message, CoreResponse};
+use atom_ipc::{capabilities, server_handshake, CoreRequest, IpcClient, IpcConfig, IpcMessage, IpcPayload, read_ipc_message, write_ipc_message, CoreResponse, MAX_MESSAGE_SIZE};
 
 #[tokio::test]
 async fn cancel_long_running_request() {
     use tokio::net::TcpListener;
 use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
 
-    // Minimal server: handles initial Ping handshake, then responds to Sleep by sleeping long; Cancel is ignored
+    // Minimal server: handles the Hello/Welcome handshake, then responds to Sleep by sleeping long; Cancel is ignored
     let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
     let addr = listener.local_addr().unwrap();
 
@@ -14,12 +14,8 @@ use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
         let (r, w) = stream.split();
         let mut reader = BufReader::new(r);
         let mut writer = BufWriter::new(w);
-        // 1) Handshake: expect Ping, reply Pong
-        if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Ping), .. }) = read_ipc_message(&mut reader).await {
-            let pong = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Pong) };
-            let _ = write_ipc_message(&mut writer, &pong).await;
-            let _ = writer.flush().await;
-        }
+        // 1) Handshake: answer Hello with Welcome
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
         // 2) Next, expect Sleep; simulate long work (ignore Cancel), then reply Success
         if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Sleep { millis }), .. }) = read_ipc_message(&mut reader).await {
             let _ = tokio::time::timeout(std::time::Duration::from_secs(30), tokio::time::sleep(std::time::Duration::from_millis(millis))).await;
@@ -0,0 +1,107 @@
+//! Multi-frame messages: FRAME_FLAG_CONTINUED fragments reassembled by the reader
+use atom_ipc::{
+    read_ipc_frame, write_ipc_frame, CoreResponse, FrameOptions, IpcError, IpcMessage, IpcPayload,
+    RequestId, FRAME_FLAG_CONTINUED,
+};
+
+const FRAME: u32 = 256 * 1024;
+
+/// Incompressible text (pseudo-random hex), so size limits apply to raw bytes
+fn noise(len: usize) -> String {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            char::from_digit(((state >> 59) & 0xF) as u32, 16).unwrap()
+        })
+        .collect()
+}
+
+fn buffer_opened(content: String) -> IpcMessage {
+    IpcMessage {
+        id: RequestId::new(),
+        deadline_millis: 0,
+        payload: IpcPayload::Response(CoreResponse::BufferOpened { buffer_id: "b1".into(), content, version: 0, mode: Default::default() }),
+    }
+}
+
+fn chunked(max_reassembled_size: u32) -> FrameOptions {
+    FrameOptions { chunking: true, max_reassembled_size, ..FrameOptions::with_limit(FRAME) }
+}
+
+/// Flags of every frame on the wire
+fn frame_flags(mut wire: &[u8]) -> Vec<u8> {
+    let mut flags = Vec::new();
+    while !wire.is_empty() {
+        let len = u32::from_le_bytes([wire[6], wire[7], wire[8], wire[9]]) as usize;
+        assert!(len <= FRAME as usize, "fragment exceeds frame limit");
+        flags.push(wire[5]);
+        wire = &wire[14 + len..];
+    }
+    flags
+}
+
+#[tokio::test]
+async fn large_message_is_split_and_reassembled() {
+    let content = noise(1024 * 1024);
+    let msg = buffer_opened(content.clone());
+    let options = chunked(4 * 1024 * 1024);
+
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &options).await.expect("write chunked");
+    let flags = frame_flags(&wire);
+    assert_eq!(flags.len(), 5, "1 MiB + envelope in 256 KiB frames");
+    assert!(flags[..4].iter().all(|f| f & FRAME_FLAG_CONTINUED != 0));
+    assert_eq!(flags[4] & FRAME_FLAG_CONTINUED, 0, "last fragment ends the message");
+
+    let recv = read_ipc_frame(&mut wire.as_slice(), &options).await.expect("reassemble");
+    assert_eq!(recv.id, msg.id);
+    match recv.payload {
+        IpcPayload::Response(CoreResponse::BufferOpened { content: got, .. }) => assert_eq!(got, content),
+        other => panic!("unexpected payload: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn reassembled_size_cap_is_enforced() {
+    let msg = buffer_opened(noise(1024 * 1024));
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &chunked(4 * 1024 * 1024)).await.expect("write");
+
+    let err = read_ipc_frame(&mut wire.as_slice(), &chunked(512 * 1024)).await.expect_err("must err");
+    assert!(matches!(err, IpcError::InvalidFrame(ref m) if m.contains("Reassembled message too large")), "got {:?}", err);
+
+    // The writer applies the same cap before sending anything
+    let err = write_ipc_frame(&mut Vec::new(), &msg, &chunked(512 * 1024)).await.expect_err("must err");
+    assert!(err.to_string().contains("Message too large"), "got {}", err);
+}
+
+#[tokio::test]
+async fn continuation_requires_negotiation() {
+    let msg = buffer_opened(noise(512 * 1024));
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &chunked(4 * 1024 * 1024)).await.expect("write");
+
+    let err = read_ipc_frame(&mut wire.as_slice(), &FrameOptions::with_limit(FRAME)).await.expect_err("must err");
+    assert!(matches!(err, IpcError::InvalidFrame(ref m) if m.contains("without negotiated chunking")), "got {:?}", err);
+
+    // Without chunking a message above the frame limit is refused as before
+    let err = write_ipc_frame(&mut Vec::new(), &msg, &FrameOptions::with_limit(FRAME)).await.expect_err("must err");
+    assert!(err.to_string().contains("Message too large"), "got {}", err);
+}
+
+#[tokio::test]
+async fn messages_after_a_chunked_one_are_read_normally() {
+    let options = chunked(4 * 1024 * 1024);
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &buffer_opened(noise(600 * 1024)), &options).await.expect("write big");
+    write_ipc_frame(&mut wire, &buffer_opened("small".into()), &options).await.expect("write small");
+
+    let mut reader = wire.as_slice();
+    read_ipc_frame(&mut reader, &options).await.expect("big");
+    match read_ipc_frame(&mut reader, &options).await.expect("small").payload {
+        IpcPayload::Response(CoreResponse::BufferOpened { content, .. }) => assert_eq!(content, "small"),
+        other => panic!("unexpected payload: {:?}", other),
+    }
+    assert!(reader.is_empty());
+}
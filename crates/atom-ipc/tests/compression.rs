@@ -0,0 +1,96 @@
+//! LZ4 frame compression negotiated via the `lz4` capability
+use atom_ipc::{
+    capabilities, read_ipc_frame, server_handshake, write_ipc_frame, CoreResponse, FrameOptions,
+    Hello, IpcError, IpcMessage, IpcPayload, RequestId, ServerHandshake, FRAME_FLAG_LZ4,
+    MAX_MESSAGE_SIZE,
+};
+
+fn buffer_opened(content: String) -> IpcMessage {
+    IpcMessage {
+        id: RequestId::new(),
+        deadline_millis: 0,
+        payload: IpcPayload::Response(CoreResponse::BufferOpened { buffer_id: "b1".into(), content, version: 0, mode: Default::default() }),
+    }
+}
+
+fn compressed(max_decompressed_size: u32) -> FrameOptions {
+    FrameOptions { compression: true, max_decompressed_size, ..FrameOptions::with_limit(MAX_MESSAGE_SIZE) }
+}
+
+#[tokio::test]
+async fn large_payload_is_compressed_below_frame_limit() {
+    // 4 MiB of source text does not fit into a 1 MiB frame uncompressed
+    let content = "fn main() { println!(\"hello\"); }\n".repeat(128 * 1024);
+    let msg = buffer_opened(content.clone());
+    let options = compressed(16 * 1024 * 1024);
+
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &options).await.expect("write compressed");
+    assert_eq!(wire[5] & FRAME_FLAG_LZ4, FRAME_FLAG_LZ4, "flags byte must mark LZ4");
+    assert!(wire.len() < MAX_MESSAGE_SIZE as usize);
+
+    let recv = read_ipc_frame(&mut wire.as_slice(), &options).await.expect("read compressed");
+    match recv.payload {
+        IpcPayload::Response(CoreResponse::BufferOpened { content: got, .. }) => assert_eq!(got, content),
+        other => panic!("unexpected payload: {:?}", other),
+    }
+
+    // Without negotiated compression the same message is rejected by size
+    let err = write_ipc_frame(&mut Vec::new(), &msg, &FrameOptions::default()).await.expect_err("must err");
+    assert!(err.to_string().contains("Message too large"), "got {}", err);
+}
+
+#[tokio::test]
+async fn small_payload_is_sent_uncompressed() {
+    let msg = buffer_opened("short".into());
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &compressed(16 * 1024 * 1024)).await.expect("write");
+    assert_eq!(wire[5], 0);
+    // ...and is readable by a peer that never negotiated compression
+    read_ipc_frame(&mut wire.as_slice(), &FrameOptions::default()).await.expect("read plain");
+}
+
+#[tokio::test]
+async fn compressed_frame_requires_negotiation() {
+    let msg = buffer_opened("x".repeat(64 * 1024));
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &compressed(16 * 1024 * 1024)).await.expect("write");
+
+    let err = read_ipc_frame(&mut wire.as_slice(), &FrameOptions::default()).await.expect_err("must err");
+    assert!(matches!(err, IpcError::InvalidFrame(ref m) if m.contains("without negotiated compression")), "got {:?}", err);
+}
+
+#[tokio::test]
+async fn decompressed_size_limit_is_enforced() {
+    // ~8 KiB on the wire inflating to 8 MiB: must be refused by a 1 MiB decompression cap
+    let msg = buffer_opened("a".repeat(8 * 1024 * 1024));
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &msg, &compressed(16 * 1024 * 1024)).await.expect("write");
+    assert!(wire.len() < 64 * 1024);
+
+    let err = read_ipc_frame(&mut wire.as_slice(), &compressed(1024 * 1024)).await.expect_err("must err");
+    assert!(matches!(err, IpcError::InvalidFrame(ref m) if m.contains("Decompressed message too large")), "got {:?}", err);
+
+    // Too big to compress for this peer and too big for a single frame
+    let err = write_ipc_frame(&mut Vec::new(), &msg, &compressed(1024 * 1024)).await.expect_err("must err");
+    assert!(err.to_string().contains("Message too large"), "got {}", err);
+}
+
+#[tokio::test]
+async fn compression_is_negotiated_only_when_both_sides_offer_it() {
+    for (offered, expected) in [(vec![capabilities::LZ4.to_string()], true), (vec![], false)] {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let options = FrameOptions::handshake(MAX_MESSAGE_SIZE);
+        let hello = Hello { min_version: 1, max_version: 2, capabilities: offered };
+        let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Hello(hello) };
+        write_ipc_frame(&mut client, &msg, &options).await.expect("write hello");
+
+        let (mut r, mut w) = tokio::io::split(&mut server);
+        match server_handshake(&mut r, &mut w, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake") {
+            ServerHandshake::Negotiated(protocol) => {
+                assert_eq!(protocol.frame_options(&FrameOptions::default()).compression, expected);
+            }
+            other => panic!("expected negotiated protocol, got {:?}", other),
+        }
+    }
+}
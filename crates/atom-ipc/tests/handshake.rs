@@ -0,0 +1,162 @@
+//! Hello/Welcome protocol version negotiation
+use atom_ipc::{
+    capabilities, negotiate_version, read_ipc_frame, read_ipc_message, server_handshake,
+    write_ipc_frame, write_ipc_message, CoreRequest, CoreResponse, ErrorCode, FrameOptions, Hello,
+    IpcClient, IpcError, IpcMessage, IpcPayload, RequestId, SearchOptions, ServerHandshake,
+    Welcome, MAX_MESSAGE_SIZE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use futures::StreamExt;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+#[test]
+fn negotiate_picks_highest_common_version() {
+    assert_eq!(negotiate_version(1, 2, 1, 5).unwrap(), 2);
+    assert_eq!(negotiate_version(1, 4, 2, 3).unwrap(), 3);
+    assert_eq!(negotiate_version(1, 2, 1, 1).unwrap(), 1);
+    match negotiate_version(1, 2, 3, 4) {
+        Err(IpcError::IncompatibleVersion { local_min: 1, local_max: 2, remote_min: 3, remote_max: 4 }) => {}
+        other => panic!("expected IncompatibleVersion, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn server_handshake_negotiates_version_and_capabilities() {
+    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+    let options = FrameOptions::handshake(MAX_MESSAGE_SIZE);
+
+    let hello = Hello { min_version: 1, max_version: 9, capabilities: vec![capabilities::STREAMING.into(), "future-feature".into()] };
+    let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Hello(hello) };
+    write_ipc_frame(&mut client, &msg, &options).await.expect("write hello");
+
+    let (mut r, mut w) = tokio::io::split(&mut server);
+    match server_handshake(&mut r, &mut w, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake") {
+        ServerHandshake::Negotiated(protocol) => {
+            assert_eq!(protocol.version, PROTOCOL_VERSION);
+            assert!(protocol.supports(capabilities::STREAMING));
+            assert!(!protocol.supports("future-feature"));
+        }
+        other => panic!("expected negotiated protocol, got {:?}", other),
+    }
+
+    let reply = read_ipc_frame(&mut client, &options).await.expect("read welcome");
+    assert_eq!(reply.id, msg.id);
+    match reply.payload {
+        IpcPayload::Welcome(Welcome { version, capabilities, .. }) => {
+            assert_eq!(version, PROTOCOL_VERSION);
+            assert_eq!(capabilities, vec![capabilities::STREAMING.to_string()]);
+        }
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn server_handshake_rejects_disjoint_range() {
+    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+    let options = FrameOptions::handshake(MAX_MESSAGE_SIZE);
+
+    let hello = Hello { min_version: PROTOCOL_VERSION + 1, max_version: PROTOCOL_VERSION + 3, capabilities: vec![] };
+    let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Hello(hello) };
+    write_ipc_frame(&mut client, &msg, &options).await.expect("write hello");
+
+    let (mut r, mut w) = tokio::io::split(&mut server);
+    let err = server_handshake(&mut r, &mut w, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect_err("must fail");
+    assert!(matches!(err, IpcError::IncompatibleVersion { .. }), "got {:?}", err);
+
+    // The client still learns why: Welcome with version 0 and the server range
+    match read_ipc_frame(&mut client, &options).await.expect("read welcome").payload {
+        IpcPayload::Welcome(w) => {
+            assert_eq!(w.version, 0);
+            assert_eq!((w.min_version, w.max_version), (MIN_PROTOCOL_VERSION, PROTOCOL_VERSION));
+        }
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn server_handshake_passes_legacy_first_message_through() {
+    let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+    let msg = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Request(CoreRequest::Ping) };
+    write_ipc_message(&mut client, &msg).await.expect("write ping");
+
+    let (mut r, mut w) = tokio::io::split(&mut server);
+    match server_handshake(&mut r, &mut w, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake") {
+        ServerHandshake::Legacy(first) => {
+            assert_eq!(first.id, msg.id);
+            assert!(matches!(first.payload, IpcPayload::Request(CoreRequest::Ping)));
+        }
+        other => panic!("expected legacy client, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_reports_incompatible_version() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let options = FrameOptions::handshake(MAX_MESSAGE_SIZE);
+        if let Ok(IpcMessage { id, payload: IpcPayload::Hello(_), .. }) = read_ipc_frame(&mut stream, &options).await {
+            let welcome = Welcome { version: 0, min_version: 7, max_version: 8, capabilities: vec![] };
+            let reply = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Welcome(welcome) };
+            let _ = write_ipc_frame(&mut stream, &reply, &options).await;
+        }
+    });
+
+    match IpcClient::connect(addr.to_string()).await {
+        Err(IpcError::IncompatibleVersion { local_min, local_max, remote_min: 7, remote_max: 8 }) => {
+            assert_eq!((local_min, local_max), (MIN_PROTOCOL_VERSION, PROTOCOL_VERSION));
+        }
+        Err(other) => panic!("expected IncompatibleVersion, got {:?}", other),
+        Ok(_) => panic!("connect must fail"),
+    }
+    let _ = server.await;
+}
+
+#[tokio::test]
+async fn client_falls_back_to_legacy_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        // 1) Pre-handshake server: the unknown Hello payload kills the connection
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let _ = read_ipc_message(&mut stream).await;
+        drop(stream);
+
+        // 2) Reconnect: plain v1 traffic, connect() verifies it with a Ping
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (r, w) = stream.split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        while let Ok(IpcMessage { id, payload, .. }) = read_ipc_message(&mut reader).await {
+            let response = match payload {
+                IpcPayload::Request(CoreRequest::Ping) => CoreResponse::Pong,
+                // Without the streaming capability the client must not send StreamRequest
+                IpcPayload::Request(CoreRequest::Search { .. }) => CoreResponse::SearchResults { results: vec![] },
+                other => CoreResponse::error(ErrorCode::Internal, format!("unexpected {:?}", other)),
+            };
+            let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(response) }).await;
+            let _ = writer.flush().await;
+        }
+    });
+
+    let client = IpcClient::connect(addr.to_string()).await.expect("legacy connect");
+    let protocol = client.protocol().await;
+    assert_eq!(protocol.version, MIN_PROTOCOL_VERSION);
+    assert!(!protocol.supports(capabilities::STREAMING));
+
+    let (_id, stream) = client
+        .start_stream(CoreRequest::Search { query: "needle".into(), options: SearchOptions::default() })
+        .await
+        .expect("start stream");
+    let frames: Vec<_> = tokio::time::timeout(std::time::Duration::from_secs(2), stream.collect::<Vec<_>>())
+        .await
+        .expect("stream finished");
+    assert_eq!(frames.len(), 1);
+    assert!(matches!(frames[0], Ok(CoreResponse::SearchResults { .. })), "got {:?}", frames);
+
+    drop(client);
+    let _ = server.await;
+}
@@ -0,0 +1,153 @@
+//! Wire compatibility with protocol versions 1 and 2
+use atom_ipc::{
+    read_ipc_frame, write_ipc_frame, BufferMode, CoreRequest, CoreResponse, ErrorCode, FrameOptions,
+    IpcError, IpcMessage, IpcPayload, Notification, RequestId, TextChange, TextRange,
+    MAX_MESSAGE_SIZE,
+};
+use serde::{Deserialize, Serialize};
+
+/// Message shapes as a pre-series version 2 peer encodes them. Only the
+/// position of unused variants matters, so they carry no fields here.
+#[derive(Debug, Serialize, Deserialize)]
+struct OldMessage {
+    id: RequestId,
+    deadline_millis: u64,
+    payload: OldPayload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OldPayload {
+    Request(OldRequest),
+    Response(OldResponse),
+    Notification(OldNotification),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OldRequest {
+    Ping,
+    Sleep,
+    OpenBuffer,
+    SaveBuffer { buffer_id: String, content: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OldResponse {
+    Pong,
+    BufferOpened { buffer_id: String, content: String },
+    BufferSaved { buffer_id: String },
+    BufferClosed,
+    SearchResults,
+    LspResponse,
+    ProjectFiles,
+    Stats,
+    Success,
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum OldNotification {
+    BufferChanged { buffer_id: String, changes: Vec<TextChange> },
+}
+
+/// Frame exactly as the old codec wrote it: magic, version, flags, length, CRC32
+fn old_frame(version: u8, payload: OldPayload) -> Vec<u8> {
+    let body = bincode::serialize(&OldMessage { id: RequestId::new(), deadline_millis: 0, payload }).unwrap();
+    let mut frame = b"ATOM".to_vec();
+    frame.extend([version, 0]);
+    frame.extend((body.len() as u32).to_le_bytes());
+    frame.extend(crc32fast::hash(&body).to_le_bytes());
+    frame.extend(body);
+    frame
+}
+
+fn v2() -> FrameOptions {
+    FrameOptions { version: 2, ..FrameOptions::with_limit(MAX_MESSAGE_SIZE) }
+}
+
+async fn decode(frame: Vec<u8>) -> IpcPayload {
+    read_ipc_frame(&mut frame.as_slice(), &v2()).await.expect("decode old frame").payload
+}
+
+/// Encode at version 2 and decode with the old shapes
+async fn encode_old(payload: IpcPayload) -> Result<OldPayload, IpcError> {
+    let mut wire = Vec::new();
+    write_ipc_frame(&mut wire, &IpcMessage { id: RequestId::new(), deadline_millis: 0, payload }, &v2()).await?;
+    assert_eq!(wire[4], 2, "frame version");
+    Ok(bincode::deserialize::<OldMessage>(&wire[14..]).expect("old peer decodes the frame").payload)
+}
+
+#[tokio::test]
+async fn decodes_pre_series_v2_frames() {
+    let error = decode(old_frame(2, OldPayload::Response(OldResponse::Error { message: "boom".into() }))).await;
+    match error {
+        IpcPayload::Response(CoreResponse::Error { code, message, detail }) => {
+            assert_eq!((code, message.as_str(), detail), (ErrorCode::Internal, "boom", None));
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+
+    let opened = OldResponse::BufferOpened { buffer_id: "b1".into(), content: "text".into() };
+    match decode(old_frame(2, OldPayload::Response(opened))).await {
+        IpcPayload::Response(CoreResponse::BufferOpened { buffer_id, content, version: 0, mode: BufferMode::Normal }) => {
+            assert_eq!((buffer_id.as_str(), content.as_str()), ("b1", "text"));
+        }
+        other => panic!("expected BufferOpened, got {:?}", other),
+    }
+
+    let saved = OldResponse::BufferSaved { buffer_id: "b1".into() };
+    assert!(matches!(
+        decode(old_frame(2, OldPayload::Response(saved))).await,
+        IpcPayload::Response(CoreResponse::BufferSaved { version: 0, changes, .. }) if changes.is_empty()
+    ));
+
+    let save = OldRequest::SaveBuffer { buffer_id: "b1".into(), content: "new".into() };
+    assert!(matches!(
+        decode(old_frame(1, OldPayload::Request(save))).await,
+        IpcPayload::Request(CoreRequest::SaveBuffer { force: false, .. })
+    ));
+
+    let changed = OldNotification::BufferChanged { buffer_id: "b1".into(), changes: Vec::new() };
+    assert!(matches!(
+        decode(old_frame(2, OldPayload::Notification(changed))).await,
+        IpcPayload::Notification(Notification::BufferChanged { version: 0, .. })
+    ));
+}
+
+#[tokio::test]
+async fn writes_old_shapes_to_old_peers() {
+    let error = CoreResponse::error(ErrorCode::Conflict, "changed on disk");
+    assert!(matches!(
+        encode_old(IpcPayload::Response(error)).await.unwrap(),
+        OldPayload::Response(OldResponse::Error { message }) if message == "changed on disk"
+    ));
+
+    let opened = CoreResponse::BufferOpened { buffer_id: "b1".into(), content: "text".into(), version: 4, mode: BufferMode::LargeFile };
+    assert!(matches!(
+        encode_old(IpcPayload::Response(opened)).await.unwrap(),
+        OldPayload::Response(OldResponse::BufferOpened { content, .. }) if content == "text"
+    ));
+
+    let range = TextRange { start_line: 0, start_column: 0, end_line: 0, end_column: 1 };
+    let changes = vec![TextChange { range, new_text: "y".into(), old_text: "x".into() }];
+    let changed = Notification::BufferChanged { buffer_id: "b1".into(), changes, version: 7 };
+    assert!(matches!(
+        encode_old(IpcPayload::Notification(changed)).await.unwrap(),
+        OldPayload::Notification(OldNotification::BufferChanged { changes, .. }) if changes.len() == 1
+    ));
+
+    let save = CoreRequest::SaveBuffer { buffer_id: "b1".into(), content: "new".into(), force: true };
+    assert!(matches!(
+        encode_old(IpcPayload::Request(save)).await.unwrap(),
+        OldPayload::Request(OldRequest::SaveBuffer { content, .. }) if content == "new"
+    ));
+}
+
+#[tokio::test]
+async fn requests_without_old_shape_are_rejected_before_the_wire() {
+    let mut wire = Vec::new();
+    let undo = IpcMessage { id: RequestId::new(), deadline_millis: 0, payload: IpcPayload::Request(CoreRequest::Undo { buffer_id: "b1".into() }) };
+    let err = write_ipc_frame(&mut wire, &undo, &v2()).await.expect_err("Undo is version 3 only");
+    assert!(matches!(&err, IpcError::Unsupported(what) if what.starts_with("Undo")), "got {:?}", err);
+    assert!(wire.is_empty());
+}
+
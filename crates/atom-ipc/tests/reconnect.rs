@@ -0,0 +1,163 @@
+//! Client auto-reconnect: backoff, re-handshake, replay of idempotent requests
+use atom_ipc::{
+    capabilities, read_ipc_message, server_handshake, write_ipc_message, ConnectionState,
+    CoreRequest, CoreResponse, ErrorCode, IpcClient, IpcConfig, IpcError, IpcMessage, IpcPayload,
+    SubscriptionTopic, MAX_MESSAGE_SIZE,
+};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+fn fast_reconnect(attempts: usize) -> IpcConfig {
+    IpcConfig { max_reconnect_attempts: attempts, reconnect_base_delay: Duration::from_millis(20), ..IpcConfig::default() }
+}
+
+#[tokio::test]
+async fn reconnect_replays_idempotent_requests_and_fails_others() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        // 1) First connection: take two requests, then "crash"
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (r, w) = stream.split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        let mut sleep_id = None;
+        for _ in 0..2 {
+            if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Sleep { .. }), .. }) = read_ipc_message(&mut reader).await {
+                sleep_id = Some(id);
+            }
+        }
+        drop(stream);
+
+        // 2) After restart: the replayed Sleep keeps its RequestId; SaveBuffer must not come back
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (r, w) = stream.split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        while let Ok(IpcMessage { id, payload, .. }) = read_ipc_message(&mut reader).await {
+            let response = match payload {
+                IpcPayload::Request(CoreRequest::Sleep { .. }) if Some(id) == sleep_id => CoreResponse::Success,
+                IpcPayload::Request(CoreRequest::Ping) => CoreResponse::Pong,
+                other => CoreResponse::error(ErrorCode::Internal, format!("unexpected {:?}", other)),
+            };
+            let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(response) }).await;
+            let _ = writer.flush().await;
+        }
+    });
+
+    let client = IpcClient::connect_with_config(addr.to_string(), fast_reconnect(5)).await.expect("connect");
+    let mut states = client.watch_state();
+    let seen = tokio::spawn(async move {
+        let mut seen = Vec::new();
+        while states.changed().await.is_ok() {
+            let state = *states.borrow_and_update();
+            seen.push(state);
+            if state == ConnectionState::Connected {
+                break;
+            }
+        }
+        seen
+    });
+
+    let (_, sleep_rx) = client.start_request(CoreRequest::Sleep { millis: 1 }).await.expect("start sleep");
+    let (_, save_rx) = client
+        .start_request(CoreRequest::SaveBuffer { buffer_id: "b1".into(), content: "x".into(), force: false })
+        .await
+        .expect("start save");
+
+    let save = tokio::time::timeout(Duration::from_secs(5), save_rx).await.expect("save resolved");
+    assert!(matches!(save, Ok(Err(IpcError::ConnectionLost))), "got {:?}", save);
+
+    let sleep = tokio::time::timeout(Duration::from_secs(5), sleep_rx).await.expect("sleep resolved");
+    assert!(matches!(sleep, Ok(Ok(CoreResponse::Success))), "got {:?}", sleep);
+
+    let seen = tokio::time::timeout(Duration::from_secs(5), seen).await.expect("states").expect("join");
+    assert_eq!(seen, vec![ConnectionState::Reconnecting, ConnectionState::Connected]);
+    assert_eq!(client.state(), ConnectionState::Connected);
+    client.ping().await.expect("ping after reconnect");
+
+    drop(client);
+    let _ = server.await;
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (mut r, mut w) = stream.split();
+        server_handshake(&mut r, &mut w, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        // Wait for one request, then go away for good (listener included)
+        let _ = read_ipc_message(&mut r).await;
+    });
+
+    let client = IpcClient::connect_with_config(addr.to_string(), fast_reconnect(2)).await.expect("connect");
+    let mut states = client.watch_state();
+    let (_, rx) = client.start_request(CoreRequest::Ping).await.expect("start");
+    server.await.expect("server done");
+
+    let res = tokio::time::timeout(Duration::from_secs(5), rx).await.expect("resolved");
+    assert!(matches!(res, Ok(Err(IpcError::ChannelClosed))), "got {:?}", res);
+    tokio::time::timeout(Duration::from_secs(5), states.wait_for(|s| *s == ConnectionState::Disconnected))
+        .await
+        .expect("disconnected in time")
+        .expect("state channel open");
+    assert!(matches!(client.ping().await, Err(IpcError::ChannelClosed)));
+}
+
+#[tokio::test]
+async fn subscriptions_are_restored_after_reconnect() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        // Первое соединение обрывается после ответа на Ping; во втором
+        // записываем первый запрос — это должно быть восстановление подписок
+        let mut first_after_restart = None;
+        for session in 0..2 {
+            let (mut stream, _addr) = listener.accept().await.expect("accept");
+            let (r, w) = stream.split();
+            let mut reader = BufReader::new(r);
+            let mut writer = BufWriter::new(w);
+            server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+            while let Ok(IpcMessage { id, payload, .. }) = read_ipc_message(&mut reader).await {
+                let IpcPayload::Request(request) = payload else { continue };
+                let response = match request {
+                    CoreRequest::Ping => CoreResponse::Pong,
+                    _ => CoreResponse::Success,
+                };
+                if session == 1 && first_after_restart.is_none() {
+                    first_after_restart = Some(request);
+                }
+                let pong = matches!(response, CoreResponse::Pong);
+                let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(response) }).await;
+                let _ = writer.flush().await;
+                if session == 0 && pong {
+                    break;
+                }
+            }
+        }
+        first_after_restart
+    });
+
+    let client = IpcClient::connect_with_config(addr.to_string(), fast_reconnect(5)).await.expect("connect");
+    client.subscribe(vec![SubscriptionTopic::FileSystem, SubscriptionTopic::Diagnostics]).await.expect("subscribe");
+    client.unsubscribe(vec![SubscriptionTopic::Diagnostics]).await.expect("unsubscribe");
+    client.ping().await.expect("ping before restart");
+
+    // Ping во время переподключения уходит после восстановленной подписки
+    tokio::time::timeout(Duration::from_secs(5), client.ping()).await.expect("ping in time").expect("ping after reconnect");
+    drop(client);
+
+    let first = tokio::time::timeout(Duration::from_secs(5), server).await.expect("server done").expect("join");
+    match first {
+        Some(CoreRequest::Subscribe { topics }) => assert_eq!(topics, vec![SubscriptionTopic::FileSystem]),
+        other => panic!("expected Subscribe first, got {:?}", other),
+    }
+}
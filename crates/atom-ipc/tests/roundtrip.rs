@@ -1,7 +1,7 @@
 //! IPC round-trip integration tests
 use atom_ipc::{
-    read_ipc_message, write_ipc_message, CoreRequest, CoreResponse, IpcMessage, IpcPayload,
-    RequestId, MAX_MESSAGE_SIZE,
+    capabilities, read_ipc_message, server_handshake, write_ipc_message, CoreRequest, CoreResponse,
+    IpcMessage, IpcPayload, RequestId, MAX_MESSAGE_SIZE,
 };
 
 #[tokio::test]
@@ -36,6 +36,8 @@ async fn frame_oversize_rejected() {
         payload: IpcPayload::Response(CoreResponse::BufferOpened {
             buffer_id: "b1".to_string(),
             content: huge,
+            version: 0,
+            mode: Default::default(),
         }),
     };
 
@@ -60,14 +62,10 @@ use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
         let mut reader = BufReader::new(r);
         let mut writer = BufWriter::new(w);
 
-        // 1) Handshake: respond to initial Ping
-        if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Ping), .. }) = read_ipc_message(&mut reader).await {
-            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Pong) };
-            let _ = write_ipc_message(&mut writer, &resp).await;
-            let _ = writer.flush().await;
-        }
+        // 1) Handshake: answer Hello with Welcome
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
 
-        // 2) After connect(), client.ping() sends another Ping; respond again
+        // 2) After connect(), client.ping() sends a Ping; respond
         if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Ping), .. }) = read_ipc_message(&mut reader).await {
             let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Pong) };
             let _ = write_ipc_message(&mut writer, &resp).await;
@@ -75,7 +73,7 @@ use tokio::io::{BufReader, BufWriter, AsyncWriteExt};
         }
     });
 
-    // Client connect should perform ping and succeed, and explicit ping also succeeds
+    // Client connect should perform the handshake and succeed, and explicit ping also succeeds
     let client = atom_ipc::IpcClient::connect(addr.to_string()).await.expect("client connected");
     client.ping().await.expect("ping ok");
 
@@ -0,0 +1,115 @@
+//! Streaming responses: several partial frames under one RequestId + final frame
+use atom_ipc::{
+    capabilities, read_ipc_message, server_handshake, write_ipc_message, CoreRequest, CoreResponse,
+    IpcClient, IpcMessage, IpcPayload, SearchOptions, SearchResult, MAX_MESSAGE_SIZE,
+};
+use futures::StreamExt;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+
+fn result(i: usize) -> SearchResult {
+    SearchResult {
+        path: format!("src/file{}.rs", i),
+        line_number: i + 1,
+        column: 1,
+        line_text: "let needle = 1;".to_string(),
+        match_text: "needle".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn stream_request_yields_chunks_then_final() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (r, w) = stream.split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        // 1) Handshake: answer Hello with Welcome
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        // 2) Streamed search: three chunks, then the final frame
+        if let Ok(IpcMessage { id, payload: IpcPayload::StreamRequest(CoreRequest::Search { .. }), .. }) = read_ipc_message(&mut reader).await {
+            for i in 0..3 {
+                let chunk = CoreResponse::SearchResultsChunk { results: vec![result(i)] };
+                let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(chunk) }).await;
+            }
+            let done = CoreResponse::SearchComplete { total: 3 };
+            let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(done) }).await;
+            let _ = writer.flush().await;
+        }
+    });
+
+    let client = IpcClient::connect(addr.to_string()).await.expect("connect");
+    let (_id, mut stream) = client
+        .start_stream(CoreRequest::Search { query: "needle".into(), options: SearchOptions::default() })
+        .await
+        .expect("start stream");
+
+    let mut chunks = 0;
+    let mut total = None;
+    while let Some(frame) = stream.next().await {
+        match frame.expect("frame ok") {
+            CoreResponse::SearchResultsChunk { results } => {
+                assert_eq!(results.len(), 1);
+                chunks += 1;
+            }
+            CoreResponse::SearchComplete { total: t } => total = Some(t),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+    assert_eq!(chunks, 3);
+    assert_eq!(total, Some(3));
+
+    drop(client);
+    let _ = server.await;
+}
+
+#[tokio::test]
+async fn cancel_stops_stream() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _addr) = listener.accept().await.expect("accept");
+        let (r, w) = stream.split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        // Send one chunk, then keep streaming until the client cancels
+        if let Ok(IpcMessage { id, payload: IpcPayload::StreamRequest(_), .. }) = read_ipc_message(&mut reader).await {
+            let chunk = CoreResponse::SearchResultsChunk { results: vec![result(0)] };
+            let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(chunk) }).await;
+            let _ = writer.flush().await;
+            if let Ok(IpcMessage { payload: IpcPayload::Cancel(cancelled), .. }) = read_ipc_message(&mut reader).await {
+                assert_eq!(cancelled, id);
+                // Late chunk after cancel must not reach the consumer
+                let late = CoreResponse::SearchResultsChunk { results: vec![result(1)] };
+                let _ = write_ipc_message(&mut writer, &IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(late) }).await;
+                let _ = writer.flush().await;
+            }
+        }
+    });
+
+    let client = IpcClient::connect(addr.to_string()).await.expect("connect");
+    let (id, mut stream) = client
+        .start_stream(CoreRequest::Search { query: "needle".into(), options: SearchOptions::default() })
+        .await
+        .expect("start stream");
+
+    match stream.next().await {
+        Some(Ok(CoreResponse::SearchResultsChunk { .. })) => {}
+        other => panic!("expected first chunk, got {:?}", other),
+    }
+    client.cancel(id).await.expect("cancel sent");
+
+    let rest: Vec<_> = tokio::time::timeout(std::time::Duration::from_secs(2), stream.collect::<Vec<_>>())
+        .await
+        .expect("stream finished");
+    assert_eq!(rest.len(), 1, "only the cancellation error is expected: {:?}", rest);
+    assert!(matches!(rest[0], Err(atom_ipc::IpcError::Cancelled)));
+
+    drop(client);
+    let _ = server.await;
+}
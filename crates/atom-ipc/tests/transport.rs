@@ -0,0 +1,49 @@
+//! Transport selection: TCP and Unix domain sockets
+use atom_ipc::{
+    capabilities, read_ipc_message, server_handshake, write_ipc_message, CoreRequest, CoreResponse,
+    IpcEndpoint, IpcListener, IpcMessage, IpcPayload, MAX_MESSAGE_SIZE,
+};
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+
+#[test]
+fn endpoint_parse_forms() {
+    assert_eq!(IpcEndpoint::parse("127.0.0.1:8877").unwrap(), IpcEndpoint::Tcp("127.0.0.1:8877".into()));
+    assert_eq!(IpcEndpoint::parse("tcp:127.0.0.1:9000").unwrap(), IpcEndpoint::Tcp("127.0.0.1:9000".into()));
+    assert!(IpcEndpoint::parse("unix:").is_err());
+    assert!(IpcEndpoint::parse("").is_err());
+    #[cfg(unix)]
+    {
+        let ep = IpcEndpoint::parse("unix:/tmp/atomd.sock").unwrap();
+        assert_eq!(ep, IpcEndpoint::Unix("/tmp/atomd.sock".into()));
+        assert_eq!(ep.to_string(), "unix:/tmp/atomd.sock");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn client_ping_over_unix_socket() {
+    let dir = tempfile::tempdir().expect("tmp");
+    let sock = dir.path().join("ipc.sock");
+    let endpoint = IpcEndpoint::Unix(sock.clone());
+    let listener = IpcListener::bind(&endpoint).await.expect("bind");
+
+    let server = tokio::spawn(async move {
+        let (stream, _peer) = listener.accept().await.expect("accept");
+        let (r, w) = stream.into_split();
+        let mut reader = BufReader::new(r);
+        let mut writer = BufWriter::new(w);
+        server_handshake(&mut reader, &mut writer, MAX_MESSAGE_SIZE, capabilities::ALL).await.expect("handshake");
+        if let Ok(IpcMessage { id, payload: IpcPayload::Request(CoreRequest::Ping), .. }) = read_ipc_message(&mut reader).await {
+            let resp = IpcMessage { id, deadline_millis: 0, payload: IpcPayload::Response(CoreResponse::Pong) };
+            let _ = write_ipc_message(&mut writer, &resp).await;
+            let _ = writer.flush().await;
+        }
+        // Listener dropped here: the socket file is removed
+    });
+
+    let client = atom_ipc::IpcClient::connect(endpoint.to_string()).await.expect("client connected");
+    client.ping().await.expect("ping ok");
+    drop(client);
+    server.await.expect("server ok");
+    assert!(!sock.exists(), "socket file must be cleaned up");
+}
@@ -3,8 +3,14 @@
 //! LSP 3.17 protocol implementation with supervisor, health monitoring,
 //! and viewport-oriented optimizations for language server integration.
 
+mod positions;
+
+pub use positions::{negotiated_encoding, to_core_edits, to_lsp_position};
+
+use atom_core::{AppliedEdit, BufferManager, LanguageRegistry, PositionEncoding};
 use atom_settings::Settings;
 use lsp_types::*;
+use ropey::Rope;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -38,19 +44,20 @@ pub enum LspError {
     InvalidResponse(String),
     #[error("Settings error: {0}")]
     SettingsError(#[from] atom_settings::SettingsError),
+    #[error("Buffer error: {0}")]
+    CoreError(#[from] atom_core::CoreError),
 }
 
 /// Language server configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LspServerConfig {
-    /// Language ID (e.g., "rust", "typescript")
+    /// LSP language ID (e.g., "rust", "typescript"); files are matched to it
+    /// through `LanguageDefinition::lsp_id`
     pub language_id: String,
     /// Server executable command
     pub command: String,
     /// Command arguments
     pub args: Vec<String>,
-    /// File extensions handled by this server
-    pub file_extensions: Vec<String>,
     /// Root patterns to find project root
     pub root_patterns: Vec<String>,
     /// Environment variables
@@ -76,10 +83,11 @@ pub(crate) struct LspServer {
     process: Option<Child>,
     state: ServerState,
     capabilities: Option<ServerCapabilities>,
+    /// Units of the columns this server sends and expects
+    position_encoding: PositionEncoding,
     last_health_check: Instant,
     restart_count: u32,
     stdin_tx: Option<mpsc::UnboundedSender<String>>,
-    #[allow(dead_code)]
     request_id_counter: Arc<Mutex<i64>>,
     pending_requests: Arc<Mutex<PendingLspMap>>,
 }
@@ -87,13 +95,13 @@ pub(crate) struct LspServer {
 type PendingLspMap = HashMap<i64, oneshot::Sender<Result<Value, LspError>>>;
 
 impl LspServer {
-    #[allow(dead_code)]
     fn new(config: LspServerConfig) -> Self {
         Self {
             config,
             process: None,
             state: ServerState::Stopped,
             capabilities: None,
+            position_encoding: PositionEncoding::Utf16,
             last_health_check: Instant::now(),
             restart_count: 0,
             stdin_tx: None,
@@ -262,7 +270,6 @@ impl LspServer {
     }
 
     /// Send request to language server
-    #[allow(dead_code)]
     async fn send_request(&mut self, method: &str, params: Value) -> Result<Value, LspError> {
         if !matches!(self.state, ServerState::Running) {
             return Err(LspError::ServerNotFound(self.config.language_id.clone()));
@@ -337,6 +344,34 @@ impl LspServer {
         Ok(())
     }
 
+    /// Params for a request about `position` in the document at `uri`,
+    /// with the column counted in this server's units
+    fn position_params(
+        &self,
+        uri: Url,
+        text: &Rope,
+        position: atom_core::Position,
+    ) -> Result<TextDocumentPositionParams, LspError> {
+        Ok(TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: to_lsp_position(text, position, self.position_encoding)?,
+        })
+    }
+
+    /// Apply edits this server sent for a buffer, reading its columns in the
+    /// server's units
+    async fn apply_edits(
+        &self,
+        buffers: &mut BufferManager,
+        buffer_id: &str,
+        base_version: u64,
+        edits: &[lsp_types::TextEdit],
+    ) -> Result<Vec<AppliedEdit>, LspError> {
+        Ok(buffers
+            .apply_edits_with_encoding(buffer_id, base_version, to_core_edits(edits), self.position_encoding)
+            .await?)
+    }
+
     /// Stop the language server
     async fn stop(&mut self) -> Result<(), LspError> {
         if let Some(mut process) = self.process.take() {
@@ -388,8 +423,9 @@ impl LspServer {
 /// LSP manager handling multiple language servers
 pub struct LspManager {
     servers: Arc<RwLock<HashMap<String, Arc<Mutex<LspServer>>>>>,
-    #[allow(dead_code)]
     configs: HashMap<String, LspServerConfig>,
+    /// Languages of files, the same as atom-core detects for buffers
+    languages: LanguageRegistry,
     #[allow(dead_code)]
     settings: Settings,
     supervisor_handle: Option<tokio::task::JoinHandle<()>>,
@@ -408,7 +444,6 @@ impl LspManager {
                 language_id: "rust".to_string(),
                 command: "rust-analyzer".to_string(),
                 args: vec![],
-                file_extensions: vec!["rs".to_string()],
                 root_patterns: vec!["Cargo.toml".to_string()],
                 env: HashMap::new(),
                 init_options: None,
@@ -422,7 +457,6 @@ impl LspManager {
                 language_id: "typescript".to_string(),
                 command: "typescript-language-server".to_string(),
                 args: vec!["--stdio".to_string()],
-                file_extensions: vec!["ts".to_string(), "tsx".to_string()],
                 root_patterns: vec!["tsconfig.json".to_string(), "package.json".to_string()],
                 env: HashMap::new(),
                 init_options: None,
@@ -436,7 +470,6 @@ impl LspManager {
                 language_id: "python".to_string(),
                 command: "pylsp".to_string(),
                 args: vec![],
-                file_extensions: vec!["py".to_string()],
                 root_patterns: vec!["setup.py".to_string(), "pyproject.toml".to_string()],
                 env: HashMap::new(),
                 init_options: None,
@@ -446,6 +479,7 @@ impl LspManager {
         Self {
             servers: Arc::new(RwLock::new(HashMap::new())),
             configs,
+            languages: LanguageRegistry::with_settings(&settings),
             settings,
             supervisor_handle: None,
         }
@@ -516,24 +550,13 @@ impl LspManager {
         }
     }
 
-    /// Get or start a language server for a file
-    #[allow(dead_code)]
+    /// Get or start a language server for a file with content `text`
     pub(crate) async fn get_server_for_file(
         &mut self,
         file_path: &Path,
+        text: &Rope,
     ) -> Result<Arc<Mutex<LspServer>>, LspError> {
-        // Detect language from file extension
-        let extension = file_path
-            .extension()
-            .and_then(|e| e.to_str())
-            .ok_or_else(|| LspError::ServerNotFound("Unknown file type".to_string()))?;
-
-        // Find matching config
-        let config = self
-            .configs
-            .values()
-            .find(|c| c.file_extensions.contains(&extension.to_string()))
-            .ok_or_else(|| LspError::ServerNotFound(format!("No server for .{}", extension)))?;
+        let config = self.config_for_file(file_path, text)?;
 
         let language_id = config.language_id.clone();
 
@@ -570,6 +593,10 @@ impl LspManager {
             initialization_options: config.init_options.clone(),
             workspace_folders,
             capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(positions::offered_encodings()),
+                    ..Default::default()
+                }),
                 text_document: Some(TextDocumentClientCapabilities {
                     completion: Some(CompletionClientCapabilities {
                         completion_item: Some(CompletionItemCapability {
@@ -593,6 +620,7 @@ impl LspManager {
             .send_request("initialize", serde_json::to_value(init_params)?)
             .await?;
         let capabilities: InitializeResult = serde_json::from_value(init_result)?;
+        server.position_encoding = negotiated_encoding(&capabilities.capabilities);
         server.capabilities = Some(capabilities.capabilities);
 
         // Send initialized notification
@@ -610,8 +638,54 @@ impl LspManager {
         Ok(server)
     }
 
+    /// Send a position request (hover, definition, ...) about `file_path`
+    /// whose buffer text is `text`
+    pub async fn request_at(
+        &mut self,
+        file_path: &Path,
+        text: &Rope,
+        position: atom_core::Position,
+        method: &str,
+    ) -> Result<Value, LspError> {
+        let uri = Url::from_file_path(file_path)
+            .map_err(|_| LspError::ServerNotFound(format!("Not a file path: {}", file_path.display())))?;
+        let server = self.get_server_for_file(file_path, text).await?;
+        let mut server = server.lock().await;
+        let params = server.position_params(uri, text, position)?;
+        server.send_request(method, serde_json::to_value(params)?).await
+    }
+
+    /// Apply edits the server for `file_path` sent (formatting, code
+    /// actions, rename) to its buffer
+    pub async fn apply_edits(
+        &mut self,
+        file_path: &Path,
+        buffers: &mut BufferManager,
+        buffer_id: &str,
+        base_version: u64,
+        edits: &[lsp_types::TextEdit],
+    ) -> Result<Vec<AppliedEdit>, LspError> {
+        let text = buffers
+            .get_buffer(buffer_id)
+            .map(|buffer| buffer.content.clone())
+            .ok_or_else(|| LspError::CoreError(atom_core::CoreError::BufferNotFound(buffer_id.to_string())))?;
+        let server = self.get_server_for_file(file_path, &text).await?;
+        let server = server.lock().await;
+        server.apply_edits(buffers, buffer_id, base_version, edits).await
+    }
+
+    /// Server config for the LSP id of the file's language
+    fn config_for_file(&self, file_path: &Path, text: &Rope) -> Result<&LspServerConfig, LspError> {
+        let language = self
+            .languages
+            .detect(file_path, text)
+            .ok_or_else(|| LspError::ServerNotFound(format!("Unknown file type: {}", file_path.display())))?;
+        self.configs
+            .get(&language.lsp_id)
+            .ok_or_else(|| LspError::ServerNotFound(format!("No server for {}", language.lsp_id)))
+    }
+
     /// Find workspace root based on patterns
-    #[allow(dead_code)]
     fn find_workspace_root(&self, file_path: &Path, patterns: &[String]) -> Option<PathBuf> {
         let mut current = file_path.parent();
 
@@ -672,4 +746,53 @@ mod tests {
         // Clean shutdown
         manager.stop_all().await.unwrap();
     }
+
+    #[test]
+    fn servers_are_picked_by_language_lsp_id() {
+        let settings = Settings {
+            languages: vec![atom_settings::LanguageSettings {
+                id: "deno".into(),
+                extensions: vec!["dts".into()],
+                lsp_id: Some("typescript".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let manager = LspManager::new(settings);
+        let server = |path: &str, text: &str| {
+            manager.config_for_file(Path::new(path), &Rope::from_str(text)).map(|config| config.language_id.clone())
+        };
+        assert_eq!(server("src/main.rs", "").unwrap(), "rust");
+        assert_eq!(server("bin/tool", "#!/usr/bin/env python3\n").unwrap(), "python");
+        assert_eq!(server("types.dts", "").unwrap(), "typescript");
+        assert!(matches!(server("style.css", ""), Err(LspError::ServerNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn positions_use_the_negotiated_encoding() {
+        let config = LspManager::new(Settings::default()).configs["rust"].clone();
+        let mut server = LspServer::new(config);
+        server.position_encoding = PositionEncoding::Utf8;
+        let text = Rope::from_str("// ё\nlet x;\n");
+        let uri = Url::parse("file:///main.rs").unwrap();
+        let params = server.position_params(uri, &text, atom_core::Position { line: 0, column: 4 }).unwrap();
+        assert_eq!(params.position, lsp_types::Position { line: 0, character: 5 });
+
+        let mut buffers = BufferManager::new(Settings::default());
+        let id = buffers.new_buffer();
+        let insert = atom_core::TextEdit {
+            range: atom_core::Range { start: atom_core::Position { line: 0, column: 0 }, end: atom_core::Position { line: 0, column: 0 } },
+            new_text: "// ё x".into(),
+        };
+        buffers.apply_edit(&id, insert).await.unwrap();
+        // Byte 6 is `x`, char 5
+        let range = lsp_types::Range {
+            start: lsp_types::Position { line: 0, character: 6 },
+            end: lsp_types::Position { line: 0, character: 7 },
+        };
+        let edits = [lsp_types::TextEdit { range, new_text: "y".into() }];
+        let applied = server.apply_edits(&mut buffers, &id, 1, &edits).await.unwrap();
+        assert_eq!(buffers.get_buffer(&id).unwrap().content.to_string(), "// ё y");
+        assert_eq!(applied[0].edit.range.start.column, 5);
+    }
 }
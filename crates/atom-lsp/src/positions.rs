@@ -0,0 +1,95 @@
+//! Positions between buffers and language servers
+//!
+//! The client offers every encoding atom-core converts (`utf-32` being plain
+//! char columns) and the server picks one in `ServerCapabilities::position_encoding`;
+//! servers that do not pick count UTF-16 code units, as LSP requires.
+
+use atom_core::{CoreError, PositionEncoding};
+use lsp_types::{PositionEncodingKind, ServerCapabilities};
+use ropey::Rope;
+
+/// Encodings offered to servers, preferred first
+pub(crate) fn offered_encodings() -> Vec<PositionEncodingKind> {
+    vec![PositionEncodingKind::UTF32, PositionEncodingKind::UTF8, PositionEncodingKind::UTF16]
+}
+
+/// Encoding a server picked during initialization
+pub fn negotiated_encoding(capabilities: &ServerCapabilities) -> PositionEncoding {
+    match capabilities.position_encoding.as_ref().map(PositionEncodingKind::as_str) {
+        Some("utf-8") => PositionEncoding::Utf8,
+        Some("utf-32") => PositionEncoding::Char,
+        _ => PositionEncoding::Utf16,
+    }
+}
+
+/// Buffer position as a server position in `encoding`
+pub fn to_lsp_position(
+    text: &Rope,
+    position: atom_core::Position,
+    encoding: PositionEncoding,
+) -> Result<lsp_types::Position, CoreError> {
+    let converted = position.convert(text, PositionEncoding::Char, encoding)?;
+    Ok(lsp_types::Position { line: converted.line as u32, character: converted.column as u32 })
+}
+
+/// Server edits, all relative to one document, as a batch for
+/// `BufferManager::apply_edits_with_encoding` (which applies edits one after
+/// another): in reverse document order, inserts at one point keeping their order
+pub fn to_core_edits(edits: &[lsp_types::TextEdit]) -> Vec<atom_core::TextEdit> {
+    let position = |position: lsp_types::Position| atom_core::Position {
+        line: position.line as usize,
+        column: position.character as usize,
+    };
+    let mut batch: Vec<_> = edits
+        .iter()
+        .rev()
+        .map(|edit| atom_core::TextEdit {
+            range: atom_core::Range { start: position(edit.range.start), end: position(edit.range.end) },
+            new_text: edit.new_text.clone(),
+        })
+        .collect();
+    batch.sort_by_key(|edit| std::cmp::Reverse(edit.range.start));
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_positions_past_astral_chars() {
+        let text = Rope::from_str("let s = \"😀\"; x\n");
+        let x = atom_core::Position { line: 0, column: 13 };
+        let utf16 = to_lsp_position(&text, x, PositionEncoding::Utf16).unwrap();
+        assert_eq!(utf16, lsp_types::Position { line: 0, character: 14 });
+        assert_eq!(to_lsp_position(&text, x, PositionEncoding::Utf8).unwrap().character, 16);
+
+        let mut capabilities = ServerCapabilities::default();
+        assert_eq!(negotiated_encoding(&capabilities), PositionEncoding::Utf16);
+        capabilities.position_encoding = Some(PositionEncodingKind::UTF32);
+        assert_eq!(negotiated_encoding(&capabilities), PositionEncoding::Char);
+    }
+
+    #[tokio::test]
+    async fn applies_server_edits_in_utf16() {
+        let range = |start: u32, end: u32| lsp_types::Range {
+            start: lsp_types::Position { line: 0, character: start },
+            end: lsp_types::Position { line: 0, character: end },
+        };
+        let edit = |start, end, text: &str| lsp_types::TextEdit { range: range(start, end), new_text: text.into() };
+        // "😀" takes two UTF-16 units: `b` starts at 3, `c` at 4
+        let edits = [edit(0, 0, "<"), edit(0, 0, "["), edit(3, 4, "B"), edit(5, 5, ">")];
+
+        let mut bm = atom_core::BufferManager::new(atom_settings::Settings::default());
+        let id = bm.new_buffer();
+        let insert = atom_core::TextEdit {
+            range: atom_core::Range { start: atom_core::Position { line: 0, column: 0 }, end: atom_core::Position { line: 0, column: 0 } },
+            new_text: "a😀bc".into(),
+        };
+        bm.apply_edit(&id, insert).await.unwrap();
+        let applied = bm.apply_edits_with_encoding(&id, 1, to_core_edits(&edits), PositionEncoding::Utf16).await.unwrap();
+        assert_eq!(bm.get_buffer(&id).unwrap().content.to_string(), "<[a😀Bc>");
+        // Applied edits report char columns
+        assert_eq!(applied[1].edit.range.start.column, 2);
+    }
+}
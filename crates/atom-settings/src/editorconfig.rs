@@ -0,0 +1,532 @@
+//! `.editorconfig` support
+//!
+//! For a given file, `.editorconfig` files are read from its directory up to
+//! the filesystem root or the first file with `root = true`. Sections whose
+//! glob matches the file apply in order: files closer to the file override
+//! those further up, and later sections override earlier ones. See
+//! <https://spec.editorconfig.org> for the format.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+use tokio::fs;
+
+const FILE_NAME: &str = ".editorconfig";
+
+/// `indent_style`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+/// `indent_size`: a number of columns, or the width of a tab
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentSize {
+    Columns(u8),
+    Tab,
+}
+
+/// `end_of_line`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndOfLine {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+/// `charset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Latin1,
+    Utf8,
+    Utf8Bom,
+    Utf16Be,
+    Utf16Le,
+}
+
+/// EditorConfig properties in effect for one file (`None`: not set)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EditorConfig {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<IndentSize>,
+    pub tab_width: Option<u8>,
+    pub end_of_line: Option<EndOfLine>,
+    pub charset: Option<Charset>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+impl EditorConfig {
+    /// Resolve the properties for the file at `path` (which need not exist)
+    pub async fn resolve(path: &Path) -> io::Result<Self> {
+        let path = if path.is_absolute() { path.to_path_buf() } else { std::env::current_dir()?.join(path) };
+
+        // Nearest file first
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let Some(file) = load(&dir.join(FILE_NAME)).await? else { continue };
+            let root = file.root;
+            files.push((dir.to_path_buf(), file));
+            if root {
+                break;
+            }
+        }
+
+        let mut properties = HashMap::new();
+        for (dir, file) in files.iter().rev() {
+            let Some(relative) = relative_path(dir, &path) else { continue };
+            for section in file.sections.iter().filter(|s| s.matches(&relative)) {
+                for (key, value) in &section.properties {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(Self::from_properties(&properties))
+    }
+
+    fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let get = |key: &str| properties.get(key).map(String::as_str).filter(|v| *v != "unset");
+        let flag = |key: &str| match get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+        let tab_width = get("tab_width").and_then(|v| v.parse().ok());
+        let indent_size = match get("indent_size") {
+            Some("tab") => Some(IndentSize::Tab),
+            Some(v) => v.parse().ok().map(IndentSize::Columns),
+            None => None,
+        };
+        Self {
+            indent_style: match get("indent_style") {
+                Some("tab") => Some(IndentStyle::Tab),
+                Some("space") => Some(IndentStyle::Space),
+                _ => None,
+            },
+            indent_size,
+            // Defaults to the indent size
+            tab_width: tab_width.or(match indent_size {
+                Some(IndentSize::Columns(n)) => Some(n),
+                _ => None,
+            }),
+            end_of_line: match get("end_of_line") {
+                Some("lf") => Some(EndOfLine::Lf),
+                Some("crlf") => Some(EndOfLine::Crlf),
+                Some("cr") => Some(EndOfLine::Cr),
+                _ => None,
+            },
+            charset: match get("charset") {
+                Some("latin1") => Some(Charset::Latin1),
+                Some("utf-8") => Some(Charset::Utf8),
+                Some("utf-8-bom") => Some(Charset::Utf8Bom),
+                Some("utf-16be") => Some(Charset::Utf16Be),
+                Some("utf-16le") => Some(Charset::Utf16Le),
+                _ => None,
+            },
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+        }
+    }
+}
+
+/// Parsed `.editorconfig` files by path, with the size and modification time
+/// they had when read
+type Cache = HashMap<PathBuf, (u64, SystemTime, Arc<ConfigFile>)>;
+
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(Default::default);
+
+/// The parsed `.editorconfig` file at `path`, if there is one. A file is
+/// parsed again only when its size or modification time changes.
+async fn load(path: &Path) -> io::Result<Option<Arc<ConfigFile>>> {
+    let meta = match fs::metadata(path).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let modified = meta.modified().ok();
+    if let Some(modified) = modified {
+        let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((len, cached_at, file)) = cache.get(path) {
+            if (*len, *cached_at) == (meta.len(), modified) {
+                return Ok(Some(Arc::clone(file)));
+            }
+        }
+    }
+
+    let content = match fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let file = Arc::new(ConfigFile::parse(&content));
+    if let Some(modified) = modified {
+        let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+        cache.insert(path.to_path_buf(), (meta.len(), modified, Arc::clone(&file)));
+    }
+    Ok(Some(file))
+}
+
+/// `path` relative to `dir` with `/` separators
+fn relative_path(dir: &Path, path: &Path) -> Option<String> {
+    let relative: PathBuf = path.strip_prefix(dir).ok()?.to_path_buf();
+    let parts: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    Some(parts.join("/"))
+}
+
+/// One parsed `.editorconfig` file
+#[derive(Debug, Default)]
+struct ConfigFile {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug)]
+struct Section {
+    glob: Vec<Token>,
+    /// The glob contains a `/`: it matches paths relative to the file's
+    /// directory, otherwise file names at any depth
+    anchored: bool,
+    properties: Vec<(String, String)>,
+}
+
+impl Section {
+    fn matches(&self, relative: &str) -> bool {
+        let target = if self.anchored { relative } else { relative.rsplit('/').next().unwrap_or(relative) };
+        matches(&self.glob, &target.chars().collect::<Vec<_>>())
+    }
+}
+
+impl ConfigFile {
+    fn parse(content: &str) -> Self {
+        let mut file = ConfigFile::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let anchored = name.contains('/');
+                file.sections.push(Section {
+                    glob: parse_glob(name.strip_prefix('/').unwrap_or(name)),
+                    anchored,
+                    properties: Vec::new(),
+                });
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_string();
+            match file.sections.last_mut() {
+                Some(section) => {
+                    // Values of known properties are case-insensitive
+                    section.properties.push((key, value.to_lowercase()));
+                }
+                None if key == "root" => file.root = value.eq_ignore_ascii_case("true"),
+                None => {}
+            }
+        }
+        file
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    /// `?`: any character except `/`
+    Any,
+    /// `*`: any string without `/`
+    Star,
+    /// `**`: any string
+    DoubleStar,
+    /// `[...]` / `[!...]`
+    Class { negated: bool, ranges: Vec<(char, char)> },
+    /// `{a,b,c}`
+    Alternatives(Vec<Vec<Token>>),
+    /// `{n1..n2}`
+    Numbers(i64, i64),
+}
+
+fn parse_glob(glob: &str) -> Vec<Token> {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                tokens.push(Token::Char(chars[i + 1]));
+                i += 2;
+                continue;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                tokens.push(Token::DoubleStar);
+                i += 2;
+                continue;
+            }
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '[' => {
+                if let Some((token, end)) = parse_class(&chars, i) {
+                    tokens.push(token);
+                    i = end + 1;
+                    continue;
+                }
+                tokens.push(Token::Char('['));
+            }
+            '{' => {
+                if let Some(end) = closing_brace(&chars, i) {
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    tokens.extend(parse_braces(&inner));
+                    i = end + 1;
+                    continue;
+                }
+                tokens.push(Token::Char('{'));
+            }
+            c => tokens.push(Token::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// `[...]` starting at `start`; returns the token and the index of `]`
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = chars.get(i) == Some(&'!');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    while i < chars.len() {
+        match chars[i] {
+            ']' => return Some((Token::Class { negated, ranges }, i)),
+            '/' => return None,
+            c if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&e| e != ']') => {
+                ranges.push((c, chars[i + 2]));
+                i += 3;
+                continue;
+            }
+            c => ranges.push((c, c)),
+        }
+        i += 1;
+    }
+    None
+}
+
+fn closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Contents of `{...}`: a number range, alternatives, or (without a comma) literal text
+fn parse_braces(inner: &str) -> Vec<Token> {
+    if let Some((from, to)) = inner.split_once("..") {
+        if let (Ok(from), Ok(to)) = (from.parse::<i64>(), to.parse::<i64>()) {
+            return vec![Token::Numbers(from.min(to), from.max(to))];
+        }
+    }
+
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                current.extend(chars.next());
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(parse_glob(&current));
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if alternatives.is_empty() {
+        let mut tokens = vec![Token::Char('{')];
+        tokens.extend(parse_glob(inner));
+        tokens.push(Token::Char('}'));
+        return tokens;
+    }
+    alternatives.push(parse_glob(&current));
+    vec![Token::Alternatives(alternatives)]
+}
+
+/// Whether `tokens` match all of `text`. Tracks the set of text positions
+/// reachable after each token instead of backtracking, so patterns with many
+/// stars stay linear in the pattern length.
+fn matches(tokens: &[Token], text: &[char]) -> bool {
+    let mut start = vec![false; text.len() + 1];
+    start[0] = true;
+    advance(tokens, text, start)[text.len()]
+}
+
+/// Positions in `text` reachable from `positions` by matching `tokens`
+fn advance(tokens: &[Token], text: &[char], mut positions: Vec<bool>) -> Vec<bool> {
+    for token in tokens {
+        if !positions.contains(&true) {
+            break;
+        }
+        positions = match token {
+            Token::Char(c) => one_char(&positions, text, |t| t == *c),
+            Token::Any => one_char(&positions, text, |t| t != '/'),
+            Token::Class { negated, ranges } => one_char(&positions, text, |t| {
+                t != '/' && ranges.iter().any(|&(from, to)| from <= t && t <= to) != *negated
+            }),
+            Token::Star | Token::DoubleStar => {
+                let mut next = vec![false; text.len() + 1];
+                let mut open = false;
+                for (i, reachable) in next.iter_mut().enumerate() {
+                    open |= positions[i];
+                    *reachable = open;
+                    // `*` stops at a path separator, `**` does not
+                    if *token == Token::Star && text.get(i) == Some(&'/') {
+                        open = false;
+                    }
+                }
+                next
+            }
+            Token::Alternatives(alternatives) => {
+                let mut next = vec![false; text.len() + 1];
+                for alternative in alternatives {
+                    for (i, reachable) in advance(alternative, text, positions.clone()).into_iter().enumerate() {
+                        next[i] |= reachable;
+                    }
+                }
+                next
+            }
+            Token::Numbers(from, to) => {
+                let mut next = vec![false; text.len() + 1];
+                for start in (0..text.len()).filter(|&i| positions[i]) {
+                    let sign = usize::from(text[start] == '-');
+                    let digits = text[start + sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+                    for end in start + sign + 1..=start + sign + digits {
+                        let number: String = text[start..end].iter().collect();
+                        next[end] |= number.parse::<i64>().is_ok_and(|n| *from <= n && n <= *to);
+                    }
+                }
+                next
+            }
+        };
+    }
+    positions
+}
+
+/// Positions after one character accepted by `accept`
+fn one_char(positions: &[bool], text: &[char], accept: impl Fn(char) -> bool) -> Vec<bool> {
+    let mut next = vec![false; text.len() + 1];
+    for (i, &c) in text.iter().enumerate() {
+        next[i + 1] = positions[i] && accept(c);
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, path: &str) -> bool {
+        matches(&parse_glob(pattern), &path.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("*.rs", "main.rs"));
+        assert!(!glob("*.rs", "src/main.rs"));
+        assert!(glob("src/**.rs", "src/core/main.rs"));
+        assert!(glob("*.{js,ts}", "app.ts"));
+        assert!(!glob("*.{js,ts}", "app.rs"));
+        assert!(glob("{Makefile,*.mk}", "rules.mk"));
+        assert!(glob("file[0-9].txt", "file7.txt"));
+        assert!(!glob("file[!0-9].txt", "file7.txt"));
+        assert!(glob("part{1..12}.md", "part10.md"));
+        assert!(!glob("part{1..12}.md", "part13.md"));
+        assert!(glob("{single}", "{single}"));
+        assert!(glob("a\\*b", "a*b"));
+        assert!(glob("src/**/{a,b}*.rs", "src/x/y/b1.rs"));
+        assert!(glob("*{-1..1}", "v-1"));
+    }
+
+    #[test]
+    fn many_stars_do_not_backtrack() {
+        let pattern = format!("{}b", "*a".repeat(30));
+        assert!(!glob(&pattern, &"a".repeat(200)));
+        assert!(glob(&pattern, &format!("{}b", "a".repeat(200))));
+    }
+
+    #[tokio::test]
+    async fn nearest_file_wins_and_root_stops_lookup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("project/web")).unwrap();
+        std::fs::write(dir.join(".editorconfig"), "[*]\nindent_style = tab\n").unwrap();
+        std::fs::write(
+            dir.join("project/.editorconfig"),
+            "root = true\n\n[*]\nend_of_line = lf\ninsert_final_newline = true\ncharset = utf-8\n\n\
+             [*.{js,ts}]\nindent_style = space\nindent_size = 2\n\n[Makefile]\nindent_style = tab\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("project/web/.editorconfig"), "[*.ts]\nend_of_line = CRLF\ncharset = unset\n").unwrap();
+
+        let config = EditorConfig::resolve(&dir.join("project/web/app.ts")).await.unwrap();
+        assert_eq!(
+            config,
+            EditorConfig {
+                indent_style: Some(IndentStyle::Space),
+                indent_size: Some(IndentSize::Columns(2)),
+                tab_width: Some(2),
+                end_of_line: Some(EndOfLine::Crlf),
+                charset: None,
+                trim_trailing_whitespace: None,
+                insert_final_newline: Some(true),
+            }
+        );
+        // `root = true` hides the outer file
+        let config = EditorConfig::resolve(&dir.join("project/main.rs")).await.unwrap();
+        assert_eq!(config.indent_style, None);
+        assert_eq!(config.charset, Some(Charset::Utf8));
+    }
+
+    #[tokio::test]
+    async fn parsed_files_are_cached_until_they_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join(FILE_NAME);
+        let file = dir.path().join("main.rs");
+        std::fs::write(&config_path, "root = true\n[*]\nindent_size = 2\n").unwrap();
+        let modified = std::fs::metadata(&config_path).unwrap().modified().unwrap();
+        let set_modified = |time| std::fs::File::options().write(true).open(&config_path).unwrap().set_modified(time).unwrap();
+        assert_eq!(EditorConfig::resolve(&file).await.unwrap().indent_size, Some(IndentSize::Columns(2)));
+
+        // Same size and modification time: the cached parse is used
+        std::fs::write(&config_path, "root = true\n[*]\nindent_size = 4\n").unwrap();
+        set_modified(modified);
+        assert_eq!(EditorConfig::resolve(&file).await.unwrap().indent_size, Some(IndentSize::Columns(2)));
+
+        set_modified(modified + std::time::Duration::from_secs(1));
+        assert_eq!(EditorConfig::resolve(&file).await.unwrap().indent_size, Some(IndentSize::Columns(4)));
+    }
+}
@@ -3,6 +3,10 @@
 //! This crate handles configuration and settings for Atom IDE,
 //! including user preferences, workspace settings, and daemon configuration.
 
+mod editorconfig;
+
+pub use editorconfig::{Charset, EditorConfig, EndOfLine, IndentSize, IndentStyle};
+
 // use atom_ipc::IpcError; // not used directly here
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
@@ -23,6 +27,7 @@ pub enum SettingsError {
 
 /// Main settings structure for Atom IDE
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct Settings {
     /// Daemon configuration
     pub daemon: DaemonSettings,
@@ -34,12 +39,16 @@ pub struct Settings {
     pub extensions: ExtensionSettings,
     /// AI integration settings
     pub ai: AiSettings,
+    /// Language entries (`[[languages]]`) extending or adding languages
+    pub languages: Vec<LanguageSettings>,
 }
 
 /// Daemon connection and process settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DaemonSettings {
-    /// Socket address for IPC connection
+    /// Socket address for IPC connection: `127.0.0.1:8877` / `tcp:127.0.0.1:8877`
+    /// or `unix:/run/user/1000/atomd.sock` (Unix domain socket, owner-only access)
     pub daemon_socket: String,
     /// Auto-start daemon if not running
     pub auto_start: bool,
@@ -55,6 +64,20 @@ pub struct DaemonSettings {
     pub ipc_request_timeout_ms: u64,
     /// IPC: лимит одновременных запросов на соединение (бэкпрешер)
     pub ipc_max_inflight_per_conn: usize,
+    /// IPC: лимит размера сообщения после распаковки сжатого кадра (байт)
+    pub ipc_max_decompressed_bytes: u32,
+    /// IPC: разрешить сжатие кадров LZ4 (включается, если клиент тоже его поддерживает)
+    pub ipc_compression: bool,
+    /// IPC: лимит размера сообщения, собранного из нескольких кадров (байт)
+    pub ipc_max_reassembled_bytes: u32,
+    /// IPC: ёмкость очереди уведомлений одного подписчика (старые отбрасываются)
+    pub ipc_notification_queue: usize,
+    /// IPC: число ответов, сохраняемых для повторов запросов с тем же RequestId
+    pub ipc_idempotency_cache_entries: usize,
+    /// IPC: суммарный размер сохранённых ответов (байт); больший ответ не сохраняется
+    pub ipc_idempotency_cache_bytes: usize,
+    /// IPC: время жизни сохранённого ответа (мс)
+    pub ipc_idempotency_ttl_ms: u64,
 }
 
 /// UI appearance and behavior settings
@@ -82,6 +105,7 @@ pub struct UiSettings {
 
 /// Editor behavior settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EditorSettings {
     /// Auto-save delay in milliseconds
     pub auto_save_delay: u32,
@@ -95,6 +119,30 @@ pub struct EditorSettings {
     pub trim_trailing_whitespace: bool,
     /// Insert final newline on save
     pub insert_final_newline: bool,
+    /// Edits typed within this many milliseconds of each other form one undo step
+    pub undo_group_ms: u64,
+    /// Undo steps kept per buffer; older ones are dropped
+    pub undo_max_steps: usize,
+    /// Text kept in undo steps per buffer (bytes); older steps are dropped
+    pub undo_max_bytes: usize,
+}
+
+/// Editor settings in effect for one file: the user and workspace settings
+/// overridden by `.editorconfig`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSettings {
+    /// Use spaces instead of tabs
+    pub insert_spaces: bool,
+    /// Indentation width in columns
+    pub tab_size: u8,
+    /// Line ending for the file; `None` keeps what the file uses
+    pub end_of_line: Option<EndOfLine>,
+    /// Encoding for the file; `None` keeps the detected one
+    pub charset: Option<Charset>,
+    /// Trim trailing whitespace on save
+    pub trim_trailing_whitespace: bool,
+    /// Insert final newline on save
+    pub insert_final_newline: bool,
 }
 
 /// Extension and plugin settings
@@ -114,6 +162,27 @@ pub struct ExtensionSettings {
     pub registry_url: String,
 }
 
+/// Language entry: extends the built-in language with this id, or adds a
+/// language without a grammar
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LanguageSettings {
+    /// Language identifier (`python`, `terraform`)
+    pub id: String,
+    /// File extensions without the dot
+    pub extensions: Vec<String>,
+    /// Exact file names (`Makefile`)
+    pub file_names: Vec<String>,
+    /// Shebang interpreters (`python3`)
+    pub shebangs: Vec<String>,
+    /// Line comment token
+    pub line_comment: Option<String>,
+    /// Block comment start and end tokens
+    pub block_comment: Option<(String, String)>,
+    /// Language id reported to language servers (defaults to `id`)
+    pub lsp_id: Option<String>,
+}
+
 /// AI integration settings
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AiSettings {
@@ -159,6 +228,13 @@ impl Default for DaemonSettings {
             ipc_max_frame_bytes: 1024 * 1024, // 1 MiB
             ipc_request_timeout_ms: 30_000,
             ipc_max_inflight_per_conn: 1024,
+            ipc_max_decompressed_bytes: 16 * 1024 * 1024, // 16 MiB
+            ipc_compression: true,
+            ipc_max_reassembled_bytes: 64 * 1024 * 1024, // 64 MiB
+            ipc_notification_queue: 256,
+            ipc_idempotency_cache_entries: 1024,
+            ipc_idempotency_cache_bytes: 16 * 1024 * 1024, // 16 MiB
+            ipc_idempotency_ttl_ms: 120_000,
         }
     }
 }
@@ -188,6 +264,9 @@ impl Default for EditorSettings {
             auto_close_brackets: true,
             trim_trailing_whitespace: true,
             insert_final_newline: true,
+            undo_group_ms: 500,
+            undo_max_steps: 10_000,
+            undo_max_bytes: 64 * 1024 * 1024,
         }
     }
 }
@@ -222,6 +301,27 @@ impl Default for AiSettings {
 }
 
 impl Settings {
+    /// Settings for the file at `path`, with `.editorconfig` files applied.
+    /// Unreadable `.editorconfig` files are logged and skipped.
+    pub async fn for_file<P: AsRef<Path>>(&self, path: P) -> FileSettings {
+        let path = path.as_ref();
+        let config = EditorConfig::resolve(path).await.unwrap_or_else(|e| {
+            tracing::warn!("Cannot read .editorconfig for {:?}: {}", path, e);
+            EditorConfig::default()
+        });
+        FileSettings {
+            insert_spaces: config.indent_style.map_or(self.ui.insert_spaces, |style| style == IndentStyle::Space),
+            tab_size: match config.indent_size {
+                Some(IndentSize::Columns(n)) => n,
+                Some(IndentSize::Tab) | None => config.tab_width.unwrap_or(self.ui.tab_size),
+            },
+            end_of_line: config.end_of_line,
+            charset: config.charset,
+            trim_trailing_whitespace: config.trim_trailing_whitespace.unwrap_or(self.editor.trim_trailing_whitespace),
+            insert_final_newline: config.insert_final_newline.unwrap_or(self.editor.insert_final_newline),
+        }
+    }
+
     /// Load settings from default location
     pub async fn load() -> Result<Self, SettingsError> {
         let config_path = Self::default_config_path();
@@ -315,6 +415,16 @@ impl Settings {
         if other.ui.font_size != UiSettings::default().font_size {
             self.ui.font_size = other.ui.font_size;
         }
+        let editor_defaults = EditorSettings::default();
+        if other.editor.trim_trailing_whitespace != editor_defaults.trim_trailing_whitespace {
+            self.editor.trim_trailing_whitespace = other.editor.trim_trailing_whitespace;
+        }
+        if other.editor.insert_final_newline != editor_defaults.insert_final_newline {
+            self.editor.insert_final_newline = other.editor.insert_final_newline;
+        }
+        if !other.languages.is_empty() {
+            self.languages.extend(other.languages);
+        }
         // ... continue for other fields as needed
     }
 
@@ -3,12 +3,16 @@
 //! This crate provides Slint-based UI components and window management
 //! for the Atom IDE, including the main window, panels, and themes.
 
-use atom_ipc::{CoreRequest, CoreResponse, IpcClient, IpcError, Notification, SearchOptions, RequestId};
+use atom_ipc::{BufferMode, ConnectionState, CoreRequest, CoreResponse, ErrorCode, IpcClient, IpcError, Notification, SearchOptions, RequestId, SubscriptionTopic};
 use atom_settings::Settings;
+use futures::StreamExt;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 use tracing::{error, info, warn};
 
+/// Lines of a large file fetched when it is opened
+const LARGE_FILE_VIEWPORT_LINES: usize = 200;
+
 /// UI-related errors
 #[derive(Debug, thiserror::Error)]
 pub enum UiError {
@@ -26,6 +30,17 @@ pub enum UiError {
     WindowError(String),
 }
 
+impl UiError {
+    /// Error class reported to the UI in `UiEvent::Error`
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            UiError::IpcError(e) => e.code(),
+            UiError::ComponentNotFound(_) => ErrorCode::NotFound,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
 // Note: текущая реализация UI не тянет Slint напрямую; зависимости UI фичей находятся в других модулях.
 
 /// UI command that can be sent to the window
@@ -69,20 +84,32 @@ pub enum UiEvent {
     FileOpened {
         buffer_id: String,
         content: String,
+        /// Large-file or read-only binary view
+        mode: BufferMode,
     },
     FileSaved {
         buffer_id: String,
+        /// Edits made while saving, to apply to the editor view
+        changes: Vec<atom_ipc::TextChange>,
     },
     SearchResults {
         results: Vec<atom_ipc::SearchResult>,
     },
+    /// Partial search results while the search is still running
+    SearchResultsChunk {
+        results: Vec<atom_ipc::SearchResult>,
+    },
     ProjectFiles {
         files: Vec<String>,
     },
     Stats { cancels: u64, deadlines: u64, backpressure: u64 },
     SearchStarted { request_id: RequestId },
     SearchCancelled { request_id: RequestId },
+    /// IPC connection state changed (e.g. `Reconnecting` while atomd restarts)
+    ConnectionStateChanged { state: ConnectionState },
+    /// Operation failed; `code` tells the UI how to react (retry, prompt, ignore)
     Error {
+        code: ErrorCode,
         message: String,
     },
 }
@@ -96,6 +123,7 @@ pub struct AtomWindow {
     ui_event_tx: mpsc::UnboundedSender<UiEvent>,
     ui_event_rx: Option<mpsc::UnboundedReceiver<UiEvent>>,
     notification_handler: Option<tokio::task::JoinHandle<()>>,
+    connection_watcher: Option<tokio::task::JoinHandle<()>>,
     current_search_id: Arc<Mutex<Option<RequestId>>>,
 }
 
@@ -126,6 +154,15 @@ impl AtomWindow {
                 "Failed to subscribe to notifications".to_string(),
             ))
         })?;
+        let state_rx = ipc_client.watch_state();
+
+        // File tree and diagnostics updates are pushed by the daemon
+        if let Err(e) = ipc_client
+            .subscribe(vec![SubscriptionTopic::FileSystem, SubscriptionTopic::Diagnostics])
+            .await
+        {
+            warn!("Daemon notifications unavailable: {}", e);
+        }
 
         let ipc_client = Arc::new(Mutex::new(ipc_client));
         let settings = Arc::new(Mutex::new(settings));
@@ -138,12 +175,16 @@ impl AtomWindow {
             ui_event_tx,
             ui_event_rx: Some(ui_event_rx),
             notification_handler: None,
+            connection_watcher: None,
             current_search_id: Arc::new(Mutex::new(None)),
         };
 
         // Start notification handler
         window.start_notification_handler(notification_rx).await?;
 
+        // Forward connection state changes to the UI
+        window.start_connection_watcher(state_rx);
+
         // Apply initial settings
         window.apply_settings().await?;
 
@@ -233,14 +274,31 @@ impl AtomWindow {
         Ok(())
     }
 
+    /// Forward IPC connection state changes as `UiEvent::ConnectionStateChanged`
+    fn start_connection_watcher(&mut self, mut state_rx: watch::Receiver<ConnectionState>) {
+        let ui_event_tx = self.ui_event_tx.clone();
+
+        let handle = tokio::spawn(async move {
+            while state_rx.changed().await.is_ok() {
+                let state = *state_rx.borrow_and_update();
+                info!("IPC connection state: {:?}", state);
+                if ui_event_tx.send(UiEvent::ConnectionStateChanged { state }).is_err() {
+                    break;
+                }
+            }
+        });
+
+        self.connection_watcher = Some(handle);
+    }
+
     /// Handle an individual notification from the daemon
     async fn handle_notification(
         notification: Notification,
         _ui_event_tx: &mpsc::UnboundedSender<UiEvent>,
     ) -> Result<(), UiError> {
         match notification {
-            Notification::BufferChanged { buffer_id, changes } => {
-                info!("Buffer changed: {} ({} changes)", buffer_id, changes.len());
+            Notification::BufferChanged { buffer_id, changes, version } => {
+                info!("Buffer changed: {} ({} changes, version {})", buffer_id, changes.len(), version);
                 // In real implementation, update the editor buffer
             }
             Notification::DiagnosticsUpdate { uri, diagnostics } => {
@@ -278,6 +336,7 @@ impl AtomWindow {
                         error!("Error processing UI command: {}", e);
 
                         if let Err(send_err) = ui_event_tx.send(UiEvent::Error {
+                            code: e.code(),
                             message: format!("Command failed: {}", e),
                         }) {
                             error!("Failed to send error event: {}", send_err);
@@ -335,17 +394,35 @@ impl AtomWindow {
                     .request(CoreRequest::OpenBuffer { path: path.clone() })
                     .await
                 {
-                    Ok(CoreResponse::BufferOpened { buffer_id, content }) => {
+                    Ok(CoreResponse::BufferOpened { buffer_id, mut content, mode, .. }) => {
                         info!("File opened successfully: {} ({})", path, buffer_id);
+                        // Large files come without content: fetch the first screenful
+                        if mode == BufferMode::LargeFile && content.is_empty() {
+                            let lines = CoreRequest::GetLines {
+                                buffer_id: buffer_id.clone(),
+                                start_line: 0,
+                                end_line: LARGE_FILE_VIEWPORT_LINES,
+                            };
+                            match client.request(lines).await {
+                                Ok(CoreResponse::Lines { text, .. }) => content = text,
+                                Ok(response) => warn!("Unexpected response to GetLines: {:?}", response),
+                                Err(e) => warn!("Failed to fetch lines of {}: {}", buffer_id, e),
+                            }
+                        }
+                        // Changes made by other clients to this buffer
+                        let topic = SubscriptionTopic::Buffer { buffer_id: buffer_id.clone() };
+                        if let Err(e) = client.subscribe(vec![topic]).await {
+                            warn!("Failed to subscribe to buffer {}: {}", buffer_id, e);
+                        }
                         ui_event_tx
-                            .send(UiEvent::FileOpened { buffer_id, content })
+                            .send(UiEvent::FileOpened { buffer_id, content, mode })
                             .map_err(|_| UiError::ChannelError)?;
                     }
-                    Ok(CoreResponse::Error { message }) => {
+                    Ok(CoreResponse::Error { code, message, .. }) => {
                         let error_msg = format!("Failed to open file '{}': {}", path, message);
                         error!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Ok(response) => {
@@ -355,7 +432,7 @@ impl AtomWindow {
                         );
                         warn!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code: ErrorCode::Internal, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Err(ipc_error) => {
@@ -380,18 +457,18 @@ impl AtomWindow {
                             .send(UiEvent::ProjectFiles { files })
                             .map_err(|_| UiError::ChannelError)?;
                     }
-                    Ok(CoreResponse::Error { message }) => {
+                    Ok(CoreResponse::Error { code, message, .. }) => {
                         let error_msg = format!("Failed to open folder '{}': {}", path, message);
                         error!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Ok(other) => {
                         let error_msg = format!("Unexpected response to open folder '{}': {:?}", path, other);
                         warn!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code: ErrorCode::Internal, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Err(ipc_error) => {
@@ -410,21 +487,22 @@ impl AtomWindow {
                     .request(CoreRequest::SaveBuffer {
                         buffer_id: buffer_id.clone(),
                         content: String::new(), // In real implementation, get content from editor
+                        force: false,
                     })
                     .await
                 {
-                    Ok(CoreResponse::BufferSaved { buffer_id }) => {
+                    Ok(CoreResponse::BufferSaved { buffer_id, changes, .. }) => {
                         info!("File saved successfully: {}", buffer_id);
                         ui_event_tx
-                            .send(UiEvent::FileSaved { buffer_id })
+                            .send(UiEvent::FileSaved { buffer_id, changes })
                             .map_err(|_| UiError::ChannelError)?;
                     }
-                    Ok(CoreResponse::Error { message }) => {
+                    Ok(CoreResponse::Error { code, message, .. }) => {
                         let error_msg =
                             format!("Failed to save buffer '{}': {}", buffer_id, message);
                         error!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Ok(response) => {
@@ -434,7 +512,7 @@ impl AtomWindow {
                         );
                         warn!("{}", error_msg);
                         ui_event_tx
-                            .send(UiEvent::Error { message: error_msg })
+                            .send(UiEvent::Error { code: ErrorCode::Internal, message: error_msg })
                             .map_err(|_| UiError::ChannelError)?;
                     }
                     Err(ipc_error) => {
@@ -449,30 +527,46 @@ impl AtomWindow {
             UiCommand::Search { query, options } => {
                 info!("Processing search command: '{}'", query);
                 let client = ipc_client.lock().await;
-                match client.start_request(CoreRequest::Search { query: query.clone(), options }).await {
-                    Ok((req_id, rx)) => {
+                match client.start_stream(CoreRequest::Search { query: query.clone(), options }).await {
+                    Ok((req_id, mut stream)) => {
                         // Уведомляем UI о старте
                         ui_event_tx.send(UiEvent::SearchStarted { request_id: req_id }).map_err(|_| UiError::ChannelError)?;
                         *current_search_id.lock().await = Some(req_id);
                         drop(client);
-                        // Ожидаем результат в отдельной задаче
+                        // Читаем чанки в отдельной задаче; итоговый список отдаём по финальному кадру
                         let tx = ui_event_tx.clone();
                         tokio::spawn(async move {
-                            match rx.await {
-                                Ok(Ok(CoreResponse::SearchResults { results })) => {
-                                    let _ = tx.send(UiEvent::SearchResults { results });
-                                }
-                                Ok(Ok(CoreResponse::Error { message })) => {
-                                    let _ = tx.send(UiEvent::Error { message });
-                                }
-                                Ok(Ok(other)) => {
-                                    let _ = tx.send(UiEvent::Error { message: format!("Unexpected response: {:?}", other) });
-                                }
-                                Ok(Err(e)) => {
-                                    let _ = tx.send(UiEvent::Error { message: format!("IPC error: {}", e) });
-                                }
-                                Err(_) => {
-                                    let _ = tx.send(UiEvent::Error { message: "Await error".into() });
+                            let mut all = Vec::new();
+                            while let Some(frame) = stream.next().await {
+                                match frame {
+                                    Ok(CoreResponse::SearchResultsChunk { results }) => {
+                                        all.extend(results.iter().cloned());
+                                        let _ = tx.send(UiEvent::SearchResultsChunk { results });
+                                    }
+                                    Ok(CoreResponse::SearchComplete { .. }) => {
+                                        let _ = tx.send(UiEvent::SearchResults { results: all });
+                                        return;
+                                    }
+                                    Ok(CoreResponse::SearchResults { results }) => {
+                                        let _ = tx.send(UiEvent::SearchResults { results });
+                                        return;
+                                    }
+                                    // Подтверждение отмены от демона — ошибкой не считается
+                                    Ok(CoreResponse::Error { code: ErrorCode::Cancelled, .. }) => return,
+                                    Ok(CoreResponse::Error { code, message, .. }) => {
+                                        let _ = tx.send(UiEvent::Error { code, message });
+                                        return;
+                                    }
+                                    Ok(other) => {
+                                        let _ = tx.send(UiEvent::Error { code: ErrorCode::Internal, message: format!("Unexpected response: {:?}", other) });
+                                        return;
+                                    }
+                                    // Отмена уже отражена событием SearchCancelled
+                                    Err(IpcError::Cancelled) => return,
+                                    Err(e) => {
+                                        let _ = tx.send(UiEvent::Error { code: e.code(), message: format!("IPC error: {}", e) });
+                                        return;
+                                    }
                                 }
                             }
                         });
@@ -490,7 +584,7 @@ impl AtomWindow {
                             ui_event_tx.send(UiEvent::SearchCancelled { request_id: req_id }).map_err(|_| UiError::ChannelError)?;
                         }
                         Err(e) => {
-                            let _ = ui_event_tx.send(UiEvent::Error { message: format!("Cancel failed: {}", e) });
+                            let _ = ui_event_tx.send(UiEvent::Error { code: e.code(), message: format!("Cancel failed: {}", e) });
                         }
                     }
                 } else {
@@ -545,6 +639,10 @@ impl AtomWindow {
             }
         }
 
+        if let Some(handle) = self.connection_watcher.take() {
+            handle.abort();
+        }
+
         // Shutdown IPC client
         {
             let client = self.ipc_client.lock().await;